@@ -1,19 +1,33 @@
 use super::{DEVICE_ID_LENGTH, SESSION_ID_LENGTH, TOKEN_LENGTH};
-use crate::{api::client_server, services, utils, Error, Result, Ruma};
+use crate::{
+    api::client_server,
+    service::threepid::{self, ValidationPurpose},
+    services, utils, Error, Result, Ruma,
+};
+use axum::{
+    extract::Query,
+    response::{IntoResponse, Redirect, Response},
+    Json,
+};
 use ruma::{
     api::client::{
         account::{
-            change_password, deactivate, get_3pids, get_username_availability,
+            add_3pid, change_password, deactivate, delete_3pid, get_3pids,
+            get_username_availability,
             register::{self, LoginType},
             request_3pid_management_token_via_email, request_3pid_management_token_via_msisdn,
-            whoami, ThirdPartyIdRemovalStatus,
+            request_password_change_token_via_email, whoami, ThirdPartyIdRemovalStatus,
         },
         error::ErrorKind,
-        uiaa::{AuthFlow, AuthType, UiaaInfo},
+        uiaa::{AuthData, AuthFlow, AuthType, EmailIdentity, UiaaInfo},
     },
     events::{room::message::RoomMessageEventContent, GlobalAccountDataEventType},
-    push, UserId,
+    push,
+    thirdparty::Medium,
+    UserId,
 };
+use serde::Deserialize;
+use serde_json::json;
 use tracing::{info, warn};
 
 use register::RegistrationKind;
@@ -69,24 +83,52 @@ pub async fn get_register_available_route(
 /// You can use [`GET /_matrix/client/r0/register/available`](fn.get_register_available_route.html)
 /// to check if the user id is valid and available.
 ///
-/// - Only works if registration is enabled
+/// - Only works if registration is enabled, or guest access for guest registrations
 /// - If type is guest: ignores all parameters except initial_device_display_name
+/// - If the sender is a guest: upgrades the guest account to a full account with the same user id
 /// - If sender is not appservice: Requires UIAA (but we only use a dummy stage)
 /// - If type is not guest and no username is given: Always fails after UIAA check
 /// - Creates a new account and populates it with default account data
 /// - If `inhibit_login` is false: Creates a device and returns device id and access_token
 pub async fn register_route(body: Ruma<register::v3::Request>) -> Result<register::v3::Response> {
-    if !services().globals.allow_registration().await && body.appservice_info.is_none() {
+    let is_guest = body.kind == RegistrationKind::Guest;
+
+    if is_guest {
+        if !services().globals.allow_guest_access().await {
+            return Err(Error::BadRequest(
+                ErrorKind::GuestAccessForbidden,
+                "Guest access has been disabled.",
+            ));
+        }
+    } else if !services().globals.allow_registration().await && body.appservice_info.is_none() {
         return Err(Error::BadRequest(
             ErrorKind::forbidden(),
             "Registration has been disabled.",
         ));
     }
 
-    let is_guest = body.kind == RegistrationKind::Guest;
+    // Guests registering while logged in upgrade their account instead of creating a new one
+    let upgraded_guest = match &body.sender_user {
+        Some(sender_user) if !is_guest && services().users.is_guest(sender_user)? => {
+            Some(sender_user.clone())
+        }
+        _ => None,
+    };
 
-    let user_id = match (&body.username, is_guest) {
-        (Some(username), false) => {
+    let user_id = match (&body.username, is_guest, upgraded_guest) {
+        (username, _, Some(guest_user_id)) => {
+            if username
+                .as_ref()
+                .is_some_and(|username| username.to_lowercase() != guest_user_id.localpart())
+            {
+                return Err(Error::BadRequest(
+                    ErrorKind::InvalidUsername,
+                    "Guests keep their user ID when upgrading their account.",
+                ));
+            }
+            guest_user_id
+        }
+        (Some(username), false, None) => {
             let proposed_user_id = UserId::parse_with_server_name(
                 username.to_lowercase(),
                 services().globals.server_name(),
@@ -203,33 +245,49 @@ pub async fn register_route(body: Ruma<register::v3::Request>) -> Result<registe
         body.password.as_deref()
     };
 
-    // Create user
-    services().users.create(&user_id, password)?;
+    let displayname = if services().users.is_guest(&user_id)? {
+        // Upgrade the guest, keeping their profile and account data
+        services().users.set_password(&user_id, password)?;
+        services().users.set_guest(&user_id, false)?;
 
-    // Default to pretty displayname
-    let mut displayname = user_id.localpart().to_owned();
+        services()
+            .users
+            .displayname(&user_id)?
+            .unwrap_or_else(|| user_id.localpart().to_owned())
+    } else {
+        // Create user
+        services().users.create(&user_id, password)?;
+        if is_guest {
+            services().users.set_guest(&user_id, true)?;
+        }
 
-    // If enabled append lightning bolt to display name (default true)
-    if services().globals.enable_lightning_bolt() {
-        displayname.push_str(" ⚡️");
-    }
+        // Default to pretty displayname
+        let mut displayname = user_id.localpart().to_owned();
 
-    services()
-        .users
-        .set_displayname(&user_id, Some(displayname.clone()))?;
+        // If enabled append lightning bolt to display name (default true)
+        if services().globals.enable_lightning_bolt() {
+            displayname.push_str(" ⚡️");
+        }
 
-    // Initial account data
-    services().account_data.update(
-        None,
-        &user_id,
-        GlobalAccountDataEventType::PushRules.to_string().into(),
-        &serde_json::to_value(ruma::events::push_rules::PushRulesEvent {
-            content: ruma::events::push_rules::PushRulesEventContent {
-                global: push::Ruleset::server_default(&user_id),
-            },
-        })
-        .expect("to json always works"),
-    )?;
+        services()
+            .users
+            .set_displayname(&user_id, Some(displayname.clone()))?;
+
+        // Initial account data
+        services().account_data.update(
+            None,
+            &user_id,
+            GlobalAccountDataEventType::PushRules.to_string().into(),
+            &serde_json::to_value(ruma::events::push_rules::PushRulesEvent {
+                content: ruma::events::push_rules::PushRulesEventContent {
+                    global: push::Ruleset::server_default(&user_id),
+                },
+            })
+            .expect("to json always works"),
+        )?;
+
+        displayname
+    };
 
     // Inhibit login does not work for guests
     if !is_guest && body.inhibit_login {
@@ -303,7 +361,8 @@ pub async fn register_route(body: Ruma<register::v3::Request>) -> Result<registe
 ///
 /// Changes the password of this account.
 ///
-/// - Requires UIAA to verify user password
+/// - Requires UIAA to verify user password, or a validated email address of the user if the
+///   request is not authenticated (password reset)
 /// - Changes the password of the sender user
 /// - The password hash is calculated using argon2 with 32 character salt, the plain password is
 ///   not saved
@@ -316,11 +375,9 @@ pub async fn register_route(body: Ruma<register::v3::Request>) -> Result<registe
 pub async fn change_password_route(
     body: Ruma<change_password::v3::Request>,
 ) -> Result<change_password::v3::Response> {
-    let sender_user = body
-        .sender_user
-        .as_ref()
-        // In the future password changes could be performed with UIA with 3PIDs, but we don't support that currently
-        .ok_or_else(|| Error::BadRequest(ErrorKind::MissingToken, "Missing access token."))?;
+    let Some(sender_user) = body.sender_user.as_ref() else {
+        return reset_password(&body.body);
+    };
     let sender_device = body.sender_device.as_ref().expect("user is authenticated");
 
     let mut uiaainfo = UiaaInfo {
@@ -378,6 +435,102 @@ pub async fn change_password_route(
     Ok(change_password::v3::Response {})
 }
 
+/// Changes the password of a user who forgot it, after they validated one of their email
+/// addresses
+fn reset_password(body: &change_password::v3::Request) -> Result<change_password::v3::Response> {
+    let thirdparty_id_creds = match &body.auth {
+        Some(AuthData::EmailIdentity(EmailIdentity {
+            thirdparty_id_creds,
+            ..
+        })) => thirdparty_id_creds,
+        _ => {
+            return Err(Error::Uiaa(UiaaInfo {
+                flows: vec![AuthFlow {
+                    stages: vec![AuthType::EmailIdentity],
+                }],
+                completed: Vec::new(),
+                params: Default::default(),
+                session: Some(utils::random_string(SESSION_ID_LENGTH)),
+                auth_error: None,
+            }))
+        }
+    };
+
+    let email = services().threepid.take_validated(
+        ValidationPurpose::PasswordReset,
+        &thirdparty_id_creds.sid,
+        &thirdparty_id_creds.client_secret,
+    )?;
+
+    let user_id = services()
+        .users
+        .find_from_threepid(&Medium::Email, &email)?
+        .filter(|user_id| !services().users.is_deactivated(user_id).unwrap_or(true))
+        .ok_or(Error::BadRequest(
+            ErrorKind::ThreepidNotFound,
+            "Email address is not associated with an account.",
+        ))?;
+
+    services()
+        .users
+        .set_password(&user_id, Some(&body.new_password))?;
+
+    if body.logout_devices {
+        let device_ids: Vec<_> = services()
+            .users
+            .all_device_ids(&user_id)
+            .filter_map(|id| id.ok())
+            .collect();
+        for id in device_ids {
+            services().users.remove_device(&user_id, &id)?;
+        }
+    }
+
+    info!("User {} reset their password.", user_id);
+    services()
+        .admin
+        .send_message(RoomMessageEventContent::notice_plain(format!(
+            "User {user_id} reset their password."
+        )));
+
+    Ok(change_password::v3::Response {})
+}
+
+/// # `POST /_matrix/client/v3/account/password/email/requestToken`
+///
+/// Sends a validation token to an email address of an account, so that the password of the
+/// account can be reset.
+pub async fn request_password_change_token_via_email_route(
+    body: Ruma<request_password_change_token_via_email::v3::Request>,
+) -> Result<request_password_change_token_via_email::v3::Response> {
+    if services()
+        .users
+        .find_from_threepid(&Medium::Email, &body.email)?
+        .is_none()
+    {
+        return Err(Error::BadRequest(
+            ErrorKind::ThreepidNotFound,
+            "Email address is not associated with an account.",
+        ));
+    }
+
+    let sid = services()
+        .threepid
+        .request_token(
+            ValidationPurpose::PasswordReset,
+            &body.client_secret,
+            &body.email,
+            body.send_attempt,
+            body.next_link.clone(),
+        )
+        .await?;
+
+    Ok(request_password_change_token_via_email::v3::Response {
+        sid,
+        submit_url: Some(threepid::submit_url()),
+    })
+}
+
 /// # `GET _matrix/client/r0/account/whoami`
 ///
 /// Get user_id of the sender user.
@@ -390,7 +543,7 @@ pub async fn whoami_route(body: Ruma<whoami::v3::Request>) -> Result<whoami::v3:
     Ok(whoami::v3::Response {
         user_id: sender_user.clone(),
         device_id,
-        is_guest: services().users.is_deactivated(sender_user)? && body.appservice_info.is_none(),
+        is_guest: services().users.is_guest(sender_user)?,
     })
 }
 
@@ -404,6 +557,7 @@ pub async fn whoami_route(body: Ruma<whoami::v3::Request>) -> Result<whoami::v3:
 /// - Forgets all to-device events
 /// - Triggers device list updates
 /// - Removes ability to log in again
+/// - Unhooks all third party identifiers
 pub async fn deactivate_route(
     body: Ruma<deactivate::v3::Request>,
 ) -> Result<deactivate::v3::Response> {
@@ -464,14 +618,14 @@ pub async fn deactivate_route(
 /// # `GET _matrix/client/v3/account/3pid`
 ///
 /// Get a list of third party identifiers associated with this account.
-///
-/// - Currently always returns empty list
 pub async fn third_party_route(
     body: Ruma<get_3pids::v3::Request>,
 ) -> Result<get_3pids::v3::Response> {
-    let _sender_user = body.sender_user.as_ref().expect("user is authenticated");
+    let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
-    Ok(get_3pids::v3::Response::new(Vec::new()))
+    Ok(get_3pids::v3::Response::new(
+        services().users.threepids(sender_user)?,
+    ))
 }
 
 /// # `POST /_matrix/client/v3/account/3pid/email/requestToken`
@@ -479,13 +633,149 @@ pub async fn third_party_route(
 /// "This API should be used to request validation tokens when adding an email address to an account"
 ///
 /// - 403 signals that The homeserver does not allow the third party identifier as a contact option.
+/// - Sends an email with the token and a link to validate the address
 pub async fn request_3pid_management_token_via_email_route(
-    _body: Ruma<request_3pid_management_token_via_email::v3::Request>,
+    body: Ruma<request_3pid_management_token_via_email::v3::Request>,
 ) -> Result<request_3pid_management_token_via_email::v3::Response> {
-    Err(Error::BadRequest(
-        ErrorKind::ThreepidDenied,
-        "Third party identifiers are currently unsupported by this server implementation",
-    ))
+    if services()
+        .users
+        .find_from_threepid(&Medium::Email, &body.email)?
+        .is_some()
+    {
+        return Err(Error::BadRequest(
+            ErrorKind::ThreepidInUse,
+            "Email address is already in use.",
+        ));
+    }
+
+    let sid = services()
+        .threepid
+        .request_token(
+            ValidationPurpose::AddThreepid,
+            &body.client_secret,
+            &body.email,
+            body.send_attempt,
+            body.next_link.clone(),
+        )
+        .await?;
+
+    Ok(request_3pid_management_token_via_email::v3::Response {
+        sid,
+        submit_url: Some(threepid::submit_url()),
+    })
+}
+
+/// # `POST /_matrix/client/v3/account/3pid/add`
+///
+/// Adds a validated email address to the account of the sender user.
+///
+/// - Requires UIAA to verify user password
+pub async fn add_3pid_route(body: Ruma<add_3pid::v3::Request>) -> Result<add_3pid::v3::Response> {
+    let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+    let sender_device = body.sender_device.as_ref().expect("user is authenticated");
+
+    let mut uiaainfo = UiaaInfo {
+        flows: vec![AuthFlow {
+            stages: vec![AuthType::Password],
+        }],
+        completed: Vec::new(),
+        params: Default::default(),
+        session: None,
+        auth_error: None,
+    };
+
+    if let Some(auth) = &body.auth {
+        let (worked, uiaainfo) =
+            services()
+                .uiaa
+                .try_auth(sender_user, sender_device, auth, &uiaainfo)?;
+        if !worked {
+            return Err(Error::Uiaa(uiaainfo));
+        }
+    // Success!
+    } else if let Some(json) = &body.json_body {
+        uiaainfo.session = Some(utils::random_string(SESSION_ID_LENGTH));
+        services()
+            .uiaa
+            .create(sender_user, sender_device, &uiaainfo, json)?;
+        return Err(Error::Uiaa(uiaainfo));
+    } else {
+        return Err(Error::BadRequest(ErrorKind::NotJson, "Not json."));
+    }
+
+    let email = services().threepid.take_validated(
+        ValidationPurpose::AddThreepid,
+        &body.sid,
+        &body.client_secret,
+    )?;
+
+    services()
+        .users
+        .add_threepid(sender_user, &Medium::Email, &email)?;
+
+    info!("User {} added email address {}.", sender_user, email);
+
+    Ok(add_3pid::v3::Response {})
+}
+
+/// # `POST /_matrix/client/v3/account/3pid/delete`
+///
+/// Removes a third party identifier from the account of the sender user.
+///
+/// - Identifiers are never bound to identity servers, so there is nothing to unbind
+pub async fn delete_3pid_route(
+    body: Ruma<delete_3pid::v3::Request>,
+) -> Result<delete_3pid::v3::Response> {
+    let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+
+    services()
+        .users
+        .remove_threepid(sender_user, &body.medium, &body.address)?;
+
+    Ok(delete_3pid::v3::Response {
+        id_server_unbind_result: ThirdPartyIdRemovalStatus::NoSupport,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct SubmitTokenParams {
+    sid: String,
+    client_secret: String,
+    token: String,
+}
+
+/// # `GET /_conduit/client/email/submit_token`
+///
+/// Validates an email address when the user opens the link from the validation email.
+///
+/// - Redirects to the `next_link` the client requested, if any
+pub async fn submit_email_token_link_route(
+    Query(params): Query<SubmitTokenParams>,
+) -> Result<Response> {
+    let next_link =
+        services()
+            .threepid
+            .submit_token(&params.sid, &params.client_secret, &params.token)?;
+
+    Ok(match next_link {
+        Some(next_link) => Redirect::to(&next_link).into_response(),
+        None => "Your email address has been validated. You can now return to your client."
+            .into_response(),
+    })
+}
+
+/// # `POST /_conduit/client/email/submit_token`
+///
+/// Validates an email address with the token the user entered in their client. This is the
+/// `submit_url` returned by the `requestToken` endpoints.
+pub async fn submit_email_token_route(
+    Json(params): Json<SubmitTokenParams>,
+) -> Result<Json<serde_json::Value>> {
+    services()
+        .threepid
+        .submit_token(&params.sid, &params.client_secret, &params.token)?;
+
+    Ok(Json(json!({ "success": true })))
 }
 
 /// # `POST /_matrix/client/v3/account/3pid/msisdn/requestToken`
@@ -24,6 +24,7 @@ pub async fn get_capabilities_route(
         default: services().globals.default_room_version(),
         available,
     };
+    capabilities.thirdparty_id_changes.enabled = services().globals.config.email.enabled;
 
     Ok(get_capabilities::v3::Response { capabilities })
 }
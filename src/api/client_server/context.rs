@@ -10,8 +10,8 @@ use tracing::error;
 ///
 /// Allows loading room history around an event.
 ///
-/// - Only works if the user is joined (TODO: always allow, but only show events if the user was
-///   joined, depending on history_visibility)
+/// - Only works if the user is allowed to see the event, depending on history_visibility
+/// - Only returns surrounding events the user is allowed to see
 pub async fn get_context_route(
     body: Ruma<get_context::v3::Request>,
 ) -> Result<get_context::v3::Response> {
@@ -80,6 +80,16 @@ pub async fn get_context_route(
         .pdus_until(sender_user, &room_id, base_token)?
         .take(limit / 2)
         .filter_map(|r| r.ok()) // Remove buggy events
+        .collect();
+
+    // Paginate past events the user can't see, instead of stopping at them
+    let start_token = events_before
+        .last()
+        .map(|(count, _)| count.stringify())
+        .unwrap_or_else(|| base_token.stringify());
+
+    let events_before: Vec<_> = events_before
+        .into_iter()
         .filter(|(_, pdu)| {
             services()
                 .rooms
@@ -101,11 +111,6 @@ pub async fn get_context_route(
         }
     }
 
-    let start_token = events_before
-        .last()
-        .map(|(count, _)| count.stringify())
-        .unwrap_or_else(|| base_token.stringify());
-
     let events_before: Vec<_> = events_before
         .into_iter()
         .map(|(_, pdu)| pdu.to_room_event())
@@ -117,6 +122,16 @@ pub async fn get_context_route(
         .pdus_after(sender_user, &room_id, base_token)?
         .take(limit / 2)
         .filter_map(|r| r.ok()) // Remove buggy events
+        .collect();
+
+    // Paginate past events the user can't see, instead of stopping at them
+    let end_token = events_after
+        .last()
+        .map(|(count, _)| count.stringify())
+        .unwrap_or_else(|| base_token.stringify());
+
+    let events_after: Vec<_> = events_after
+        .into_iter()
         .filter(|(_, pdu)| {
             services()
                 .rooms
@@ -157,11 +172,6 @@ pub async fn get_context_route(
         .state_full_ids(shortstatehash)
         .await?;
 
-    let end_token = events_after
-        .last()
-        .map(|(count, _)| count.stringify())
-        .unwrap_or_else(|| base_token.stringify());
-
     let events_after: Vec<_> = events_after
         .into_iter()
         .map(|(_, pdu)| pdu.to_room_event())
@@ -1,11 +1,14 @@
 use crate::{services, utils, Error, Result, Ruma};
-use ruma::api::client::{
-    device::{self, delete_device, delete_devices, get_device, get_devices, update_device},
-    error::ErrorKind,
-    uiaa::{AuthFlow, AuthType, UiaaInfo},
+use ruma::{
+    api::client::{
+        device::{self, delete_device, delete_devices, get_device, get_devices, update_device},
+        error::ErrorKind,
+        uiaa::{AuthData, AuthFlow, AuthType, UiaaInfo},
+    },
+    CanonicalJsonValue, DeviceId, UserId,
 };
 
-use super::SESSION_ID_LENGTH;
+use super::{SESSION_ID_LENGTH, TOKEN_LENGTH};
 
 /// # `GET /_matrix/client/r0/devices`
 ///
@@ -43,15 +46,30 @@ pub async fn get_device_route(
 /// # `PUT /_matrix/client/r0/devices/{deviceId}`
 ///
 /// Updates the metadata on a given device of the sender user.
+///
+/// - Appservices create the device if it doesn't exist yet (MSC4190)
 pub async fn update_device_route(
     body: Ruma<update_device::v3::Request>,
 ) -> Result<update_device::v3::Response> {
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
-    let mut device = services()
+    let mut device = match services()
         .users
         .get_device_metadata(sender_user, &body.device_id)?
-        .ok_or(Error::BadRequest(ErrorKind::NotFound, "Device not found."))?;
+    {
+        Some(device) => device,
+        None if body.appservice_info.is_some() => {
+            services().users.create_device(
+                sender_user,
+                &body.device_id,
+                &utils::random_string(TOKEN_LENGTH),
+                body.display_name.clone(),
+            )?;
+
+            return Ok(update_device::v3::Response {});
+        }
+        None => return Err(Error::BadRequest(ErrorKind::NotFound, "Device not found.")),
+    };
 
     device.display_name.clone_from(&body.display_name);
 
@@ -66,7 +84,7 @@ pub async fn update_device_route(
 ///
 /// Deletes the given device.
 ///
-/// - Requires UIAA to verify user password
+/// - Requires UIAA to verify user password, unless the request is made by an appservice (MSC4190)
 /// - Invalidates access token
 /// - Deletes device metadata (device id, device display name, last seen ip, last seen ts)
 /// - Forgets to-device events
@@ -75,36 +93,14 @@ pub async fn delete_device_route(
     body: Ruma<delete_device::v3::Request>,
 ) -> Result<delete_device::v3::Response> {
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
-    let sender_device = body.sender_device.as_ref().expect("user is authenticated");
-
-    // UIAA
-    let mut uiaainfo = UiaaInfo {
-        flows: vec![AuthFlow {
-            stages: vec![AuthType::Password],
-        }],
-        completed: Vec::new(),
-        params: Default::default(),
-        session: None,
-        auth_error: None,
-    };
 
-    if let Some(auth) = &body.auth {
-        let (worked, uiaainfo) =
-            services()
-                .uiaa
-                .try_auth(sender_user, sender_device, auth, &uiaainfo)?;
-        if !worked {
-            return Err(Error::Uiaa(uiaainfo));
-        }
-    // Success!
-    } else if let Some(json) = body.json_body {
-        uiaainfo.session = Some(utils::random_string(SESSION_ID_LENGTH));
-        services()
-            .uiaa
-            .create(sender_user, sender_device, &uiaainfo, &json)?;
-        return Err(Error::Uiaa(uiaainfo));
-    } else {
-        return Err(Error::BadRequest(ErrorKind::NotJson, "Not json."));
+    if body.appservice_info.is_none() {
+        uiaa_password(
+            sender_user,
+            body.sender_device.as_deref(),
+            &body.auth,
+            body.json_body.as_ref(),
+        )?;
     }
 
     services()
@@ -118,7 +114,7 @@ pub async fn delete_device_route(
 ///
 /// Deletes the given device.
 ///
-/// - Requires UIAA to verify user password
+/// - Requires UIAA to verify user password, unless the request is made by an appservice (MSC4190)
 ///
 /// For each device:
 /// - Invalidates access token
@@ -129,9 +125,32 @@ pub async fn delete_devices_route(
     body: Ruma<delete_devices::v3::Request>,
 ) -> Result<delete_devices::v3::Response> {
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
-    let sender_device = body.sender_device.as_ref().expect("user is authenticated");
 
-    // UIAA
+    if body.appservice_info.is_none() {
+        uiaa_password(
+            sender_user,
+            body.sender_device.as_deref(),
+            &body.auth,
+            body.json_body.as_ref(),
+        )?;
+    }
+
+    for device_id in &body.devices {
+        services().users.remove_device(sender_user, device_id)?
+    }
+
+    Ok(delete_devices::v3::Response {})
+}
+
+/// Makes sure the user confirmed the request with their password
+fn uiaa_password(
+    sender_user: &UserId,
+    sender_device: Option<&DeviceId>,
+    auth: &Option<AuthData>,
+    json_body: Option<&CanonicalJsonValue>,
+) -> Result<()> {
+    let sender_device = sender_device.expect("user is authenticated");
+
     let mut uiaainfo = UiaaInfo {
         flows: vec![AuthFlow {
             stages: vec![AuthType::Password],
@@ -142,7 +161,7 @@ pub async fn delete_devices_route(
         auth_error: None,
     };
 
-    if let Some(auth) = &body.auth {
+    if let Some(auth) = auth {
         let (worked, uiaainfo) =
             services()
                 .uiaa
@@ -151,19 +170,15 @@ pub async fn delete_devices_route(
             return Err(Error::Uiaa(uiaainfo));
         }
     // Success!
-    } else if let Some(json) = body.json_body {
+    } else if let Some(json) = json_body {
         uiaainfo.session = Some(utils::random_string(SESSION_ID_LENGTH));
         services()
             .uiaa
-            .create(sender_user, sender_device, &uiaainfo, &json)?;
+            .create(sender_user, sender_device, &uiaainfo, json)?;
         return Err(Error::Uiaa(uiaainfo));
     } else {
         return Err(Error::BadRequest(ErrorKind::NotJson, "Not json."));
     }
 
-    for device_id in &body.devices {
-        services().users.remove_device(sender_user, device_id)?
-    }
-
-    Ok(delete_devices::v3::Response {})
+    Ok(())
 }
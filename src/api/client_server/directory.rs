@@ -2,6 +2,7 @@ use crate::{services, Error, Result, Ruma};
 use ruma::{
     api::{
         client::{
+            appservice::set_room_visibility as set_appservice_room_visibility,
             directory::{
                 get_public_rooms, get_public_rooms_filtered, get_room_visibility,
                 set_room_visibility,
@@ -26,6 +27,7 @@ use ruma::{
     },
     ServerName, UInt,
 };
+use std::collections::BTreeSet;
 use tracing::{error, info, warn};
 
 /// # `POST /_matrix/client/r0/publicRooms`
@@ -103,6 +105,65 @@ pub async fn set_room_visibility_route(
     Ok(set_room_visibility::v3::Response {})
 }
 
+/// # `PUT /_matrix/client/v3/directory/list/appservice/{networkId}/{roomId}`
+///
+/// Sets the visibility of a given room in the room directory of a network of the appservice.
+///
+/// - Only appservices can use this endpoint
+/// - Clients find these rooms by passing `{appserviceId}|{networkId}` as `third_party_instance_id`
+pub async fn set_appservice_room_visibility_route(
+    body: Ruma<set_appservice_room_visibility::v3::Request>,
+) -> Result<set_appservice_room_visibility::v3::Response> {
+    let appservice_id = &body
+        .appservice_info
+        .as_ref()
+        .ok_or(Error::BadRequest(
+            ErrorKind::forbidden(),
+            "Only appservices can use this endpoint.",
+        ))?
+        .registration
+        .id;
+
+    if !services().rooms.metadata.exists(&body.room_id)? {
+        // Return 404 if the room doesn't exist
+        return Err(Error::BadRequest(ErrorKind::NotFound, "Room not found"));
+    }
+
+    if body.network_id.contains('|') {
+        return Err(Error::BadRequest(
+            ErrorKind::InvalidParam,
+            "Network ID must not contain '|'.",
+        ));
+    }
+
+    match &body.visibility {
+        room::Visibility::Public => {
+            services().rooms.directory.set_public_in_network(
+                appservice_id,
+                &body.network_id,
+                &body.room_id,
+            )?;
+            info!(
+                "Appservice {} made {} public in network {}",
+                appservice_id, body.room_id, body.network_id
+            );
+        }
+        room::Visibility::Private => services().rooms.directory.set_not_public_in_network(
+            appservice_id,
+            &body.network_id,
+            &body.room_id,
+        )?,
+        _ => {
+            return Err(Error::BadRequest(
+                ErrorKind::InvalidParam,
+                "Room visibility type is not supported.",
+            ));
+        }
+    }
+
+    Ok(set_appservice_room_visibility::v3::Response {})
+}
+
 /// # `GET /_matrix/client/r0/directory/list/room/{roomId}`
 ///
 /// Gets the visibility of a given room in the room directory.
@@ -128,7 +189,7 @@ pub(crate) async fn get_public_rooms_filtered_helper(
     limit: Option<UInt>,
     since: Option<&str>,
     filter: &Filter,
-    _network: &RoomNetwork,
+    network: &RoomNetwork,
 ) -> Result<get_public_rooms_filtered::v3::Response> {
     if let Some(other_server) =
         server.filter(|server| *server != services().globals.server_name().as_str())
@@ -144,7 +205,7 @@ pub(crate) async fn get_public_rooms_filtered_helper(
                         generic_search_term: filter.generic_search_term.clone(),
                         room_types: filter.room_types.clone(),
                     },
-                    room_network: RoomNetwork::Matrix,
+                    room_network: network.clone(),
                 },
             )
             .await?;
@@ -183,13 +244,33 @@ pub(crate) async fn get_public_rooms_filtered_helper(
         }
     }
 
-    let mut all_rooms: Vec<_> = services()
-        .rooms
-        .directory
-        .public_rooms()
-        .map(|room_id| {
-            let room_id = room_id?;
+    let room_ids: Vec<_> = match network {
+        RoomNetwork::Matrix => services().rooms.directory.public_rooms().collect(),
+        RoomNetwork::All => services()
+            .rooms
+            .directory
+            .public_rooms()
+            .chain(services().rooms.directory.all_network_public_rooms())
+            .collect(),
+        RoomNetwork::ThirdParty(instance_id) => {
+            let (appservice_id, network_id) = instance_id.split_once('|').ok_or(
+                Error::BadRequest(ErrorKind::InvalidParam, "Unknown third party instance."),
+            )?;
+
+            services()
+                .rooms
+                .directory
+                .network_public_rooms(appservice_id, network_id)
+                .collect()
+        }
+    };
 
+    // Filter out buggy room ids and rooms listed in multiple directories
+    let room_ids: BTreeSet<_> = room_ids.into_iter().filter_map(|r| r.ok()).collect();
+
+    let mut all_rooms: Vec<_> = room_ids
+        .into_iter()
+        .map(|room_id| {
             let chunk = PublicRoomsChunk {
                 canonical_alias: services()
                     .rooms
@@ -12,7 +12,7 @@ use ruma::{
                 get_content, get_content_as_filename, get_content_thumbnail, get_media_config,
             },
             error::ErrorKind,
-            media::{self, create_content},
+            media::{self, create_content, create_content_async, create_mxc_uri},
         },
         federation::authenticated_media::{self as federation_media, FileOrLocation},
     },
@@ -81,6 +81,64 @@ pub async fn create_content_route(
     })
 }
 
+/// # `POST /_matrix/media/v1/create`
+///
+/// Reserves an MXC URI, which can be used in events before the media itself is uploaded.
+///
+/// - The MXC URI expires if nothing is uploaded to it in time
+/// - Users can only have a limited amount of pending MXC URIs
+pub async fn create_mxc_uri_route(
+    body: Ruma<create_mxc_uri::v1::Request>,
+) -> Result<create_mxc_uri::v1::Response> {
+    let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+
+    let media_id = utils::random_string(MXC_LENGTH);
+
+    let expires_at = services().media.create_pending(
+        services().globals.server_name(),
+        &media_id,
+        sender_user,
+    )?;
+
+    Ok(create_mxc_uri::v1::Response {
+        content_uri: (format!("mxc://{}/{}", services().globals.server_name(), media_id)).into(),
+        unused_expires_at: Some(expires_at),
+    })
+}
+
+/// # `PUT /_matrix/media/v3/upload/{serverName}/{mediaId}`
+///
+/// Uploads media to an MXC URI created with
+/// [`POST /_matrix/media/v1/create`](fn.create_mxc_uri_route.html).
+///
+/// - Only the user who created the MXC URI can upload to it, and only once
+pub async fn create_content_async_route(
+    body: Ruma<create_content_async::v3::Request>,
+) -> Result<create_content_async::v3::Response> {
+    let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+
+    if body.server_name != services().globals.server_name() {
+        return Err(Error::BadRequest(
+            ErrorKind::forbidden(),
+            "Media can only be uploaded to this server.",
+        ));
+    }
+
+    services()
+        .media
+        .upload_pending(
+            &body.server_name,
+            &body.media_id,
+            body.filename.as_deref(),
+            body.content_type.as_deref(),
+            &body.file,
+            sender_user,
+        )
+        .await?;
+
+    Ok(create_content_async::v3::Response {})
+}
+
 pub async fn get_remote_content(
     server_name: &ServerName,
     media_id: String,
@@ -164,6 +222,12 @@ pub async fn get_remote_content(
 pub async fn get_content_route(
     body: Ruma<media::get_content::v3::Request>,
 ) -> Result<media::get_content::v3::Response> {
+    services().media.check_access(
+        &body.server_name,
+        &body.media_id,
+        body.sender_user.as_deref(),
+    )?;
+
     let get_content::v1::Response {
         file,
         content_disposition,
@@ -171,6 +235,7 @@ pub async fn get_content_route(
     } = get_content(
         &body.server_name,
         body.media_id.clone(),
+        body.timeout_ms,
         body.allow_remote,
         false,
     )
@@ -190,16 +255,34 @@ pub async fn get_content_route(
 pub async fn get_content_auth_route(
     body: Ruma<get_content::v1::Request>,
 ) -> Result<get_content::v1::Response> {
-    get_content(&body.server_name, body.media_id.clone(), true, true).await
+    services().media.check_access(
+        &body.server_name,
+        &body.media_id,
+        body.sender_user.as_deref(),
+    )?;
+
+    get_content(
+        &body.server_name,
+        body.media_id.clone(),
+        body.timeout_ms,
+        true,
+        true,
+    )
+    .await
 }
 
 pub async fn get_content(
     server_name: &ServerName,
     media_id: String,
+    timeout_ms: Duration,
     allow_remote: bool,
     authenticated: bool,
 ) -> Result<get_content::v1::Response, Error> {
     services().media.check_blocked(server_name, &media_id)?;
+    services()
+        .media
+        .wait_for_upload(server_name, &media_id, timeout_ms)
+        .await?;
 
     if let Ok(Some(FileMeta {
         content_disposition,
@@ -236,6 +319,12 @@ pub async fn get_content(
 pub async fn get_content_as_filename_route(
     body: Ruma<media::get_content_as_filename::v3::Request>,
 ) -> Result<media::get_content_as_filename::v3::Response> {
+    services().media.check_access(
+        &body.server_name,
+        &body.media_id,
+        body.sender_user.as_deref(),
+    )?;
+
     let get_content_as_filename::v1::Response {
         file,
         content_type,
@@ -244,6 +333,7 @@ pub async fn get_content_as_filename_route(
         &body.server_name,
         body.media_id.clone(),
         body.filename.clone(),
+        body.timeout_ms,
         body.allow_remote,
         false,
     )
@@ -263,10 +353,17 @@ pub async fn get_content_as_filename_route(
 pub async fn get_content_as_filename_auth_route(
     body: Ruma<get_content_as_filename::v1::Request>,
 ) -> Result<get_content_as_filename::v1::Response, Error> {
+    services().media.check_access(
+        &body.server_name,
+        &body.media_id,
+        body.sender_user.as_deref(),
+    )?;
+
     get_content_as_filename(
         &body.server_name,
         body.media_id.clone(),
         body.filename.clone(),
+        body.timeout_ms,
         true,
         true,
     )
@@ -277,10 +374,15 @@ async fn get_content_as_filename(
     server_name: &ServerName,
     media_id: String,
     filename: String,
+    timeout_ms: Duration,
     allow_remote: bool,
     authenticated: bool,
 ) -> Result<get_content_as_filename::v1::Response, Error> {
     services().media.check_blocked(server_name, &media_id)?;
+    services()
+        .media
+        .wait_for_upload(server_name, &media_id, timeout_ms)
+        .await?;
 
     if let Ok(Some(FileMeta {
         file, content_type, ..
@@ -321,6 +423,12 @@ async fn get_content_as_filename(
 pub async fn get_content_thumbnail_route(
     body: Ruma<media::get_content_thumbnail::v3::Request>,
 ) -> Result<media::get_content_thumbnail::v3::Response> {
+    services().media.check_access(
+        &body.server_name,
+        &body.media_id,
+        body.sender_user.as_deref(),
+    )?;
+
     let get_content_thumbnail::v1::Response {
         file,
         content_type,
@@ -332,6 +440,7 @@ pub async fn get_content_thumbnail_route(
         body.width,
         body.method.clone(),
         body.animated,
+        body.timeout_ms,
         body.allow_remote,
         false,
     )
@@ -351,6 +460,12 @@ pub async fn get_content_thumbnail_route(
 pub async fn get_content_thumbnail_auth_route(
     body: Ruma<get_content_thumbnail::v1::Request>,
 ) -> Result<get_content_thumbnail::v1::Response> {
+    services().media.check_access(
+        &body.server_name,
+        &body.media_id,
+        body.sender_user.as_deref(),
+    )?;
+
     get_content_thumbnail(
         &body.server_name,
         body.media_id.clone(),
@@ -358,6 +473,7 @@ pub async fn get_content_thumbnail_auth_route(
         body.width,
         body.method.clone(),
         body.animated,
+        body.timeout_ms,
         true,
         true,
     )
@@ -372,10 +488,15 @@ async fn get_content_thumbnail(
     width: UInt,
     method: Option<Method>,
     animated: Option<bool>,
+    timeout_ms: Duration,
     allow_remote: bool,
     authenticated: bool,
 ) -> Result<get_content_thumbnail::v1::Response, Error> {
     services().media.check_blocked(server_name, &media_id)?;
+    services()
+        .media
+        .wait_for_upload(server_name, &media_id, timeout_ms)
+        .await?;
 
     if let Some(FileMeta {
         file,
@@ -3,16 +3,23 @@ use crate::{
     services, utils, Error, Result, Ruma,
 };
 use ruma::{
-    api::client::{
-        error::ErrorKind,
-        message::{get_message_events, send_message_event},
+    api::{
+        client::{
+            error::ErrorKind,
+            message::{get_message_events, send_message_event},
+            room::get_event_by_timestamp,
+        },
+        federation, Direction,
     },
     events::{StateEventType, TimelineEventType},
+    EventId, MilliSecondsSinceUnixEpoch, RoomId, ServerName,
 };
 use std::{
     collections::{BTreeMap, HashSet},
     sync::Arc,
 };
+use tokio::sync::RwLock;
+use tracing::debug;
 
 /// # `PUT /_matrix/client/r0/rooms/{roomId}/send/{eventType}/{txnId}`
 ///
@@ -114,8 +121,8 @@ pub async fn send_message_event_route(
 ///
 /// Allows paginating through room history.
 ///
-/// - Only works if the user is joined (TODO: always allow, but only show events where the user was
-///   joined, depending on history_visibility)
+/// - Only returns events the user is allowed to see, depending on history_visibility
+/// - Works without joining for world readable rooms and rooms the user left
 pub async fn get_message_events_route(
     body: Ruma<get_message_events::v3::Request>,
 ) -> Result<get_message_events::v3::Response> {
@@ -135,6 +142,17 @@ pub async fn get_message_events_route(
         .as_ref()
         .and_then(|t| PduCount::try_from_string(t).ok());
 
+    if !services()
+        .rooms
+        .state_accessor
+        .user_can_see_room_history(sender_user, &body.room_id)?
+    {
+        return Err(Error::BadRequest(
+            ErrorKind::forbidden(),
+            "You don't have permission to view this room.",
+        ));
+    }
+
     services()
         .rooms
         .lazy_loading
@@ -157,6 +175,14 @@ pub async fn get_message_events_route(
                 .pdus_after(sender_user, &body.room_id, from)?
                 .take(limit)
                 .filter_map(|r| r.ok()) // Filter out buggy events
+                .take_while(|&(k, _)| Some(k) != to) // Stop at `to`
+                .collect();
+
+            // Paginate past events the user can't see, instead of stopping at them
+            next_token = events_after.last().map(|(count, _)| count).copied();
+
+            let events_after: Vec<_> = events_after
+                .into_iter()
                 .filter(|(_, pdu)| {
                     services()
                         .rooms
@@ -164,7 +190,6 @@ pub async fn get_message_events_route(
                         .user_can_see_event(sender_user, &body.room_id, &pdu.event_id)
                         .unwrap_or(false)
                 })
-                .take_while(|&(k, _)| Some(k) != to) // Stop at `to`
                 .collect();
 
             for (_, event) in &events_after {
@@ -183,8 +208,6 @@ pub async fn get_message_events_route(
                 lazy_loaded.insert(event.sender.clone());
             }
 
-            next_token = events_after.last().map(|(count, _)| count).copied();
-
             let events_after: Vec<_> = events_after
                 .into_iter()
                 .map(|(_, pdu)| pdu.to_room_event())
@@ -206,6 +229,14 @@ pub async fn get_message_events_route(
                 .pdus_until(sender_user, &body.room_id, from)?
                 .take(limit)
                 .filter_map(|r| r.ok()) // Filter out buggy events
+                .take_while(|&(k, _)| Some(k) != to) // Stop at `to`
+                .collect();
+
+            // Paginate past events the user can't see, instead of stopping at them
+            next_token = events_before.last().map(|(count, _)| count).copied();
+
+            let events_before: Vec<_> = events_before
+                .into_iter()
                 .filter(|(_, pdu)| {
                     services()
                         .rooms
@@ -213,7 +244,6 @@ pub async fn get_message_events_route(
                         .user_can_see_event(sender_user, &body.room_id, &pdu.event_id)
                         .unwrap_or(false)
                 })
-                .take_while(|&(k, _)| Some(k) != to) // Stop at `to`
                 .collect();
 
             for (_, event) in &events_before {
@@ -232,8 +262,6 @@ pub async fn get_message_events_route(
                 lazy_loaded.insert(event.sender.clone());
             }
 
-            next_token = events_before.last().map(|(count, _)| count).copied();
-
             let events_before: Vec<_> = events_before
                 .into_iter()
                 .map(|(_, pdu)| pdu.to_room_event())
@@ -271,3 +299,165 @@ pub async fn get_message_events_route(
 
     Ok(resp)
 }
+
+/// # `GET /_matrix/client/v1/rooms/{roomId}/timestamp_to_event`
+///
+/// Finds the event closest to the given timestamp, sent at or after it for `dir=f` and at or
+/// before it for `dir=b`.
+///
+/// - Asks other servers in the room if the history known to this server might be incomplete
+pub async fn get_event_by_timestamp_route(
+    body: Ruma<get_event_by_timestamp::v1::Request>,
+) -> Result<get_event_by_timestamp::v1::Response> {
+    let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+
+    // Checked before anything is looked up, so users can't make this server query other servers
+    // about rooms they never had access to
+    if !services()
+        .rooms
+        .state_accessor
+        .user_can_see_room_history(sender_user, &body.room_id)?
+    {
+        return Err(Error::BadRequest(
+            ErrorKind::forbidden(),
+            "You don't have permission to view this room.",
+        ));
+    }
+
+    let (local_pdu, incomplete) =
+        services()
+            .rooms
+            .timeline
+            .pdu_by_timestamp(&body.room_id, body.ts, body.dir)?;
+
+    let mut closest = local_pdu.map(|pdu| {
+        (
+            pdu.event_id.as_ref().to_owned(),
+            MilliSecondsSinceUnixEpoch(pdu.origin_server_ts),
+        )
+    });
+
+    if closest.is_none() || incomplete {
+        for server in services()
+            .rooms
+            .state_cache
+            .room_servers(&body.room_id)
+            .filter_map(|r| r.ok())
+            .filter(|server| server != services().globals.server_name())
+        {
+            match services()
+                .sending
+                .send_federation_request(
+                    &server,
+                    federation::event::get_event_by_timestamp::v1::Request {
+                        room_id: body.room_id.clone(),
+                        ts: body.ts,
+                        dir: body.dir,
+                    },
+                )
+                .await
+            {
+                Ok(response) => {
+                    if let Err(e) = verify_event_by_timestamp(
+                        &server,
+                        &body.room_id,
+                        &response.event_id,
+                        response.origin_server_ts,
+                        body.ts,
+                        body.dir,
+                    )
+                    .await
+                    {
+                        debug!("{server} returned an invalid event by timestamp: {e}");
+                        continue;
+                    }
+
+                    let distance = |ts: MilliSecondsSinceUnixEpoch| {
+                        u64::from(ts.0).abs_diff(u64::from(body.ts.0))
+                    };
+                    if closest.as_ref().is_none_or(|(_, local_ts)| {
+                        distance(response.origin_server_ts) < distance(*local_ts)
+                    }) {
+                        closest = Some((response.event_id, response.origin_server_ts));
+                    }
+                    break;
+                }
+                Err(e) => debug!("Failed to ask {server} for event by timestamp: {e}"),
+            }
+        }
+    }
+
+    let (event_id, origin_server_ts) = closest.ok_or(Error::BadRequest(
+        ErrorKind::NotFound,
+        "No event found in the given direction.",
+    ))?;
+
+    // Events only fetched from other servers have no state here, so it can't be known whether
+    // the user may see them
+    let state_accessor = &services().rooms.state_accessor;
+    if state_accessor.pdu_shortstatehash(&event_id)?.is_none()
+        || !state_accessor.user_can_see_event(sender_user, &body.room_id, &event_id)?
+    {
+        return Err(Error::BadRequest(
+            ErrorKind::forbidden(),
+            "You don't have permission to view this room.",
+        ));
+    }
+
+    Ok(get_event_by_timestamp::v1::Response {
+        event_id,
+        origin_server_ts,
+    })
+}
+
+/// Fetches the event another server found by timestamp, checking its signatures and hashes, and
+/// that it was really sent at the claimed time, on the requested side of the timestamp
+async fn verify_event_by_timestamp(
+    server: &ServerName,
+    room_id: &RoomId,
+    event_id: &EventId,
+    origin_server_ts: MilliSecondsSinceUnixEpoch,
+    ts: MilliSecondsSinceUnixEpoch,
+    dir: Direction,
+) -> Result<()> {
+    let create_event = services()
+        .rooms
+        .state_accessor
+        .room_state_get(room_id, &StateEventType::RoomCreate, "")?
+        .ok_or_else(|| Error::bad_database("Failed to find create event in db."))?;
+    let room_version_rules = services()
+        .rooms
+        .state
+        .get_room_version(room_id)?
+        .rules()
+        .expect("Supported room version has rules");
+    let pub_key_map = RwLock::new(BTreeMap::new());
+
+    let (pdu, _) = services()
+        .rooms
+        .event_handler
+        .fetch_and_handle_outliers(
+            server,
+            &[Arc::from(event_id)],
+            &create_event,
+            room_id,
+            &room_version_rules,
+            &pub_key_map,
+        )
+        .await
+        .pop()
+        .ok_or(Error::BadServerResponse("Failed to fetch the event."))?;
+
+    let on_requested_side = match dir {
+        Direction::Forward => pdu.origin_server_ts >= ts.0,
+        Direction::Backward => pdu.origin_server_ts <= ts.0,
+    };
+    if *pdu.room_id != *room_id || pdu.origin_server_ts != origin_server_ts.0 || !on_requested_side
+    {
+        return Err(Error::BadServerResponse(
+            "Event doesn't match the timestamp it was returned for.",
+        ));
+    }
+
+    Ok(())
+}
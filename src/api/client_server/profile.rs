@@ -1,18 +1,17 @@
-use crate::{service::pdu::PduBuilder, services, utils, Error, Result, Ruma};
+use crate::{api::endpoints, service::pdu::PduBuilder, services, utils, Error, Result, Ruma};
 use ruma::{
     api::{
         client::{
             error::ErrorKind,
-            profile::{
-                get_avatar_url, get_display_name, get_profile, set_avatar_url, set_display_name,
-            },
+            profile::{get_avatar_url, get_display_name, set_avatar_url, set_display_name},
         },
         federation::{self, query::get_profile_information::v1::ProfileField},
     },
     events::{room::member::RoomMemberEventContent, StateEventType, TimelineEventType},
+    UserId,
 };
-use serde_json::value::to_raw_value;
-use std::sync::Arc;
+use serde_json::{value::to_raw_value, Value as JsonValue};
+use std::{collections::BTreeMap, sync::Arc};
 
 /// # `PUT /_matrix/client/r0/profile/{userId}/displayname`
 ///
@@ -286,28 +285,26 @@ pub async fn get_avatar_url_route(
 
 /// # `GET /_matrix/client/r0/profile/{userId}`
 ///
-/// Returns the displayname, avatar_url and blurhash of the user.
+/// Returns the displayname, avatar_url, blurhash and custom profile fields of the user.
 ///
 /// - If user is on another server: Fetches profile over federation
 pub async fn get_profile_route(
-    body: Ruma<get_profile::v3::Request>,
-) -> Result<get_profile::v3::Response> {
+    body: Ruma<endpoints::profile::get_profile::v3::Request>,
+) -> Result<endpoints::profile::get_profile::v3::Response> {
     if body.user_id.server_name() != services().globals.server_name() {
         let response = services()
             .sending
             .send_federation_request(
                 body.user_id.server_name(),
-                federation::query::get_profile_information::v1::Request {
+                endpoints::profile::get_profile_information::v1::Request {
                     user_id: body.user_id.clone(),
                     field: None,
                 },
             )
             .await?;
 
-        return Ok(get_profile::v3::Response {
-            displayname: response.displayname,
-            avatar_url: response.avatar_url,
-            blurhash: response.blurhash,
+        return Ok(endpoints::profile::get_profile::v3::Response {
+            profile: response.profile,
         });
     }
 
@@ -319,9 +316,165 @@ pub async fn get_profile_route(
         ));
     }
 
-    Ok(get_profile::v3::Response {
-        avatar_url: services().users.avatar_url(&body.user_id)?,
-        blurhash: services().users.blurhash(&body.user_id)?,
-        displayname: services().users.displayname(&body.user_id)?,
+    let mut profile = services().users.profile_fields(&body.user_id)?;
+
+    if let Some(displayname) = services().users.displayname(&body.user_id)? {
+        profile.insert("displayname".to_owned(), displayname.into());
+    }
+    if let Some(avatar_url) = services().users.avatar_url(&body.user_id)? {
+        profile.insert("avatar_url".to_owned(), avatar_url.to_string().into());
+    }
+    if let Some(blurhash) = services().users.blurhash(&body.user_id)? {
+        profile.insert("xyz.amorgan.blurhash".to_owned(), blurhash.into());
+    }
+
+    Ok(endpoints::profile::get_profile::v3::Response { profile })
+}
+
+/// # `GET /_matrix/client/v3/profile/{userId}/{keyName}`
+///
+/// Returns a single field of the profile of the user, see MSC4133.
+///
+/// - If user is on another server: Fetches the field over federation
+pub async fn get_profile_field_route(
+    body: Ruma<endpoints::profile::get_profile_field::v3::Request>,
+) -> Result<endpoints::profile::get_profile_field::v3::Response> {
+    let not_found = || Error::BadRequest(ErrorKind::NotFound, "Profile field was not found.");
+
+    let value = if body.user_id.server_name() != services().globals.server_name() {
+        services()
+            .sending
+            .send_federation_request(
+                body.user_id.server_name(),
+                endpoints::profile::get_profile_information::v1::Request {
+                    user_id: body.user_id.clone(),
+                    field: Some(body.field.clone()),
+                },
+            )
+            .await?
+            .profile
+            .remove(&body.field)
+    } else {
+        match body.field.as_str() {
+            "displayname" => services()
+                .users
+                .displayname(&body.user_id)?
+                .map(JsonValue::from),
+            "avatar_url" => services()
+                .users
+                .avatar_url(&body.user_id)?
+                .map(|avatar_url| avatar_url.to_string().into()),
+            field => services().users.profile_field(&body.user_id, field)?,
+        }
+    }
+    .ok_or_else(not_found)?;
+
+    Ok(endpoints::profile::get_profile_field::v3::Response {
+        field: BTreeMap::from([(body.field.clone(), value)]),
     })
 }
+
+/// # `PUT /_matrix/client/v3/profile/{userId}/{keyName}`
+///
+/// Sets a custom field of the profile of the user, see MSC4133.
+///
+/// - Only fields in the `allowed_fields` of the `[profile]` config section can be set
+/// - The value and the whole profile must stay below the configured size limits
+pub async fn set_profile_field_route(
+    body: Ruma<endpoints::profile::set_profile_field::v3::Request>,
+) -> Result<endpoints::profile::set_profile_field::v3::Response> {
+    let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+
+    check_profile_field_access(sender_user, &body.user_id, &body.field)?;
+
+    let value = body.value.get(&body.field).ok_or(Error::BadRequest(
+        ErrorKind::BadJson,
+        "Request body must contain the profile field.",
+    ))?;
+
+    let config = &services().globals.config.profile;
+
+    let size = serde_json::to_vec(value)
+        .expect("JsonValue can be serialized")
+        .len();
+    if size > config.max_field_size {
+        return Err(Error::BadRequest(
+            ErrorKind::TooLarge,
+            "Profile field is too large.",
+        ));
+    }
+
+    let other_fields_size: usize = services()
+        .users
+        .profile_fields(&body.user_id)?
+        .iter()
+        .filter(|(other_field, _)| **other_field != body.field)
+        .map(|(other_field, value)| {
+            other_field.len()
+                + serde_json::to_vec(value)
+                    .expect("JsonValue can be serialized")
+                    .len()
+        })
+        .sum();
+    if other_fields_size + body.field.len() + size > config.max_profile_size {
+        return Err(Error::BadRequest(
+            ErrorKind::TooLarge,
+            "Profile is too large.",
+        ));
+    }
+
+    services()
+        .users
+        .set_profile_field(&body.user_id, &body.field, Some(value))?;
+
+    Ok(endpoints::profile::set_profile_field::v3::Response {})
+}
+
+/// # `DELETE /_matrix/client/v3/profile/{userId}/{keyName}`
+///
+/// Removes a custom field from the profile of the user, see MSC4133.
+pub async fn delete_profile_field_route(
+    body: Ruma<endpoints::profile::delete_profile_field::v3::Request>,
+) -> Result<endpoints::profile::delete_profile_field::v3::Response> {
+    let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+
+    check_profile_field_access(sender_user, &body.user_id, &body.field)?;
+
+    services()
+        .users
+        .set_profile_field(&body.user_id, &body.field, None)?;
+
+    Ok(endpoints::profile::delete_profile_field::v3::Response {})
+}
+
+/// Makes sure the request is made by the owner of the profile and the field may be changed.
+fn check_profile_field_access(sender_user: &UserId, user_id: &UserId, field: &str) -> Result<()> {
+    if sender_user != user_id {
+        return Err(Error::BadRequest(
+            ErrorKind::forbidden(),
+            "You can only change your own profile.",
+        ));
+    }
+
+    if field == "displayname" || field == "avatar_url" {
+        return Err(Error::BadRequest(
+            ErrorKind::Unrecognized,
+            "Use the displayname and avatar_url endpoints to change these fields.",
+        ));
+    }
+
+    if !services()
+        .globals
+        .config
+        .profile
+        .allowed_fields
+        .contains(field)
+    {
+        return Err(Error::BadRequest(
+            ErrorKind::forbidden(),
+            "This profile field can't be changed on this server.",
+        ));
+    }
+
+    Ok(())
+}
@@ -1,10 +1,12 @@
 use crate::{
-    api::client_server::invite_helper, service::pdu::PduBuilder, services, Error, Result, Ruma,
+    api::{client_server::invite_helper, endpoints},
+    service::{pdu::PduBuilder, rooms::timeline::PduCount},
+    services, Error, Result, Ruma,
 };
 use ruma::{
     api::client::{
         error::ErrorKind,
-        room::{self, aliases, create_room, get_room_event, upgrade_room},
+        room::{self, aliases, create_room, get_room_event, get_summary, upgrade_room},
     },
     events::{
         room::{
@@ -23,7 +25,8 @@ use ruma::{
     },
     int,
     serde::JsonObject,
-    CanonicalJsonObject, CanonicalJsonValue, OwnedRoomAliasId, OwnedUserId, RoomAliasId,
+    CanonicalJsonObject, CanonicalJsonValue, OwnedRoomAliasId, OwnedRoomId, OwnedUserId,
+    RoomAliasId,
 };
 use serde::Deserialize;
 use serde_json::{json, value::to_raw_value};
@@ -502,7 +505,7 @@ pub async fn create_room_route(
 ///
 /// Gets a single event.
 ///
-/// - You have to currently be joined to the room (TODO: Respect history visibility)
+/// - Only works if the user is allowed to see the event, depending on history_visibility
 pub async fn get_room_event_route(
     body: Ruma<get_room_event::v3::Request>,
 ) -> Result<get_room_event::v3::Response> {
@@ -536,6 +539,177 @@ pub async fn get_room_event_route(
     })
 }
 
+/// Number of events returned by [`room_initial_sync_route`]
+const INITIAL_SYNC_EVENT_LIMIT: usize = 20;
+
+/// # `GET /_matrix/client/r0/rooms/{roomId}/initialSync`
+///
+/// Gets a snapshot of a room: its state and most recent events. Guests use this to peek into
+/// world readable rooms.
+///
+/// - Only works if the user is a member of the room, or the room is world readable
+/// - Only returns events the user is allowed to see
+pub async fn room_initial_sync_route(
+    body: Ruma<endpoints::room_initial_sync::v3::Request>,
+) -> Result<endpoints::room_initial_sync::v3::Response> {
+    let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+    let room_id = &body.room_id;
+
+    let membership = if services()
+        .rooms
+        .state_cache
+        .is_joined(sender_user, room_id)?
+    {
+        Some(MembershipState::Join)
+    } else if services()
+        .rooms
+        .state_cache
+        .is_invited(sender_user, room_id)?
+    {
+        Some(MembershipState::Invite)
+    } else {
+        None
+    };
+
+    if !services()
+        .rooms
+        .state_accessor
+        .user_can_see_state_events(sender_user, room_id)?
+    {
+        return Err(Error::BadRequest(
+            ErrorKind::forbidden(),
+            "You don't have permission to view this room.",
+        ));
+    }
+
+    let mut messages: Vec<_> = services()
+        .rooms
+        .timeline
+        .pdus_until(sender_user, room_id, PduCount::max())?
+        .filter_map(|r| r.ok())
+        .filter(|(_, pdu)| {
+            services()
+                .rooms
+                .state_accessor
+                .user_can_see_event(sender_user, room_id, &pdu.event_id)
+                .unwrap_or(false)
+        })
+        .take(INITIAL_SYNC_EVENT_LIMIT)
+        .collect();
+    messages.reverse();
+
+    let end = messages.last().map_or_else(
+        || {
+            services()
+                .rooms
+                .timeline
+                .last_timeline_count(sender_user, room_id)
+        },
+        |(count, _)| Ok(*count),
+    )?;
+    let start = messages.first().map_or(end, |(count, _)| *count);
+
+    let state: Vec<_> = services()
+        .rooms
+        .state_accessor
+        .room_state_full(room_id)
+        .await?
+        .values()
+        .map(|pdu| pdu.to_state_event())
+        .collect();
+
+    let visibility = if services().rooms.directory.is_public_room(room_id)? {
+        room::Visibility::Public
+    } else {
+        room::Visibility::Private
+    };
+
+    Ok(endpoints::room_initial_sync::v3::Response {
+        room_id: room_id.clone(),
+        membership,
+        messages: endpoints::room_initial_sync::v3::PaginationChunk {
+            chunk: messages
+                .into_iter()
+                .map(|(_, pdu)| pdu.to_room_event())
+                .collect(),
+            start: start.stringify(),
+            end: end.stringify(),
+        },
+        state,
+        visibility,
+        account_data: Vec::new(),
+        presence: Vec::new(),
+        receipts: Vec::new(),
+    })
+}
+
+/// # `GET /_matrix/client/v1/room_summary/{roomIdOrAlias}`
+///
+/// Gets a summary of a room, so clients can show a preview of it before joining.
+///
+/// - Resolves aliases
+/// - Asks the servers in `via` if the room is not known to this server
+/// - Only rooms the user could join, peek into or is already in are returned
+pub async fn get_room_summary_route(
+    body: Ruma<get_summary::v1::Request>,
+) -> Result<get_summary::v1::Response> {
+    let sender_user = body.sender_user.as_deref();
+
+    let (via, room_id) = match sender_user {
+        Some(sender_user) => {
+            services()
+                .rooms
+                .state_cache
+                .get_room_id_and_via_servers(
+                    sender_user,
+                    body.room_id_or_alias.clone(),
+                    body.via.clone(),
+                )
+                .await?
+        }
+        None => match OwnedRoomId::try_from(body.room_id_or_alias.clone()) {
+            Ok(room_id) => {
+                let mut via = body.via.clone();
+                via.extend(room_id.server_name().map(ToOwned::to_owned));
+                (via, room_id)
+            }
+            Err(room_alias) => {
+                let response = services().rooms.alias.get_alias_helper(room_alias).await?;
+                (response.servers, response.room_id)
+            }
+        },
+    };
+
+    let summary = services()
+        .rooms
+        .spaces
+        .get_room_preview(&room_id, sender_user, &via)
+        .await?;
+
+    let membership = match sender_user {
+        Some(sender_user) => {
+            let state_cache = &services().rooms.state_cache;
+            if state_cache.is_joined(sender_user, &room_id)? {
+                Some(MembershipState::Join)
+            } else if state_cache.is_invited(sender_user, &room_id)? {
+                Some(MembershipState::Invite)
+            } else if state_cache.is_knocked(sender_user, &room_id)? {
+                Some(MembershipState::Knock)
+            } else if state_cache.is_left(sender_user, &room_id)? {
+                Some(MembershipState::Leave)
+            } else {
+                None
+            }
+        }
+        None => None,
+    };
+
+    Ok(get_summary::v1::Response {
+        summary,
+        membership,
+    })
+}
+
 /// # `GET /_matrix/client/r0/rooms/{roomId}/aliases`
 ///
 /// Lists all aliases of the room.
@@ -13,7 +13,8 @@ use std::collections::BTreeMap;
 ///
 /// Searches rooms for messages.
 ///
-/// - Only works if the user is currently joined to the room (TODO: Respect history visibility)
+/// - Searches the rooms the user is joined to, unless other rooms are requested
+/// - Only returns events the user is allowed to see, depending on history_visibility
 pub async fn search_events_route(
     body: Ruma<search_events::v3::Request>,
 ) -> Result<search_events::v3::Response> {
@@ -39,8 +40,8 @@ pub async fn search_events_route(
     for room_id in room_ids {
         if !services()
             .rooms
-            .state_cache
-            .is_joined(sender_user, &room_id)?
+            .state_accessor
+            .user_can_see_room_history(sender_user, &room_id)?
         {
             return Err(Error::BadRequest(
                 ErrorKind::forbidden(),
@@ -1,15 +1,18 @@
 use super::{DEVICE_ID_LENGTH, TOKEN_LENGTH};
-use crate::{services, utils, Error, Result, Ruma};
+use crate::{service::oidc, services, utils, Error, Result, Ruma};
+use axum::{extract::Query, response::Redirect};
 use ldap3::LdapConn;
 use ruma::{
     api::client::{
         error::ErrorKind,
-        session::{get_login_types, login, logout, logout_all},
+        session::{get_login_types, login, logout, logout_all, sso_login, sso_login_with_provider},
         uiaa::UserIdentifier,
     },
+    thirdparty::Medium,
     UserId,
 };
 use serde::Deserialize;
+use serde_json::Value as JsonValue;
 use tracing::{info, warn};
 
 #[derive(Debug, Deserialize)]
@@ -25,10 +28,106 @@ struct Claims {
 pub async fn get_login_types_route(
     _body: Ruma<get_login_types::v3::Request>,
 ) -> Result<get_login_types::v3::Response> {
-    Ok(get_login_types::v3::Response::new(vec![
+    let mut flows = vec![
         get_login_types::v3::LoginType::Password(Default::default()),
         get_login_types::v3::LoginType::ApplicationService(Default::default()),
-    ]))
+    ];
+
+    let oidc_config = &services().globals.config.oidc;
+    if oidc_config.enabled {
+        flows.push(get_login_types::v3::LoginType::Sso(
+            get_login_types::v3::SsoLoginType {
+                identity_providers: vec![get_login_types::v3::IdentityProvider::new(
+                    oidc::IDP_ID.to_owned(),
+                    oidc_config.idp_name.clone(),
+                )],
+            },
+        ));
+        flows.push(get_login_types::v3::LoginType::Token(Default::default()));
+    }
+
+    Ok(get_login_types::v3::Response::new(flows))
+}
+
+/// # `GET /_matrix/client/v3/login/sso/redirect`
+///
+/// Redirects the user to the OpenID Connect provider to log in.
+pub async fn sso_login_route(
+    body: Ruma<sso_login::v3::Request>,
+) -> Result<sso_login::v3::Response> {
+    let location = sso_redirect(body.body.redirect_url).await?;
+
+    Ok(sso_login::v3::Response::new(location))
+}
+
+/// # `GET /_matrix/client/v3/login/sso/redirect/{idpId}`
+///
+/// Redirects the user to the given identity provider to log in. Only the configured OpenID
+/// Connect provider is available.
+pub async fn sso_login_with_provider_route(
+    body: Ruma<sso_login_with_provider::v3::Request>,
+) -> Result<sso_login_with_provider::v3::Response> {
+    if body.idp_id != oidc::IDP_ID {
+        return Err(Error::BadRequest(
+            ErrorKind::NotFound,
+            "Unknown identity provider.",
+        ));
+    }
+
+    let location = sso_redirect(body.body.redirect_url).await?;
+
+    Ok(sso_login_with_provider::v3::Response::new(location))
+}
+
+async fn sso_redirect(redirect_url: String) -> Result<String> {
+    if !services().globals.config.oidc.enabled {
+        return Err(Error::BadRequest(
+            ErrorKind::Unrecognized,
+            "Single sign-on is not enabled on this server.",
+        ));
+    }
+
+    Ok(services()
+        .oidc
+        .authorization_url(redirect_url)
+        .await?
+        .into())
+}
+
+#[derive(Deserialize)]
+pub struct OidcCallbackParams {
+    state: String,
+    code: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+/// # `GET /_conduit/client/oidc/callback`
+///
+/// The OpenID Connect provider redirects the user here after they logged in.
+///
+/// - Validates the ID token and creates the user on their first login
+/// - Redirects the user back to the client with a short-lived `m.login.token`
+pub async fn oidc_callback_route(Query(params): Query<OidcCallbackParams>) -> Result<Redirect> {
+    if let Some(error) = params.error {
+        warn!(
+            "OpenID Connect login failed: {} {:?}",
+            error, params.error_description
+        );
+        return Err(Error::BadRequest(
+            ErrorKind::forbidden(),
+            "Login at the identity provider failed.",
+        ));
+    }
+
+    let code = params.code.ok_or(Error::BadRequest(
+        ErrorKind::MissingParam,
+        "Missing authorization code.",
+    ))?;
+
+    let redirect_url = services().oidc.complete_login(&params.state, &code).await?;
+
+    Ok(Redirect::to(redirect_url.as_str()))
 }
 
 /// # `POST /_matrix/client/r0/login`
@@ -52,14 +151,27 @@ pub async fn login_route(body: Ruma<login::v3::Request>) -> Result<login::v3::Re
             identifier,
             password,
             user,
-            address: _,
-            medium: _,
+            address,
+            medium,
         }) => {
             let user_id = if let Some(UserIdentifier::UserIdOrLocalpart(user_id)) = identifier {
                 UserId::parse_with_server_name(
                     user_id.to_lowercase(),
                     services().globals.server_name(),
                 )
+            } else if let Some(address) = match (identifier, medium, address) {
+                (Some(UserIdentifier::Email { address }), _, _)
+                | (None, Some(Medium::Email), Some(address)) => Some(address),
+                _ => None,
+            } {
+                // m.id.thirdparty identifiers log in the user the email address belongs to
+                Ok(services()
+                    .users
+                    .find_from_threepid(&Medium::Email, address)?
+                    .ok_or(Error::BadRequest(
+                        ErrorKind::forbidden(),
+                        "Wrong username or password.",
+                    ))?)
             } else if let Some(user) = user {
                 UserId::parse(user)
             } else {
@@ -70,7 +182,18 @@ pub async fn login_route(body: Ruma<login::v3::Request>) -> Result<login::v3::Re
             (user_id, Some(password.clone()))
         }
         login::v3::LoginInfo::Token(login::v3::Token { token }) => {
-            if let Some(jwt_decoding_key) = services().globals.jwt_decoding_key() {
+            if let Some(user_id) = services().oidc.consume_login_token(token) {
+                // Issued after a single sign-on login, but the user might have been deactivated
+                // since, which removes their subject
+                if services().users.oidc_subject(&user_id)?.is_none() {
+                    return Err(Error::BadRequest(
+                        ErrorKind::UserDeactivated,
+                        "The user has been deactivated",
+                    ));
+                }
+
+                (user_id, None)
+            } else if let Some(jwt_decoding_key) = services().globals.jwt_decoding_key() {
                 let token = jsonwebtoken::decode::<Claims>(
                     token,
                     jwt_decoding_key,
@@ -198,10 +321,10 @@ pub async fn login_route(body: Ruma<login::v3::Request>) -> Result<login::v3::Re
 }
 
 async fn authenticate_user(user_id: &UserId, password: &str) -> Result<()> {
-    if services().globals.config.ldap.enabled {
+    if services().globals.ldap().enabled {
         if let Ok(ldap_user) = services().ldap.find_ldap_user(user_id.localpart()).await {
             // User was found in LDAP, so we MUST authenticate against LDAP.
-            let ldap_config = services().globals.config.ldap.clone();
+            let ldap_config = services().globals.ldap();
             let password_clone = password.to_owned();
             let user_id_clone = user_id.to_owned();
 
@@ -223,6 +346,14 @@ async fn authenticate_user(user_id: &UserId, password: &str) -> Result<()> {
                                     .set_displayname(&user_id_clone, Some(ldap_user.displayname))?;
                                 services().users.set_email(&user_id_clone, Some(ldap_user.email))?;
                             }
+                            // LDAP stays the source of truth for the mapped profile fields
+                            for (field, value) in ldap_user.profile_fields {
+                                services().users.set_profile_field(
+                                    &user_id_clone,
+                                    &field,
+                                    value.map(JsonValue::from).as_ref(),
+                                )?;
+                            }
                             return Ok(());
                         }
                         Err(e) => {
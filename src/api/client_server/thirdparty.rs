@@ -1,16 +1,319 @@
-use crate::{Result, Ruma};
-use ruma::api::client::thirdparty::get_protocols;
+use crate::{services, Error, Result, Ruma};
+use ruma::{
+    api::{
+        appservice::{thirdparty as appservice_thirdparty, Registration},
+        client::{
+            error::ErrorKind,
+            thirdparty::{
+                get_location_for_protocol, get_location_for_room_alias, get_protocol,
+                get_protocols, get_user_for_protocol, get_user_for_user_id,
+            },
+        },
+    },
+    thirdparty::Protocol,
+};
+use serde_json::Value as JsonValue;
+use tracing::warn;
 
 use std::collections::BTreeMap;
 
 /// # `GET /_matrix/client/r0/thirdparty/protocols`
 ///
-/// TODO: Fetches all metadata about protocols supported by the homeserver.
+/// Fetches all metadata about protocols supported by the homeserver.
+///
+/// - Asks every appservice about the protocols listed in its registration
+/// - Instances of the same protocol from multiple appservices are merged
 pub async fn get_protocols_route(
     _body: Ruma<get_protocols::v3::Request>,
 ) -> Result<get_protocols::v3::Response> {
-    // TODO
+    let mut protocols = BTreeMap::new();
+
+    for registration in registrations().await {
+        for protocol in registration.protocols.clone().unwrap_or_default() {
+            if let Some(metadata) = query_protocol(&registration, &protocol).await {
+                merge_protocol(&mut protocols, protocol, metadata);
+            }
+        }
+    }
+
     Ok(get_protocols::v3::Response {
-        protocols: BTreeMap::new(),
+        protocols: protocols
+            .into_iter()
+            .filter_map(|(protocol, metadata)| {
+                Some((protocol, serde_json::from_value(metadata).ok()?))
+            })
+            .collect(),
     })
 }
+
+/// # `GET /_matrix/client/r0/thirdparty/protocol/{protocol}`
+///
+/// Fetches the metadata about a protocol supported by the homeserver.
+pub async fn get_protocol_route(
+    body: Ruma<get_protocol::v3::Request>,
+) -> Result<get_protocol::v3::Response> {
+    let mut protocols = BTreeMap::new();
+
+    for registration in protocol_registrations(&body.protocol).await {
+        if let Some(metadata) = query_protocol(&registration, &body.protocol).await {
+            merge_protocol(&mut protocols, body.protocol.clone(), metadata);
+        }
+    }
+
+    let protocol: Protocol = protocols
+        .remove(&body.protocol)
+        .and_then(|metadata| serde_json::from_value(metadata).ok())
+        .ok_or(Error::BadRequest(
+            ErrorKind::NotFound,
+            "Protocol is not supported.",
+        ))?;
+
+    Ok(get_protocol::v3::Response { protocol })
+}
+
+/// # `GET /_matrix/client/r0/thirdparty/location/{protocol}`
+///
+/// Looks up third party locations of a protocol in the appservices that support it.
+pub async fn get_location_for_protocol_route(
+    body: Ruma<get_location_for_protocol::v3::Request>,
+) -> Result<get_location_for_protocol::v3::Response> {
+    let mut locations = Vec::new();
+
+    for registration in protocol_registrations(&body.protocol).await {
+        let id = registration.id.clone();
+        match services()
+            .sending
+            .send_appservice_request(
+                registration,
+                appservice_thirdparty::get_location_for_protocol::v1::Request {
+                    protocol: body.protocol.clone(),
+                    fields: body.fields.clone(),
+                },
+            )
+            .await
+        {
+            Ok(Some(response)) => locations.extend(response.locations),
+            Ok(None) => {}
+            Err(e) => warn!("Could not look up location from appservice {id}: {e}"),
+        }
+    }
+
+    Ok(get_location_for_protocol::v3::Response { locations })
+}
+
+/// # `GET /_matrix/client/r0/thirdparty/location`
+///
+/// Looks up the third party locations of a room alias in the appservices responsible for it.
+pub async fn get_location_for_room_alias_route(
+    body: Ruma<get_location_for_room_alias::v3::Request>,
+) -> Result<get_location_for_room_alias::v3::Response> {
+    let mut locations = Vec::new();
+
+    let appservices: Vec<_> = services()
+        .appservice
+        .read()
+        .await
+        .values()
+        .cloned()
+        .collect();
+
+    for info in appservices {
+        if !info.aliases.is_match(body.alias.as_str()) {
+            continue;
+        }
+
+        match services()
+            .sending
+            .send_appservice_request(
+                info.registration.clone(),
+                appservice_thirdparty::get_location_for_room_alias::v1::Request {
+                    alias: body.alias.clone(),
+                },
+            )
+            .await
+        {
+            Ok(Some(response)) => locations.extend(response.locations),
+            Ok(None) => {}
+            Err(e) => warn!(
+                "Could not look up location from appservice {}: {e}",
+                info.registration.id
+            ),
+        }
+    }
+
+    Ok(get_location_for_room_alias::v3::Response { locations })
+}
+
+/// # `GET /_matrix/client/r0/thirdparty/user/{protocol}`
+///
+/// Looks up third party users of a protocol in the appservices that support it.
+pub async fn get_user_for_protocol_route(
+    body: Ruma<get_user_for_protocol::v3::Request>,
+) -> Result<get_user_for_protocol::v3::Response> {
+    let mut users = Vec::new();
+
+    for registration in protocol_registrations(&body.protocol).await {
+        let id = registration.id.clone();
+        match services()
+            .sending
+            .send_appservice_request(
+                registration,
+                appservice_thirdparty::get_user_for_protocol::v1::Request {
+                    protocol: body.protocol.clone(),
+                    fields: body.fields.clone(),
+                },
+            )
+            .await
+        {
+            Ok(Some(response)) => users.extend(response.users),
+            Ok(None) => {}
+            Err(e) => warn!("Could not look up user from appservice {id}: {e}"),
+        }
+    }
+
+    Ok(get_user_for_protocol::v3::Response { users })
+}
+
+/// # `GET /_matrix/client/r0/thirdparty/user`
+///
+/// Looks up the third party users of a Matrix user in the appservices responsible for it.
+pub async fn get_user_for_user_id_route(
+    body: Ruma<get_user_for_user_id::v3::Request>,
+) -> Result<get_user_for_user_id::v3::Response> {
+    let mut users = Vec::new();
+
+    let appservices: Vec<_> = services()
+        .appservice
+        .read()
+        .await
+        .values()
+        .cloned()
+        .collect();
+
+    for info in appservices {
+        if !info.is_user_match(&body.userid) {
+            continue;
+        }
+
+        match services()
+            .sending
+            .send_appservice_request(
+                info.registration.clone(),
+                appservice_thirdparty::get_user_for_user_id::v1::Request {
+                    userid: body.userid.clone(),
+                },
+            )
+            .await
+        {
+            Ok(Some(response)) => users.extend(response.users),
+            Ok(None) => {}
+            Err(e) => warn!(
+                "Could not look up user from appservice {}: {e}",
+                info.registration.id
+            ),
+        }
+    }
+
+    Ok(get_user_for_user_id::v3::Response { users })
+}
+
+async fn registrations() -> Vec<Registration> {
+    services()
+        .appservice
+        .read()
+        .await
+        .values()
+        .map(|info| info.registration.clone())
+        .collect()
+}
+
+/// Returns the registrations of all appservices that list the protocol
+async fn protocol_registrations(protocol: &str) -> Vec<Registration> {
+    registrations()
+        .await
+        .into_iter()
+        .filter(|registration| {
+            registration
+                .protocols
+                .as_ref()
+                .is_some_and(|protocols| protocols.iter().any(|p| p == protocol))
+        })
+        .collect()
+}
+
+/// Asks the appservice about a protocol, returning the metadata as JSON
+///
+/// The `instance_id` of every instance is set to `{appserviceId}|{networkId}`, which clients can
+/// pass as `third_party_instance_id` to list the rooms the appservice published for that network.
+async fn query_protocol(registration: &Registration, protocol: &str) -> Option<JsonValue> {
+    let response = match services()
+        .sending
+        .send_appservice_request(
+            registration.clone(),
+            appservice_thirdparty::get_protocol::v1::Request {
+                protocol: protocol.to_owned(),
+            },
+        )
+        .await
+    {
+        Ok(response) => response?,
+        Err(e) => {
+            warn!(
+                "Could not get protocol {protocol} from appservice {}: {e}",
+                registration.id
+            );
+            return None;
+        }
+    };
+
+    let mut metadata = serde_json::to_value(response.protocol).ok()?;
+
+    if let Some(instances) = metadata
+        .get_mut("instances")
+        .and_then(JsonValue::as_array_mut)
+    {
+        for instance in instances {
+            let network_id = instance
+                .get("network_id")
+                .and_then(JsonValue::as_str)
+                .unwrap_or_default()
+                .to_owned();
+
+            if let Some(instance) = instance.as_object_mut() {
+                instance.insert(
+                    "instance_id".to_owned(),
+                    format!("{}|{network_id}", registration.id).into(),
+                );
+            }
+        }
+    }
+
+    Some(metadata)
+}
+
+/// Adds the metadata of a protocol, merging its instances into those of other appservices
+fn merge_protocol(
+    protocols: &mut BTreeMap<String, JsonValue>,
+    protocol: String,
+    metadata: JsonValue,
+) {
+    match protocols.get_mut(&protocol) {
+        Some(existing) => {
+            let instances = metadata
+                .get("instances")
+                .and_then(JsonValue::as_array)
+                .cloned()
+                .unwrap_or_default();
+
+            if let Some(existing_instances) = existing
+                .get_mut("instances")
+                .and_then(JsonValue::as_array_mut)
+            {
+                existing_instances.extend(instances);
+            }
+        }
+        None => {
+            protocols.insert(protocol, metadata);
+        }
+    }
+}
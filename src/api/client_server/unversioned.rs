@@ -35,9 +35,12 @@ pub async fn get_supported_versions_route(
             "v1.12".to_owned(), // Clarifies that guests can use auth media, which Element-* might depend on support being declared
         ],
         unstable_features: BTreeMap::from_iter([
+            ("im.nheko.summary".to_owned(), true),
             ("org.matrix.e2e_cross_signing".to_owned(), true),
             ("org.matrix.msc3916.stable".to_owned(), true),
             ("org.matrix.simplified_msc3575".to_owned(), true),
+            ("uk.half-shot.msc2666.query_mutual_rooms".to_owned(), true),
+            ("uk.tcpip.msc4133".to_owned(), true),
         ]),
     };
 
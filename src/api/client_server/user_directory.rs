@@ -1,10 +1,11 @@
-use crate::{services, Result, Ruma};
+use crate::{api::endpoints, services, Error, Result, Ruma};
 use ruma::{
-    api::client::user_directory::search_users,
+    api::client::{error::ErrorKind, user_directory::search_users},
     events::{
         room::join_rules::{JoinRule, RoomJoinRulesEventContent},
         StateEventType,
     },
+    OwnedRoomId,
 };
 
 /// # `POST /_matrix/client/r0/user_directory/search`
@@ -98,3 +99,64 @@ pub async fn search_users_route(
 
     Ok(search_users::v3::Response { results, limited })
 }
+
+/// Number of rooms returned per page by [`get_mutual_rooms_route`]
+const MUTUAL_ROOMS_LIMIT: usize = 100;
+
+/// # `GET /_matrix/client/unstable/uk.half-shot.msc2666/user/mutual_rooms`
+///
+/// Returns the rooms both the sender and the given user are joined to, see MSC2666.
+///
+/// - Only works for users on this server, to not reveal memberships of remote users
+/// - The batch token is the last room id of the previous page, as rooms are returned sorted
+pub async fn get_mutual_rooms_route(
+    body: Ruma<endpoints::mutual_rooms::unstable::Request>,
+) -> Result<endpoints::mutual_rooms::unstable::Response> {
+    let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+
+    if body.user_id == *sender_user {
+        return Err(Error::BadRequest(
+            ErrorKind::InvalidParam,
+            "You can't request mutual rooms with yourself.",
+        ));
+    }
+
+    if body.user_id.server_name() != services().globals.server_name() {
+        return Err(Error::BadRequest(
+            ErrorKind::forbidden(),
+            "Mutual rooms can only be requested for users on this server.",
+        ));
+    }
+
+    let from = body
+        .batch_token
+        .as_deref()
+        .map(|token| {
+            OwnedRoomId::try_from(token)
+                .map_err(|_| Error::BadRequest(ErrorKind::InvalidParam, "Invalid batch token."))
+        })
+        .transpose()?;
+
+    let mut rooms = services()
+        .rooms
+        .user
+        .get_shared_rooms(vec![sender_user.clone(), body.user_id.clone()])?
+        .filter_map(|r| r.ok())
+        .filter(|room_id| {
+            from.as_ref()
+                .is_none_or(|from| room_id.as_bytes() > from.as_bytes())
+        });
+
+    let joined: Vec<_> = rooms.by_ref().take(MUTUAL_ROOMS_LIMIT).collect();
+
+    let next_batch_token = if rooms.next().is_some() {
+        joined.last().map(|room_id| room_id.to_string())
+    } else {
+        None
+    };
+
+    Ok(endpoints::mutual_rooms::unstable::Response {
+        joined,
+        next_batch_token,
+    })
+}
@@ -0,0 +1,7 @@
+//! Request and response types of endpoints ruma has no types for, or whose ruma types drop data
+//! Conduit needs. They are laid out like the ones in ruma, so that they can be swapped for ruma's
+//! once it supports them.
+
+pub mod mutual_rooms;
+pub mod profile;
+pub mod room_initial_sync;
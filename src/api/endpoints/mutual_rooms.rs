@@ -0,0 +1,38 @@
+//! `GET /_matrix/client/unstable/uk.half-shot.msc2666/user/mutual_rooms`, see MSC2666
+
+pub mod unstable {
+    use ruma::{
+        api::{request, response, Metadata},
+        metadata, OwnedRoomId, OwnedUserId,
+    };
+
+    const METADATA: Metadata = metadata! {
+        method: GET,
+        rate_limited: true,
+        authentication: AccessToken,
+        history: {
+            unstable => "/_matrix/client/unstable/uk.half-shot.msc2666/user/mutual_rooms",
+        }
+    };
+
+    #[request]
+    pub struct Request {
+        /// The user to find mutual rooms with
+        #[ruma_api(query)]
+        pub user_id: OwnedUserId,
+
+        /// The `next_batch_token` of the previous page
+        #[ruma_api(query)]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub batch_token: Option<String>,
+    }
+
+    #[response]
+    pub struct Response {
+        pub joined: Vec<OwnedRoomId>,
+
+        /// Set if there are more rooms than the ones in `joined`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub next_batch_token: Option<String>,
+    }
+}
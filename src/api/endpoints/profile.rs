@@ -0,0 +1,193 @@
+//! Profiles with custom fields, see MSC4133
+
+/// `GET /_matrix/client/*/profile/{userId}`
+///
+/// Like ruma's `get_profile`, but keeps the custom fields of the profile.
+pub mod get_profile {
+    pub mod v3 {
+        use std::collections::BTreeMap;
+
+        use ruma::{
+            api::{request, response, Metadata},
+            metadata, OwnedUserId,
+        };
+        use serde_json::Value as JsonValue;
+
+        const METADATA: Metadata = metadata! {
+            method: GET,
+            rate_limited: false,
+            authentication: None,
+            history: {
+                1.0 => "/_matrix/client/r0/profile/{user_id}",
+                1.1 => "/_matrix/client/v3/profile/{user_id}",
+            }
+        };
+
+        #[request]
+        pub struct Request {
+            #[ruma_api(path)]
+            pub user_id: OwnedUserId,
+        }
+
+        #[response]
+        pub struct Response {
+            /// All fields of the profile, keyed by their name
+            #[ruma_api(body)]
+            pub profile: BTreeMap<String, JsonValue>,
+        }
+    }
+}
+
+/// `GET /_matrix/client/*/profile/{userId}/{keyName}`
+pub mod get_profile_field {
+    pub mod v3 {
+        use std::collections::BTreeMap;
+
+        use ruma::{
+            api::{request, response, Metadata},
+            metadata, OwnedUserId,
+        };
+        use serde_json::Value as JsonValue;
+
+        const METADATA: Metadata = metadata! {
+            method: GET,
+            rate_limited: false,
+            authentication: AccessTokenOptional,
+            history: {
+                unstable => "/_matrix/client/unstable/uk.tcpip.msc4133/profile/{user_id}/{field}",
+                1.1 => "/_matrix/client/v3/profile/{user_id}/{field}",
+            }
+        };
+
+        #[request]
+        pub struct Request {
+            #[ruma_api(path)]
+            pub user_id: OwnedUserId,
+
+            #[ruma_api(path)]
+            pub field: String,
+        }
+
+        #[response]
+        pub struct Response {
+            /// The requested field, keyed by its name
+            #[ruma_api(body)]
+            pub field: BTreeMap<String, JsonValue>,
+        }
+    }
+}
+
+/// `PUT /_matrix/client/*/profile/{userId}/{keyName}`
+pub mod set_profile_field {
+    pub mod v3 {
+        use std::collections::BTreeMap;
+
+        use ruma::{
+            api::{request, response, Metadata},
+            metadata, OwnedUserId,
+        };
+        use serde_json::Value as JsonValue;
+
+        const METADATA: Metadata = metadata! {
+            method: PUT,
+            rate_limited: true,
+            authentication: AccessToken,
+            history: {
+                unstable => "/_matrix/client/unstable/uk.tcpip.msc4133/profile/{user_id}/{field}",
+                1.1 => "/_matrix/client/v3/profile/{user_id}/{field}",
+            }
+        };
+
+        #[request]
+        pub struct Request {
+            #[ruma_api(path)]
+            pub user_id: OwnedUserId,
+
+            #[ruma_api(path)]
+            pub field: String,
+
+            /// Must contain the new value of the field, keyed by its name
+            #[ruma_api(body)]
+            pub value: BTreeMap<String, JsonValue>,
+        }
+
+        #[response]
+        #[derive(Default)]
+        pub struct Response {}
+    }
+}
+
+/// `DELETE /_matrix/client/*/profile/{userId}/{keyName}`
+pub mod delete_profile_field {
+    pub mod v3 {
+        use ruma::{
+            api::{request, response, Metadata},
+            metadata, OwnedUserId,
+        };
+
+        const METADATA: Metadata = metadata! {
+            method: DELETE,
+            rate_limited: true,
+            authentication: AccessToken,
+            history: {
+                unstable => "/_matrix/client/unstable/uk.tcpip.msc4133/profile/{user_id}/{field}",
+                1.1 => "/_matrix/client/v3/profile/{user_id}/{field}",
+            }
+        };
+
+        #[request]
+        pub struct Request {
+            #[ruma_api(path)]
+            pub user_id: OwnedUserId,
+
+            #[ruma_api(path)]
+            pub field: String,
+        }
+
+        #[response]
+        #[derive(Default)]
+        pub struct Response {}
+    }
+}
+
+/// `GET /_matrix/federation/v1/query/profile`
+///
+/// Like ruma's `get_profile_information`, but keeps the custom fields of the profile.
+pub mod get_profile_information {
+    pub mod v1 {
+        use std::collections::BTreeMap;
+
+        use ruma::{
+            api::{request, response, Metadata},
+            metadata, OwnedUserId,
+        };
+        use serde_json::Value as JsonValue;
+
+        const METADATA: Metadata = metadata! {
+            method: GET,
+            rate_limited: false,
+            authentication: ServerSignatures,
+            history: {
+                1.0 => "/_matrix/federation/v1/query/profile",
+            }
+        };
+
+        #[request]
+        pub struct Request {
+            #[ruma_api(query)]
+            pub user_id: OwnedUserId,
+
+            /// Only this field is requested if set
+            #[ruma_api(query)]
+            #[serde(skip_serializing_if = "Option::is_none")]
+            pub field: Option<String>,
+        }
+
+        #[response]
+        pub struct Response {
+            /// The requested fields, keyed by their name
+            #[ruma_api(body)]
+            pub profile: BTreeMap<String, JsonValue>,
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! `GET /_matrix/client/*/rooms/{roomId}/initialSync`
+//!
+//! Deprecated in the spec, but still used by guests to peek into world readable rooms.
+
+pub mod v3 {
+    use ruma::{
+        api::{client::room::Visibility, request, response, Metadata},
+        events::{
+            room::member::MembershipState, AnyRoomAccountDataEvent, AnyStateEvent, AnyTimelineEvent,
+        },
+        metadata,
+        serde::Raw,
+        OwnedRoomId,
+    };
+    use serde::{Deserialize, Serialize};
+    use serde_json::Value as JsonValue;
+
+    const METADATA: Metadata = metadata! {
+        method: GET,
+        rate_limited: false,
+        authentication: AccessToken,
+        history: {
+            1.0 => "/_matrix/client/r0/rooms/{room_id}/initialSync",
+            1.1 => "/_matrix/client/v3/rooms/{room_id}/initialSync",
+        }
+    };
+
+    #[request]
+    pub struct Request {
+        #[ruma_api(path)]
+        pub room_id: OwnedRoomId,
+    }
+
+    #[response]
+    pub struct Response {
+        pub room_id: OwnedRoomId,
+
+        /// The membership of the user, if they are joined or invited
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub membership: Option<MembershipState>,
+
+        /// The most recent events of the room
+        pub messages: PaginationChunk,
+
+        /// The current state of the room
+        pub state: Vec<Raw<AnyStateEvent>>,
+
+        pub visibility: Visibility,
+
+        pub account_data: Vec<Raw<AnyRoomAccountDataEvent>>,
+
+        pub presence: Vec<JsonValue>,
+
+        pub receipts: Vec<JsonValue>,
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct PaginationChunk {
+        /// The events, oldest first
+        pub chunk: Vec<Raw<AnyTimelineEvent>>,
+
+        /// A token to paginate backwards from the first event of the chunk
+        pub start: String,
+
+        /// A token to paginate forwards from the last event of the chunk
+        pub end: String,
+    }
+}
@@ -0,0 +1,41 @@
+use axum::response::IntoResponse;
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use http::header;
+use ruma::api::client::error::ErrorKind;
+
+use crate::{services, Error, Result};
+
+/// # `GET /metrics`
+///
+/// Exposes the metrics of the server in the Prometheus text exposition format.
+///
+/// - Requires the configured bearer token, if any
+pub async fn metrics_route(
+    auth_header: Option<TypedHeader<Authorization<Bearer>>>,
+) -> Result<impl IntoResponse> {
+    if let Some(bearer_token) = &services().globals.config.metrics.bearer_token {
+        match auth_header {
+            None => {
+                return Err(Error::BadRequest(
+                    ErrorKind::MissingToken,
+                    "Missing access token.",
+                ))
+            }
+            Some(TypedHeader(Authorization(bearer))) if bearer.token() != bearer_token => {
+                return Err(Error::BadRequest(
+                    ErrorKind::UnknownToken { soft_logout: false },
+                    "Unknown access token.",
+                ))
+            }
+            Some(_) => {}
+        }
+    }
+
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        services().metrics.render().await,
+    ))
+}
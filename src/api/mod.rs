@@ -1,4 +1,6 @@
 pub mod appservice_server;
 pub mod client_server;
+pub mod endpoints;
+pub mod metrics;
 pub mod ruma_wrapper;
 pub mod server_server;
@@ -15,8 +15,9 @@ use bytes::{BufMut, BytesMut};
 use http::{Request, StatusCode};
 use ruma::{
     api::{
-        client::error::ErrorKind, federation::authentication::XMatrix, AuthScheme, IncomingRequest,
-        OutgoingResponse,
+        client::{device::update_device, error::ErrorKind},
+        federation::authentication::XMatrix,
+        AuthScheme, IncomingRequest, Metadata, OutgoingResponse,
     },
     CanonicalJsonValue, MilliSecondsSinceUnixEpoch, OwnedDeviceId, OwnedUserId, UserId,
 };
@@ -45,6 +46,8 @@ where
         struct QueryParams {
             access_token: Option<String>,
             user_id: Option<String>,
+            #[serde(alias = "org.matrix.msc3202.device_id")]
+            device_id: Option<OwnedDeviceId>,
         }
 
         let (mut parts, mut body) = {
@@ -159,7 +162,24 @@ where
                         ));
                     }
 
-                    (Some(user_id), None, None, Some(*info))
+                    // Appservices can act as a specific device of their users (MSC3202). The
+                    // device has to be created with the device management endpoints first
+                    // (MSC4190), which create it themselves if it doesn't exist yet.
+                    if let Some(device_id) = &query_params.device_id {
+                        if !is_endpoint::<update_device::v3::Request>(&metadata)
+                            && services()
+                                .users
+                                .get_device_metadata(&user_id, device_id)?
+                                .is_none()
+                        {
+                            return Err(Error::BadRequest(
+                                ErrorKind::forbidden(),
+                                "Device does not exist.",
+                            ));
+                        }
+                    }
+
+                    (Some(user_id), query_params.device_id, None, Some(*info))
                 }
                 (
                     AuthScheme::None
@@ -176,7 +196,27 @@ where
                 (
                     AuthScheme::AccessToken | AuthScheme::AccessTokenOptional | AuthScheme::None,
                     Token::User((user_id, device_id)),
-                ) => (Some(user_id), Some(device_id), None, None),
+                ) => {
+                    if services().users.is_guest(&user_id)? {
+                        if !services().globals.allow_guest_access().await {
+                            return Err(Error::BadRequest(
+                                ErrorKind::GuestAccessForbidden,
+                                "Guest access is disabled on this server.",
+                            ));
+                        }
+
+                        if metadata.authentication == AuthScheme::AccessToken
+                            && !is_guest_allowed(&metadata)
+                        {
+                            return Err(Error::BadRequest(
+                                ErrorKind::GuestAccessForbidden,
+                                "Guests cannot use this endpoint.",
+                            ));
+                        }
+                    }
+
+                    (Some(user_id), Some(device_id), None, None)
+                }
                 (AuthScheme::ServerSignatures, Token::None) => {
                     let TypedHeader(Authorization(x_matrix)) = parts
                         .extract::<TypedHeader<Authorization<XMatrix>>>()
@@ -316,6 +356,12 @@ where
                         "Only server signatures should be used on this endpoint.",
                     ));
                 }
+                // Guests upgrade their account by registering while logged in
+                (AuthScheme::AppserviceTokenOptional, Token::User((user_id, device_id)))
+                    if services().users.is_guest(&user_id)? =>
+                {
+                    (Some(user_id), Some(device_id), None, None)
+                }
                 (
                     AuthScheme::AppserviceToken | AuthScheme::AppserviceTokenOptional,
                     Token::User(_),
@@ -381,6 +427,84 @@ where
     }
 }
 
+/// Whether the metadata is the one of the endpoint of `R`
+fn is_endpoint<R: IncomingRequest>(metadata: &Metadata) -> bool {
+    metadata.method == R::METADATA.method
+        && metadata
+            .history
+            .all_paths()
+            .eq(R::METADATA.history.all_paths())
+}
+
+/// Whether guest accounts may use the endpoint, following the list of endpoints in the
+/// "Guest access" section of the client-server spec
+fn is_guest_allowed(metadata: &Metadata) -> bool {
+    use crate::api::endpoints::room_initial_sync;
+    use ruma::api::client::{
+        account::whoami,
+        authenticated_media::{
+            get_content, get_content_as_filename, get_content_thumbnail, get_media_config,
+        },
+        context::get_context,
+        directory::{get_public_rooms, get_public_rooms_filtered},
+        discovery::get_capabilities,
+        filter::{create_filter, get_filter},
+        keys::{claim_keys, get_key_changes, get_keys, upload_keys},
+        membership::{get_member_events, join_room_by_id, join_room_by_id_or_alias, leave_room},
+        message::{get_message_events, send_message_event},
+        presence::{get_presence, set_presence},
+        profile::{get_avatar_url, get_display_name, get_profile, set_display_name},
+        read_marker::set_read_marker,
+        receipt::create_receipt,
+        room::get_room_event,
+        session::logout,
+        state::{get_state_event_for_key, get_state_events},
+        sync::sync_events,
+        to_device::send_event_to_device,
+        typing::create_typing_event,
+        voip::get_turn_server_info,
+    };
+
+    is_endpoint::<whoami::v3::Request>(metadata)
+        || is_endpoint::<get_content::v1::Request>(metadata)
+        || is_endpoint::<get_content_as_filename::v1::Request>(metadata)
+        || is_endpoint::<get_content_thumbnail::v1::Request>(metadata)
+        || is_endpoint::<get_media_config::v1::Request>(metadata)
+        || is_endpoint::<get_context::v3::Request>(metadata)
+        || is_endpoint::<get_public_rooms::v3::Request>(metadata)
+        || is_endpoint::<get_public_rooms_filtered::v3::Request>(metadata)
+        || is_endpoint::<create_filter::v3::Request>(metadata)
+        || is_endpoint::<get_filter::v3::Request>(metadata)
+        || is_endpoint::<claim_keys::v3::Request>(metadata)
+        || is_endpoint::<get_key_changes::v3::Request>(metadata)
+        || is_endpoint::<get_keys::v3::Request>(metadata)
+        || is_endpoint::<upload_keys::v3::Request>(metadata)
+        || is_endpoint::<get_member_events::v3::Request>(metadata)
+        || is_endpoint::<join_room_by_id::v3::Request>(metadata)
+        || is_endpoint::<join_room_by_id_or_alias::v3::Request>(metadata)
+        || is_endpoint::<leave_room::v3::Request>(metadata)
+        || is_endpoint::<get_message_events::v3::Request>(metadata)
+        || is_endpoint::<send_message_event::v3::Request>(metadata)
+        || is_endpoint::<get_presence::v3::Request>(metadata)
+        || is_endpoint::<set_presence::v3::Request>(metadata)
+        || is_endpoint::<get_avatar_url::v3::Request>(metadata)
+        || is_endpoint::<get_display_name::v3::Request>(metadata)
+        || is_endpoint::<get_profile::v3::Request>(metadata)
+        || is_endpoint::<set_display_name::v3::Request>(metadata)
+        || is_endpoint::<set_read_marker::v3::Request>(metadata)
+        || is_endpoint::<create_receipt::v3::Request>(metadata)
+        || is_endpoint::<get_room_event::v3::Request>(metadata)
+        || is_endpoint::<room_initial_sync::v3::Request>(metadata)
+        || is_endpoint::<logout::v3::Request>(metadata)
+        || is_endpoint::<get_state_events::v3::Request>(metadata)
+        || is_endpoint::<get_state_event_for_key::v3::Request>(metadata)
+        || is_endpoint::<sync_events::v3::Request>(metadata)
+        || is_endpoint::<send_event_to_device::v3::Request>(metadata)
+        || is_endpoint::<create_typing_event::v3::Request>(metadata)
+        || is_endpoint::<get_capabilities::v3::Request>(metadata)
+        || is_endpoint::<get_turn_server_info::v3::Request>(metadata)
+}
+
 impl<T: OutgoingResponse> IntoResponse for RumaResponse<T> {
     fn into_response(self) -> Response {
         match self.0.try_into_http_response::<BytesMut>() {
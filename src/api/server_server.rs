@@ -29,7 +29,10 @@ use ruma::{
                 discover_homeserver, get_server_keys, get_server_version, ServerSigningKeys,
                 VerifyKey,
             },
-            event::{get_event, get_missing_events, get_room_state, get_room_state_ids},
+            event::{
+                get_event, get_event_by_timestamp, get_missing_events, get_room_state,
+                get_room_state_ids,
+            },
             keys::{claim_keys, get_keys},
             membership::{
                 create_invite, create_join_event, create_knock_event, create_leave_event,
@@ -60,7 +63,7 @@ use ruma::{
     OwnedEventId, OwnedRoomId, OwnedServerName, OwnedServerSigningKeyId, OwnedUserId, RoomId,
     RoomVersionId, ServerName, Signatures, UserId,
 };
-use serde_json::value::{to_raw_value, RawValue as RawJsonValue};
+use serde_json::value::{to_raw_value, RawValue as RawJsonValue, Value as JsonValue};
 use std::{
     collections::BTreeMap,
     fmt::Debug,
@@ -101,6 +104,10 @@ impl FedDest {
     fn into_https_string(self) -> String {
         match self {
             Self::Literal(addr) => format!("https://{addr}"),
+            // Test server processes (see `conduit::testing::ServerProcess`) are named
+            // `localhost:{port}` and only speak plain HTTP
+            #[cfg(feature = "testing")]
+            Self::Named(host, port) if host == "localhost" => format!("http://{host}{port}"),
             Self::Named(host, port) => format!("https://{host}{port}"),
         }
     }
@@ -1026,37 +1033,44 @@ pub async fn send_transaction_message_route(
                         for (target_device_id_maybe, event) in map {
                             match target_device_id_maybe {
                                 DeviceIdOrAllDevices::DeviceId(target_device_id) => {
-                                    services().users.add_to_device_event(
-                                        &sender,
-                                        target_user_id,
-                                        target_device_id,
-                                        &ev_type.to_string(),
-                                        event.deserialize_as().map_err(|e| {
-                                            warn!("To-Device event is invalid: {event:?} {e}");
-                                            Error::BadRequest(
-                                                ErrorKind::InvalidParam,
-                                                "Event is invalid",
-                                            )
-                                        })?,
-                                    )?
-                                }
-
-                                DeviceIdOrAllDevices::AllDevices => {
-                                    for target_device_id in
-                                        services().users.all_device_ids(target_user_id)
-                                    {
-                                        services().users.add_to_device_event(
+                                    services()
+                                        .users
+                                        .add_to_device_event(
                                             &sender,
                                             target_user_id,
-                                            &target_device_id?,
+                                            target_device_id,
                                             &ev_type.to_string(),
-                                            event.deserialize_as().map_err(|_| {
+                                            event.deserialize_as().map_err(|e| {
+                                                warn!("To-Device event is invalid: {event:?} {e}");
                                                 Error::BadRequest(
                                                     ErrorKind::InvalidParam,
                                                     "Event is invalid",
                                                 )
                                             })?,
-                                        )?;
+                                        )
+                                        .await?
+                                }
+
+                                DeviceIdOrAllDevices::AllDevices => {
+                                    let target_device_ids: Vec<_> =
+                                        services().users.all_device_ids(target_user_id).collect();
+
+                                    for target_device_id in target_device_ids {
+                                        services()
+                                            .users
+                                            .add_to_device_event(
+                                                &sender,
+                                                target_user_id,
+                                                &target_device_id?,
+                                                &ev_type.to_string(),
+                                                event.deserialize_as().map_err(|_| {
+                                                    Error::BadRequest(
+                                                        ErrorKind::InvalidParam,
+                                                        "Event is invalid",
+                                                    )
+                                                })?,
+                                            )
+                                            .await?;
                                     }
                                 }
                             }
@@ -1308,6 +1322,60 @@ pub async fn get_missing_events_route(
     Ok(get_missing_events::v1::Response { events })
 }
 
+/// # `GET /_matrix/federation/v1/timestamp_to_event/{roomId}`
+///
+/// Finds the event closest to the given timestamp in the given direction.
+pub async fn get_event_by_timestamp_route(
+    body: Ruma<get_event_by_timestamp::v1::Request>,
+) -> Result<get_event_by_timestamp::v1::Response> {
+    let sender_servername = body
+        .sender_servername
+        .as_ref()
+        .expect("server is authenticated");
+
+    if !services()
+        .rooms
+        .state_cache
+        .server_in_room(sender_servername, &body.room_id)?
+    {
+        return Err(Error::BadRequest(
+            ErrorKind::forbidden(),
+            "Server is not in room",
+        ));
+    }
+
+    services()
+        .rooms
+        .event_handler
+        .acl_check(sender_servername, &body.room_id)?;
+
+    let pdu = services()
+        .rooms
+        .timeline
+        .pdu_by_timestamp(&body.room_id, body.ts, body.dir)?
+        .0
+        .ok_or(Error::BadRequest(
+            ErrorKind::NotFound,
+            "No event found in the given direction.",
+        ))?;
+
+    if !services().rooms.state_accessor.server_can_see_event(
+        sender_servername,
+        &body.room_id,
+        &pdu.event_id,
+    )? {
+        return Err(Error::BadRequest(
+            ErrorKind::forbidden(),
+            "Server is not allowed to see event.",
+        ));
+    }
+
+    Ok(get_event_by_timestamp::v1::Response {
+        event_id: pdu.event_id.as_ref().to_owned(),
+        origin_server_ts: MilliSecondsSinceUnixEpoch(pdu.origin_server_ts),
+    })
+}
+
 /// # `GET /_matrix/federation/v1/event_auth/{roomId}/{eventId}`
 ///
 /// Retrieves the auth chain for a given event.
@@ -2240,6 +2308,20 @@ pub async fn get_content_route(
     services()
         .media
         .check_blocked(services().globals.server_name(), &body.media_id)?;
+    services().media.check_server_access(
+        &body.media_id,
+        body.sender_servername
+            .as_ref()
+            .expect("server is authenticated"),
+    )?;
+    services()
+        .media
+        .wait_for_upload(
+            services().globals.server_name(),
+            &body.media_id,
+            body.timeout_ms,
+        )
+        .await?;
 
     if let Some(FileMeta {
         content_disposition,
@@ -2272,6 +2354,20 @@ pub async fn get_content_thumbnail_route(
     services()
         .media
         .check_blocked(services().globals.server_name(), &body.media_id)?;
+    services().media.check_server_access(
+        &body.media_id,
+        body.sender_servername
+            .as_ref()
+            .expect("server is authenticated"),
+    )?;
+    services()
+        .media
+        .wait_for_upload(
+            services().globals.server_name(),
+            &body.media_id,
+            body.timeout_ms,
+        )
+        .await?;
 
     let Some(FileMeta {
         file,
@@ -2394,9 +2490,12 @@ pub async fn get_room_information_route(
 /// # `GET /_matrix/federation/v1/query/profile`
 ///
 /// Gets information on a profile.
+///
+/// - Custom profile fields (MSC4133) are included, which is why this returns plain JSON instead of
+///   the ruma response that only knows displayname, avatar_url and blurhash
 pub async fn get_profile_information_route(
     body: Ruma<get_profile_information::v1::Request>,
-) -> Result<get_profile_information::v1::Response> {
+) -> Result<Json<BTreeMap<String, JsonValue>>> {
     if body.user_id.server_name() != services().globals.server_name() {
         return Err(Error::BadRequest(
             ErrorKind::InvalidParam,
@@ -2404,32 +2503,42 @@ pub async fn get_profile_information_route(
         ));
     }
 
-    let mut displayname = None;
-    let mut avatar_url = None;
-    let mut blurhash = None;
+    let mut profile = BTreeMap::new();
+
+    let (displayname, avatar_url) = match &body.field {
+        Some(ProfileField::DisplayName) => (true, false),
+        Some(ProfileField::AvatarUrl) => (false, true),
+        Some(field) => {
+            if let Some(value) = services()
+                .users
+                .profile_field(&body.user_id, field.as_str())?
+            {
+                profile.insert(field.as_str().to_owned(), value);
+            }
+            (false, false)
+        }
+        None => {
+            profile = services().users.profile_fields(&body.user_id)?;
+            (true, true)
+        }
+    };
 
-    match &body.field {
-        Some(ProfileField::DisplayName) => {
-            displayname = services().users.displayname(&body.user_id)?
+    if displayname {
+        if let Some(displayname) = services().users.displayname(&body.user_id)? {
+            profile.insert("displayname".to_owned(), displayname.into());
         }
-        Some(ProfileField::AvatarUrl) => {
-            avatar_url = services().users.avatar_url(&body.user_id)?;
-            blurhash = services().users.blurhash(&body.user_id)?
+    }
+
+    if avatar_url {
+        if let Some(avatar_url) = services().users.avatar_url(&body.user_id)? {
+            profile.insert("avatar_url".to_owned(), avatar_url.to_string().into());
         }
-        // TODO: what to do with custom
-        Some(_) => {}
-        None => {
-            displayname = services().users.displayname(&body.user_id)?;
-            avatar_url = services().users.avatar_url(&body.user_id)?;
-            blurhash = services().users.blurhash(&body.user_id)?;
+        if let Some(blurhash) = services().users.blurhash(&body.user_id)? {
+            profile.insert("xyz.amorgan.blurhash".to_owned(), blurhash.into());
         }
     }
 
-    Ok(get_profile_information::v1::Response {
-        blurhash,
-        displayname,
-        avatar_url,
-    })
+    Ok(Json(profile))
 }
 
 /// # `POST /_matrix/federation/v1/user/keys/query`
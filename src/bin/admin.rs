@@ -1,12 +1,21 @@
-use std::io::{Read, Write};
-use std::os::unix::net::UnixStream;
 use std::env;
-use std::net::Shutdown;
-use figment::{Figment, providers::{Format, Toml, Env}};
+use std::io::Read;
+use std::os::unix::net::UnixStream;
+use std::process::exit;
+
+use conduit::admin_protocol::{self, Request, Response};
+use figment::{
+    providers::{Env, Format, Toml},
+    Figment,
+};
 use serde::Deserialize;
 
 use tracing::{error, info};
 
+/// Exit code used when the command couldn't be sent to the server or its response couldn't be
+/// read. The other exit codes are given by [`admin_protocol::Status::exit_code`].
+const EXIT_UNREACHABLE: i32 = 3;
+
 #[derive(Deserialize)]
 struct Config {
     #[serde(default)]
@@ -22,50 +31,72 @@ fn main() {
     tracing_subscriber::fmt::init();
 
     let config: Config = Figment::new()
-        .merge(Toml::file(Env::var("CONDUIT_CONFIG").unwrap_or_else(| | {
-            error!("CONDUIT_CONFIG env var not set");
-            std::process::exit(1);
-        })))
+        .merge(Toml::file(Env::var("CONDUIT_CONFIG").unwrap_or_else(
+            || {
+                error!("CONDUIT_CONFIG env var not set");
+                exit(EXIT_UNREACHABLE);
+            },
+        )))
         .extract()
         .unwrap_or_else(|e| {
             error!("Could not parse config: {}", e);
-            std::process::exit(1);
+            exit(EXIT_UNREACHABLE);
         });
 
-    let mut stream = UnixStream::connect(config.global.unix_socket_path).unwrap_or_else(|e| {
-        error!("Could not connect to admin socket: {}", e);
-        std::process::exit(1);
-    });
-    let args: Vec<String> = env::args().collect();
-    let command = args[1..].join(" ");
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let json = args.first().is_some_and(|arg| arg == "--json");
+    if json {
+        args.remove(0);
+    }
 
+    // A lone `-` means that the body of the command, such as a code block, comes from stdin
     let mut body = String::new();
-    if command.contains(" - ") {
+    if let Some(position) = args.iter().position(|arg| arg == "-") {
+        args.remove(position);
         info!("Reading from stdin...");
-        std::io::stdin().read_to_string(&mut body).unwrap_or_else(|e| {
-            error!("Could not read from stdin: {}", e);
-            std::process::exit(1);
-        });
+        std::io::stdin()
+            .read_to_string(&mut body)
+            .unwrap_or_else(|e| {
+                error!("Could not read from stdin: {}", e);
+                exit(EXIT_UNREACHABLE);
+            });
     }
 
-    let full_command = format!("{}\n{}", command, body);
+    let request = Request {
+        command: shell_words::join(&args),
+        body,
+    };
 
-    stream.write_all(full_command.as_bytes()).unwrap_or_else(|e| {
-        error!("Could not write to admin socket: {}", e);
-        std::process::exit(1);
+    let mut stream = UnixStream::connect(config.global.unix_socket_path).unwrap_or_else(|e| {
+        error!("Could not connect to admin socket: {}", e);
+        exit(EXIT_UNREACHABLE);
     });
-    stream.shutdown(Shutdown::Write).unwrap_or_else(|e| {
-        error!("Could not shutdown admin socket: {}", e);
-        std::process::exit(1);
+    admin_protocol::write_frame(&mut stream, &request).unwrap_or_else(|e| {
+        error!("Could not write to admin socket: {}", e);
+        exit(EXIT_UNREACHABLE);
     });
+    let response: Response = match admin_protocol::read_frame(&mut stream) {
+        Ok(Some(response)) => response,
+        Ok(None) => {
+            error!("Admin socket closed the connection without responding");
+            exit(EXIT_UNREACHABLE);
+        }
+        Err(e) => {
+            error!("Could not read from admin socket: {}", e);
+            exit(EXIT_UNREACHABLE);
+        }
+    };
 
-    let mut response = String::new();
-    stream.read_to_string(&mut response).unwrap_or_else(|e| {
-        error!("Could not read from admin socket: {}", e);
-        std::process::exit(1);
-    });
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string(&response).expect("responses can be serialized")
+        );
+    } else if response.status.exit_code() == 0 {
+        println!("{}", response.text.trim_end());
+    } else {
+        eprintln!("{}", response.text.trim_end());
+    }
 
-    // TODO: Find a better way to parse this
-    let body = response.split("body: \"").collect::<Vec<&str>>()[1].split("\", formatted:").collect::<Vec<&str>>()[0];
-    println!("{}", body.replace("\\n", "\n"));
+    exit(response.status.exit_code());
 }
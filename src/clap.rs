@@ -1,6 +1,6 @@
 //! Integration with `clap`
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 /// Returns the current version of the crate with extra info if supplied
 ///
@@ -19,7 +19,42 @@ fn version() -> String {
 /// Command line arguments
 #[derive(Parser)]
 #[clap(about, version = version())]
-pub struct Args {}
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Commands to run instead of the server
+#[derive(Subcommand)]
+pub enum Command {
+    /// Copy the database to a new database using another backend
+    ///
+    /// The database configured with `database_backend` and `database_path` is copied, so Conduit
+    /// must not be running. An interrupted conversion continues where it stopped when run again.
+    ConvertDatabase {
+        /// The backend to convert to, `sqlite` or `rocksdb`
+        #[arg(long)]
+        to: String,
+
+        /// The directory of the converted database, which must not be `database_path`
+        #[arg(long)]
+        to_path: String,
+
+        /// How many entries are copied at once
+        #[arg(long, default_value_t = 10_000)]
+        chunk_size: usize,
+    },
+
+    /// Check the references between the trees of the database
+    ///
+    /// Conduit must not be running. Exits with status 1 if problems remain. To check the database
+    /// of a running server without repairing it, use the `check-database` admin command.
+    CheckDatabase {
+        /// Repair the problems for which that's safe
+        #[arg(long)]
+        repair: bool,
+    },
+}
 
 /// Parse command line arguments into structured data
 pub fn parse() -> Args {
@@ -0,0 +1,21 @@
+use serde::Deserialize;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct AdminSocketConfig {
+    /// Users, by UID, whose processes may run every command. Root and the user Conduit runs as
+    /// always may
+    #[serde(default)]
+    pub allowed_uids: Vec<u32>,
+    /// Groups, by GID, whose processes may run every command
+    #[serde(default)]
+    pub allowed_gids: Vec<u32>,
+    /// Users, by UID, whose processes may only run commands which show information
+    #[serde(default)]
+    pub read_only_uids: Vec<u32>,
+    /// Groups, by GID, whose processes may only run commands which show information
+    #[serde(default)]
+    pub read_only_gids: Vec<u32>,
+    /// File every command sent to the admin socket is appended to, one JSON object per line.
+    /// Defaults to `admin-audit.log` in `database_path`
+    pub audit_log: Option<String>,
+}
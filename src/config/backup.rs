@@ -0,0 +1,19 @@
+use serde::Deserialize;
+use std::time::Duration;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct BackupConfig {
+    /// Directory backups are created in, each in a directory of its own
+    pub path: Option<String>,
+    /// How often a backup is created automatically, e.g. `"1d"`
+    #[serde(default, with = "humantime_serde::option")]
+    pub interval: Option<Duration>,
+    /// How many backups are kept, older ones being removed after creating a new one. All backups
+    /// are kept if this is 0
+    #[serde(default)]
+    pub keep: usize,
+    /// Whether backups include the media files, linking files which are already in the previous
+    /// backup instead of copying them again
+    #[serde(default)]
+    pub media: bool,
+}
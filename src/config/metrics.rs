@@ -0,0 +1,15 @@
+use serde::Deserialize;
+use std::net::SocketAddr;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct MetricsConfig {
+    /// Whether metrics are served in the Prometheus text format at `/metrics`
+    #[serde(default)]
+    pub enabled: bool,
+    /// Address to serve `/metrics` on instead of the address of the client and federation APIs,
+    /// e.g. `"127.0.0.1:9090"`
+    pub address: Option<SocketAddr>,
+    /// Token scrapers must send as `Authorization: Bearer <token>`. Required to serve `/metrics`
+    /// next to the client and federation APIs.
+    pub bearer_token: Option<String>,
+}
@@ -8,22 +8,44 @@ use std::{
 };
 
 use bytesize::ByteSize;
+use figment::{
+    providers::{Env, Format, Toml},
+    value::{Dict, Uncased, Value},
+    Figment,
+};
 use ruma::{OwnedServerName, RoomVersionId};
 use serde::{de::IgnoredAny, Deserialize};
-use tokio::time::{interval, Interval};
 use tracing::warn;
 use url::Url;
 
 use crate::Error;
 
 mod proxy;
+mod admin_socket;
+mod backup;
+mod email;
 mod ldap;
+mod metrics;
+mod oidc;
+mod profile;
 
 use self::proxy::ProxyConfig;
+pub use self::admin_socket::AdminSocketConfig;
+pub use self::backup::BackupConfig;
+pub use self::email::EmailConfig;
 pub use self::ldap::LdapConfig;
+pub use self::metrics::MetricsConfig;
+pub use self::oidc::OidcConfig;
+pub use self::profile::ProfileConfig;
 
 const SHA256_HEX_LENGTH: u8 = 64;
 
+static SUB_TABLES: [&str; 5] = ["well_known", "tls", "media", "metrics", "admin_socket"]; // Not doing `proxy` cause setting that with env vars would be a pain
+
+// Yeah, I know it's terrible, but since it seems the container users dont want syntax like A[B][C]="...",
+// this is what we have to deal with. Also see: https://github.com/SergioBenitez/Figment/issues/12#issuecomment-801449465
+static SUB_SUB_TABLES: [&str; 2] = ["directory_structure", "retention"];
+
 #[derive(Deserialize)]
 pub struct IncompleteConfig {
     #[serde(default = "default_address")]
@@ -36,6 +58,8 @@ pub struct IncompleteConfig {
     pub database_backend: String,
     pub database_path: String,
     pub unix_socket_path: String,
+    #[serde(default)]
+    pub admin_socket: AdminSocketConfig,
     #[serde(default = "default_db_cache_capacity_mb")]
     pub db_cache_capacity_mb: f64,
     #[serde(default = "true_fn")]
@@ -59,6 +83,8 @@ pub struct IncompleteConfig {
     #[serde(default = "false_fn")]
     pub allow_registration: bool,
     pub registration_token: Option<String>,
+    #[serde(default = "false_fn")]
+    pub allow_guest_access: bool,
     #[serde(default = "default_openid_token_ttl")]
     pub openid_token_ttl: u64,
     #[serde(default = "true_fn")]
@@ -101,6 +127,24 @@ pub struct IncompleteConfig {
     #[serde(default)]
     pub ldap: LdapConfig,
 
+    #[serde(default)]
+    pub oidc: OidcConfig,
+
+    #[serde(default)]
+    pub email: EmailConfig,
+
+    #[serde(default)]
+    pub profile: ProfileConfig,
+
+    #[serde(default)]
+    pub backup: BackupConfig,
+
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+
+    #[serde(default, with = "humantime_serde::option")]
+    pub appservice_unreachable_alert: Option<Duration>,
+
     #[serde(flatten)]
     pub catchall: BTreeMap<String, IgnoredAny>,
 }
@@ -116,6 +160,7 @@ pub struct Config {
     pub database_backend: String,
     pub database_path: String,
     pub unix_socket_path: String,
+    pub admin_socket: AdminSocketConfig,
     pub db_cache_capacity_mb: f64,
     pub enable_lightning_bolt: bool,
     pub allow_check_for_updates: bool,
@@ -128,6 +173,7 @@ pub struct Config {
     pub max_fetch_prev_events: u16,
     pub allow_registration: bool,
     pub registration_token: Option<String>,
+    pub allow_guest_access: bool,
     pub openid_token_ttl: u64,
     pub allow_encryption: bool,
     pub allow_federation: bool,
@@ -150,7 +196,23 @@ pub struct Config {
 
     pub ldap: LdapConfig,
 
+    pub oidc: OidcConfig,
+
+    pub email: EmailConfig,
+
+    pub profile: ProfileConfig,
+
+    pub backup: BackupConfig,
+
+    pub metrics: MetricsConfig,
+
+    pub appservice_unreachable_alert: Option<Duration>,
+
     pub catchall: BTreeMap<String, IgnoredAny>,
+
+    /// The raw values this config was read from, used to find which settings changed when
+    /// reloading
+    pub values: Dict,
 }
 
 impl Default for Config {
@@ -163,6 +225,7 @@ impl Default for Config {
             database_backend: "rocksdb".to_owned(),
             database_path: "/tmp/conduit_db/".to_owned(),
             unix_socket_path: "/tmp/conduit_db/admin.sock".to_owned(),
+            admin_socket: AdminSocketConfig::default(),
             db_cache_capacity_mb: default_db_cache_capacity_mb(),
             enable_lightning_bolt: true,
             allow_check_for_updates: true,
@@ -175,6 +238,7 @@ impl Default for Config {
             max_fetch_prev_events: default_max_fetch_prev_events(),
             allow_registration: false,
             registration_token: None,
+            allow_guest_access: false,
             openid_token_ttl: default_openid_token_ttl(),
             allow_encryption: true,
             allow_federation: false,
@@ -201,10 +265,19 @@ impl Default for Config {
                     scoped: HashMap::new(),
                     global_space: None,
                 },
+                pending_upload_expiry: default_pending_upload_expiry(),
+                max_pending_uploads: default_max_pending_uploads(),
             },
             emergency_password: None,
             ldap: LdapConfig::default(),
+            oidc: OidcConfig::default(),
+            email: EmailConfig::default(),
+            profile: ProfileConfig::default(),
+            backup: BackupConfig::default(),
+            metrics: MetricsConfig::default(),
+            appservice_unreachable_alert: None,
             catchall: BTreeMap::new(),
+            values: Dict::new(),
         }
     }
 }
@@ -219,6 +292,7 @@ impl From<IncompleteConfig> for Config {
             server_name,
             database_backend,
             database_path,
+            admin_socket,
             db_cache_capacity_mb,
             enable_lightning_bolt,
             allow_check_for_updates,
@@ -231,6 +305,7 @@ impl From<IncompleteConfig> for Config {
             max_fetch_prev_events,
             allow_registration,
             registration_token,
+            allow_guest_access,
             openid_token_ttl,
             allow_encryption,
             allow_federation,
@@ -253,6 +328,12 @@ impl From<IncompleteConfig> for Config {
             media,
             emergency_password,
             ldap,
+            oidc,
+            email,
+            profile,
+            backup,
+            metrics,
+            appservice_unreachable_alert,
             catchall,
             ref unix_socket_path,
         } = val;
@@ -314,6 +395,12 @@ impl From<IncompleteConfig> for Config {
                 IncompleteMediaBackendConfig::S3(value) => MediaBackendConfig::S3(value),
             },
             retention: media.retention.into(),
+            pending_upload_expiry: media
+                .pending_upload_expiry
+                .unwrap_or_else(default_pending_upload_expiry),
+            max_pending_uploads: media
+                .max_pending_uploads
+                .unwrap_or_else(default_max_pending_uploads),
         };
 
         let unix_socket_path = if val.unix_socket_path.is_empty() {
@@ -332,6 +419,7 @@ impl From<IncompleteConfig> for Config {
             database_backend,
             database_path,
             unix_socket_path,
+            admin_socket,
             db_cache_capacity_mb,
             enable_lightning_bolt,
             allow_check_for_updates,
@@ -344,6 +432,7 @@ impl From<IncompleteConfig> for Config {
             max_fetch_prev_events,
             allow_registration,
             registration_token,
+            allow_guest_access,
             openid_token_ttl,
             allow_encryption,
             allow_federation,
@@ -361,7 +450,14 @@ impl From<IncompleteConfig> for Config {
             media,
             emergency_password,
             ldap,
+            oidc,
+            email,
+            profile,
+            backup,
+            metrics,
+            appservice_unreachable_alert,
             catchall,
+            values: Dict::new(),
         }
     }
 }
@@ -408,12 +504,19 @@ pub struct IncompleteMediaConfig {
     #[serde(flatten, default)]
     pub backend: IncompleteMediaBackendConfig,
     pub retention: IncompleteMediaRetentionConfig,
+    #[serde(default, with = "humantime_serde::option")]
+    pub pending_upload_expiry: Option<Duration>,
+    pub max_pending_uploads: Option<usize>,
 }
 
 #[derive(Clone, Debug)]
 pub struct MediaConfig {
     pub backend: MediaBackendConfig,
     pub retention: MediaRetentionConfig,
+    /// How long an MXC URI created for an asynchronous upload stays reserved
+    pub pending_upload_expiry: Duration,
+    /// How many reserved MXC URIs a user can have at once
+    pub max_pending_uploads: usize,
 }
 
 type IncompleteMediaRetentionConfig = Option<HashSet<IncompleteScopedMediaRetentionConfig>>;
@@ -425,8 +528,8 @@ pub struct MediaRetentionConfig {
 }
 
 impl MediaRetentionConfig {
-    /// Interval for the duration-based retention policies to be checked & enforced
-    pub fn cleanup_interval(&self) -> Option<Interval> {
+    /// Period for the duration-based retention policies to be checked & enforced
+    pub fn cleanup_period(&self) -> Option<Duration> {
         self.scoped
             .values()
             .filter_map(|scoped| match (scoped.created, scoped.accessed) {
@@ -439,7 +542,6 @@ impl MediaRetentionConfig {
                     .max(Duration::from_secs(60).min(Duration::from_secs(60 * 60 * 24)))
             })
             .min()
-            .map(interval)
     }
 }
 
@@ -698,6 +800,89 @@ const DEPRECATED_KEYS: &[&str] = &[
 ];
 
 impl Config {
+    /// Reads the config from the file `CONDUIT_CONFIG` points to and the `CONDUIT_` environment
+    /// variables
+    pub fn load() -> Result<Self, figment::Error> {
+        let raw_config = Figment::new()
+            .merge(
+                Toml::file(Env::var("CONDUIT_CONFIG").expect(
+                    "The CONDUIT_CONFIG env var needs to be set. Example: /etc/conduit.toml",
+                ))
+                .nested(),
+            )
+            .merge(Env::prefixed("CONDUIT_").global().map(|k| {
+                let mut key: Uncased = k.into();
+
+                'outer: for table in SUB_TABLES {
+                    if k.starts_with(&(table.to_owned() + "_")) {
+                        for sub_table in SUB_SUB_TABLES {
+                            if k.starts_with(&(table.to_owned() + "_" + sub_table + "_")) {
+                                key = Uncased::from(
+                                    table.to_owned()
+                                        + "."
+                                        + sub_table
+                                        + "."
+                                        + k[table.len() + 1 + sub_table.len() + 1..k.len()]
+                                            .as_str(),
+                                );
+
+                                break 'outer;
+                            }
+                        }
+
+                        key = Uncased::from(
+                            table.to_owned() + "." + k[table.len() + 1..k.len()].as_str(),
+                        );
+
+                        break;
+                    }
+                }
+
+                key
+            }));
+
+        let mut config = raw_config.extract::<Config>()?;
+        config.values = raw_config.extract()?;
+
+        Ok(config)
+    }
+
+    /// Names of the settings which differ between the two configs. Changes within the `media`
+    /// table are named by their key in that table, e.g. `media.retention`, as only some of them
+    /// can be applied without restarting.
+    pub fn changed_settings(&self, new: &Config) -> Vec<String> {
+        fn changed_keys<'a>(
+            old: &'a Dict,
+            new: &'a Dict,
+        ) -> impl Iterator<Item = (&'a String, Option<&'a Value>, Option<&'a Value>)> {
+            old.keys()
+                .chain(new.keys().filter(|key| !old.contains_key(*key)))
+                .map(|key| (key, old.get(key), new.get(key)))
+                .filter(|(_, old, new)| old != new)
+        }
+
+        let empty = Dict::new();
+        let mut changed = Vec::new();
+        for (key, old_value, new_value) in changed_keys(&self.values, &new.values) {
+            // Unknown keys are ignored
+            if self.catchall.contains_key(key) || new.catchall.contains_key(key) {
+                continue;
+            }
+
+            if key == "media" {
+                let old_media = old_value.and_then(Value::as_dict).unwrap_or(&empty);
+                let new_media = new_value.and_then(Value::as_dict).unwrap_or(&empty);
+                changed.extend(
+                    changed_keys(old_media, new_media).map(|(key, _, _)| format!("media.{key}")),
+                );
+            } else {
+                changed.push(key.clone());
+            }
+        }
+
+        changed
+    }
+
     pub fn warn_deprecated(&self) {
         let mut was_deprecated = false;
         for key in self
@@ -746,6 +931,7 @@ impl fmt::Display for Config {
                 &self.max_concurrent_requests.to_string(),
             ),
             ("Allow registration", &self.allow_registration.to_string()),
+            ("Allow guest access", &self.allow_guest_access.to_string()),
             (
                 "Enabled lightning bolt",
                 &self.enable_lightning_bolt.to_string(),
@@ -862,6 +1048,14 @@ pub fn default_default_room_version() -> RoomVersionId {
     RoomVersionId::V12
 }
 
+fn default_pending_upload_expiry() -> Duration {
+    Duration::from_secs(60 * 60 * 24)
+}
+
+fn default_max_pending_uploads() -> usize {
+    10
+}
+
 fn default_s3_duration() -> u64 {
     30
 }
\ No newline at end of file
@@ -0,0 +1,57 @@
+use jsonwebtoken::Algorithm;
+use serde::Deserialize;
+use std::collections::HashMap;
+use url::Url;
+
+#[derive(Clone, Debug, Deserialize, Default)]
+pub struct OidcConfig {
+    #[serde(default = "default_oidc_enabled")]
+    pub enabled: bool,
+    /// Issuer URL, used to discover the provider's endpoints
+    pub issuer: Option<Url>,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    #[serde(default = "default_idp_name")]
+    pub idp_name: String,
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<String>,
+    #[serde(default = "default_attribute_mapping")]
+    pub attribute_mapping: HashMap<String, String>,
+    #[serde(default = "default_login_token_ttl")]
+    pub login_token_ttl: u64,
+    /// Clients which may receive login tokens, by origin (e.g. `"https://app.element.io"` or
+    /// `"im.fluffychat://login"`). The origin of the well-known client URL is always allowed
+    #[serde(default)]
+    pub allowed_redirect_origins: Vec<Url>,
+    /// Algorithms ID tokens may be signed with. Defaults to the asymmetric algorithms the
+    /// provider supports; HMAC algorithms are only accepted if listed here
+    pub id_token_signing_algs: Option<Vec<Algorithm>>,
+    /// Whether logging in through the provider may link it to an existing account which wasn't
+    /// created through it, if the localpart matches
+    #[serde(default)]
+    pub link_existing_accounts: bool,
+}
+
+fn default_oidc_enabled() -> bool {
+    false
+}
+
+fn default_idp_name() -> String {
+    "OpenID Connect".to_owned()
+}
+
+fn default_scopes() -> Vec<String> {
+    vec!["openid".to_owned(), "profile".to_owned(), "email".to_owned()]
+}
+
+fn default_attribute_mapping() -> HashMap<String, String> {
+    let mut map = HashMap::new();
+    map.insert("localpart".to_owned(), "preferred_username".to_owned());
+    map.insert("displayname".to_owned(), "name".to_owned());
+    map.insert("email".to_owned(), "email".to_owned());
+    map
+}
+
+fn default_login_token_ttl() -> u64 {
+    2 * 60
+}
@@ -0,0 +1,33 @@
+use serde::Deserialize;
+use std::collections::HashSet;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ProfileConfig {
+    /// Custom profile fields users may set, e.g. `m.tz` or `org.example.job_title`
+    #[serde(default)]
+    pub allowed_fields: HashSet<String>,
+    /// Maximum size of the JSON value of a single custom field, in bytes
+    #[serde(default = "default_max_field_size")]
+    pub max_field_size: usize,
+    /// Maximum size of all custom fields of a user combined, in bytes
+    #[serde(default = "default_max_profile_size")]
+    pub max_profile_size: usize,
+}
+
+impl Default for ProfileConfig {
+    fn default() -> Self {
+        Self {
+            allowed_fields: HashSet::new(),
+            max_field_size: default_max_field_size(),
+            max_profile_size: default_max_profile_size(),
+        }
+    }
+}
+
+fn default_max_field_size() -> usize {
+    1024
+}
+
+fn default_max_profile_size() -> usize {
+    64 * 1024
+}
@@ -1,7 +1,7 @@
 use super::Config;
-use crate::Result;
+use crate::{Error, Result};
 
-use std::{future::Future, pin::Pin, sync::Arc};
+use std::{future::Future, path::Path, pin::Pin, sync::Arc};
 
 #[cfg(feature = "sqlite")]
 pub mod sqlite;
@@ -9,7 +9,8 @@ pub mod sqlite;
 #[cfg(feature = "rocksdb")]
 pub mod rocksdb;
 
-#[cfg(any(feature = "sqlite", feature = "rocksdb"))]
+pub mod memory;
+
 pub mod watchers;
 
 pub trait KeyValueDatabaseEngine: Send + Sync {
@@ -24,9 +25,27 @@ pub trait KeyValueDatabaseEngine: Send + Sync {
     fn memory_usage(&self) -> Result<String> {
         Ok("Current database engine does not support memory usage reporting.".to_owned())
     }
+    /// Numbers describing the state of the engine for metrics, as name, description and value
+    fn statistics(&self) -> Result<Vec<(&'static str, &'static str, u64)>> {
+        Ok(Vec::new())
+    }
+    /// Writes a consistent copy of the database to the given path, which must not exist yet,
+    /// without blocking writes for long. The copy can be used as `database_path` directly.
+    fn backup(&self, _path: &Path) -> Result<()> {
+        Err(Error::BadConfig(
+            "Current database engine does not support backups.",
+        ))
+    }
+
+    /// Applies all writes of the batch at once, so either all or none of them are persisted. The
+    /// trees of the batch must have been opened by this engine.
+    fn write(&self, batch: WriteBatch) -> Result<()>;
 }
 
 pub trait KvTree: Send + Sync {
+    /// The name this tree was opened with
+    fn name(&self) -> &str;
+
     fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
 
     fn insert(&self, key: &[u8], value: &[u8]) -> Result<()>;
@@ -51,6 +70,8 @@ pub trait KvTree: Send + Sync {
     ) -> Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a>;
 
     fn watch_prefix<'a>(&'a self, prefix: &[u8]) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
+    /// Wakes the watchers of the key, after it was inserted by a write batch
+    fn wake(&self, key: &[u8]);
 
     fn clear(&self) -> Result<()> {
         for (key, _) in self.iter() {
@@ -60,3 +81,87 @@ pub trait KvTree: Send + Sync {
         Ok(())
     }
 }
+
+/// Writes to one or more trees, which are applied atomically by
+/// [`KeyValueDatabaseEngine::write`]
+#[derive(Default)]
+pub struct WriteBatch {
+    ops: Vec<(Arc<dyn KvTree>, BatchOp)>,
+}
+
+enum BatchOp {
+    Insert(Vec<u8>, Vec<u8>),
+    Remove(Vec<u8>),
+}
+
+impl WriteBatch {
+    pub fn insert(&mut self, tree: &Arc<dyn KvTree>, key: &[u8], value: &[u8]) {
+        self.ops.push((
+            Arc::clone(tree),
+            BatchOp::Insert(key.to_vec(), value.to_vec()),
+        ));
+    }
+
+    pub fn remove(&mut self, tree: &Arc<dyn KvTree>, key: &[u8]) {
+        self.ops
+            .push((Arc::clone(tree), BatchOp::Remove(key.to_vec())));
+    }
+
+    /// Wakes the watchers of all inserted keys, once the batch was written
+    fn wake(&self) {
+        for (tree, op) in &self.ops {
+            if let BatchOp::Insert(key, _) = op {
+                tree.wake(key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a batch inserting into and removing from two trees, and checks all of it applied
+    fn mixed_batch<E: KeyValueDatabaseEngine>() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.database_path = directory.path().to_str().unwrap().to_owned();
+
+        let engine = E::open(&config).unwrap();
+        let first = engine.open_tree("first").unwrap();
+        let second = engine.open_tree("second").unwrap();
+        first.insert(b"removed", b"old").unwrap();
+        first.insert(b"replaced", b"old").unwrap();
+
+        let mut batch = WriteBatch::default();
+        batch.insert(&first, b"inserted", b"new");
+        batch.insert(&first, b"replaced", b"new");
+        batch.remove(&first, b"removed");
+        batch.insert(&second, b"inserted", b"new");
+        batch.remove(&second, b"missing");
+        engine.write(batch).unwrap();
+
+        assert_eq!(first.get(b"inserted").unwrap(), Some(b"new".to_vec()));
+        assert_eq!(first.get(b"replaced").unwrap(), Some(b"new".to_vec()));
+        assert_eq!(first.get(b"removed").unwrap(), None);
+        assert_eq!(second.get(b"inserted").unwrap(), Some(b"new".to_vec()));
+        assert_eq!(second.get(b"missing").unwrap(), None);
+    }
+
+    #[test]
+    fn memory_mixed_batch() {
+        mixed_batch::<Arc<super::memory::Engine>>();
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite_mixed_batch() {
+        mixed_batch::<Arc<super::sqlite::Engine>>();
+    }
+
+    #[cfg(feature = "rocksdb")]
+    #[test]
+    fn rocksdb_mixed_batch() {
+        mixed_batch::<Arc<super::rocksdb::Engine>>();
+    }
+}
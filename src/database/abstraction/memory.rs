@@ -0,0 +1,281 @@
+use super::{watchers::Watchers, BatchOp, KeyValueDatabaseEngine, KvTree, WriteBatch};
+use crate::{database::Config, utils, Result};
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    ops::Bound,
+    pin::Pin,
+    sync::{Arc, RwLock},
+};
+
+type Map = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// Engine keeping every tree in memory, so all data is lost when the server stops
+pub struct Engine {
+    trees: RwLock<HashMap<&'static str, Arc<MemoryTree>>>,
+}
+
+impl KeyValueDatabaseEngine for Arc<Engine> {
+    fn open(_config: &Config) -> Result<Self> {
+        Ok(Arc::new(Engine {
+            trees: RwLock::new(HashMap::new()),
+        }))
+    }
+
+    fn open_tree(&self, name: &'static str) -> Result<Arc<dyn KvTree>> {
+        let tree = Arc::clone(self.trees.write().unwrap().entry(name).or_insert_with(|| {
+            Arc::new(MemoryTree {
+                name,
+                map: RwLock::new(BTreeMap::new()),
+                watchers: Watchers::default(),
+            })
+        }));
+
+        Ok(tree)
+    }
+
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn memory_usage(&self) -> Result<String> {
+        let (entries, bytes) = self.size();
+
+        Ok(format!(
+            "Trees: {}\nEntries: {entries}\nKeys and values: {:.2} MB\n",
+            self.trees.read().unwrap().len(),
+            bytes as f64 / 1024.0 / 1024.0,
+        ))
+    }
+
+    fn write(&self, batch: WriteBatch) -> Result<()> {
+        let trees = {
+            let opened = self.trees.read().unwrap();
+            batch
+                .ops
+                .iter()
+                .map(|(tree, _)| {
+                    let memory_tree = opened
+                        .get(tree.name())
+                        .expect("trees of write batches are opened by this engine");
+                    (tree.name(), Arc::clone(memory_tree))
+                })
+                .collect::<BTreeMap<_, _>>()
+        };
+
+        // All trees of the batch are locked at once, so no one sees only part of the batch. They
+        // are locked in the order of their names, so concurrent batches can't deadlock.
+        let mut maps = trees
+            .iter()
+            .map(|(name, tree)| (*name, tree.map.write().unwrap()))
+            .collect::<BTreeMap<_, _>>();
+        for (tree, op) in &batch.ops {
+            let map = maps
+                .get_mut(tree.name())
+                .expect("all trees of the batch are locked");
+
+            match op {
+                BatchOp::Insert(key, value) => map.insert(key.clone(), value.clone()),
+                BatchOp::Remove(key) => map.remove(key),
+            };
+        }
+
+        drop(maps);
+
+        batch.wake();
+
+        Ok(())
+    }
+
+    fn statistics(&self) -> Result<Vec<(&'static str, &'static str, u64)>> {
+        let (entries, bytes) = self.size();
+
+        Ok(vec![
+            ("entries", "Number of entries in all trees", entries as u64),
+            (
+                "bytes",
+                "Size of the keys and values of all entries",
+                bytes as u64,
+            ),
+        ])
+    }
+}
+
+impl Engine {
+    /// Number of entries and bytes of their keys and values, over all trees
+    fn size(&self) -> (usize, usize) {
+        self.trees
+            .read()
+            .unwrap()
+            .values()
+            .fold((0, 0), |(entries, bytes), tree| {
+                let map = tree.map.read().unwrap();
+                (
+                    entries + map.len(),
+                    bytes
+                        + map
+                            .iter()
+                            .map(|(key, value)| key.len() + value.len())
+                            .sum::<usize>(),
+                )
+            })
+    }
+}
+
+pub struct MemoryTree {
+    name: &'static str,
+    map: RwLock<Map>,
+    watchers: Watchers,
+}
+
+impl KvTree for MemoryTree {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.map.read().unwrap().get(key).cloned())
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.map
+            .write()
+            .unwrap()
+            .insert(key.to_vec(), value.to_vec());
+
+        self.watchers.wake(key);
+
+        Ok(())
+    }
+
+    fn insert_batch(&self, iter: &mut dyn Iterator<Item = (Vec<u8>, Vec<u8>)>) -> Result<()> {
+        let mut map = self.map.write().unwrap();
+        let mut keys = Vec::new();
+        for (key, value) in iter {
+            keys.push(key.clone());
+            map.insert(key, value);
+        }
+        drop(map);
+
+        for key in keys {
+            self.watchers.wake(&key);
+        }
+
+        Ok(())
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<()> {
+        self.map.write().unwrap().remove(key);
+
+        self.watchers.wake(key);
+
+        Ok(())
+    }
+
+    fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a> {
+        Box::new(MemoryIter {
+            map: &self.map,
+            bound: Bound::Unbounded,
+            backwards: false,
+        })
+    }
+
+    fn iter_from<'a>(
+        &'a self,
+        from: &[u8],
+        backwards: bool,
+    ) -> Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a> {
+        Box::new(MemoryIter {
+            map: &self.map,
+            bound: Bound::Included(from.to_vec()),
+            backwards,
+        })
+    }
+
+    fn increment(&self, key: &[u8]) -> Result<Vec<u8>> {
+        let mut map = self.map.write().unwrap();
+
+        let new = utils::increment(map.get(key).map(Vec::as_slice))
+            .expect("utils::increment always returns Some");
+        map.insert(key.to_vec(), new.clone());
+        drop(map);
+
+        self.watchers.wake(key);
+
+        Ok(new)
+    }
+
+    fn increment_batch(&self, iter: &mut dyn Iterator<Item = Vec<u8>>) -> Result<()> {
+        let mut map = self.map.write().unwrap();
+        let mut keys = Vec::new();
+
+        for key in iter {
+            let new = utils::increment(map.get(&key).map(Vec::as_slice))
+                .expect("utils::increment always returns Some");
+            keys.push(key.clone());
+            map.insert(key, new);
+        }
+        drop(map);
+
+        for key in keys {
+            self.watchers.wake(&key);
+        }
+
+        Ok(())
+    }
+
+    fn scan_prefix<'a>(
+        &'a self,
+        prefix: Vec<u8>,
+    ) -> Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a> {
+        Box::new(
+            self.iter_from(&prefix, false)
+                .take_while(move |(key, _)| key.starts_with(&prefix)),
+        )
+    }
+
+    fn watch_prefix<'a>(&'a self, prefix: &[u8]) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
+        self.watchers.watch(prefix)
+    }
+
+    fn wake(&self, key: &[u8]) {
+        self.watchers.wake(key);
+    }
+
+    fn clear(&self) -> Result<()> {
+        self.map.write().unwrap().clear();
+
+        Ok(())
+    }
+}
+
+/// Iterates over a tree without holding its lock between items, so the tree can be written to
+/// while iterating, like with the other engines.
+struct MemoryIter<'a> {
+    map: &'a RwLock<Map>,
+    /// Where the next item is searched from, excluding the key returned last
+    bound: Bound<Vec<u8>>,
+    backwards: bool,
+}
+
+impl Iterator for MemoryIter<'_> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let map = self.map.read().unwrap();
+        let bound = self.bound.as_ref().map(Vec::as_slice);
+
+        let (key, value) = if self.backwards {
+            map.range::<[u8], _>((Bound::Unbounded, bound)).next_back()
+        } else {
+            map.range::<[u8], _>((bound, Bound::Unbounded)).next()
+        }?;
+
+        let item = (key.clone(), value.clone());
+        drop(map);
+
+        self.bound = Bound::Excluded(item.0.clone());
+
+        Some(item)
+    }
+}
@@ -1,7 +1,10 @@
-use super::{super::Config, watchers::Watchers, KeyValueDatabaseEngine, KvTree};
+use super::{
+    super::Config, watchers::Watchers, BatchOp, KeyValueDatabaseEngine, KvTree, WriteBatch,
+};
 use crate::{utils, Result};
 use std::{
     future::Future,
+    path::Path,
     pin::Pin,
     sync::{Arc, RwLock},
 };
@@ -126,6 +129,67 @@ impl KeyValueDatabaseEngine for Arc<Engine> {
             self.cache.get_pinned_usage() as f64 / 1024.0 / 1024.0,
         ))
     }
+
+    fn statistics(&self) -> Result<Vec<(&'static str, &'static str, u64)>> {
+        let stats =
+            rocksdb::perf::get_memory_usage_stats(Some(&[&self.rocks]), Some(&[&self.cache]))?;
+
+        Ok(vec![
+            (
+                "mem_table_bytes",
+                "Approximate memory usage of all the mem-tables",
+                stats.mem_table_total,
+            ),
+            (
+                "mem_table_unflushed_bytes",
+                "Approximate memory usage of un-flushed mem-tables",
+                stats.mem_table_unflushed,
+            ),
+            (
+                "table_readers_bytes",
+                "Approximate memory usage of all the table readers",
+                stats.mem_table_readers_total,
+            ),
+            (
+                "cache_bytes",
+                "Approximate memory usage by cache",
+                stats.cache_total,
+            ),
+            (
+                "cache_pinned_bytes",
+                "Approximate memory usage by cache pinned",
+                self.cache.get_pinned_usage() as u64,
+            ),
+        ])
+    }
+
+    fn backup(&self, path: &Path) -> Result<()> {
+        // Checkpoints hard link the immutable SST files, so they are cheap if on the same
+        // filesystem
+        rocksdb::checkpoint::Checkpoint::new(&self.rocks)?.create_checkpoint(path)?;
+
+        Ok(())
+    }
+
+    fn write(&self, batch: WriteBatch) -> Result<()> {
+        let mut rocks_batch = rocksdb::WriteBatch::default();
+        for (tree, op) in &batch.ops {
+            let cf = self
+                .rocks
+                .cf_handle(tree.name())
+                .expect("trees of write batches are opened by this engine");
+
+            match op {
+                BatchOp::Insert(key, value) => rocks_batch.put_cf(&cf, key, value),
+                BatchOp::Remove(key) => rocks_batch.delete_cf(&cf, key),
+            }
+        }
+        self.rocks.write(rocks_batch)?;
+
+        batch.wake();
+
+        Ok(())
+    }
 }
 
 impl RocksDbEngineTree<'_> {
@@ -135,6 +199,10 @@ impl RocksDbEngineTree<'_> {
 }
 
 impl KvTree for RocksDbEngineTree<'_> {
+    fn name(&self) -> &str {
+        self.name
+    }
+
     fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         let readoptions = rocksdb::ReadOptions::default();
 
@@ -270,4 +338,8 @@ impl KvTree for RocksDbEngineTree<'_> {
     fn watch_prefix<'a>(&'a self, prefix: &[u8]) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
         self.watchers.watch(prefix)
     }
+
+    fn wake(&self, key: &[u8]) {
+        self.watchers.wake(key);
+    }
 }
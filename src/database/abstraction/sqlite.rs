@@ -1,9 +1,10 @@
-use super::{watchers::Watchers, KeyValueDatabaseEngine, KvTree};
+use super::{watchers::Watchers, BatchOp, KeyValueDatabaseEngine, KvTree, WriteBatch};
 use crate::{database::Config, Result};
 use parking_lot::{Mutex, MutexGuard};
 use rusqlite::{Connection, DatabaseName::Main, OptionalExtension};
 use std::{
     cell::RefCell,
+    fs,
     future::Future,
     path::{Path, PathBuf},
     pin::Pin,
@@ -123,6 +124,63 @@ impl KeyValueDatabaseEngine for Arc<Engine> {
     fn cleanup(&self) -> Result<()> {
         self.flush_wal()
     }
+
+    fn statistics(&self) -> Result<Vec<(&'static str, &'static str, u64)>> {
+        let conn = self.read_lock();
+        let pragma = |name| conn.pragma_query_value(Some(Main), name, |row| row.get::<_, u64>(0));
+
+        let page_size = pragma("page_size")?;
+
+        Ok(vec![
+            (
+                "size_bytes",
+                "Size of the database file",
+                pragma("page_count")? * page_size,
+            ),
+            (
+                "free_bytes",
+                "Unused space in the database file, which is reused before the file grows",
+                pragma("freelist_count")? * page_size,
+            ),
+        ])
+    }
+
+    fn backup(&self, path: &Path) -> Result<()> {
+        fs::create_dir_all(path)?;
+
+        // Runs in a read transaction, so writers aren't blocked thanks to WAL
+        self.read_lock()
+            .execute("VACUUM INTO ?", [path.join("conduit.db").to_string_lossy()])?;
+
+        Ok(())
+    }
+
+    fn write(&self, batch: WriteBatch) -> Result<()> {
+        let guard = self.write_lock();
+
+        // Rolled back when dropped without committing, e.g. if a statement fails
+        let transaction = guard.unchecked_transaction()?;
+        for (tree, op) in &batch.ops {
+            match op {
+                BatchOp::Insert(key, value) => transaction.execute(
+                    &format!(
+                        "INSERT OR REPLACE INTO {} (key, value) VALUES (?, ?)",
+                        tree.name()
+                    ),
+                    [key, value],
+                )?,
+                BatchOp::Remove(key) => transaction
+                    .execute(&format!("DELETE FROM {} WHERE key = ?", tree.name()), [key])?,
+            };
+        }
+        transaction.commit()?;
+
+        drop(guard);
+
+        batch.wake();
+
+        Ok(())
+    }
 }
 
 pub struct SqliteTable {
@@ -185,6 +243,10 @@ impl SqliteTable {
 }
 
 impl KvTree for SqliteTable {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
     fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         self.get_with_guard(self.engine.read_lock(), key)
     }
@@ -327,6 +389,10 @@ impl KvTree for SqliteTable {
         self.watchers.watch(prefix)
     }
 
+    fn wake(&self, key: &[u8]) {
+        self.watchers.wake(key);
+    }
+
     fn clear(&self) -> Result<()> {
         debug!("clear: running");
         self.engine
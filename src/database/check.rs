@@ -0,0 +1,394 @@
+//! Checking the references between trees, which migrations and writes that were interrupted can
+//! leave dangling
+
+use std::{
+    collections::BTreeMap,
+    mem::size_of,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use super::{
+    abstraction::{KeyValueDatabaseEngine, WriteBatch},
+    KeyValueDatabase, DATABASE_VERSION,
+};
+use crate::{
+    config::MediaBackendConfig,
+    service::globals::{split_media_path, CheckOutcome, DatabaseCheck},
+    utils, Config, Error, Result,
+};
+
+/// Number of problems listed for every check, the others are only counted
+const MAX_EXAMPLES: usize = 10;
+
+/// Runs every check on the trees of the engine, repairing the problems for which that's safe if
+/// `repair` is set.
+///
+/// Repairs only remove entries pointing to data that doesn't exist, or add and correct entries
+/// which can be derived from other trees. Problems which would need guessing what the correct
+/// data is are only reported.
+pub(super) fn run(
+    engine: &dyn KeyValueDatabaseEngine,
+    config: &Config,
+    repair: bool,
+) -> Result<DatabaseCheck> {
+    let mut checker = Checker {
+        engine,
+        repair,
+        result: DatabaseCheck::default(),
+    };
+
+    checker.check_pdus()?;
+    checker.check_short_event_ids()?;
+    checker.check_memberships()?;
+    checker.check_search_tokens()?;
+    checker.check_media(config)?;
+
+    Ok(checker.result)
+}
+
+impl KeyValueDatabase {
+    /// Checks the database in the config while Conduit isn't running, see `check-database`.
+    pub fn check(config: &Config, repair: bool) -> Result<DatabaseCheck> {
+        if !["sqlite", "rocksdb"].contains(&&*config.database_backend) {
+            return Err(Error::BadConfig(
+                "Only sqlite and rocksdb databases can be checked.",
+            ));
+        }
+
+        Self::check_db_setup(config)?;
+
+        let path = Path::new(&config.database_path);
+        if !path.join("conduit.db").exists() && !path.join("IDENTITY").exists() {
+            return Err(Error::BadConfig("There is no database at database_path."));
+        }
+
+        let engine = Self::open_engine(config)?;
+
+        // The checks expect the trees as they are after the last migration
+        let version = engine
+            .open_tree("global")?
+            .get(b"version")?
+            .and_then(|version| utils::u64_from_bytes(&version).ok());
+        if version != Some(DATABASE_VERSION) {
+            return Err(Error::BadConfig(
+                "The database needs to be migrated by starting Conduit before it can be checked.",
+            ));
+        }
+
+        let check = run(&*engine, config, repair)?;
+        engine.flush()?;
+
+        Ok(check)
+    }
+}
+
+struct Checker<'a> {
+    engine: &'a dyn KeyValueDatabaseEngine,
+    repair: bool,
+    result: DatabaseCheck,
+}
+
+/// Problems found by a check, and the writes repairing them
+struct Problems {
+    outcome: CheckOutcome,
+    repairable: usize,
+    batch: WriteBatch,
+}
+
+impl Problems {
+    fn new(description: &'static str) -> Self {
+        Self {
+            outcome: CheckOutcome {
+                description,
+                problems: 0,
+                repaired: 0,
+                examples: Vec::new(),
+            },
+            repairable: 0,
+            batch: WriteBatch::default(),
+        }
+    }
+
+    /// Records a problem which can't be repaired safely
+    fn report(&mut self, problem: String) {
+        self.outcome.problems += 1;
+        if self.outcome.examples.len() < MAX_EXAMPLES {
+            self.outcome.examples.push(problem);
+        }
+    }
+
+    /// Records a problem which is repaired by the writes added by `repair`
+    fn repairable(&mut self, problem: String, repair: impl FnOnce(&mut WriteBatch)) {
+        self.report(problem);
+        self.repairable += 1;
+        repair(&mut self.batch);
+    }
+}
+
+impl Checker<'_> {
+    /// Adds the outcome of a check, writing its repairs if repairing
+    fn finish(&mut self, problems: Problems) -> Result<()> {
+        let Problems {
+            mut outcome,
+            repairable,
+            batch,
+        } = problems;
+
+        if self.repair && repairable > 0 {
+            self.engine.write(batch)?;
+            outcome.repaired = repairable;
+        }
+
+        self.result.checks.push(outcome);
+
+        Ok(())
+    }
+
+    fn check_pdus(&mut self) -> Result<()> {
+        #[derive(Deserialize)]
+        struct ExtractEventId {
+            event_id: String,
+        }
+
+        let eventid_pduid = self.engine.open_tree("eventid_pduid")?;
+        let pduid_pdu = self.engine.open_tree("pduid_pdu")?;
+
+        let mut problems = Problems::new("Event ids point to existing PDUs");
+        for (event_id, pdu_id) in eventid_pduid.iter() {
+            if pduid_pdu.get(&pdu_id)?.is_none() {
+                problems.repairable(
+                    format!(
+                        "{} points to missing PDU {}",
+                        String::from_utf8_lossy(&event_id),
+                        hex::encode(pdu_id)
+                    ),
+                    |batch| batch.remove(&eventid_pduid, &event_id),
+                );
+            }
+        }
+        self.finish(problems)?;
+
+        let mut problems = Problems::new("PDUs can be found by their event id");
+        for (pdu_id, pdu) in pduid_pdu.iter() {
+            let Ok(ExtractEventId { event_id }) = serde_json::from_slice(&pdu) else {
+                problems.report(format!("PDU {} is invalid", hex::encode(&pdu_id)));
+                continue;
+            };
+
+            match eventid_pduid.get(event_id.as_bytes())? {
+                Some(other_pdu_id) if other_pdu_id == pdu_id => {}
+                Some(other_pdu_id) => problems.report(format!(
+                    "{event_id} of PDU {} points to PDU {}",
+                    hex::encode(&pdu_id),
+                    hex::encode(other_pdu_id)
+                )),
+                None => problems.repairable(
+                    format!(
+                        "{event_id} of PDU {} doesn't point to it",
+                        hex::encode(&pdu_id)
+                    ),
+                    |batch| batch.insert(&eventid_pduid, event_id.as_bytes(), &pdu_id),
+                ),
+            }
+        }
+        self.finish(problems)
+    }
+
+    fn check_short_event_ids(&mut self) -> Result<()> {
+        let shorteventid_eventid = self.engine.open_tree("shorteventid_eventid")?;
+        let eventid_shorteventid = self.engine.open_tree("eventid_shorteventid")?;
+        let eventid_pduid = self.engine.open_tree("eventid_pduid")?;
+        let eventid_outlierpdu = self.engine.open_tree("eventid_outlierpdu")?;
+
+        let mut mapped = Problems::new("Short event ids map back to their event ids");
+        let mut without_pdu = Problems::new("Events with a short event id have a PDU");
+        for (shorteventid, event_id) in shorteventid_eventid.iter() {
+            let event = String::from_utf8_lossy(&event_id);
+
+            match eventid_shorteventid.get(&event_id)? {
+                Some(other) if other == shorteventid => {}
+                Some(other) => mapped.report(format!(
+                    "{event} has short event id {}, but maps to {}",
+                    hex::encode(&shorteventid),
+                    hex::encode(other)
+                )),
+                None => mapped.repairable(
+                    format!(
+                        "{event} has short event id {}, but doesn't map to it",
+                        hex::encode(&shorteventid)
+                    ),
+                    |batch| batch.insert(&eventid_shorteventid, &event_id, &shorteventid),
+                ),
+            }
+
+            // Room states reference events by their short event id, so it can't be removed
+            if eventid_pduid.get(&event_id)?.is_none()
+                && eventid_outlierpdu.get(&event_id)?.is_none()
+            {
+                without_pdu.report(format!("{event} has a short event id, but no PDU"));
+            }
+        }
+        self.finish(mapped)?;
+        self.finish(without_pdu)
+    }
+
+    fn check_memberships(&mut self) -> Result<()> {
+        let userroomid_joined = self.engine.open_tree("userroomid_joined")?;
+        let roomuserid_joined = self.engine.open_tree("roomuserid_joined")?;
+        let roomuserid_invitecount = self.engine.open_tree("roomuserid_invitecount")?;
+        let roomid_joinedcount = self.engine.open_tree("roomid_joinedcount")?;
+        let roomid_invitedcount = self.engine.open_tree("roomid_invitedcount")?;
+
+        // Which of the two is right can only be told from the room state
+        let mut problems = Problems::new("Joined members are stored for the user and the room");
+        for (key, _) in userroomid_joined.iter() {
+            if roomuserid_joined.get(&swap_parts(&key))?.is_none() {
+                let (user_id, room_id) = parts(&key);
+                problems.report(format!(
+                    "{} is only stored as joined to {} for the user",
+                    String::from_utf8_lossy(user_id),
+                    String::from_utf8_lossy(room_id)
+                ));
+            }
+        }
+        for (key, _) in roomuserid_joined.iter() {
+            if userroomid_joined.get(&swap_parts(&key))?.is_none() {
+                let (room_id, user_id) = parts(&key);
+                problems.report(format!(
+                    "{} is only stored as joined to {} for the room",
+                    String::from_utf8_lossy(user_id),
+                    String::from_utf8_lossy(room_id)
+                ));
+            }
+        }
+        self.finish(problems)?;
+
+        // Joined and invited members of every room, by room id
+        let mut members = BTreeMap::<Vec<u8>, (u64, u64)>::new();
+        for (key, _) in roomuserid_joined.iter() {
+            members.entry(parts(&key).0.to_vec()).or_default().0 += 1;
+        }
+        for (key, _) in roomuserid_invitecount.iter() {
+            members.entry(parts(&key).0.to_vec()).or_default().1 += 1;
+        }
+        for (room_id, _) in roomid_joinedcount.iter().chain(roomid_invitedcount.iter()) {
+            members.entry(room_id).or_default();
+        }
+
+        let mut problems = Problems::new("Joined and invited counts match the room members");
+        for (room_id, (joined, invited)) in members {
+            for (tree, count, kind) in [
+                (&roomid_joinedcount, joined, "joined"),
+                (&roomid_invitedcount, invited, "invited"),
+            ] {
+                let stored = tree
+                    .get(&room_id)?
+                    .and_then(|stored| utils::u64_from_bytes(&stored).ok());
+
+                if stored != Some(count) {
+                    problems.repairable(
+                        format!(
+                            "{} has a {kind} count of {}, but {count} {kind} members",
+                            String::from_utf8_lossy(&room_id),
+                            stored.map_or_else(|| "none".to_owned(), |stored| stored.to_string())
+                        ),
+                        |batch| batch.insert(tree, &room_id, &count.to_be_bytes()),
+                    );
+                }
+            }
+        }
+        self.finish(problems)
+    }
+
+    fn check_search_tokens(&mut self) -> Result<()> {
+        let tokenids = self.engine.open_tree("tokenids")?;
+        let pduid_pdu = self.engine.open_tree("pduid_pdu")?;
+
+        let mut problems = Problems::new("Search tokens point to existing PDUs");
+        for (key, _) in tokenids.iter() {
+            // Keys are the short room id, the word, 0xff and the pdu id. The short room id may
+            // contain 0xff, but the word can't.
+            let pdu_id = key
+                .get(size_of::<u64>()..)
+                .and_then(|rest| rest.iter().position(|&b| b == 0xff).map(|i| &rest[i + 1..]));
+
+            match pdu_id {
+                Some(pdu_id) if pduid_pdu.get(pdu_id)?.is_some() => {}
+                Some(pdu_id) => problems.repairable(
+                    format!("Search token for missing PDU {}", hex::encode(pdu_id)),
+                    |batch| batch.remove(&tokenids, &key),
+                ),
+                None => problems.repairable(
+                    format!("Search token {} is invalid", hex::encode(&key)),
+                    |batch| batch.remove(&tokenids, &key),
+                ),
+            }
+        }
+        self.finish(problems)
+    }
+
+    fn check_media(&mut self, config: &Config) -> Result<()> {
+        let MediaBackendConfig::FileSystem {
+            path,
+            directory_structure,
+        } = &config.media.backend
+        else {
+            self.result
+                .skipped
+                .push("Media files exist, as media isn't stored on the filesystem");
+            return Ok(());
+        };
+
+        let filehash_metadata = self.engine.open_tree("filehash_metadata")?;
+
+        let mut problems = Problems::new("Media files exist");
+        for (sha256_digest, _) in filehash_metadata.iter() {
+            if sha256_digest.len() != 32 {
+                problems.report(format!(
+                    "Media file hash {} is invalid",
+                    hex::encode(&sha256_digest)
+                ));
+                continue;
+            }
+
+            let sha256_hex = hex::encode(&sha256_digest);
+            let file = PathBuf::from_iter(split_media_path(
+                Some(path),
+                directory_structure,
+                &sha256_hex,
+            ));
+
+            // Media without metadata for its file hash is treated as missing, so remote media is
+            // fetched again when requested
+            if !file.exists() {
+                problems.repairable(
+                    format!("Media file with sha256 hash {sha256_hex} is missing"),
+                    |batch| batch.remove(&filehash_metadata, &sha256_digest),
+                );
+            }
+        }
+        self.finish(problems)
+    }
+}
+
+/// Splits a key of the form `a 0xff b` into `a` and `b`
+fn parts(key: &[u8]) -> (&[u8], &[u8]) {
+    let mut parts = key.splitn(2, |&b| b == 0xff);
+    (
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or_default(),
+    )
+}
+
+/// Turns `a 0xff b` into `b 0xff a`, to find the entry of the other tree of a pair like
+/// `userroomid_joined` and `roomuserid_joined`
+fn swap_parts(key: &[u8]) -> Vec<u8> {
+    let (first, second) = parts(key);
+
+    let mut swapped = second.to_vec();
+    swapped.push(0xff);
+    swapped.extend_from_slice(first);
+    swapped
+}
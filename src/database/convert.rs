@@ -0,0 +1,378 @@
+//! Offline conversion of a database to another backend
+
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+use super::{abstraction::KvTree, KeyValueDatabase};
+use crate::{Config, Error, Result};
+
+/// Every tree opened by `KeyValueDatabase::load_or_create`, including those only still opened by
+/// migrations
+const TREES: &[&str] = &[
+    "userid_password",
+    "userid_displayname",
+    "userid_avatarurl",
+    "userid_blurhash",
+    "userid_email",
+    "userid_oidcsubject",
+    "userid_guest",
+    "useridprofilefield_value",
+    "threepid_userid",
+    "userthreepid_addedat",
+    "userdeviceid_token",
+    "userdeviceid_metadata",
+    "userid_devicelistversion",
+    "token_userdeviceid",
+    "onetimekeyid_onetimekeys",
+    "userid_lastonetimekeyupdate",
+    "keychangeid_userid",
+    "keyid_key",
+    "userid_masterkeyid",
+    "userid_selfsigningkeyid",
+    "userid_usersigningkeyid",
+    "openidtoken_expiresatuserid",
+    "userfilterid_filter",
+    "todeviceid_events",
+    "userdevicesessionid_uiaainfo",
+    "readreceiptid_readreceipt",
+    "roomuserid_privateread",
+    "roomuserid_lastprivatereadupdate",
+    "presenceid_presence",
+    "userid_lastpresenceupdate",
+    "pduid_pdu",
+    "eventid_pduid",
+    "roomid_pduleaves",
+    "alias_roomid",
+    "aliasid_alias",
+    "publicroomids",
+    "appservicenetworkroomids",
+    "threadid_userids",
+    "tokenids",
+    "roomserverids",
+    "serverroomids",
+    "userroomid_joined",
+    "roomuserid_joined",
+    "roomid_joinedcount",
+    "roomid_invitedcount",
+    "roomuseroncejoinedids",
+    "userroomid_invitestate",
+    "roomuserid_invitecount",
+    "userroomid_knockstate",
+    "roomuserid_knockcount",
+    "userroomid_leftstate",
+    "roomuserid_leftcount",
+    "alias_userid",
+    "disabledroomids",
+    "lazyloadedids",
+    "userroomid_notificationcount",
+    "userroomid_highlightcount",
+    "statekey_shortstatekey",
+    "shortstatekey_statekey",
+    "shorteventid_authchain",
+    "roomid_shortroomid",
+    "shortstatehash_statediff",
+    "eventid_shorteventid",
+    "shorteventid_eventid",
+    "shorteventid_shortstatehash",
+    "roomid_shortstatehash",
+    "roomsynctoken_shortstatehash",
+    "statehash_shortstatehash",
+    "eventid_outlierpdu",
+    "softfailedeventids",
+    "tofrom_relation",
+    "referencedevents",
+    "roomuserdataid_accountdata",
+    "roomusertype_roomuserdataid",
+    "servernamemediaid_metadata",
+    "filehash_servername_mediaid",
+    "filehash_metadata",
+    "blocked_servername_mediaid",
+    "servername_userlocalpart_mediaid",
+    "servernamemediaid_userlocalpart",
+    "thumbnailid_metadata",
+    "filehash_thumbnailid",
+    "servernamemediaid_pending",
+    "servernamemediaid_roomideventid",
+    "roomid_servernamemediaid",
+    "backupid_algorithm",
+    "backupid_etag",
+    "backupkeyid_backup",
+    "userdevicetxnid_response",
+    "servername_educount",
+    "servernameevent_data",
+    "servercurrentevent_data",
+    "id_appserviceregistrations",
+    "senderkey_pusher",
+    "global",
+    "server_signingkeys",
+    "mediaid_file",
+    "stateid_shorteventid",
+    "userdevicesessionid_uiaarequest",
+];
+
+/// Name of the file in the destination directory which records how far the conversion got
+const PROGRESS_LOG: &str = "conversion-progress.log";
+
+/// Number of entries and checksum of a tree, or of the part of it which was copied so far
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+struct Summary {
+    count: u64,
+    /// XOR of the SHA-256 hashes of all entries, so it doesn't depend on the order the entries
+    /// are added in and can be continued after resuming
+    checksum: [u8; 32],
+}
+
+impl Summary {
+    fn add(&mut self, key: &[u8], value: &[u8]) {
+        let mut hasher = Sha256::new();
+        hasher.update((key.len() as u64).to_be_bytes());
+        hasher.update(key);
+        hasher.update(value);
+
+        for (byte, hash_byte) in self.checksum.iter_mut().zip(hasher.finalize()) {
+            *byte ^= hash_byte;
+        }
+        self.count += 1;
+    }
+
+    fn of(tree: &dyn KvTree) -> Self {
+        let mut summary = Self::default();
+        for (key, value) in tree.iter() {
+            summary.add(&key, &value);
+        }
+        summary
+    }
+}
+
+/// How far a tree was converted, as recorded in the progress log
+#[derive(Default)]
+struct Progress {
+    copied: Summary,
+    /// The last key that was copied, which the conversion continues after
+    last_key: Option<Vec<u8>>,
+    done: bool,
+}
+
+/// Appends to and reads the progress log, whose lines are either
+/// `<tree> copied <count> <checksum> <last key>` after a chunk was copied, or
+/// `<tree> done <count> <checksum>` once the tree was verified. Binary values are hex encoded.
+struct ProgressLog {
+    path: PathBuf,
+    file: File,
+}
+
+impl ProgressLog {
+    fn open(path: PathBuf) -> Result<(Self, HashMap<String, Progress>)> {
+        let content = if path.exists() {
+            fs::read_to_string(&path)?
+        } else {
+            String::new()
+        };
+
+        // A line without a newline was cut off while writing it, so it's removed
+        let complete = content.rfind('\n').map_or(0, |i| i + 1);
+
+        let mut progress = HashMap::new();
+        for line in content[..complete].lines() {
+            let (tree, entry) = Self::parse_line(line)
+                .ok_or_else(|| Error::bad_database("Conversion progress log is invalid."))?;
+            progress.insert(tree, entry);
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        file.set_len(complete as u64)?;
+
+        Ok((Self { path, file }, progress))
+    }
+
+    fn parse_line(line: &str) -> Option<(String, Progress)> {
+        let mut parts = line.split(' ');
+
+        let tree = parts.next()?.to_owned();
+        let done = match parts.next()? {
+            "copied" => false,
+            "done" => true,
+            _ => return None,
+        };
+        let count = parts.next()?.parse().ok()?;
+        let checksum = hex::decode(parts.next()?).ok()?.try_into().ok()?;
+        let last_key = if done {
+            None
+        } else {
+            Some(hex::decode(parts.next()?).ok()?)
+        };
+
+        Some((
+            tree,
+            Progress {
+                copied: Summary { count, checksum },
+                last_key,
+                done,
+            },
+        ))
+    }
+
+    fn write(&mut self, line: String) -> Result<()> {
+        self.file.write_all(format!("{line}\n").as_bytes())?;
+        self.file.sync_data()?;
+
+        Ok(())
+    }
+
+    fn copied(&mut self, tree: &str, copied: &Summary, last_key: &[u8]) -> Result<()> {
+        self.write(format!(
+            "{tree} copied {} {} {}",
+            copied.count,
+            hex::encode(copied.checksum),
+            hex::encode(last_key)
+        ))
+    }
+
+    fn done(&mut self, tree: &str, copied: &Summary) -> Result<()> {
+        self.write(format!(
+            "{tree} done {} {}",
+            copied.count,
+            hex::encode(copied.checksum)
+        ))
+    }
+}
+
+impl KeyValueDatabase {
+    /// Copies the database in the config to a new database using another backend.
+    ///
+    /// Conduit must not be running while converting. Every tree is copied in chunks of
+    /// `chunk_size` entries, after which the number of entries and checksum of the copy are
+    /// compared to those of the original. Progress is recorded in the destination directory, so
+    /// running the conversion again after it was interrupted continues where it stopped.
+    pub fn convert(
+        config: &Config,
+        to_backend: &str,
+        to_path: &str,
+        chunk_size: usize,
+    ) -> Result<()> {
+        for backend in [&*config.database_backend, to_backend] {
+            if !["sqlite", "rocksdb"].contains(&backend) {
+                return Err(Error::BadConfig(
+                    "Only sqlite and rocksdb databases can be converted.",
+                ));
+            }
+        }
+
+        if config.database_backend == to_backend {
+            return Err(Error::BadConfig("The database already uses this backend."));
+        }
+
+        if chunk_size == 0 {
+            return Err(Error::BadConfig("Chunk size must be at least 1."));
+        }
+
+        let to_dir = Path::new(to_path);
+        if to_dir.exists() && fs::canonicalize(to_dir)? == fs::canonicalize(&config.database_path)?
+        {
+            return Err(Error::BadConfig(
+                "The converted database needs to be in another directory.",
+            ));
+        }
+
+        let progress_path = to_dir.join(PROGRESS_LOG);
+        if !progress_path.exists()
+            && (to_dir.join("conduit.db").exists() || to_dir.join("IDENTITY").exists())
+        {
+            return Err(Error::BadConfig(
+                "There already is a database in the directory of the converted database.",
+            ));
+        }
+
+        fs::create_dir_all(to_dir)?;
+        let (mut log, mut progress) = ProgressLog::open(progress_path)?;
+
+        let mut to_config = config.clone();
+        to_config.database_backend = to_backend.to_owned();
+        to_config.database_path = to_path.to_owned();
+
+        let from = Self::open_engine(config)?;
+        let to = Self::open_engine(&to_config)?;
+
+        println!(
+            "Converting {} database at {} to {to_backend} database at {to_path}",
+            config.database_backend, config.database_path
+        );
+
+        for (i, &name) in TREES.iter().enumerate() {
+            let progress = progress.remove(name).unwrap_or_default();
+            let position = format!("[{}/{}] {name}", i + 1, TREES.len());
+
+            if progress.done {
+                println!("{position}: already converted");
+                continue;
+            }
+
+            let from_tree = from.open_tree(name)?;
+            let to_tree = to.open_tree(name)?;
+
+            let mut copied = progress.copied;
+            let mut entries: Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + '_> =
+                match progress.last_key {
+                    Some(last_key) => Box::new(
+                        from_tree
+                            .iter_from(&last_key, false)
+                            .skip_while(move |(key, _)| *key == last_key),
+                    ),
+                    None => from_tree.iter(),
+                };
+
+            loop {
+                let chunk: Vec<_> = entries.by_ref().take(chunk_size).collect();
+                let Some((last_key, _)) = chunk.last() else {
+                    break;
+                };
+                let last_key = last_key.clone();
+
+                for (key, value) in &chunk {
+                    copied.add(key, value);
+                }
+
+                to_tree.insert_batch(&mut chunk.into_iter())?;
+                to.flush()?;
+                log.copied(name, &copied, &last_key)?;
+
+                println!("{position}: copied {} entries", copied.count);
+            }
+
+            drop(entries);
+
+            // Compare both trees entirely, in case they were changed while not converting
+            let original = Summary::of(&*from_tree);
+            let converted = Summary::of(&*to_tree);
+            if original != converted || copied != original {
+                println!(
+                    "{position}: verification failed, the original has {} entries and the converted database {}",
+                    original.count, converted.count
+                );
+                return Err(Error::bad_database(
+                    "Converted tree differs from the original.",
+                ));
+            }
+
+            log.done(name, &copied)?;
+            println!("{position}: verified {} entries", copied.count);
+        }
+
+        to.flush()?;
+        to.cleanup()?;
+
+        fs::remove_file(&log.path)?;
+
+        println!(
+            "Conversion finished. Set database_backend to \"{to_backend}\" and database_path to \"{to_path}\" to use the converted database."
+        );
+
+        Ok(())
+    }
+}
@@ -1,17 +1,40 @@
 use ruma::api::appservice::Registration;
+use serde::{Deserialize, Serialize};
 
-use crate::{database::KeyValueDatabase, service, utils, Error, Result};
+use crate::{
+    database::KeyValueDatabase,
+    service::{self, appservice::UnstableRegistration},
+    utils, Error, Result,
+};
+
+/// A registration as it is stored, with the keys of unstable features next to the ones ruma knows
+#[derive(Deserialize, Serialize)]
+struct StoredRegistration {
+    #[serde(flatten)]
+    registration: Registration,
+    #[serde(flatten)]
+    unstable: UnstableRegistration,
+}
 
 impl service::appservice::Data for KeyValueDatabase {
     /// Registers an appservice and returns the ID to the caller
-    fn register_appservice(&self, yaml: Registration) -> Result<String> {
-        let id = yaml.id.as_str();
+    fn register_appservice(
+        &self,
+        yaml: Registration,
+        unstable: UnstableRegistration,
+    ) -> Result<String> {
+        let id = yaml.id.clone();
         self.id_appserviceregistrations.insert(
             id.as_bytes(),
-            serde_yaml::to_string(&yaml).unwrap().as_bytes(),
+            serde_yaml::to_string(&StoredRegistration {
+                registration: yaml,
+                unstable,
+            })
+            .unwrap()
+            .as_bytes(),
         )?;
 
-        Ok(id.to_owned())
+        Ok(id)
     }
 
     /// Remove an appservice registration
@@ -46,15 +69,17 @@ impl service::appservice::Data for KeyValueDatabase {
         )))
     }
 
-    fn all(&self) -> Result<Vec<(String, Registration)>> {
-        self.iter_ids()?
-            .filter_map(|id| id.ok())
-            .map(move |id| {
-                Ok((
-                    id.clone(),
-                    self.get_registration(&id)?
-                        .expect("iter_ids only returns appservices that exist"),
-                ))
+    fn all(&self) -> Result<Vec<(String, Registration, UnstableRegistration)>> {
+        self.id_appserviceregistrations
+            .iter()
+            .map(|(id, bytes)| {
+                let id = utils::string_from_bytes(&id).map_err(|_| {
+                    Error::bad_database("Invalid id bytes in id_appserviceregistrations.")
+                })?;
+                let stored: StoredRegistration = serde_yaml::from_slice(&bytes).map_err(|_| {
+                    Error::bad_database("Invalid registration bytes in id_appserviceregistrations.")
+                })?;
+                Ok((id, stored.registration, stored.unstable))
             })
             .collect()
     }
@@ -0,0 +1,17 @@
+use std::path::Path;
+
+use crate::{database::KeyValueDatabase, service, Result};
+
+impl service::backup::Data for KeyValueDatabase {
+    fn backup(&self, path: &Path) -> Result<()> {
+        self._db.backup(path)
+    }
+
+    fn media_filehashes<'a>(&'a self) -> Box<dyn Iterator<Item = Vec<u8>> + 'a> {
+        Box::new(
+            self.filehash_metadata
+                .iter()
+                .map(|(sha256_digest, _)| sha256_digest),
+        )
+    }
+}
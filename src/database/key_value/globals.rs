@@ -10,8 +10,11 @@ use ruma::{
 };
 
 use crate::{
-    database::KeyValueDatabase,
-    service::{self, globals::SigningKeys},
+    database::{check, KeyValueDatabase},
+    service::{
+        self,
+        globals::{DatabaseCheck, SigningKeys},
+    },
     services, utils, Error, Result,
 };
 
@@ -144,6 +147,18 @@ impl service::globals::Data for KeyValueDatabase {
     }
 
     fn memory_usage(&self) -> String {
+        let mut response = String::new();
+        for (cache, size) in self.cache_sizes() {
+            response += &format!("{cache}: {size}\n");
+        }
+        if let Ok(db_stats) = self._db.memory_usage() {
+            response += &db_stats;
+        }
+
+        response
+    }
+
+    fn cache_sizes(&self) -> Vec<(&'static str, usize)> {
         let pdu_cache = self.pdu_cache.lock().unwrap().len();
         let shorteventid_cache = self.shorteventid_cache.lock().unwrap().len();
         let auth_chain_cache = self.auth_chain_cache.lock().unwrap().len();
@@ -153,22 +168,20 @@ impl service::globals::Data for KeyValueDatabase {
         let appservice_in_room_cache = self.appservice_in_room_cache.read().unwrap().len();
         let lasttimelinecount_cache = self.lasttimelinecount_cache.lock().unwrap().len();
 
-        let mut response = format!(
-            "\
-pdu_cache: {pdu_cache}
-shorteventid_cache: {shorteventid_cache}
-auth_chain_cache: {auth_chain_cache}
-eventidshort_cache: {eventidshort_cache}
-statekeyshort_cache: {statekeyshort_cache}
-our_real_users_cache: {our_real_users_cache}
-appservice_in_room_cache: {appservice_in_room_cache}
-lasttimelinecount_cache: {lasttimelinecount_cache}\n"
-        );
-        if let Ok(db_stats) = self._db.memory_usage() {
-            response += &db_stats;
-        }
+        vec![
+            ("pdu_cache", pdu_cache),
+            ("shorteventid_cache", shorteventid_cache),
+            ("auth_chain_cache", auth_chain_cache),
+            ("eventidshort_cache", eventidshort_cache),
+            ("statekeyshort_cache", statekeyshort_cache),
+            ("our_real_users_cache", our_real_users_cache),
+            ("appservice_in_room_cache", appservice_in_room_cache),
+            ("lasttimelinecount_cache", lasttimelinecount_cache),
+        ]
+    }
 
-        response
+    fn database_statistics(&self) -> Result<Vec<(&'static str, &'static str, u64)>> {
+        self._db.statistics()
     }
 
     fn clear_caches(&self, amount: u32) {
@@ -206,6 +219,10 @@ lasttimelinecount_cache: {lasttimelinecount_cache}\n"
         }
     }
 
+    fn check_database(&self) -> Result<DatabaseCheck> {
+        check::run(&*self._db, &services().globals.config, false)
+    }
+
     fn load_keypair(&self) -> Result<Ed25519KeyPair> {
         let keypair_bytes = self.global.get(b"keypair")?.map_or_else(
             || {
@@ -1,13 +1,16 @@
 use std::{collections::BTreeMap, ops::Range, slice::Split};
 
 use bytesize::ByteSize;
-use ruma::{api::client::error::ErrorKind, OwnedServerName, ServerName, UserId};
+use ruma::{
+    api::client::error::ErrorKind, EventId, OwnedEventId, OwnedRoomId, OwnedServerName,
+    OwnedUserId, RoomId, ServerName, UserId,
+};
 use sha2::{digest::Output, Sha256};
 use tracing::error;
 
 use crate::{
     config::{MediaRetentionConfig, MediaRetentionScope},
-    database::KeyValueDatabase,
+    database::{abstraction::WriteBatch, KeyValueDatabase},
     service::{
         self,
         media::{
@@ -30,11 +33,12 @@ impl service::media::Data for KeyValueDatabase {
         user_id: Option<&UserId>,
         is_blocked_filehash: bool,
     ) -> Result<()> {
+        let mut batch = WriteBatch::default();
+
         if !is_blocked_filehash {
             let metadata = FilehashMetadata::new(file_size);
 
-            self.filehash_metadata
-                .insert(&sha256_digest, metadata.value())?;
+            batch.insert(&self.filehash_metadata, &sha256_digest, metadata.value());
         };
 
         let mut key = sha256_digest.to_vec();
@@ -42,7 +46,7 @@ impl service::media::Data for KeyValueDatabase {
         key.push(0xff);
         key.extend_from_slice(media_id.as_bytes());
 
-        self.filehash_servername_mediaid.insert(&key, &[])?;
+        batch.insert(&self.filehash_servername_mediaid, &key, &[]);
 
         let mut key = servername.as_bytes().to_vec();
         key.push(0xff);
@@ -53,7 +57,7 @@ impl service::media::Data for KeyValueDatabase {
         value.push(0xff);
         value.extend_from_slice(content_type.map(|f| f.as_bytes()).unwrap_or_default());
 
-        self.servernamemediaid_metadata.insert(&key, &value)?;
+        batch.insert(&self.servernamemediaid_metadata, &key, &value);
 
         if let Some(user_id) = user_id {
             let mut key = servername.as_bytes().to_vec();
@@ -62,17 +66,20 @@ impl service::media::Data for KeyValueDatabase {
             key.push(0xff);
             key.extend_from_slice(media_id.as_bytes());
 
-            self.servername_userlocalpart_mediaid.insert(&key, &[])?;
+            batch.insert(&self.servername_userlocalpart_mediaid, &key, &[]);
 
             let mut key = servername.as_bytes().to_vec();
             key.push(0xff);
             key.extend_from_slice(media_id.as_bytes());
 
-            self.servernamemediaid_userlocalpart
-                .insert(&key, user_id.localpart().as_bytes())?;
+            batch.insert(
+                &self.servernamemediaid_userlocalpart,
+                &key,
+                user_id.localpart().as_bytes(),
+            );
         }
 
-        Ok(())
+        self._db.write(batch)
     }
 
     fn search_file_metadata(&self, servername: &ServerName, media_id: &str) -> Result<DbFileMeta> {
@@ -107,10 +114,11 @@ impl service::media::Data for KeyValueDatabase {
         filename: Option<&str>,
         content_type: Option<&str>,
     ) -> Result<()> {
+        let mut batch = WriteBatch::default();
+
         let metadata = FilehashMetadata::new(file_size);
 
-        self.filehash_metadata
-            .insert(&sha256_digest, metadata.value())?;
+        batch.insert(&self.filehash_metadata, &sha256_digest, metadata.value());
 
         let mut key = sha256_digest.to_vec();
         key.extend_from_slice(servername.as_bytes());
@@ -120,7 +128,7 @@ impl service::media::Data for KeyValueDatabase {
         key.extend_from_slice(&width.to_be_bytes());
         key.extend_from_slice(&height.to_be_bytes());
 
-        self.filehash_thumbnailid.insert(&key, &[])?;
+        batch.insert(&self.filehash_thumbnailid, &key, &[]);
 
         let mut key = servername.as_bytes().to_vec();
         key.push(0xff);
@@ -134,7 +142,9 @@ impl service::media::Data for KeyValueDatabase {
         value.push(0xff);
         value.extend_from_slice(content_type.map(|f| f.as_bytes()).unwrap_or_default());
 
-        self.thumbnailid_metadata.insert(&key, &value)
+        batch.insert(&self.thumbnailid_metadata, &key, &value);
+
+        self._db.write(batch)
     }
 
     fn search_thumbnail_metadata(
@@ -1353,6 +1363,217 @@ impl service::media::Data for KeyValueDatabase {
             Ok(())
         }
     }
+
+    fn create_pending(
+        &self,
+        servername: &ServerName,
+        media_id: &str,
+        user_id: &UserId,
+        expires_at: u64,
+    ) -> Result<()> {
+        let mut key = servername.as_bytes().to_vec();
+        key.push(0xff);
+        key.extend_from_slice(media_id.as_bytes());
+
+        let mut value = user_id.as_bytes().to_vec();
+        value.push(0xff);
+        value.extend_from_slice(&expires_at.to_be_bytes());
+
+        self.servernamemediaid_pending.insert(&key, &value)
+    }
+
+    fn search_pending(
+        &self,
+        servername: &ServerName,
+        media_id: &str,
+    ) -> Result<Option<(OwnedUserId, u64)>> {
+        let mut key = servername.as_bytes().to_vec();
+        key.push(0xff);
+        key.extend_from_slice(media_id.as_bytes());
+
+        self.servernamemediaid_pending
+            .get(&key)?
+            .map(|value| parse_pending(&value))
+            .transpose()
+    }
+
+    fn remove_pending(&self, servername: &ServerName, media_id: &str) -> Result<()> {
+        let mut key = servername.as_bytes().to_vec();
+        key.push(0xff);
+        key.extend_from_slice(media_id.as_bytes());
+
+        self.servernamemediaid_pending.remove(&key)
+    }
+
+    fn pending_from_user(&self, user_id: &UserId) -> Result<Vec<(String, u64)>> {
+        let mut prefix = user_id.server_name().as_bytes().to_vec();
+        prefix.push(0xff);
+
+        self.servernamemediaid_pending
+            .scan_prefix(prefix.clone())
+            .filter_map(|(key, value)| {
+                let (owner, expires_at) = match parse_pending(&value) {
+                    Ok(pending) => pending,
+                    Err(e) => return Some(Err(e)),
+                };
+
+                (owner == user_id).then(|| {
+                    Ok((
+                        utils::string_from_bytes(&key[prefix.len()..]).map_err(|_| {
+                            Error::bad_database("Invalid media id in servernamemediaid_pending")
+                        })?,
+                        expires_at,
+                    ))
+                })
+            })
+            .collect()
+    }
+
+    fn link_to_event(
+        &self,
+        servername: &ServerName,
+        media_id: &str,
+        room_id: &RoomId,
+        event_id: &EventId,
+    ) -> Result<()> {
+        let mut key = servername.as_bytes().to_vec();
+        key.push(0xff);
+        key.extend_from_slice(media_id.as_bytes());
+        key.push(0xff);
+        key.extend_from_slice(room_id.as_bytes());
+        key.push(0xff);
+        key.extend_from_slice(event_id.as_bytes());
+
+        self.servernamemediaid_roomideventid.insert(&key, &[])?;
+
+        let mut key = room_id.as_bytes().to_vec();
+        key.push(0xff);
+        key.extend_from_slice(servername.as_bytes());
+        key.push(0xff);
+        key.extend_from_slice(media_id.as_bytes());
+
+        self.roomid_servernamemediaid.insert(&key, &[])
+    }
+
+    fn linked_events(
+        &self,
+        servername: &ServerName,
+        media_id: &str,
+    ) -> Result<Vec<(OwnedRoomId, OwnedEventId)>> {
+        let mut prefix = servername.as_bytes().to_vec();
+        prefix.push(0xff);
+        prefix.extend_from_slice(media_id.as_bytes());
+        prefix.push(0xff);
+
+        self.servernamemediaid_roomideventid
+            .scan_prefix(prefix.clone())
+            .map(|(key, _)| {
+                let mut parts = key[prefix.len()..].split(|&b| b == 0xff);
+
+                let room_id = parts
+                    .next()
+                    .and_then(|bytes| utils::string_from_bytes(bytes).ok())
+                    .and_then(|room_id| OwnedRoomId::try_from(room_id).ok())
+                    .ok_or_else(|| {
+                        Error::bad_database("Invalid room id in servernamemediaid_roomideventid")
+                    })?;
+
+                let event_id = parts
+                    .next()
+                    .and_then(|bytes| utils::string_from_bytes(bytes).ok())
+                    .and_then(|event_id| OwnedEventId::try_from(event_id).ok())
+                    .ok_or_else(|| {
+                        Error::bad_database("Invalid event id in servernamemediaid_roomideventid")
+                    })?;
+
+                Ok((room_id, event_id))
+            })
+            .collect()
+    }
+
+    fn linked_to_room(&self, room_id: &RoomId) -> Result<Vec<(OwnedServerName, String)>> {
+        let mut prefix = room_id.as_bytes().to_vec();
+        prefix.push(0xff);
+
+        self.roomid_servernamemediaid
+            .scan_prefix(prefix.clone())
+            .map(|(key, _)| {
+                let mut parts = key[prefix.len()..].split(|&b| b == 0xff);
+
+                let server_name = parts
+                    .next()
+                    .and_then(|bytes| utils::string_from_bytes(bytes).ok())
+                    .and_then(|server_name| OwnedServerName::try_from(server_name).ok())
+                    .ok_or_else(|| {
+                        Error::bad_database("Invalid server name in roomid_servernamemediaid")
+                    })?;
+
+                let media_id = parts
+                    .next()
+                    .and_then(|bytes| utils::string_from_bytes(bytes).ok())
+                    .ok_or_else(|| {
+                        Error::bad_database("Invalid media id in roomid_servernamemediaid")
+                    })?;
+
+                Ok((server_name, media_id))
+            })
+            .collect()
+    }
+
+    fn unlink(&self, servername: &ServerName, media_id: &str) -> Result<()> {
+        for (room_id, event_id) in self.linked_events(servername, media_id)? {
+            let mut key = servername.as_bytes().to_vec();
+            key.push(0xff);
+            key.extend_from_slice(media_id.as_bytes());
+            key.push(0xff);
+            key.extend_from_slice(room_id.as_bytes());
+            key.push(0xff);
+            key.extend_from_slice(event_id.as_bytes());
+
+            self.servernamemediaid_roomideventid.remove(&key)?;
+
+            let mut key = room_id.as_bytes().to_vec();
+            key.push(0xff);
+            key.extend_from_slice(servername.as_bytes());
+            key.push(0xff);
+            key.extend_from_slice(media_id.as_bytes());
+
+            self.roomid_servernamemediaid.remove(&key)?;
+        }
+
+        Ok(())
+    }
+
+    fn store_size(&self) -> (usize, u64) {
+        self.filehash_metadata
+            .iter()
+            .filter_map(|(sha256_digest, value)| {
+                FilehashMetadata::from_vec(value).size(&sha256_digest).ok()
+            })
+            .fold((0, 0), |(files, bytes), size| (files + 1, bytes + size))
+    }
+}
+
+fn parse_pending(value: &[u8]) -> Result<(OwnedUserId, u64)> {
+    // The expiry time is a fixed size, and could contain 0xff bytes itself
+    let (user_id, expires_at) = value
+        .len()
+        .checked_sub(9)
+        .map(|separator| (&value[..separator], &value[separator + 1..]))
+        .ok_or_else(|| Error::bad_database("Invalid value in servernamemediaid_pending"))?;
+
+    let expires_at = u64::from_be_bytes(
+        expires_at
+            .try_into()
+            .expect("slice is 8 bytes long, as we just checked"),
+    );
+
+    let user_id = utils::string_from_bytes(user_id)
+        .ok()
+        .and_then(|user_id| OwnedUserId::try_from(user_id).ok())
+        .ok_or_else(|| Error::bad_database("Invalid user id in servernamemediaid_pending"))?;
+
+    Ok((user_id, expires_at))
 }
 
 impl KeyValueDatabase {
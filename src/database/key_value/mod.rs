@@ -1,6 +1,7 @@
 mod account_data;
 //mod admin;
 mod appservice;
+mod backup;
 mod globals;
 mod key_backups;
 pub(super) mod media;
@@ -1,6 +1,6 @@
 use ruma::{
     api::client::push::{set_pusher, Pusher},
-    UserId,
+    OwnedUserId, UserId,
 };
 
 use crate::{database::KeyValueDatabase, service, utils, Error, Result};
@@ -73,4 +73,23 @@ impl service::pusher::Data for KeyValueDatabase {
             Ok(push_key_string)
         }))
     }
+
+    fn all_pushers<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(OwnedUserId, Pusher)>> + 'a> {
+        Box::new(self.senderkey_pusher.iter().map(|(k, push)| {
+            let sender = k
+                .split(|&b| b == 0xff)
+                .next()
+                .expect("split always returns one element");
+            let sender = utils::string_from_bytes(sender)
+                .map_err(|_| Error::bad_database("Invalid sender bytes in senderkey_pusher"))
+                .and_then(|sender| {
+                    OwnedUserId::try_from(sender)
+                        .map_err(|_| Error::bad_database("Invalid user id in senderkey_pusher"))
+                })?;
+            let pusher = serde_json::from_slice(&push)
+                .map_err(|_| Error::bad_database("Invalid Pusher in db."))?;
+
+            Ok((sender, pusher))
+        }))
+    }
 }
@@ -25,4 +25,84 @@ impl service::rooms::directory::Data for KeyValueDatabase {
             .map_err(|_| Error::bad_database("Room ID in publicroomids is invalid."))
         }))
     }
+
+    fn set_public_in_network(
+        &self,
+        appservice_id: &str,
+        network_id: &str,
+        room_id: &RoomId,
+    ) -> Result<()> {
+        self.appservicenetworkroomids
+            .insert(&network_room_key(appservice_id, network_id, room_id), &[])
+    }
+
+    fn set_not_public_in_network(
+        &self,
+        appservice_id: &str,
+        network_id: &str,
+        room_id: &RoomId,
+    ) -> Result<()> {
+        self.appservicenetworkroomids
+            .remove(&network_room_key(appservice_id, network_id, room_id))
+    }
+
+    fn network_public_rooms<'a>(
+        &'a self,
+        appservice_id: &str,
+        network_id: &str,
+    ) -> Box<dyn Iterator<Item = Result<OwnedRoomId>> + 'a> {
+        let mut prefix = appservice_id.as_bytes().to_vec();
+        prefix.push(0xff);
+        prefix.extend_from_slice(network_id.as_bytes());
+        prefix.push(0xff);
+
+        Box::new(
+            self.appservicenetworkroomids
+                .scan_prefix(prefix)
+                .map(|(key, _)| parse_network_room_key(&key)),
+        )
+    }
+
+    fn clear_appservice_networks(&self, appservice_id: &str) -> Result<()> {
+        let mut prefix = appservice_id.as_bytes().to_vec();
+        prefix.push(0xff);
+
+        for (key, _) in self.appservicenetworkroomids.scan_prefix(prefix) {
+            self.appservicenetworkroomids.remove(&key)?;
+        }
+
+        Ok(())
+    }
+
+    fn all_network_public_rooms<'a>(
+        &'a self,
+    ) -> Box<dyn Iterator<Item = Result<OwnedRoomId>> + 'a> {
+        Box::new(
+            self.appservicenetworkroomids
+                .iter()
+                .map(|(key, _)| parse_network_room_key(&key)),
+        )
+    }
+}
+
+fn network_room_key(appservice_id: &str, network_id: &str, room_id: &RoomId) -> Vec<u8> {
+    let mut key = appservice_id.as_bytes().to_vec();
+    key.push(0xff);
+    key.extend_from_slice(network_id.as_bytes());
+    key.push(0xff);
+    key.extend_from_slice(room_id.as_bytes());
+    key
+}
+
+/// Parses the room id from the end of a key of the `appservicenetworkroomids` tree
+fn parse_network_room_key(key: &[u8]) -> Result<OwnedRoomId> {
+    let room_id = key
+        .rsplit(|&b| b == 0xff)
+        .next()
+        .expect("rsplit always returns an element");
+
+    RoomId::parse(utils::string_from_bytes(room_id).map_err(|_| {
+        Error::bad_database("Room ID in appservicenetworkroomids is invalid unicode.")
+    })?)
+    .map_err(|_| Error::bad_database("Room ID in appservicenetworkroomids is invalid."))
 }
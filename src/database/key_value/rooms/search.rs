@@ -1,6 +1,9 @@
 use ruma::RoomId;
 
-use crate::{database::KeyValueDatabase, service, services, utils, Result};
+use crate::{
+    database::{abstraction::WriteBatch, KeyValueDatabase},
+    service, services, utils, Result,
+};
 
 /// Splits a string into tokens used as keys in the search inverted index
 ///
@@ -13,33 +16,45 @@ fn tokenize(body: &str) -> impl Iterator<Item = String> + '_ {
         .map(str::to_lowercase)
 }
 
+/// Returns the key of a word of a pdu in the search index
+fn token_key(shortroomid: u64, word: &str, pdu_id: &[u8]) -> Vec<u8> {
+    let mut key = shortroomid.to_be_bytes().to_vec();
+    key.extend_from_slice(word.as_bytes());
+    key.push(0xff);
+    key.extend_from_slice(pdu_id); // TODO: currently we save the room id a second time here
+    key
+}
+
+impl KeyValueDatabase {
+    /// Adds the words of the message body to the search index as part of the write batch
+    pub(super) fn index_pdu_in_batch(
+        &self,
+        batch: &mut WriteBatch,
+        shortroomid: u64,
+        pdu_id: &[u8],
+        message_body: &str,
+    ) {
+        for word in tokenize(message_body) {
+            batch.insert(&self.tokenids, &token_key(shortroomid, &word, pdu_id), &[]);
+        }
+    }
+}
+
 impl service::rooms::search::Data for KeyValueDatabase {
-    fn index_pdu<'a>(&self, shortroomid: u64, pdu_id: &[u8], message_body: &str) -> Result<()> {
-        let mut batch = tokenize(message_body).map(|word| {
-            let mut key = shortroomid.to_be_bytes().to_vec();
-            key.extend_from_slice(word.as_bytes());
-            key.push(0xff);
-            key.extend_from_slice(pdu_id); // TODO: currently we save the room id a second time here
-            (key, Vec::new())
-        });
+    fn index_pdu(&self, shortroomid: u64, pdu_id: &[u8], message_body: &str) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        self.index_pdu_in_batch(&mut batch, shortroomid, pdu_id, message_body);
 
-        self.tokenids.insert_batch(&mut batch)
+        self._db.write(batch)
     }
 
     fn deindex_pdu(&self, shortroomid: u64, pdu_id: &[u8], message_body: &str) -> Result<()> {
-        let batch = tokenize(message_body).map(|word| {
-            let mut key = shortroomid.to_be_bytes().to_vec();
-            key.extend_from_slice(word.as_bytes());
-            key.push(0xFF);
-            key.extend_from_slice(pdu_id); // TODO: currently we save the room id a second time here
-            key
-        });
-
-        for token in batch {
-            self.tokenids.remove(&token)?;
+        let mut batch = WriteBatch::default();
+        for word in tokenize(message_body) {
+            batch.remove(&self.tokenids, &token_key(shortroomid, &word, pdu_id));
         }
 
-        Ok(())
+        self._db.write(batch)
     }
 
     fn search_pdus<'a>(
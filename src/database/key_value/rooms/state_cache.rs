@@ -6,7 +6,10 @@ use ruma::{
 };
 
 use crate::{
-    database::{abstraction::KvTree, KeyValueDatabase},
+    database::{
+        abstraction::{KvTree, WriteBatch},
+        KeyValueDatabase,
+    },
     service::{self, appservice::RegistrationInfo},
     services, utils, Error, Result,
 };
@@ -22,16 +25,17 @@ impl service::rooms::state_cache::Data for KeyValueDatabase {
     fn mark_as_joined(&self, user_id: &UserId, room_id: &RoomId) -> Result<()> {
         let (roomuser_id, userroom_id) = get_room_and_user_byte_ids(room_id, user_id);
 
-        self.userroomid_joined.insert(&userroom_id, &[])?;
-        self.roomuserid_joined.insert(&roomuser_id, &[])?;
-        self.userroomid_invitestate.remove(&userroom_id)?;
-        self.roomuserid_invitecount.remove(&roomuser_id)?;
-        self.userroomid_knockstate.remove(&userroom_id)?;
-        self.roomuserid_knockcount.remove(&roomuser_id)?;
-        self.userroomid_leftstate.remove(&userroom_id)?;
-        self.roomuserid_leftcount.remove(&roomuser_id)?;
+        let mut batch = WriteBatch::default();
+        batch.insert(&self.userroomid_joined, &userroom_id, &[]);
+        batch.insert(&self.roomuserid_joined, &roomuser_id, &[]);
+        batch.remove(&self.userroomid_invitestate, &userroom_id);
+        batch.remove(&self.roomuserid_invitecount, &roomuser_id);
+        batch.remove(&self.userroomid_knockstate, &userroom_id);
+        batch.remove(&self.roomuserid_knockcount, &roomuser_id);
+        batch.remove(&self.userroomid_leftstate, &userroom_id);
+        batch.remove(&self.roomuserid_leftcount, &roomuser_id);
 
-        Ok(())
+        self._db.write(batch)
     }
 
     fn mark_as_invited(
@@ -42,23 +46,26 @@ impl service::rooms::state_cache::Data for KeyValueDatabase {
     ) -> Result<()> {
         let (roomuser_id, userroom_id) = get_room_and_user_byte_ids(room_id, user_id);
 
-        self.userroomid_invitestate.insert(
+        let mut batch = WriteBatch::default();
+        batch.insert(
+            &self.userroomid_invitestate,
             &userroom_id,
             &serde_json::to_vec(&last_state.unwrap_or_default())
                 .expect("state to bytes always works"),
-        )?;
-        self.roomuserid_invitecount.insert(
+        );
+        batch.insert(
+            &self.roomuserid_invitecount,
             &roomuser_id,
             &services().globals.next_count()?.to_be_bytes(),
-        )?;
-        self.userroomid_joined.remove(&userroom_id)?;
-        self.roomuserid_joined.remove(&roomuser_id)?;
-        self.userroomid_knockstate.remove(&userroom_id)?;
-        self.roomuserid_knockcount.remove(&roomuser_id)?;
-        self.userroomid_leftstate.remove(&userroom_id)?;
-        self.roomuserid_leftcount.remove(&roomuser_id)?;
+        );
+        batch.remove(&self.userroomid_joined, &userroom_id);
+        batch.remove(&self.roomuserid_joined, &roomuser_id);
+        batch.remove(&self.userroomid_knockstate, &userroom_id);
+        batch.remove(&self.roomuserid_knockcount, &roomuser_id);
+        batch.remove(&self.userroomid_leftstate, &userroom_id);
+        batch.remove(&self.roomuserid_leftcount, &roomuser_id);
 
-        Ok(())
+        self._db.write(batch)
     }
 
     fn mark_as_knocked(
@@ -69,44 +76,50 @@ impl service::rooms::state_cache::Data for KeyValueDatabase {
     ) -> Result<()> {
         let (roomuser_id, userroom_id) = get_room_and_user_byte_ids(room_id, user_id);
 
-        self.userroomid_knockstate.insert(
+        let mut batch = WriteBatch::default();
+        batch.insert(
+            &self.userroomid_knockstate,
             &userroom_id,
             &serde_json::to_vec(&last_state.unwrap_or_default())
                 .expect("state to bytes always works"),
-        )?;
-        self.roomuserid_knockcount.insert(
+        );
+        batch.insert(
+            &self.roomuserid_knockcount,
             &roomuser_id,
             &services().globals.next_count()?.to_be_bytes(),
-        )?;
-        self.userroomid_joined.remove(&userroom_id)?;
-        self.roomuserid_joined.remove(&roomuser_id)?;
-        self.userroomid_invitestate.remove(&userroom_id)?;
-        self.roomuserid_invitecount.remove(&roomuser_id)?;
-        self.userroomid_leftstate.remove(&userroom_id)?;
-        self.roomuserid_leftcount.remove(&roomuser_id)?;
+        );
+        batch.remove(&self.userroomid_joined, &userroom_id);
+        batch.remove(&self.roomuserid_joined, &roomuser_id);
+        batch.remove(&self.userroomid_invitestate, &userroom_id);
+        batch.remove(&self.roomuserid_invitecount, &roomuser_id);
+        batch.remove(&self.userroomid_leftstate, &userroom_id);
+        batch.remove(&self.roomuserid_leftcount, &roomuser_id);
 
-        Ok(())
+        self._db.write(batch)
     }
 
     fn mark_as_left(&self, user_id: &UserId, room_id: &RoomId) -> Result<()> {
         let (roomuser_id, userroom_id) = get_room_and_user_byte_ids(room_id, user_id);
 
-        self.userroomid_leftstate.insert(
+        let mut batch = WriteBatch::default();
+        batch.insert(
+            &self.userroomid_leftstate,
             &userroom_id,
             &serde_json::to_vec(&Vec::<Raw<AnySyncStateEvent>>::new()).unwrap(),
-        )?; // TODO
-        self.roomuserid_leftcount.insert(
+        ); // TODO
+        batch.insert(
+            &self.roomuserid_leftcount,
             &roomuser_id,
             &services().globals.next_count()?.to_be_bytes(),
-        )?;
-        self.userroomid_joined.remove(&userroom_id)?;
-        self.roomuserid_joined.remove(&roomuser_id)?;
-        self.userroomid_invitestate.remove(&userroom_id)?;
-        self.roomuserid_invitecount.remove(&roomuser_id)?;
-        self.userroomid_knockstate.remove(&userroom_id)?;
-        self.roomuserid_knockcount.remove(&roomuser_id)?;
-
-        Ok(())
+        );
+        batch.remove(&self.userroomid_joined, &userroom_id);
+        batch.remove(&self.roomuserid_joined, &roomuser_id);
+        batch.remove(&self.userroomid_invitestate, &userroom_id);
+        batch.remove(&self.roomuserid_invitecount, &roomuser_id);
+        batch.remove(&self.userroomid_knockstate, &userroom_id);
+        batch.remove(&self.roomuserid_knockcount, &roomuser_id);
+
+        self._db.write(batch)
     }
 
     fn update_joined_count(&self, room_id: &RoomId) -> Result<()> {
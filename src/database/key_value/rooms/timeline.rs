@@ -5,7 +5,10 @@ use ruma::{
 };
 use tracing::error;
 
-use crate::{database::KeyValueDatabase, service, services, utils, Error, PduEvent, Result};
+use crate::{
+    database::{abstraction::WriteBatch, KeyValueDatabase},
+    service, services, utils, Error, PduEvent, Result,
+};
 
 use service::rooms::timeline::PduCount;
 
@@ -162,20 +165,26 @@ impl service::rooms::timeline::Data for KeyValueDatabase {
         pdu: &PduEvent,
         json: &CanonicalJsonObject,
         count: u64,
+        search_body: Option<&str>,
     ) -> Result<()> {
-        self.pduid_pdu.insert(
+        let mut batch = WriteBatch::default();
+        batch.insert(
+            &self.pduid_pdu,
             pdu_id,
             &serde_json::to_vec(json).expect("CanonicalJsonObject is always a valid"),
-        )?;
+        );
+        batch.insert(&self.eventid_pduid, pdu.event_id.as_bytes(), pdu_id);
+        batch.remove(&self.eventid_outlierpdu, pdu.event_id.as_bytes());
+        if let Some(body) = search_body {
+            self.index_pdu_in_batch(&mut batch, pdu_id_shortroomid(pdu_id), pdu_id, body);
+        }
+        self._db.write(batch)?;
 
         self.lasttimelinecount_cache
             .lock()
             .unwrap()
             .insert(pdu.room_id().into_owned(), PduCount::Normal(count));
 
-        self.eventid_pduid.insert(pdu.event_id.as_bytes(), pdu_id)?;
-        self.eventid_outlierpdu.remove(pdu.event_id.as_bytes())?;
-
         Ok(())
     }
 
@@ -184,16 +193,21 @@ impl service::rooms::timeline::Data for KeyValueDatabase {
         pdu_id: &[u8],
         event_id: &EventId,
         json: &CanonicalJsonObject,
+        search_body: Option<&str>,
     ) -> Result<()> {
-        self.pduid_pdu.insert(
+        let mut batch = WriteBatch::default();
+        batch.insert(
+            &self.pduid_pdu,
             pdu_id,
             &serde_json::to_vec(json).expect("CanonicalJsonObject is always a valid"),
-        )?;
-
-        self.eventid_pduid.insert(event_id.as_bytes(), pdu_id)?;
-        self.eventid_outlierpdu.remove(event_id.as_bytes())?;
+        );
+        batch.insert(&self.eventid_pduid, event_id.as_bytes(), pdu_id);
+        batch.remove(&self.eventid_outlierpdu, event_id.as_bytes());
+        if let Some(body) = search_body {
+            self.index_pdu_in_batch(&mut batch, pdu_id_shortroomid(pdu_id), pdu_id, body);
+        }
 
-        Ok(())
+        self._db.write(batch)
     }
 
     /// Removes a pdu and creates a new one with the same id.
@@ -304,6 +318,11 @@ impl service::rooms::timeline::Data for KeyValueDatabase {
     }
 }
 
+/// Returns the `shortroomid` this pdu's id starts with.
+fn pdu_id_shortroomid(pdu_id: &[u8]) -> u64 {
+    utils::u64_from_bytes(&pdu_id[..size_of::<u64>()]).expect("pdu ids start with a shortroomid")
+}
+
 /// Returns the `count` of this pdu's id.
 fn pdu_count(pdu_id: &[u8]) -> Result<PduCount> {
     let last_u64 = utils::u64_from_bytes(&pdu_id[pdu_id.len() - size_of::<u64>()..])
@@ -1,4 +1,6 @@
-use ruma::{ServerName, UserId};
+use std::collections::HashMap;
+
+use ruma::{OwnedServerName, ServerName, UserId};
 
 use crate::{
     database::KeyValueDatabase,
@@ -96,6 +98,24 @@ impl service::sending::Data for KeyValueDatabase {
         )
     }
 
+    fn federation_queue_depths(&self) -> HashMap<OwnedServerName, usize> {
+        let mut depths = HashMap::new();
+
+        for (key, value) in self
+            .servercurrentevent_data
+            .iter()
+            .chain(self.servernameevent_data.iter())
+        {
+            if let Ok((OutgoingKind::Normal(server_name), _)) =
+                parse_servercurrentevent(&key, value)
+            {
+                *depths.entry(server_name).or_default() += 1;
+            }
+        }
+
+        depths
+    }
+
     fn mark_as_active(&self, events: &[(SendingEventType, Vec<u8>)]) -> Result<()> {
         for (e, key) in events {
             let value = if let SendingEventType::Edu(value) = &e {
@@ -123,6 +143,25 @@ impl service::sending::Data for KeyValueDatabase {
                     .map_err(|_| Error::bad_database("Invalid u64 in servername_educount."))
             })
     }
+
+    fn set_latest_appservice_educount(&self, appservice_id: &str, last_count: u64) -> Result<()> {
+        // Appservices are prefixed with a plus, which can't be part of a server name
+        let mut key = b"+".to_vec();
+        key.extend_from_slice(appservice_id.as_bytes());
+
+        self.servername_educount
+            .insert(&key, &last_count.to_be_bytes())
+    }
+
+    fn get_latest_appservice_educount(&self, appservice_id: &str) -> Result<u64> {
+        let mut key = b"+".to_vec();
+        key.extend_from_slice(appservice_id.as_bytes());
+
+        self.servername_educount.get(&key)?.map_or(Ok(0), |bytes| {
+            utils::u64_from_bytes(&bytes)
+                .map_err(|_| Error::bad_database("Invalid u64 in servername_educount."))
+        })
+    }
 }
 
 #[tracing::instrument(skip(key))]
@@ -5,9 +5,11 @@ use ruma::{
     encryption::{CrossSigningKey, DeviceKeys, OneTimeKey},
     events::{AnyToDeviceEvent, StateEventType},
     serde::Raw,
+    thirdparty::{Medium, ThirdPartyIdentifier, ThirdPartyIdentifierInit},
     DeviceId, MilliSecondsSinceUnixEpoch, OneTimeKeyAlgorithm, OwnedDeviceId, OwnedMxcUri,
     OwnedOneTimeKeyId, OwnedUserId, UInt, UserId,
 };
+use serde_json::Value as JsonValue;
 use tracing::warn;
 
 use crate::{
@@ -35,6 +37,22 @@ impl service::users::Data for KeyValueDatabase {
             .is_empty())
     }
 
+    /// Check if the user is a guest
+    fn is_guest(&self, user_id: &UserId) -> Result<bool> {
+        Ok(self.userid_guest.get(user_id.as_bytes())?.is_some())
+    }
+
+    /// Marks the user as guest, or as full account after they upgraded their account
+    fn set_guest(&self, user_id: &UserId, is_guest: bool) -> Result<()> {
+        if is_guest {
+            self.userid_guest.insert(user_id.as_bytes(), &[])?;
+        } else {
+            self.userid_guest.remove(user_id.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
     /// Returns the number of users registered on this server.
     fn count(&self) -> Result<usize> {
         Ok(self.userid_password.iter().count())
@@ -193,6 +211,17 @@ impl service::users::Data for KeyValueDatabase {
         Ok(())
     }
 
+    /// Get the email of a user, as set by LDAP or OpenID Connect.
+    fn email(&self, user_id: &UserId) -> Result<Option<String>> {
+        self.userid_email
+            .get(user_id.as_bytes())?
+            .map(|bytes| {
+                utils::string_from_bytes(&bytes)
+                    .map_err(|_| Error::bad_database("Email in db is invalid."))
+            })
+            .transpose()
+    }
+
     /// Sets a new email or removes it if email is None.
     fn set_email(&self, user_id: &UserId, email: Option<String>) -> Result<()> {
         if let Some(email) = email {
@@ -205,6 +234,182 @@ impl service::users::Data for KeyValueDatabase {
         Ok(())
     }
 
+    /// Get the subject identifying the user at the OpenID Connect provider.
+    fn oidc_subject(&self, user_id: &UserId) -> Result<Option<String>> {
+        self.userid_oidcsubject
+            .get(user_id.as_bytes())?
+            .map(|bytes| {
+                utils::string_from_bytes(&bytes)
+                    .map_err(|_| Error::bad_database("OpenID Connect subject in db is invalid."))
+            })
+            .transpose()
+    }
+
+    /// Sets the subject identifying the user at the OpenID Connect provider or removes it if
+    /// subject is None.
+    fn set_oidc_subject(&self, user_id: &UserId, subject: Option<&str>) -> Result<()> {
+        if let Some(subject) = subject {
+            self.userid_oidcsubject
+                .insert(user_id.as_bytes(), subject.as_bytes())?;
+        } else {
+            self.userid_oidcsubject.remove(user_id.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// Get a custom profile field of a user.
+    fn profile_field(&self, user_id: &UserId, field: &str) -> Result<Option<JsonValue>> {
+        let mut key = user_id.as_bytes().to_vec();
+        key.push(0xff);
+        key.extend_from_slice(field.as_bytes());
+
+        self.useridprofilefield_value
+            .get(&key)?
+            .map(|bytes| {
+                serde_json::from_slice(&bytes)
+                    .map_err(|_| Error::bad_database("Profile field in db is invalid."))
+            })
+            .transpose()
+    }
+
+    /// Sets a custom profile field or removes it if value is None.
+    fn set_profile_field(
+        &self,
+        user_id: &UserId,
+        field: &str,
+        value: Option<&JsonValue>,
+    ) -> Result<()> {
+        let mut key = user_id.as_bytes().to_vec();
+        key.push(0xff);
+        key.extend_from_slice(field.as_bytes());
+
+        if let Some(value) = value {
+            self.useridprofilefield_value.insert(
+                &key,
+                &serde_json::to_vec(value).expect("JsonValue can be serialized"),
+            )?;
+        } else {
+            self.useridprofilefield_value.remove(&key)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns all custom profile fields of a user.
+    fn profile_fields(&self, user_id: &UserId) -> Result<BTreeMap<String, JsonValue>> {
+        let mut prefix = user_id.as_bytes().to_vec();
+        prefix.push(0xff);
+
+        self.useridprofilefield_value
+            .scan_prefix(prefix.clone())
+            .map(|(key, value)| {
+                let field = utils::string_from_bytes(&key[prefix.len()..])
+                    .map_err(|_| Error::bad_database("Profile field name in db is invalid."))?;
+                let value = serde_json::from_slice(&value)
+                    .map_err(|_| Error::bad_database("Profile field in db is invalid."))?;
+
+                Ok((field, value))
+            })
+            .collect()
+    }
+
+    /// Adds a validated third party identifier to a user.
+    fn add_threepid(
+        &self,
+        user_id: &UserId,
+        medium: &Medium,
+        address: &str,
+        added_at: MilliSecondsSinceUnixEpoch,
+    ) -> Result<()> {
+        let threepid = threepid_key(medium, address);
+
+        let mut userthreepid = user_id.as_bytes().to_vec();
+        userthreepid.push(0xff);
+        userthreepid.extend_from_slice(&threepid);
+
+        self.threepid_userid.insert(&threepid, user_id.as_bytes())?;
+        self.userthreepid_addedat
+            .insert(&userthreepid, &u64::from(added_at.get()).to_be_bytes())?;
+
+        Ok(())
+    }
+
+    /// Removes a third party identifier from a user.
+    fn remove_threepid(&self, user_id: &UserId, medium: &Medium, address: &str) -> Result<()> {
+        let threepid = threepid_key(medium, address);
+
+        let mut userthreepid = user_id.as_bytes().to_vec();
+        userthreepid.push(0xff);
+        userthreepid.extend_from_slice(&threepid);
+
+        self.userthreepid_addedat.remove(&userthreepid)?;
+
+        // Only unhook the identifier if it still belongs to this user
+        if self.threepid_userid.get(&threepid)?.as_deref() == Some(user_id.as_bytes()) {
+            self.threepid_userid.remove(&threepid)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns all third party identifiers of a user.
+    fn threepids<'a>(
+        &'a self,
+        user_id: &UserId,
+    ) -> Box<dyn Iterator<Item = Result<ThirdPartyIdentifier>> + 'a> {
+        let mut prefix = user_id.as_bytes().to_vec();
+        prefix.push(0xff);
+
+        Box::new(
+            self.userthreepid_addedat
+                .scan_prefix(prefix.clone())
+                .map(move |(key, value)| {
+                    let mut parts = key[prefix.len()..].splitn(2, |&b| b == 0xff);
+
+                    let medium = utils::string_from_bytes(
+                        parts.next().expect("splitn always returns one element"),
+                    )
+                    .map_err(|_| Error::bad_database("Third party medium in db is invalid."))?;
+                    let address =
+                        utils::string_from_bytes(parts.next().ok_or_else(|| {
+                            Error::bad_database("Third party id in db is invalid.")
+                        })?)
+                        .map_err(|_| {
+                            Error::bad_database("Third party address in db is invalid.")
+                        })?;
+
+                    let added_at = MilliSecondsSinceUnixEpoch(UInt::new_saturating(
+                        utils::u64_from_bytes(&value).map_err(|_| {
+                            Error::bad_database("Third party id timestamp in db is invalid.")
+                        })?,
+                    ));
+
+                    Ok(ThirdPartyIdentifierInit {
+                        address,
+                        medium: medium.into(),
+                        // Only validated identifiers are stored
+                        validated_at: added_at,
+                        added_at,
+                    }
+                    .into())
+                }),
+        )
+    }
+
+    /// Find out which user a third party identifier belongs to.
+    fn find_from_threepid(&self, medium: &Medium, address: &str) -> Result<Option<OwnedUserId>> {
+        self.threepid_userid
+            .get(&threepid_key(medium, address))?
+            .map(|bytes| {
+                UserId::parse(utils::string_from_bytes(&bytes).map_err(|_| {
+                    Error::bad_database("User ID in threepid_userid is invalid unicode.")
+                })?)
+                .map_err(|_| Error::bad_database("User ID in threepid_userid is invalid."))
+            })
+            .transpose()
+    }
+
     /// Adds a new device to a user.
     fn create_device(
         &self,
@@ -1059,3 +1264,11 @@ fn get_username_with_valid_password(username: &[u8], password: &[u8]) -> Option<
         }
     }
 }
+
+/// Key of a third party identifier: medium + 0xff + address
+fn threepid_key(medium: &Medium, address: &str) -> Vec<u8> {
+    let mut key = medium.as_str().as_bytes().to_vec();
+    key.push(0xff);
+    key.extend_from_slice(address.as_bytes());
+    key
+}
@@ -1,4 +1,6 @@
 pub mod abstraction;
+mod check;
+mod convert;
 pub mod key_value;
 
 use crate::{
@@ -18,6 +20,7 @@ use ruma::{
         GlobalAccountDataEvent, GlobalAccountDataEventType, StateEventType,
     },
     push::Ruleset,
+    thirdparty::Medium,
     CanonicalJsonValue, EventId, OwnedDeviceId, OwnedEventId, OwnedMxcUri, OwnedRoomId,
     OwnedUserId, RoomId, UserId,
 };
@@ -36,6 +39,9 @@ use tokio::{io::AsyncReadExt, time::interval};
 
 use tracing::{debug, error, info, warn};
 
+/// Version of the database once all migrations ran
+const DATABASE_VERSION: u64 = 19;
+
 /// This trait should only be used for migrations, and hence should never be made "pub"
 trait GlobalsMigrationsExt {
     /// As the name states, old version of `get_media_file`, only for usage in migrations
@@ -75,6 +81,11 @@ pub struct KeyValueDatabase {
     pub(super) userid_avatarurl: Arc<dyn KvTree>,
     pub(super) userid_blurhash: Arc<dyn KvTree>,
     pub(super) userid_email: Arc<dyn KvTree>,
+    pub(super) userid_oidcsubject: Arc<dyn KvTree>,
+    pub(super) useridprofilefield_value: Arc<dyn KvTree>, // ProfileField = UserId + 0xff + FieldName
+    pub(super) userid_guest: Arc<dyn KvTree>,
+    pub(super) threepid_userid: Arc<dyn KvTree>, // ThreePid = Medium + 0xff + Address
+    pub(super) userthreepid_addedat: Arc<dyn KvTree>,
     pub(super) userdeviceid_token: Arc<dyn KvTree>,
     pub(super) userdeviceid_metadata: Arc<dyn KvTree>, // This is also used to check if a device exists
     pub(super) userid_devicelistversion: Arc<dyn KvTree>, // DevicelistVersion = u64
@@ -112,6 +123,7 @@ pub struct KeyValueDatabase {
     pub(super) alias_roomid: Arc<dyn KvTree>,
     pub(super) aliasid_alias: Arc<dyn KvTree>, // AliasId = RoomId + Count
     pub(super) publicroomids: Arc<dyn KvTree>,
+    pub(super) appservicenetworkroomids: Arc<dyn KvTree>, // AppserviceNetworkRoomId = AppserviceId + NetworkId + RoomId
 
     pub(super) threadid_userids: Arc<dyn KvTree>, // ThreadId = RoomId + Count
 
@@ -185,6 +197,9 @@ pub struct KeyValueDatabase {
     pub(super) servernamemediaid_userlocalpart: Arc<dyn KvTree>, // Servername + MediaID -> User Localpart, used to remove keys from above when files are deleted by unrelated means
     pub(super) thumbnailid_metadata: Arc<dyn KvTree>, // ThumbnailId = Servername + MediaID + width + height -> Filename + ContentType + extra 0xff byte if media is allowed on unauthenticated endpoints
     pub(super) filehash_thumbnailid: Arc<dyn KvTree>, // sha256 of content + "ThumbnailId", as defined above. Used to dangling references to filehashes from thumbnailIds
+    pub(super) servernamemediaid_pending: Arc<dyn KvTree>, // Servername + MediaID of media reserved for an asynchronous upload -> UserId + expiry time
+    pub(super) servernamemediaid_roomideventid: Arc<dyn KvTree>, // Servername + MediaID + RoomId + EventId of events the media is linked to
+    pub(super) roomid_servernamemediaid: Arc<dyn KvTree>, // RoomId + Servername + MediaID, used to find media linked to events of a room
     //pub key_backups: key_backups::KeyBackups,
     pub(super) backupid_algorithm: Arc<dyn KvTree>, // BackupId = UserId + Version(Count)
     pub(super) backupid_etag: Arc<dyn KvTree>,      // BackupId = UserId + Version(Count)
@@ -274,6 +289,20 @@ impl KeyValueDatabase {
         Ok(())
     }
 
+    /// Opens the engine of the `database_backend` in the config.
+    fn open_engine(config: &Config) -> Result<Arc<dyn KeyValueDatabaseEngine>> {
+        Ok(match &*config.database_backend {
+            #[cfg(feature = "sqlite")]
+            "sqlite" => Arc::new(Arc::<abstraction::sqlite::Engine>::open(config)?),
+            #[cfg(feature = "rocksdb")]
+            "rocksdb" => Arc::new(Arc::<abstraction::rocksdb::Engine>::open(config)?),
+            "memory" => Arc::new(Arc::<abstraction::memory::Engine>::open(config)?),
+            _ => {
+                return Err(Error::BadConfig("Database backend not found."));
+            }
+        })
+    }
+
     /// Load an existing database or create a new one.
     pub async fn load_or_create(config: Config) -> Result<()> {
         Self::check_db_setup(&config)?;
@@ -283,15 +312,7 @@ impl KeyValueDatabase {
                 .map_err(|_| Error::BadConfig("Database folder doesn't exists and couldn't be created (e.g. due to missing permissions). Please create the database folder yourself."))?;
         }
 
-        let builder: Arc<dyn KeyValueDatabaseEngine> = match &*config.database_backend {
-            #[cfg(feature = "sqlite")]
-            "sqlite" => Arc::new(Arc::<abstraction::sqlite::Engine>::open(&config)?),
-            #[cfg(feature = "rocksdb")]
-            "rocksdb" => Arc::new(Arc::<abstraction::rocksdb::Engine>::open(&config)?),
-            _ => {
-                return Err(Error::BadConfig("Database backend not found."));
-            }
-        };
+        let builder = Self::open_engine(&config)?;
 
         if config.registration_token == Some(String::new()) {
             return Err(Error::bad_config("Registration token is empty"));
@@ -301,6 +322,7 @@ impl KeyValueDatabase {
             error!(?config.max_request_size, "Max request size is less than 1KB. Please increase it.");
         }
 
+        // Trees added here also need to be added to `convert::TREES`
         let db_raw = Box::new(Self {
             _db: builder.clone(),
             userid_password: builder.open_tree("userid_password")?,
@@ -308,6 +330,11 @@ impl KeyValueDatabase {
             userid_avatarurl: builder.open_tree("userid_avatarurl")?,
             userid_blurhash: builder.open_tree("userid_blurhash")?,
             userid_email: builder.open_tree("userid_email")?,
+            userid_oidcsubject: builder.open_tree("userid_oidcsubject")?,
+            userid_guest: builder.open_tree("userid_guest")?,
+            useridprofilefield_value: builder.open_tree("useridprofilefield_value")?,
+            threepid_userid: builder.open_tree("threepid_userid")?,
+            userthreepid_addedat: builder.open_tree("userthreepid_addedat")?,
             userdeviceid_token: builder.open_tree("userdeviceid_token")?,
             userdeviceid_metadata: builder.open_tree("userdeviceid_metadata")?,
             userid_devicelistversion: builder.open_tree("userid_devicelistversion")?,
@@ -338,6 +365,7 @@ impl KeyValueDatabase {
             alias_roomid: builder.open_tree("alias_roomid")?,
             aliasid_alias: builder.open_tree("aliasid_alias")?,
             publicroomids: builder.open_tree("publicroomids")?,
+            appservicenetworkroomids: builder.open_tree("appservicenetworkroomids")?,
 
             threadid_userids: builder.open_tree("threadid_userids")?,
 
@@ -399,6 +427,10 @@ impl KeyValueDatabase {
                 .open_tree("servernamemediaid_userlocalpart")?,
             thumbnailid_metadata: builder.open_tree("thumbnailid_metadata")?,
             filehash_thumbnailid: builder.open_tree("filehash_thumbnailid")?,
+            servernamemediaid_pending: builder.open_tree("servernamemediaid_pending")?,
+            servernamemediaid_roomideventid: builder
+                .open_tree("servernamemediaid_roomideventid")?,
+            roomid_servernamemediaid: builder.open_tree("roomid_servernamemediaid")?,
             backupid_algorithm: builder.open_tree("backupid_algorithm")?,
             backupid_etag: builder.open_tree("backupid_etag")?,
             backupkeyid_backup: builder.open_tree("backupkeyid_backup")?,
@@ -461,7 +493,7 @@ impl KeyValueDatabase {
         }
 
         // If the database has any data, perform data migrations before starting
-        let latest_database_version = 18;
+        let latest_database_version = DATABASE_VERSION;
 
         if services().users.count()? > 0 {
             // MIGRATIONS
@@ -1092,6 +1124,37 @@ impl KeyValueDatabase {
                 warn!("Migration: 17 -> 18 finished");
             }
 
+            if services().globals.database_version()? < 19 {
+                // Emails from LDAP and OpenID Connect are now also email third party identifiers
+                for (userid, email) in db.userid_email.iter() {
+                    let user_id = match utils::string_from_bytes(&userid)
+                        .ok()
+                        .and_then(|user_id| UserId::parse(user_id).ok())
+                    {
+                        Some(user_id) => user_id,
+                        None => {
+                            error!("Migration: Invalid user id in userid_email.");
+                            continue;
+                        }
+                    };
+                    let Ok(email) = utils::string_from_bytes(&email) else {
+                        error!("Migration: Invalid email of {user_id} in userid_email.");
+                        continue;
+                    };
+
+                    if let Err(e) = services()
+                        .users
+                        .add_threepid(&user_id, &Medium::Email, &email)
+                    {
+                        warn!("Migration: Could not add email of {user_id} as third party identifier: {e}");
+                    }
+                }
+
+                services().globals.bump_database_version(19)?;
+
+                warn!("Migration: 18 -> 19 finished");
+            }
+
             assert_eq!(
                 services().globals.database_version().unwrap(),
                 latest_database_version
@@ -1142,6 +1205,7 @@ impl KeyValueDatabase {
         services().sending.start_handler();
 
         services().media.start_time_retention_checker();
+        services().backup.start_scheduler();
         services().users.start_device_last_seen_update_task();
 
         Self::start_cleanup_task().await;
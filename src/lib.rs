@@ -3,6 +3,8 @@ pub mod clap;
 mod config;
 mod database;
 mod service;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 mod utils;
 
 // Not async due to services() being used in many closures, and async closures are not stable as of writing
@@ -10,14 +12,16 @@ mod utils;
 // the current maintainer (Timo) has asked to not modify those
 use std::{
     collections::BTreeSet,
-    sync::{LazyLock, RwLock},
+    sync::{LazyLock, OnceLock, RwLock},
 };
 
 pub use api::ruma_wrapper::{Ruma, RumaResponse};
 pub use config::Config;
 pub use database::KeyValueDatabase;
 use ruma::api::{MatrixVersion, SupportedVersions};
-pub use service::{pdu::PduEvent, Services};
+pub use service::{admin::socket::protocol as admin_protocol, pdu::PduEvent, Services};
+#[cfg(any(test, feature = "testing"))]
+pub use testing::Server;
 pub use utils::error::{Error, Result};
 
 use axum::{extract::FromRequestParts, response::IntoResponse, routing::on, Router};
@@ -28,8 +32,12 @@ use ruma::api::{
 };
 use std::future::Future;
 use tracing::warn;
+use tracing_subscriber::{reload, EnvFilter, Registry};
 
 pub static SERVICES: RwLock<Option<&'static Services>> = RwLock::new(None);
+/// Used to change the log filter when reloading the config. Not set when the filter is fixed, which
+/// is the case with `tracing_flame`
+pub static LOG_FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
 pub static SUPPORTED_VERSIONS: LazyLock<SupportedVersions> = LazyLock::new(|| SupportedVersions {
     versions: BTreeSet::from_iter([MatrixVersion::V1_13]),
     features: BTreeSet::new(),
@@ -50,6 +58,12 @@ pub fn routes(_config: &Config) -> axum::Router {
         .ruma_route(api::client_server::register_route)
         .ruma_route(api::client_server::get_login_types_route)
         .ruma_route(api::client_server::login_route)
+        .ruma_route(api::client_server::sso_login_route)
+        .ruma_route(api::client_server::sso_login_with_provider_route)
+        .route(
+            service::oidc::CALLBACK_PATH,
+            axum::routing::get(api::client_server::oidc_callback_route),
+        )
         .ruma_route(api::client_server::whoami_route)
         .ruma_route(api::client_server::logout_route)
         .ruma_route(api::client_server::logout_all_route)
@@ -58,6 +72,14 @@ pub fn routes(_config: &Config) -> axum::Router {
         .ruma_route(api::client_server::third_party_route)
         .ruma_route(api::client_server::request_3pid_management_token_via_email_route)
         .ruma_route(api::client_server::request_3pid_management_token_via_msisdn_route)
+        .ruma_route(api::client_server::request_password_change_token_via_email_route)
+        .ruma_route(api::client_server::add_3pid_route)
+        .ruma_route(api::client_server::delete_3pid_route)
+        .route(
+            service::threepid::SUBMIT_TOKEN_PATH,
+            axum::routing::get(api::client_server::submit_email_token_link_route)
+                .post(api::client_server::submit_email_token_route),
+        )
         .ruma_route(api::client_server::get_capabilities_route)
         .ruma_route(api::client_server::get_pushrules_all_route)
         .ruma_route(api::client_server::set_pushrule_route)
@@ -80,7 +102,6 @@ pub fn routes(_config: &Config) -> axum::Router {
         .ruma_route(api::client_server::get_displayname_route)
         .ruma_route(api::client_server::set_avatar_url_route)
         .ruma_route(api::client_server::get_avatar_url_route)
-        .ruma_route(api::client_server::get_profile_route)
         .ruma_route(api::client_server::set_presence_route)
         .ruma_route(api::client_server::get_presence_route)
         .ruma_route(api::client_server::upload_keys_route)
@@ -121,12 +142,18 @@ pub fn routes(_config: &Config) -> axum::Router {
         .ruma_route(api::client_server::unban_user_route)
         .ruma_route(api::client_server::invite_user_route)
         .ruma_route(api::client_server::set_room_visibility_route)
+        .ruma_route(api::client_server::set_appservice_room_visibility_route)
         .ruma_route(api::client_server::get_room_visibility_route)
         .ruma_route(api::client_server::get_public_rooms_route)
         .ruma_route(api::client_server::get_public_rooms_filtered_route)
         .ruma_route(api::client_server::search_users_route)
         .ruma_route(api::client_server::get_member_events_route)
         .ruma_route(api::client_server::get_protocols_route)
+        .ruma_route(api::client_server::get_protocol_route)
+        .ruma_route(api::client_server::get_location_for_protocol_route)
+        .ruma_route(api::client_server::get_location_for_room_alias_route)
+        .ruma_route(api::client_server::get_user_for_protocol_route)
+        .ruma_route(api::client_server::get_user_for_user_id_route)
         .ruma_route(api::client_server::send_message_event_route)
         .ruma_route(api::client_server::send_state_event_for_key_route)
         .ruma_route(api::client_server::get_state_events_route)
@@ -158,12 +185,15 @@ pub fn routes(_config: &Config) -> axum::Router {
         .ruma_route(api::client_server::sync_events_v5_route)
         .ruma_route(api::client_server::get_context_route)
         .ruma_route(api::client_server::get_message_events_route)
+        .ruma_route(api::client_server::get_event_by_timestamp_route)
         .ruma_route(api::client_server::search_events_route)
         .ruma_route(api::client_server::turn_server_route)
         .ruma_route(api::client_server::send_event_to_device_route)
         .ruma_route(api::client_server::get_media_config_route)
         .ruma_route(api::client_server::get_media_config_auth_route)
         .ruma_route(api::client_server::create_content_route)
+        .ruma_route(api::client_server::create_mxc_uri_route)
+        .ruma_route(api::client_server::create_content_async_route)
         .ruma_route(api::client_server::get_content_route)
         .ruma_route(api::client_server::get_content_auth_route)
         .ruma_route(api::client_server::get_content_as_filename_route)
@@ -190,18 +220,33 @@ pub fn routes(_config: &Config) -> axum::Router {
         .ruma_route(api::client_server::get_relating_events_with_rel_type_route)
         .ruma_route(api::client_server::get_relating_events_route)
         .ruma_route(api::client_server::get_hierarchy_route)
+        .ruma_route(api::client_server::get_room_summary_route)
         .ruma_route(api::client_server::well_known_client)
-        .route(
-            "/_matrix/client/r0/rooms/{room_id}/initialSync",
-            axum::routing::get(initial_sync),
-        )
-        .route(
-            "/_matrix/client/v3/rooms/{room_id}/initialSync",
-            axum::routing::get(initial_sync),
-        )
+        .ruma_route(api::client_server::get_profile_route)
+        .ruma_route(api::client_server::get_profile_field_route)
+        .ruma_route(api::client_server::set_profile_field_route)
+        .ruma_route(api::client_server::delete_profile_field_route)
+        .ruma_route(api::client_server::get_mutual_rooms_route)
+        .ruma_route(api::client_server::room_initial_sync_route)
         .route("/", axum::routing::get(it_works))
         .fallback(not_found);
 
+    // Without a separate address, the metrics are served next to the client and federation APIs,
+    // where everyone could read them without a token
+    let metrics = &_config.metrics;
+    let router = if metrics.enabled && metrics.address.is_none() {
+        if metrics.bearer_token.is_some() {
+            router.route("/metrics", axum::routing::get(api::metrics::metrics_route))
+        } else {
+            warn!(
+                "Metrics are not served, as neither metrics.address nor metrics.bearer_token is set"
+            );
+            router
+        }
+    } else {
+        router
+    };
+
     if _config.allow_federation {
         // TODO: federation routes
     }
@@ -214,13 +259,6 @@ async fn not_found(uri: Uri) -> impl IntoResponse {
     Error::BadRequest(ErrorKind::Unrecognized, "Unrecognized request")
 }
 
-async fn initial_sync(_uri: Uri) -> impl IntoResponse {
-    Error::BadRequest(
-        ErrorKind::GuestAccessForbidden,
-        "Guest access not implemented",
-    )
-}
-
 async fn it_works() -> &'static str {
     "Hello from Conduit!"
 }
@@ -1,4 +1,10 @@
-use std::{future::Future, io, net::SocketAddr, sync::atomic, time::Duration};
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    sync::atomic,
+    time::{Duration, Instant},
+};
 
 use axum::{
     body::Body,
@@ -9,12 +15,7 @@ use axum::{
     Router,
 };
 use axum_server::{bind, bind_rustls, tls_rustls::RustlsConfig, Handle as ServerHandle};
-use conduit::api::{client_server, server_server};
-use figment::{
-    providers::{Env, Format, Toml},
-    value::Uncased,
-    Figment,
-};
+use conduit::api::{client_server, metrics::metrics_route, server_server};
 use http::{
     header::{self, HeaderName, CONTENT_SECURITY_POLICY},
     Method, StatusCode, Uri,
@@ -35,7 +36,7 @@ use tower_http::{
     ServiceBuilderExt as _,
 };
 use tracing::{debug, error, info, warn};
-use tracing_subscriber::{prelude::*, EnvFilter};
+use tracing_subscriber::{prelude::*, reload, EnvFilter};
 
 pub use conduit::*; // Re-export everything from the library crate
 
@@ -46,57 +47,12 @@ use tikv_jemallocator::Jemalloc;
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
 
-static SUB_TABLES: [&str; 3] = ["well_known", "tls", "media"]; // Not doing `proxy` cause setting that with env vars would be a pain
-
-// Yeah, I know it's terrible, but since it seems the container users dont want syntax like A[B][C]="...",
-// this is what we have to deal with. Also see: https://github.com/SergioBenitez/Figment/issues/12#issuecomment-801449465
-static SUB_SUB_TABLES: [&str; 2] = ["directory_structure", "retention"];
-
 #[tokio::main]
 async fn main() {
-    clap::parse();
+    let args = clap::parse();
 
     // Initialize config
-    let raw_config = Figment::new()
-        .merge(
-            Toml::file(
-                Env::var("CONDUIT_CONFIG").expect(
-                    "The CONDUIT_CONFIG env var needs to be set. Example: /etc/conduit.toml",
-                ),
-            )
-            .nested(),
-        )
-        .merge(Env::prefixed("CONDUIT_").global().map(|k| {
-            let mut key: Uncased = k.into();
-
-            'outer: for table in SUB_TABLES {
-                if k.starts_with(&(table.to_owned() + "_")) {
-                    for sub_table in SUB_SUB_TABLES {
-                        if k.starts_with(&(table.to_owned() + "_" + sub_table + "_")) {
-                            key = Uncased::from(
-                                table.to_owned()
-                                    + "."
-                                    + sub_table
-                                    + "."
-                                    + k[table.len() + 1 + sub_table.len() + 1..k.len()].as_str(),
-                            );
-
-                            break 'outer;
-                        }
-                    }
-
-                    key = Uncased::from(
-                        table.to_owned() + "." + k[table.len() + 1..k.len()].as_str(),
-                    );
-
-                    break;
-                }
-            }
-
-            key
-        }));
-
-    let config = match raw_config.extract::<Config>() {
+    let config = match Config::load() {
         Ok(s) => s,
         Err(e) => {
             eprintln!("It looks like your config is invalid. The following error occurred: {e}");
@@ -106,6 +62,36 @@ async fn main() {
 
     config.warn_deprecated();
 
+    match args.command {
+        Some(clap::Command::ConvertDatabase {
+            to,
+            to_path,
+            chunk_size,
+        }) => {
+            if let Err(e) = KeyValueDatabase::convert(&config, &to, &to_path, chunk_size) {
+                eprintln!("Converting the database failed: {e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(clap::Command::CheckDatabase { repair }) => {
+            match KeyValueDatabase::check(&config, repair) {
+                Ok(check) => {
+                    print!("{check}");
+                    if check.remaining_problems() > 0 {
+                        std::process::exit(1);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Checking the database failed: {e}");
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        None => {}
+    }
+
     if config.ldap.enabled {
         info!("Attempting to connect to LDAP server");
         match ldap3::LdapConn::new(&config.ldap.uri) {
@@ -155,6 +141,8 @@ async fn main() {
                 EnvFilter::try_new("warn").unwrap()
             }
         };
+        let (filter_layer, filter_handle) = reload::Layer::new(filter_layer);
+        let _ = LOG_FILTER.set(filter_handle);
 
         let subscriber = tracing_subscriber::Registry::default()
             .with(filter_layer)
@@ -184,6 +172,8 @@ async fn main() {
                 EnvFilter::try_new("warn").unwrap()
             }
         };
+        let (filter_layer, filter_handle) = reload::Layer::new(filter_layer);
+        let _ = LOG_FILTER.set(filter_handle);
 
         let subscriber = registry.with(filter_layer).with(fmt_layer);
         tracing::subscriber::set_global_default(subscriber).unwrap();
@@ -243,6 +233,7 @@ async fn run_server() -> io::Result<()> {
                 tracing::info_span!("http_request", %path)
             }),
         )
+        .layer(axum::middleware::from_fn(record_metrics))
         .layer(axum::middleware::from_fn(unrecognized_method))
         .layer(
             CorsLayer::new()
@@ -269,6 +260,20 @@ async fn run_server() -> io::Result<()> {
     let handle = ServerHandle::new();
 
     tokio::spawn(shutdown_signal(handle.clone()));
+    #[cfg(unix)]
+    tokio::spawn(reload_on_hangup());
+
+    if let (true, Some(metrics_addr)) = (config.metrics.enabled, config.metrics.address) {
+        let metrics_app = Router::new()
+            .route("/metrics", get(metrics_route))
+            .into_make_service();
+        let metrics_server = bind(metrics_addr).handle(handle.clone()).serve(metrics_app);
+        tokio::spawn(async move {
+            if let Err(e) = metrics_server.await {
+                error!("Metrics listener on {metrics_addr} failed: {e}");
+            }
+        });
+    }
 
     match &config.tls {
         Some(tls) => {
@@ -305,6 +310,20 @@ async fn spawn_task(
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
 }
 
+async fn record_metrics(req: http::Request<Body>, next: axum::middleware::Next) -> Response {
+    let method = req.method().clone();
+    let path = req.extensions().get::<MatchedPath>().cloned();
+    let start = Instant::now();
+    let response = next.run(req).await;
+    services().metrics.observe_http_request(
+        method.as_str(),
+        path.as_ref().map(MatchedPath::as_str),
+        response.status().as_u16(),
+        start.elapsed(),
+    );
+    response
+}
+
 async fn unrecognized_method(
     req: http::Request<Body>,
     next: axum::middleware::Next,
@@ -328,6 +347,27 @@ async fn unrecognized_method(
 
 
 
+#[cfg(unix)]
+async fn reload_on_hangup() {
+    let mut hangup = signal::unix::signal(signal::unix::SignalKind::hangup())
+        .expect("failed to install signal handler");
+
+    while hangup.recv().await.is_some() {
+        info!("Received SIGHUP, reloading the config");
+        match services().globals.reload_config().await {
+            Ok(reload) => {
+                if !reload.restart_required.is_empty() {
+                    warn!(
+                        "Changes to these settings require a restart: {}",
+                        reload.restart_required.join(", ")
+                    );
+                }
+            }
+            Err(e) => error!("Failed to reload the config: {e}"),
+        }
+    }
+}
+
 async fn shutdown_signal(handle: ServerHandle) {
     let ctrl_c = async {
         signal::ctrl_c()
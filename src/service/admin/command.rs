@@ -44,6 +44,12 @@ pub enum AdminCommand {
     /// List all the currently registered appservices
     ListAppservices,
 
+    /// Show whether appservices are reachable and how many events are waiting for them
+    AppserviceStatus {
+        /// The appservice to inspect, all appservices are shown if omitted
+        appservice_identifier: Option<String>,
+    },
+
     /// Shows information about a room
     RoomInfo {
         /// The room id or alias to inspect
@@ -59,6 +65,15 @@ pub enum AdminCommand {
     /// List all rooms we are currently handling an incoming pdu from
     IncomingFederation,
 
+    /// List the pushers of a user, or of all users if none is given
+    ///
+    /// Also shows when a notification was last delivered to each pusher successfully and
+    /// when the last delivery failed, since the server was started.
+    ListPushers {
+        /// The user whose pushers should be listed
+        user_id: Option<Box<UserId>>,
+    },
+
     /// Removes an alias from the server
     RemoveAlias {
         /// The alias to be removed
@@ -213,6 +228,17 @@ pub enum AdminCommand {
         force_filehash: bool,
     },
 
+    /// Purges all media sent in messages of the specified room, except media which was also sent
+    /// in other rooms
+    PurgeMediaFromRoom {
+        room_id: Box<RoomId>,
+
+        #[arg(long, short)]
+        /// Also deletes other media with the same SHA256 hash, ensuring that the file is removed from
+        /// the media backend, so only use this when all the media sent in the room is undesirable
+        force_filehash: bool,
+    },
+
     /// Prevents the list of media from being accessed, but does not delete the media if it
     /// is already downloaded. If the media has already been downloaded, the sha256 hash
     /// is blocked, meaning that any other current or future uploads/downloads of the exact same
@@ -289,6 +315,24 @@ pub enum AdminCommand {
     /// Print database memory usage statistics
     MemoryUsage,
 
+    /// Create a backup of the database in the configured backup directory
+    ///
+    /// The server keeps running while the backup is created. Media is included if it's enabled in
+    /// the config or --media is given.
+    CreateBackup {
+        #[arg(long)]
+        /// Also back up the media files
+        media: bool,
+    },
+
+    /// Check the references between the trees of the database
+    ///
+    /// Finds e.g. event ids pointing to missing PDUs, wrong member counts, search tokens of
+    /// deleted events and missing media files. The server keeps running while checking, so
+    /// problems are only reported. Stop the server and run `conduit check-database --repair` to
+    /// repair them.
+    CheckDatabase,
+
     /// Clears all of Conduit's database caches with index smaller than the amount
     ClearDatabaseCaches { amount: u32 },
 
@@ -298,6 +342,13 @@ pub enum AdminCommand {
     /// Show configuration values
     ShowConfig,
 
+    /// Read the configuration again, applying the changes which don't require a restart
+    ///
+    /// Registration, guest access, TURN, well-known, media retention, the log filter, LDAP and
+    /// the admin socket permissions are changed immediately. The server also does this when
+    /// receiving SIGHUP.
+    ReloadConfig,
+
     /// Reset user password
     ResetPassword {
         /// Username of the user for whom the password should be reset
@@ -315,6 +366,9 @@ pub enum AdminCommand {
     /// Temporarily toggle user registration by passing either true or false as an argument, does not persist between restarts
     AllowRegistration { status: Option<bool> },
 
+    /// Temporarily toggle guest access by passing either true or false as an argument, does not persist between restarts
+    AllowGuestAccess { status: Option<bool> },
+
     /// Disables incoming federation handling for a room.
     DisableRoom { room_id: Box<RoomId> },
     /// Enables incoming federation handling for a room again.
@@ -331,6 +385,35 @@ pub enum AdminCommand {
     HashAndSignEvent { room_version_id: RoomVersionId },
 }
 
+impl AdminCommand {
+    /// Whether the command only shows information, without changing anything on the server
+    pub fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            Self::ListAppservices
+                | Self::AppserviceStatus { .. }
+                | Self::RoomInfo { .. }
+                | Self::ListRooms
+                | Self::ListLocalUsers
+                | Self::IncomingFederation
+                | Self::ListPushers { .. }
+                | Self::QueryMedia { .. }
+                | Self::ShowMedia { .. }
+                | Self::ListMedia { .. }
+                | Self::ListBlockedMedia
+                | Self::GetAuthChain { .. }
+                | Self::ParsePdu
+                | Self::GetPdu { .. }
+                | Self::MemoryUsage
+                | Self::CheckDatabase
+                | Self::ShowConfig
+                | Self::AllowRegistration { status: None }
+                | Self::AllowGuestAccess { status: None }
+                | Self::VerifyJson
+        )
+    }
+}
+
 #[derive(Args, Debug)]
 #[group(multiple = true, required = false)]
 pub struct DeactivatePurgeMediaArgs {
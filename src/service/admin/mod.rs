@@ -14,7 +14,7 @@ use clap::{Args, Parser};
 use image::GenericImageView;
 use regex::Regex;
 use ruma::{
-    api::appservice::Registration,
+    api::{appservice::Registration, client::push::PusherKind},
     events::{
         room::{
             canonical_alias::RoomCanonicalAliasEventContent,
@@ -36,7 +36,7 @@ use ruma::{
     },
     room_version_rules::RoomVersionRules,
     EventId, MilliSecondsSinceUnixEpoch, MxcUri, OwnedMxcUri, OwnedRoomAliasId, OwnedRoomId,
-    OwnedServerName, RoomAliasId, RoomId, RoomVersionId, ServerName, UserId,
+    OwnedServerName, OwnedUserId, RoomAliasId, RoomId, RoomVersionId, ServerName, UserId,
 };
 use serde_json::value::to_raw_value;
 use tokio::sync::{mpsc, Mutex, RwLock};
@@ -49,11 +49,13 @@ use crate::{
 };
 
 use super::{
+    appservice::UnstableRegistration,
     media::{
-        size, BlockedMediaInfo, FileInfo, MediaListItem, MediaQuery, MediaQueryFileInfo,
+        size, BlockedMediaInfo, FileInfo, FileMeta, MediaListItem, MediaQuery, MediaQueryFileInfo,
         MediaQueryThumbInfo, ServerNameOrUserId,
     },
     pdu::PduBuilder,
+    sending::OutgoingKind,
 };
 use command::{AdminCommand, DeactivatePurgeMediaArgs, ListMediaArgs};
 
@@ -63,6 +65,53 @@ pub mod command;
 
 
 
+/// The reply to an admin command
+pub struct CommandOutput {
+    pub message: MessageType,
+    /// Whether the command couldn't do what it was asked to. The message explains why, and is sent
+    /// to the admin room like any other reply.
+    pub failed: bool,
+    /// Machine readable form of the reply, for commands listing things
+    pub data: Option<serde_json::Value>,
+}
+
+impl CommandOutput {
+    fn failure(content: impl Into<MessageType>) -> Self {
+        Self {
+            message: content.into(),
+            failed: true,
+            data: None,
+        }
+    }
+
+    fn with_data(mut self, data: serde_json::Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    /// Marks the output as a failure if some of the work couldn't be done
+    fn failed_if(mut self, failed: bool) -> Self {
+        self.failed |= failed;
+        self
+    }
+}
+
+impl From<MessageType> for CommandOutput {
+    fn from(message: MessageType) -> Self {
+        Self {
+            message,
+            failed: false,
+            data: None,
+        }
+    }
+}
+
+impl From<RoomMessageEventContent> for CommandOutput {
+    fn from(content: RoomMessageEventContent) -> Self {
+        content.msgtype.into()
+    }
+}
+
 #[derive(Debug)]
 pub enum AdminRoomEvent {
     ProcessMessage(String),
@@ -171,7 +220,7 @@ impl Service {
         };
 
         match self.process_admin_command(admin_command, body).await {
-            Ok(reply_message) => reply_message,
+            Ok(reply_message) => reply_message.message,
             Err(error) => {
                 let markdown_message = format!(
                     "Encountered an error while handling the command:\n\
@@ -244,32 +293,52 @@ impl Service {
         &self,
         command: AdminCommand,
         body: Vec<&str>,
-    ) -> Result<MessageType> {
+    ) -> Result<CommandOutput> {
         let reply_message_content = match command {
             AdminCommand::RegisterAppservice => {
                 if body.len() > 2 && body[0].trim() == "```" && body.last().unwrap().trim() == "```"
                 {
                     let appservice_config = body[1..body.len() - 1].join("\n");
-                    let parsed_config = serde_yaml::from_str::<Registration>(&appservice_config);
+                    let parsed_config = serde_yaml::from_str::<Registration>(&appservice_config)
+                        .and_then(|yaml| {
+                            let unstable =
+                                serde_yaml::from_str::<UnstableRegistration>(&appservice_config)?;
+                            Ok((yaml, unstable))
+                        });
                     match parsed_config {
-                        Ok(yaml) => match services().appservice.register_appservice(yaml).await {
-                            Ok(id) => RoomMessageEventContent::text_plain(format!(
-                                "Appservice registered with ID: {id}."
-                            )),
-                            Err(e) => RoomMessageEventContent::text_plain(format!(
-                                "Failed to register appservice: {e}"
+                        Ok((yaml, unstable)) => match services()
+                            .appservice
+                            .register_appservice(yaml, unstable)
+                            .await
+                        {
+                            Ok(id) => RoomMessageEventContent::text_plain(
+                                match services().appservice.ping(&id).await {
+                                    Ok(Some(elapsed)) => format!(
+                                        "Appservice registered with ID: {id}. It responded to a ping in {elapsed:?}."
+                                    ),
+                                    Ok(None) => format!(
+                                        "Appservice registered with ID: {id}. It has no URL configured, so it won't receive any events."
+                                    ),
+                                    Err(e) => format!(
+                                        "Appservice registered with ID: {id}, but it could not be reached: {e}\n\
+                                        Events will be queued until it becomes reachable, see `appservice-status {id}`."
+                                    ),
+                                },
+                            )
+                            .into(),
+                            Err(e) => CommandOutput::failure(RoomMessageEventContent::text_plain(
+                                format!("Failed to register appservice: {e}"),
                             )),
                         },
-                        Err(e) => RoomMessageEventContent::text_plain(format!(
-                            "Could not parse appservice config: {e}"
+                        Err(e) => CommandOutput::failure(RoomMessageEventContent::text_plain(
+                            format!("Could not parse appservice config: {e}"),
                         )),
                     }
                 } else {
-                    RoomMessageEventContent::text_plain(
+                    CommandOutput::failure(RoomMessageEventContent::text_plain(
                         "Expected code block in command body. Add --help for details.",
-                    )
+                    ))
                 }
-                .into()
             }
             AdminCommand::UnregisterAppservice {
                 appservice_identifier,
@@ -278,12 +347,11 @@ impl Service {
                 .unregister_appservice(&appservice_identifier)
                 .await
             {
-                Ok(()) => RoomMessageEventContent::text_plain("Appservice unregistered."),
-                Err(e) => RoomMessageEventContent::text_plain(format!(
+                Ok(()) => RoomMessageEventContent::text_plain("Appservice unregistered.").into(),
+                Err(e) => CommandOutput::failure(RoomMessageEventContent::text_plain(format!(
                     "Failed to unregister appservice: {e}"
-                )),
-            }
-            .into(),
+                ))),
+            },
             AdminCommand::ListAppservices => {
                 let appservices = services().appservice.iter_ids().await;
                 let output = format!(
@@ -291,6 +359,54 @@ impl Service {
                     appservices.len(),
                     appservices.join(", ")
                 );
+                CommandOutput::from(RoomMessageEventContent::text_plain(output))
+                    .with_data(serde_json::json!(appservices))
+            }
+            AdminCommand::AppserviceStatus {
+                appservice_identifier,
+            } => {
+                let appservices = match appservice_identifier {
+                    Some(id) => {
+                        if services().appservice.get_registration(&id).await.is_none() {
+                            return Err(Error::AdminCommand("Appservice not found"));
+                        }
+                        vec![id]
+                    }
+                    None => services().appservice.iter_ids().await,
+                };
+
+                let format_time = |time: Option<SystemTime>| {
+                    time.map_or_else(
+                        || "never".to_owned(),
+                        |time| humantime::format_rfc3339_seconds(time).to_string(),
+                    )
+                };
+
+                let mut output = format!("Appservices ({}):", appservices.len());
+                for id in appservices {
+                    let health = services().appservice.health(&id);
+                    let queue_depth = services()
+                        .sending
+                        .queue_depth(&OutgoingKind::Appservice(id.clone()));
+
+                    let state = match health.unreachable_since {
+                        Some(since) => format!("unreachable since {}", format_time(Some(since))),
+                        None if health.last_success.is_some() => "reachable".to_owned(),
+                        None => "not contacted yet".to_owned(),
+                    };
+
+                    output += &format!(
+                        "\n\n{id}: {state}\n\
+                        Last successful request: {}\n\
+                        Queued events: {queue_depth}",
+                        format_time(health.last_success),
+                    );
+
+                    if let Some((time, error)) = health.last_failure {
+                        output += &format!("\nLast error ({}): {error}", format_time(Some(time)));
+                    }
+                }
+
                 RoomMessageEventContent::text_plain(output).into()
             }
 			AdminCommand::RoomInfo { room_id_or_alias } => {
@@ -314,9 +430,9 @@ impl Service {
                 };
 
                 if !services().rooms.metadata.exists(&room_id)? {
-                    return Ok(
-                        RoomMessageEventContent::text_plain("Room not found.").into(),
-                    );
+                    return Ok(CommandOutput::failure(
+                        RoomMessageEventContent::text_plain("Room not found."),
+                    ));
                 }
 
                 let shortstatehash = services().rooms.state.get_room_shortstatehash(&room_id)?
@@ -446,35 +562,49 @@ impl Service {
                 RoomMessageEventContent::text_plain(message).into()
             }
             AdminCommand::ListRooms => {
-                let room_ids = services().rooms.metadata.iter_ids();
+                let rooms = services()
+                    .rooms
+                    .metadata
+                    .iter_ids()
+                    .filter_map(|r| r.ok())
+                    .map(|id| {
+                        let members = services()
+                            .rooms
+                            .state_cache
+                            .room_joined_count(&id)
+                            .ok()
+                            .flatten()
+                            .unwrap_or(0);
+                        (id, members)
+                    })
+                    .collect::<Vec<_>>();
                 let output = format!(
                     "Rooms:\n{}",
-                    room_ids
-                        .filter_map(|r| r.ok())
-                        .map(|id| id.to_string()
-                            + "\tMembers: "
-                            + &services()
-                                .rooms
-                                .state_cache
-                                .room_joined_count(&id)
-                                .ok()
-                                .flatten()
-                                .unwrap_or(0)
-                                .to_string())
+                    rooms
+                        .iter()
+                        .map(|(id, members)| format!("{id}\tMembers: {members}"))
                         .collect::<Vec<_>>()
                         .join("\n")
                 );
-                RoomMessageEventContent::text_plain(output).into()
+                let data = rooms
+                    .iter()
+                    .map(|(id, members)| serde_json::json!({ "room_id": id, "members": members }))
+                    .collect();
+
+                CommandOutput::from(RoomMessageEventContent::text_plain(output))
+                    .with_data(serde_json::Value::Array(data))
             }
             AdminCommand::ListLocalUsers => match services().users.list_local_users() {
                 Ok(users) => {
                     let mut msg: String = format!("Found {} local user account(s):\n", users.len());
                     msg += &users.join("\n");
-                    RoomMessageEventContent::text_plain(&msg)
+                    CommandOutput::from(RoomMessageEventContent::text_plain(&msg))
+                        .with_data(serde_json::json!(users))
                 }
-                Err(e) => RoomMessageEventContent::text_plain(e.to_string()),
-            }
-            .into(),
+                Err(e) => {
+                    CommandOutput::failure(RoomMessageEventContent::text_plain(e.to_string()))
+                }
+            },
             AdminCommand::IncomingFederation => {
                 let map = services().globals.roomid_federationhandletime.read().await;
                 let mut msg: String = format!("Handling {} incoming pdus:\n", map.len());
@@ -491,6 +621,71 @@ impl Service {
                 }
                 RoomMessageEventContent::text_plain(&msg).into()
             }
+            AdminCommand::ListPushers { user_id } => {
+                let pushers = match user_id {
+                    Some(user_id) => {
+                        let user_id = OwnedUserId::from(user_id);
+                        services()
+                            .pusher
+                            .get_pushers(&user_id)?
+                            .into_iter()
+                            .map(|pusher| (user_id.clone(), pusher))
+                            .collect::<Vec<_>>()
+                    }
+                    None => services()
+                        .pusher
+                        .all_pushers()
+                        .collect::<Result<Vec<_>>>()?,
+                };
+
+                let mut msg = format!("Pushers ({}):\n", pushers.len());
+                let mut data = Vec::new();
+
+                for (user_id, pusher) in pushers {
+                    let status = services()
+                        .pusher
+                        .delivery_status(&user_id, &pusher.ids.pushkey)
+                        .unwrap_or_default();
+
+                    let last_success = status.last_success.map_or_else(
+                        || "never".to_owned(),
+                        |time| humantime::format_rfc3339_seconds(time).to_string(),
+                    );
+                    let last_failure = status.last_failure.map_or_else(
+                        || "never".to_owned(),
+                        |(time, error)| {
+                            format!(
+                                "{} ({error}, {} consecutive)",
+                                humantime::format_rfc3339_seconds(time),
+                                status.consecutive_failures
+                            )
+                        },
+                    );
+                    let url = match &pusher.kind {
+                        PusherKind::Http(http) => http.url.as_str(),
+                        PusherKind::Email(_) => "email",
+                        _ => "unknown",
+                    };
+
+                    msg += &format!(
+                        "{user_id} {} ({}, {url}): last success: {last_success}, last failure: {last_failure}\n",
+                        pusher.ids.app_id, pusher.device_display_name
+                    );
+
+                    data.push(serde_json::json!({
+                        "user_id": user_id,
+                        "app_id": pusher.ids.app_id,
+                        "pushkey": pusher.ids.pushkey,
+                        "device_display_name": pusher.device_display_name,
+                        "url": url,
+                        "last_success": last_success,
+                        "last_failure": last_failure,
+                    }));
+                }
+
+                CommandOutput::from(RoomMessageEventContent::text_plain(msg))
+                    .with_data(serde_json::Value::Array(data))
+            }
             AdminCommand::GetAuthChain { event_id } => {
                 let event_id = Arc::<EventId>::from(event_id);
                 if let Some(event) = services().rooms.timeline.get_pdu_json(&event_id)? {
@@ -601,6 +796,46 @@ impl Service {
                 ))
                 .into()
             }
+            AdminCommand::CreateBackup { media } => {
+                let include_media = media || services().globals.config.backup.media;
+
+                match services().backup.create(include_media).await {
+                    Ok(backup) => {
+                        let mut message = format!("Created backup at {}.", backup.path.display());
+
+                        if let Some(media) = backup.media {
+                            message += &format!(
+                                "\nMedia files: {} copied, {} linked from the previous backup, {} missing.",
+                                media.copied, media.linked, media.missing
+                            );
+                        }
+
+                        if backup.removed > 0 {
+                            message += &format!("\nRemoved {} old backups.", backup.removed);
+                        }
+
+                        RoomMessageEventContent::text_plain(message).into()
+                    }
+                    Err(e) => CommandOutput::failure(RoomMessageEventContent::text_plain(format!(
+                        "Failed to create backup: {e}"
+                    ))),
+                }
+            }
+            AdminCommand::CheckDatabase => {
+                let check = tokio::task::spawn_blocking(|| services().globals.db.check_database())
+                    .await
+                    .expect("checking the database doesn't panic");
+
+                match check {
+                    Ok(check) if check.remaining_problems() > 0 => CommandOutput::failure(
+                        RoomMessageEventContent::text_plain(check.to_string()),
+                    ),
+                    Ok(check) => RoomMessageEventContent::text_plain(check.to_string()).into(),
+                    Err(e) => CommandOutput::failure(RoomMessageEventContent::text_plain(format!(
+                        "Failed to check the database: {e}"
+                    ))),
+                }
+            }
             AdminCommand::ClearDatabaseCaches { amount } => {
                 services().globals.db.clear_caches(amount);
 
@@ -615,6 +850,12 @@ impl Service {
                 // Construct and send the response
                 RoomMessageEventContent::text_plain(format!("{}", services().globals.config)).into()
             }
+            AdminCommand::ReloadConfig => match services().globals.reload_config().await {
+                Ok(reload) => RoomMessageEventContent::text_plain(reload.to_string()).into(),
+                Err(e) => CommandOutput::failure(RoomMessageEventContent::text_plain(format!(
+                    "Failed to reload the config: {e}"
+                ))),
+            },
             AdminCommand::ResetPassword { username } => {
                 let user_id = match UserId::parse_with_server_name(
                     username.as_str().to_lowercase(),
@@ -622,19 +863,19 @@ impl Service {
                 ) {
                     Ok(id) => id,
                     Err(e) => {
-                        return Ok(RoomMessageEventContent::text_plain(format!(
-                            "The supplied username is not a valid username: {e}"
+                        return Ok(CommandOutput::failure(
+                            RoomMessageEventContent::text_plain(format!(
+                                "The supplied username is not a valid username: {e}"
+                            )),
                         ))
-                        .into())
                     }
                 };
 
                 // Checks if user is local
                 if user_id.server_name() != services().globals.server_name() {
-                    return Ok(RoomMessageEventContent::text_plain(
+                    return Ok(CommandOutput::failure(RoomMessageEventContent::text_plain(
                         "The specified user is not from this server!",
-                    )
-                    .into());
+                    )));
                 };
 
                 // Check if the specified user is valid
@@ -646,10 +887,9 @@ impl Service {
                         )
                         .expect("conduit user exists")
                 {
-                    return Ok(RoomMessageEventContent::text_plain(
-                        "The specified user does not exist!",
-                    )
-                    .into());
+                    return Ok(CommandOutput::failure(
+                        RoomMessageEventContent::text_plain("The specified user does not exist!"),
+                    ));
                 }
 
                 let new_password = utils::random_string(AUTO_GEN_PASSWORD_LENGTH);
@@ -660,12 +900,12 @@ impl Service {
                 {
                     Ok(()) => RoomMessageEventContent::text_plain(format!(
                         "Successfully reset the password for user {user_id}: {new_password}"
-                    )),
-                    Err(e) => RoomMessageEventContent::text_plain(format!(
+                    ))
+                    .into(),
+                    Err(e) => CommandOutput::failure(RoomMessageEventContent::text_plain(format!(
                         "Couldn't reset the password for user {user_id}: {e}"
-                    )),
+                    ))),
                 }
-                .into()
             }
             AdminCommand::CreateUser { username, password } => {
                 let password =
@@ -677,32 +917,32 @@ impl Service {
                 ) {
                     Ok(id) => id,
                     Err(e) => {
-                        return Ok(RoomMessageEventContent::text_plain(format!(
-                            "The supplied username is not a valid username: {e}"
+                        return Ok(CommandOutput::failure(
+                            RoomMessageEventContent::text_plain(format!(
+                                "The supplied username is not a valid username: {e}"
+                            )),
                         ))
-                        .into())
                     }
                 };
 
                 // Checks if user is local
                 if user_id.server_name() != services().globals.server_name() {
-                    return Ok(RoomMessageEventContent::text_plain(
+                    return Ok(CommandOutput::failure(RoomMessageEventContent::text_plain(
                         "The specified user is not from this server!",
-                    )
-                    .into());
+                    )));
                 };
 
                 if user_id.is_historical() {
-                    return Ok(RoomMessageEventContent::text_plain(format!(
-                        "Userid {user_id} is not allowed due to historical"
-                    ))
-                    .into());
+                    return Ok(CommandOutput::failure(
+                        RoomMessageEventContent::text_plain(format!(
+                            "Userid {user_id} is not allowed due to historical"
+                        )),
+                    ));
                 }
                 if services().users.exists(&user_id)? {
-                    return Ok(RoomMessageEventContent::text_plain(format!(
-                        "Userid {user_id} already exists"
-                    ))
-                    .into());
+                    return Ok(CommandOutput::failure(RoomMessageEventContent::text_plain(
+                        format!("Userid {user_id} already exists"),
+                    )));
                 }
                 // Create user
                 services().users.create(&user_id, Some(password.as_str()))?;
@@ -759,6 +999,23 @@ impl Service {
                 )
             }
             .into(),
+            AdminCommand::AllowGuestAccess { status } => if let Some(status) = status {
+                services().globals.set_guest_access(status).await;
+                RoomMessageEventContent::text_plain(if status {
+                    "Guest access is now enabled"
+                } else {
+                    "Guest access is now disabled"
+                })
+            } else {
+                RoomMessageEventContent::text_plain(
+                    if services().globals.allow_guest_access().await {
+                        "Guest access is currently enabled"
+                    } else {
+                        "Guest access is currently disabled"
+                    },
+                )
+            }
+            .into(),
             AdminCommand::DisableRoom { room_id } => {
                 services().rooms.metadata.disable_room(&room_id, true)?;
                 RoomMessageEventContent::text_plain("Room disabled.").into()
@@ -774,13 +1031,13 @@ impl Service {
             } => {
                 let user_id = Arc::<UserId>::from(user_id);
                 if !services().users.exists(&user_id)? {
-                    RoomMessageEventContent::text_plain(format!(
+                    CommandOutput::failure(RoomMessageEventContent::text_plain(format!(
                         "User {user_id} doesn't exist on this server"
-                    ))
+                    )))
                 } else if user_id.server_name() != services().globals.server_name() {
-                    RoomMessageEventContent::text_plain(format!(
+                    CommandOutput::failure(RoomMessageEventContent::text_plain(format!(
                         "User {user_id} is not from this server"
-                    ))
+                    )))
                 } else {
                     RoomMessageEventContent::text_plain(format!(
                         "Making {user_id} leave all rooms before deactivation..."
@@ -816,7 +1073,8 @@ impl Service {
                         "User {user_id} has been deactivated, but {failed_purged_media} media failed to be purged, check the logs for more details"
                     ))
                     }
-                }.into()
+                    .into()
+                }
             }
             AdminCommand::DeactivateAll {
                 leave_rooms,
@@ -888,17 +1146,19 @@ impl Service {
                         ))
                     }
 
-                    RoomMessageEventContent::text_plain(message)
+                    CommandOutput::from(RoomMessageEventContent::text_plain(message))
+                        .failed_if(failed_count != 0)
                 } else {
-                    RoomMessageEventContent::text_plain(
+                    CommandOutput::failure(RoomMessageEventContent::text_plain(
                         "Expected code block in command body. Add --help for details.",
-                    )
+                    ))
                 }
-                .into()
             }
             AdminCommand::QueryMedia { mxc } => {
                 let Ok((server_name, media_id)) = mxc.parts() else {
-                    return Ok(RoomMessageEventContent::text_plain("Invalid media MXC").into());
+                    return Ok(CommandOutput::failure(
+                        RoomMessageEventContent::text_plain("Invalid media MXC"),
+                    ));
                 };
 
                 let MediaQuery {
@@ -997,16 +1257,44 @@ impl Service {
             }
             AdminCommand::ShowMedia { mxc } => {
                 let Ok((server_name, media_id)) = mxc.parts() else {
-                    return Ok(RoomMessageEventContent::text_plain("Invalid media MXC").into());
+                    return Ok(CommandOutput::failure(
+                        RoomMessageEventContent::text_plain("Invalid media MXC"),
+                    ));
+                };
+
+                // Admins need to be able to review media which was blocked, or sent in rooms they
+                // aren't in, so local media is read directly
+                let local_media = if server_name == services().globals.server_name() {
+                    services().media.get(server_name, media_id, true).await?
+                } else {
+                    None
                 };
 
-                // TODO: Bypass blocking once MSC3911 is implemented (linking media to events)
                 let ruma::api::client::authenticated_media::get_content::v1::Response {
                     file,
                     content_type,
                     content_disposition,
-                } = client_server::media::get_content(server_name, media_id.to_owned(), true, true)
-                    .await?;
+                } = match local_media {
+                    Some(FileMeta {
+                        content_disposition,
+                        content_type,
+                        file,
+                    }) => ruma::api::client::authenticated_media::get_content::v1::Response {
+                        file,
+                        content_type,
+                        content_disposition: Some(content_disposition),
+                    },
+                    None => {
+                        client_server::media::get_content(
+                            server_name,
+                            media_id.to_owned(),
+                            Duration::ZERO,
+                            true,
+                            true,
+                        )
+                        .await?
+                    }
+                };
 
                 if let Ok(image) = image::load_from_memory(&file) {
                     let filename = content_disposition.and_then(|cd| cd.filename);
@@ -1044,6 +1332,7 @@ impl Service {
                         })),
                     })
                 }
+                .into()
             }
             AdminCommand::ListMedia {
                 user_server_filter: ListMediaArgs { user, server },
@@ -1055,6 +1344,7 @@ impl Service {
                 let mut markdown_message = String::from(
                     "| MXC URI | Dimensions (if thumbnail) | Created/Downloaded at | Uploader | Content-Type | Filename | Size |\n| --- | --- | --- | --- | --- | --- | --- |",
                 );
+                let mut data = Vec::new();
                 let mut html_message = String::from(
                     r#"<table><thead><tr><th scope="col">MXC URI</th><th scope="col">Dimensions (if thumbnail)</th><th scope="col">Created/Downloaded at</th><th scope="col">Uploader</th><th scope="col">Content-Type</th><th scope="col">Filename</th><th scope="col">Size</th></tr></thead><tbody>"#,
                 );
@@ -1094,6 +1384,7 @@ impl Service {
                     let dimensions = dimensions
                         .map(|(w, h)| format!("{w}x{h}"))
                         .unwrap_or_default();
+                    let bytes = size;
                     let size = ByteSize::b(size).display().si();
                     let creation =
                         DateTime::from_timestamp(creation.try_into().unwrap_or(i64::MAX), 0)
@@ -1104,25 +1395,40 @@ impl Service {
 
                     html_message.push_str(&format!(
                         "<tr><td>mxc://{server_name}/{media_id}</td><td>{dimensions}</td><td>{creation}</td><td>{user_id}</td><td>{content_type}</td><td>{filename}</td><td>{size}</td></tr>"
-                    ))
+                    ));
+
+                    data.push(serde_json::json!({
+                        "mxc": format!("mxc://{server_name}/{media_id}"),
+                        "dimensions": dimensions,
+                        "created": creation.to_rfc3339(),
+                        "uploader": user_id,
+                        "content_type": content_type,
+                        "filename": filename,
+                        "size": bytes,
+                    }));
                 }
 
                 html_message.push_str("</tbody></table>");
 
-                RoomMessageEventContent::text_html(markdown_message, html_message).into()
+                CommandOutput::from(RoomMessageEventContent::text_html(
+                    markdown_message,
+                    html_message,
+                ))
+                .with_data(serde_json::Value::Array(data))
             }
             AdminCommand::PurgeMedia => match media_from_body(body) {
                 Ok(media) => {
                     let failed_count = services().media.purge(&media, true).await.len();
 
-                    if failed_count == 0 {
+                    let output: CommandOutput = if failed_count == 0 {
                         RoomMessageEventContent::text_plain("Successfully purged media")
                     } else {
                         RoomMessageEventContent::text_plain(format!(
                             "Failed to delete {failed_count} media, check logs for more details"
                         ))
                     }
-                    .into()
+                    .into();
+                    output.failed_if(failed_count != 0)
                 }
                 Err(message) => message,
             },
@@ -1149,19 +1455,19 @@ impl Service {
                             .len();
                     }
 
-                    if failed_count == 0 {
+                    CommandOutput::from(if failed_count == 0 {
                         RoomMessageEventContent::text_plain("Successfully purged media")
                     } else {
                         RoomMessageEventContent::text_plain(format!(
                             "Failed to purge {failed_count} media, check logs for more details"
                         ))
-                    }
+                    })
+                    .failed_if(failed_count != 0)
                 } else {
-                    RoomMessageEventContent::text_plain(
+                    CommandOutput::failure(RoomMessageEventContent::text_plain(
                         "Expected code block in command body. Add --help for details.",
-                    )
+                    ))
                 }
-                .into()
             }
             AdminCommand::PurgeMediaFromServer {
                 server_id: server_name,
@@ -1182,7 +1488,7 @@ impl Service {
                     .await
                     .len();
 
-                if failed_count == 0 {
+                CommandOutput::from(if failed_count == 0 {
                     RoomMessageEventContent::text_plain(format!(
                         "Media from {server_name} has successfully been purged"
                     ))
@@ -1190,8 +1496,29 @@ impl Service {
                     RoomMessageEventContent::text_plain(format!(
                         "Failed to purge {failed_count} media, check logs for more details"
                     ))
-                }
-                .into()
+                })
+                .failed_if(failed_count != 0)
+            }
+            AdminCommand::PurgeMediaFromRoom {
+                room_id,
+                force_filehash,
+            } => {
+                let failed_count = services()
+                    .media
+                    .purge_from_room(&room_id, force_filehash)
+                    .await
+                    .len();
+
+                CommandOutput::from(if failed_count == 0 {
+                    RoomMessageEventContent::text_plain(format!(
+                        "Media from {room_id} has successfully been purged"
+                    ))
+                } else {
+                    RoomMessageEventContent::text_plain(format!(
+                        "Failed to purge {failed_count} media, check logs for more details"
+                    ))
+                })
+                .failed_if(failed_count != 0)
             }
             AdminCommand::BlockMedia { and_purge, reason } => match media_from_body(body) {
                 Ok(media) => {
@@ -1202,7 +1529,7 @@ impl Service {
                         0
                     };
 
-                    match (failed_count == 0, failed_purge_count == 0) {
+                    let output: CommandOutput = match (failed_count == 0, failed_purge_count == 0) {
                         (true, true) => RoomMessageEventContent::text_plain("Successfully blocked media"),
                         (false, true) => RoomMessageEventContent::text_plain(format!(
                             "Failed to block {failed_count} media, check logs for more details"
@@ -1213,7 +1540,8 @@ impl Service {
                         (false, false) => RoomMessageEventContent::text_plain(format!(
                             "Failed to block {failed_count}, and purge {failed_purge_count} media, check logs for more details"
                         ))
-                    }.into()
+                    }.into();
+                    output.failed_if(failed_count != 0 || failed_purge_count != 0)
                 }
                 Err(message) => message,
             },
@@ -1241,24 +1569,25 @@ impl Service {
                             .len();
                     }
 
-                    if failed_count == 0 {
+                    CommandOutput::from(if failed_count == 0 {
                         RoomMessageEventContent::text_plain("Successfully blocked media")
                     } else {
                         RoomMessageEventContent::text_plain(format!(
                             "Failed to block {failed_count} media, check logs for more details"
                         ))
-                    }
+                    })
+                    .failed_if(failed_count != 0)
                 } else {
-                    RoomMessageEventContent::text_plain(
+                    CommandOutput::failure(RoomMessageEventContent::text_plain(
                         "Expected code block in command body. Add --help for details.",
-                    )
+                    ))
                 }
-                .into()
             }
             AdminCommand::ListBlockedMedia => {
                 let mut markdown_message = String::from(
                     "| SHA256 hash | MXC URI | Time Blocked | Reason |\n| --- | --- | --- | --- |",
                 );
+                let mut data = Vec::new();
                 let mut html_message = String::from(
                     r#"<table><thead><tr><th scope="col">SHA256 hash</th><th scope="col">MXC URI</th><th scope="col">Time Blocked</th><th scope="col">Reason</th></tr></thead><tbody>"#,
                 );
@@ -1290,26 +1619,38 @@ impl Service {
 
                     html_message.push_str(&format!(
                         "<tr><td>{sha256_hex}</td><td>mxc://{server_name}/{media_id}</td><td>{time}</td><td>{reason}</td></tr>",
-                    ))
+                    ));
+
+                    data.push(serde_json::json!({
+                        "sha256": sha256_hex,
+                        "mxc": format!("mxc://{server_name}/{media_id}"),
+                        "blocked_at": time.to_rfc3339(),
+                        "reason": reason,
+                    }));
                 }
 
                 html_message.push_str("</tbody></table>");
 
-                RoomMessageEventContent::text_html(markdown_message, html_message).into()
+                CommandOutput::from(RoomMessageEventContent::text_html(
+                    markdown_message,
+                    html_message,
+                ))
+                .with_data(serde_json::Value::Array(data))
             }
             AdminCommand::UnblockMedia => media_from_body(body).map_or_else(
                 |message| message,
                 |media| {
                     let failed_count = services().media.unblock(&media).len();
 
-                    if failed_count == 0 {
+                    let output: CommandOutput = if failed_count == 0 {
                         RoomMessageEventContent::text_plain("Successfully unblocked media")
                     } else {
                         RoomMessageEventContent::text_plain(format!(
                             "Failed to unblock {failed_count} media, check logs for more details"
                         ))
                     }
-                    .into()
+                    .into();
+                    output.failed_if(failed_count != 0)
                 },
             ),
             AdminCommand::SignJson => {
@@ -1435,16 +1776,18 @@ impl Service {
             }
             AdminCommand::RemoveAlias { alias } => {
                 if alias.server_name() != services().globals.server_name() {
-                    RoomMessageEventContent::text_plain(
+                    CommandOutput::failure(RoomMessageEventContent::text_plain(
                         "Cannot remove alias which is not from this server",
-                    )
+                    ))
                 } else if services()
                     .rooms
                     .alias
                     .resolve_local_alias(&alias)?
                     .is_none()
                 {
-                    RoomMessageEventContent::text_plain("No such alias exists")
+                    CommandOutput::failure(RoomMessageEventContent::text_plain(
+                        "No such alias exists",
+                    ))
                 } else {
                     // We execute this as the server user for two reasons
                     // 1. If the user can execute commands in the admin room, they can always remove the alias.
@@ -1455,9 +1798,8 @@ impl Service {
                         .rooms
                         .alias
                         .remove_alias(&alias, services().globals.server_user())?;
-                    RoomMessageEventContent::text_plain("Alias removed successfully")
+                    RoomMessageEventContent::text_plain("Alias removed successfully").into()
                 }
-                .into()
             }
         };
 
@@ -1924,6 +2266,58 @@ impl Service {
         Ok(())
     }
 
+    /// Removes the user from the conduit admin room, revoking their admin privileges.
+    pub(crate) async fn revoke_user_admin(&self, user_id: &UserId) -> Result<()> {
+        let Some(room_id) = services().admin.get_admin_room()? else {
+            return Ok(());
+        };
+
+        if !services().rooms.state_cache.is_joined(user_id, &room_id)? {
+            return Ok(());
+        }
+
+        let mutex_state = Arc::clone(
+            services()
+                .globals
+                .roomid_mutex_state
+                .write()
+                .await
+                .entry(room_id.clone())
+                .or_default(),
+        );
+        let state_lock = mutex_state.lock().await;
+
+        services()
+            .rooms
+            .timeline
+            .build_and_append_pdu(
+                PduBuilder {
+                    event_type: TimelineEventType::RoomMember,
+                    content: to_raw_value(&RoomMemberEventContent {
+                        membership: MembershipState::Leave,
+                        displayname: None,
+                        avatar_url: None,
+                        is_direct: None,
+                        third_party_invite: None,
+                        blurhash: None,
+                        reason: Some("Admin privileges revoked".to_owned()),
+                        join_authorized_via_users_server: None,
+                    })
+                    .expect("event is valid, we just created it"),
+                    unsigned: None,
+                    state_key: Some(user_id.to_string()),
+                    redacts: None,
+                    timestamp: None,
+                },
+                services().globals.server_user(),
+                &room_id,
+                &state_lock,
+            )
+            .await?;
+
+        Ok(())
+    }
+
     /// Checks whether a given user is an admin of this server
     pub fn user_is_admin(&self, user_id: &UserId) -> Result<bool> {
         let Some(admin_room) = self.get_admin_room()? else {
@@ -1936,7 +2330,7 @@ impl Service {
 
 fn userids_from_body<'a>(
     body: &'a [&'a str],
-) -> Result<Result<Vec<&'a UserId>, MessageType>, Error> {
+) -> Result<Result<Vec<&'a UserId>, CommandOutput>, Error> {
     let users = body.to_owned().drain(1..body.len() - 1).collect::<Vec<_>>();
 
     let mut user_ids = Vec::new();
@@ -1994,17 +2388,15 @@ fn userids_from_body<'a>(
         html_message.push_str("</pre>\n\n");
     }
     if !markdown_message.is_empty() {
-        return Ok(Err(RoomMessageEventContent::text_html(
-            markdown_message,
-            html_message,
-        )
-        .into()));
+        return Ok(Err(CommandOutput::failure(
+            RoomMessageEventContent::text_html(markdown_message, html_message),
+        )));
     }
 
     Ok(Ok(user_ids))
 }
 
-fn media_from_body(body: Vec<&str>) -> Result<Vec<(OwnedServerName, String)>, MessageType> {
+fn media_from_body(body: Vec<&str>) -> Result<Vec<(OwnedServerName, String)>, CommandOutput> {
     if body.len() > 2 && body[0].trim() == "```" && body.last().unwrap().trim() == "```" {
         Ok(body
             .clone()
@@ -2017,10 +2409,9 @@ fn media_from_body(body: Vec<&str>) -> Result<Vec<(OwnedServerName, String)>, Me
             })
             .collect::<Vec<_>>())
     } else {
-        Err(RoomMessageEventContent::text_plain(
+        Err(CommandOutput::failure(RoomMessageEventContent::text_plain(
             "Expected code block in command body. Add --help for details.",
-        )
-        .into())
+        )))
     }
 }
 
@@ -2062,4 +2453,17 @@ mod test {
         assert!(error.contains("Commands:"));
         assert!(error.contains("Options:"));
     }
+
+    #[test]
+    fn read_only_commands() {
+        let parse = |argv: &[&str]| {
+            AdminCommand::try_parse_from(["argv[0] doesn't matter"].iter().chain(argv)).unwrap()
+        };
+
+        assert!(parse(&["list-rooms"]).is_read_only());
+        assert!(parse(&["check-database"]).is_read_only());
+        assert!(parse(&["allow-registration"]).is_read_only());
+        assert!(!parse(&["allow-registration", "true"]).is_read_only());
+        assert!(!parse(&["create-user", "alice"]).is_read_only());
+    }
 }
@@ -1,11 +1,49 @@
-use std::sync::Arc;
-use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{UnixListener, UnixStream}};
-use crate::{Result, services};
-use super::command::AdminCommand;
+use std::{io, path::PathBuf, sync::Arc};
+
 use clap::Parser;
+use serde::Serialize;
+use tokio::{
+    fs::OpenOptions,
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{unix::UCred, UnixListener, UnixStream},
+};
+use tracing::{debug, error, info, warn};
+
+use self::protocol::{Request, Response, Status};
+use super::command::AdminCommand;
+use crate::{services, utils, Result};
+
+pub mod protocol;
+
+/// What a client of the admin socket may do
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Role {
+    /// May run every command
+    Admin,
+    /// May only run commands which show information
+    ReadOnly,
+}
+
+/// A line of the audit log
+#[derive(Serialize)]
+struct AuditEntry<'a> {
+    /// Milliseconds since the unix epoch
+    time: u64,
+    uid: u32,
+    gid: u32,
+    pid: Option<i32>,
+    /// `None` if the client wasn't allowed to use the socket at all
+    role: Option<Role>,
+    command: &'a str,
+    status: Status,
+}
 
 pub struct Service {
     listener: UnixListener,
+    /// The user Conduit runs as, which may always use the socket
+    server_uid: u32,
+    default_audit_log: PathBuf,
 }
 
 impl Service {
@@ -14,6 +52,8 @@ impl Service {
         let listener = UnixListener::bind(path)?;
         Ok(Arc::new(Self {
             listener,
+            server_uid: nix::unistd::geteuid().as_raw(),
+            default_audit_log: PathBuf::from(&config.database_path).join("admin-audit.log"),
         }))
     }
 
@@ -24,66 +64,229 @@ impl Service {
         });
     }
 
-    async fn run(&self) {
+    async fn run(self: Arc<Self>) {
         loop {
             match self.listener.accept().await {
                 Ok((stream, _)) => {
-                    self.handle_connection(stream).await;
+                    let service = Arc::clone(&self);
+                    tokio::spawn(async move {
+                        service.handle_connection(stream).await;
+                    });
                 }
                 Err(e) => {
-                    tracing::error!("Failed to accept admin socket connection: {}", e);
+                    error!("Failed to accept admin socket connection: {}", e);
                 }
             }
         }
     }
 
+    /// Answers requests until the client closes the connection. Clients which aren't allowed to
+    /// use the socket get one answer saying so, after which the connection is closed.
     async fn handle_connection(&self, mut stream: UnixStream) {
-        let mut buffer = Vec::new();
-        if let Err(e) = stream.read_to_end(&mut buffer).await {
-            tracing::error!("Failed to read from admin socket: {}", e);
-            return;
+        let peer = match stream.peer_cred() {
+            Ok(peer) => peer,
+            Err(e) => {
+                error!(
+                    "Failed to get the credentials of an admin socket client: {}",
+                    e
+                );
+                return;
+            }
+        };
+        let role = self.role(&peer);
+        if role.is_none() {
+            warn!(
+                uid = peer.uid(),
+                gid = peer.gid(),
+                "Rejecting admin socket client which isn't allowed to use it"
+            );
         }
 
-        let command_str = String::from_utf8_lossy(&buffer);
-        let mut lines = command_str.lines();
-        let command_line = lines.next().unwrap_or("");
-        let body = lines.collect::<Vec<&str>>();
+        loop {
+            let request = match read_request(&mut stream).await {
+                Ok(Some(request)) => request,
+                Ok(None) => return,
+                Err(e) => {
+                    error!("Failed to read from admin socket: {}", e);
+                    return;
+                }
+            };
+
+            let response = match role {
+                Some(role) => self.process(&request, role).await,
+                None => Response::new(
+                    Status::Forbidden,
+                    "You are not allowed to use the admin socket",
+                ),
+            };
+            self.audit(&peer, role, &request, response.status).await;
+
+            let frame = match protocol::encode(&response) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    // E.g. the output is larger than a frame can be, so the client still gets an
+                    // answer to its request instead of a closed connection
+                    error!("Failed to encode admin socket response: {}", e);
+                    protocol::encode(&Response::new(
+                        Status::Error,
+                        format!("The output of the command can't be sent: {e}"),
+                    ))
+                    .expect("short responses can be encoded")
+                }
+            };
+            if let Err(e) = stream.write_all(&frame).await {
+                // This can happen if the client disconnects early
+                debug!("Failed to write to admin socket: {}", e);
+                return;
+            }
+
+            if role.is_none() {
+                return;
+            }
+        }
+    }
 
-        let mut argv = match shell_words::split(command_line) {
+    /// The role of a client, `None` if it may not use the socket at all. Only the primary group
+    /// of the client is known, supplementary groups are not taken into account.
+    fn role(&self, peer: &UCred) -> Option<Role> {
+        let config = services().globals.admin_socket();
+        let (uid, gid) = (peer.uid(), peer.gid());
+
+        if uid == 0
+            || uid == self.server_uid
+            || config.allowed_uids.contains(&uid)
+            || config.allowed_gids.contains(&gid)
+        {
+            Some(Role::Admin)
+        } else if config.read_only_uids.contains(&uid) || config.read_only_gids.contains(&gid) {
+            Some(Role::ReadOnly)
+        } else {
+            None
+        }
+    }
+
+    async fn process(&self, request: &Request, role: Role) -> Response {
+        let mut argv = match shell_words::split(&request.command) {
             Ok(argv) => argv,
             Err(e) => {
-                tracing::error!("Failed to parse admin command: {}", e);
-                return;
+                return Response::new(
+                    Status::Invalid,
+                    format!("Failed to parse admin command: {e}"),
+                )
             }
         };
-        argv.insert(0, "conduit-admin".to_string());
+        argv.insert(0, "conduit-admin".to_owned());
 
         let admin_command = match AdminCommand::try_parse_from(&argv) {
             Ok(command) => command,
             Err(e) => {
-                if e.kind() == clap::error::ErrorKind::DisplayHelp {
-                    let _ = stream.write_all(e.to_string().as_bytes()).await;
-                } else {
-                    tracing::error!("Failed to parse admin command: {}", e);
-                }
-                return;
+                let status = match e.kind() {
+                    clap::error::ErrorKind::DisplayHelp
+                    | clap::error::ErrorKind::DisplayVersion => Status::Help,
+                    _ => Status::Invalid,
+                };
+                return Response::new(status, e.to_string());
             }
         };
 
-        let result = services().admin.process_admin_command(admin_command, body).await;
+        if role == Role::ReadOnly && !admin_command.is_read_only() {
+            return Response::new(
+                Status::Forbidden,
+                "You are only allowed to run commands which show information",
+            );
+        }
 
-        let response = match result {
-            Ok(message) => {
-                format!("{message:?}")
-            }
-            Err(e) => {
-                format!("Error: {e}")
-            }
+        let body = request.body.lines().collect();
+
+        match services()
+            .admin
+            .process_admin_command(admin_command, body)
+            .await
+        {
+            Ok(output) => Response {
+                status: if output.failed {
+                    Status::Failed
+                } else {
+                    Status::Ok
+                },
+                text: output.message.body().to_owned(),
+                data: output.data,
+            },
+            Err(e) => Response::new(Status::Error, format!("Error: {e}")),
+        }
+    }
+
+    /// Records a request and the status of its response in the log and the audit log
+    async fn audit(&self, peer: &UCred, role: Option<Role>, request: &Request, status: Status) {
+        let command = redact(&request.command);
+        info!(
+            uid = peer.uid(),
+            gid = peer.gid(),
+            pid = ?peer.pid(),
+            ?role,
+            command = %command,
+            ?status,
+            "Admin socket command"
+        );
+
+        let entry = AuditEntry {
+            time: utils::millis_since_unix_epoch(),
+            uid: peer.uid(),
+            gid: peer.gid(),
+            pid: peer.pid(),
+            role,
+            command: &command,
+            status,
         };
+        let mut line = serde_json::to_vec(&entry).expect("audit entries can be serialized");
+        line.push(b'\n');
+
+        let path = services()
+            .globals
+            .admin_socket()
+            .audit_log
+            .map_or_else(|| self.default_audit_log.clone(), PathBuf::from);
+        let result = async {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await?;
+            file.write_all(&line).await?;
+            file.flush().await
+        }
+        .await;
+        if let Err(e) = result {
+            error!(
+                "Failed to write to the admin audit log at {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+}
+
+/// Reads one request, returning `None` if the client closed the connection
+async fn read_request(stream: &mut UnixStream) -> io::Result<Option<Request>> {
+    let length = match stream.read_u32().await {
+        Ok(length) => length,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let mut payload = vec![0; protocol::check_length(length)?];
+    stream.read_exact(&mut payload).await?;
+    protocol::decode(&payload).map(Some)
+}
 
-        if let Err(e) = stream.write_all(response.as_bytes()).await {
-            // This can happen if the client disconnects early
-            tracing::debug!("Failed to write to admin socket: {}", e);
+/// The command line as it is recorded, without the password `create-user` may be given
+fn redact(command: &str) -> String {
+    match shell_words::split(command) {
+        Ok(mut argv) if argv.len() > 2 && argv[0] == "create-user" => {
+            argv.truncate(2);
+            argv.push("<redacted>".to_owned());
+            shell_words::join(argv)
         }
+        _ => command.to_owned(),
     }
 }
@@ -0,0 +1,128 @@
+//! Protocol spoken on the admin socket
+//!
+//! Every message is sent as a frame: the length of the payload as a big-endian `u32`, followed by
+//! the payload, which is a JSON object. The client sends a [`Request`] and the server answers with
+//! exactly one [`Response`]. A connection can be used for any number of requests.
+
+use std::io::{self, Read, Write};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// Frames larger than this are rejected, so that a bogus length can't make us allocate gigabytes
+pub const MAX_FRAME_SIZE: u32 = 16 * 1024 * 1024;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Request {
+    /// The command line, as it would be written in the admin room after the bot's name
+    pub command: String,
+    /// The lines following the command line, e.g. the code block of `register-appservice`
+    #[serde(default)]
+    pub body: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// The command was run and did what it was asked to
+    Ok,
+    /// The command was run, but couldn't do what it was asked to
+    Failed,
+    /// An internal error happened while running the command
+    Error,
+    /// The command couldn't be parsed, the text explains why
+    Invalid,
+    /// The help of a command was requested, the text is the help
+    Help,
+    /// The client isn't allowed to run the command
+    Forbidden,
+}
+
+impl Status {
+    /// The exit code of `conduit-admin` for a response with this status
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Status::Ok | Status::Help => 0,
+            Status::Failed | Status::Error => 1,
+            Status::Invalid => 2,
+            Status::Forbidden => 4,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Response {
+    pub status: Status,
+    /// The reply meant for humans, as it would be sent to the admin room
+    pub text: String,
+    /// The reply meant for scripts, only set by commands which list things
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+}
+
+impl Response {
+    pub fn new(status: Status, text: impl Into<String>) -> Self {
+        Self {
+            status,
+            text: text.into(),
+            data: None,
+        }
+    }
+}
+
+/// Serializes a message and prefixes it with its length
+pub fn encode(message: &impl Serialize) -> io::Result<Vec<u8>> {
+    let payload = serde_json::to_vec(message)?;
+    let length = u32::try_from(payload.len())
+        .ok()
+        .filter(|length| *length <= MAX_FRAME_SIZE)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Message of {} bytes is larger than the maximum of {MAX_FRAME_SIZE}",
+                    payload.len()
+                ),
+            )
+        })?;
+
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&length.to_be_bytes());
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+/// Checks the length announced at the start of a frame
+pub fn check_length(length: u32) -> io::Result<usize> {
+    if length > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Frame of {length} bytes is larger than the maximum of {MAX_FRAME_SIZE}"),
+        ));
+    }
+    Ok(length as usize)
+}
+
+/// Deserializes the payload of a frame
+pub fn decode<T: DeserializeOwned>(payload: &[u8]) -> io::Result<T> {
+    Ok(serde_json::from_slice(payload)?)
+}
+
+/// Writes a message as one frame
+pub fn write_frame(writer: &mut impl Write, message: &impl Serialize) -> io::Result<()> {
+    writer.write_all(&encode(message)?)?;
+    writer.flush()
+}
+
+/// Reads one frame, returning `None` if the connection was closed before it started
+pub fn read_frame<T: DeserializeOwned>(reader: &mut impl Read) -> io::Result<Option<T>> {
+    let mut length = [0; 4];
+    match reader.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let mut payload = vec![0; check_length(u32::from_be_bytes(length))?];
+    reader.read_exact(&mut payload)?;
+    decode(&payload).map(Some)
+}
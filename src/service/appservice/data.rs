@@ -1,10 +1,15 @@
 use ruma::api::appservice::Registration;
 
+use super::UnstableRegistration;
 use crate::Result;
 
 pub trait Data: Send + Sync {
     /// Registers an appservice and returns the ID to the caller
-    fn register_appservice(&self, yaml: Registration) -> Result<String>;
+    fn register_appservice(
+        &self,
+        yaml: Registration,
+        unstable: UnstableRegistration,
+    ) -> Result<String>;
 
     /// Remove an appservice registration
     ///
@@ -17,5 +22,5 @@ pub trait Data: Send + Sync {
 
     fn iter_ids<'a>(&'a self) -> Result<Box<dyn Iterator<Item = Result<String>> + 'a>>;
 
-    fn all(&self) -> Result<Vec<(String, Registration)>>;
+    fn all(&self) -> Result<Vec<(String, Registration, UnstableRegistration)>>;
 }
@@ -1,18 +1,30 @@
 mod data;
 
-use std::collections::BTreeMap;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Display,
+    mem,
+    sync::Mutex as StdMutex,
+    time::{Duration, Instant, SystemTime},
+};
 
 pub use data::Data;
 
 use futures_util::Future;
 use regex::RegexSet;
 use ruma::{
-    api::appservice::{Namespace, Registration},
-    RoomAliasId, RoomId, UserId,
+    api::appservice::{ping::send_ping, Namespace, Registration},
+    events::{
+        room::{canonical_alias::RoomCanonicalAliasEventContent, message::RoomMessageEventContent},
+        StateEventType,
+    },
+    OwnedRoomId, RoomAliasId, RoomId, UserId,
 };
+use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
+use tracing::warn;
 
-use crate::{services, Result};
+use crate::{api::appservice_server, services, Result};
 
 /// Compiled regular expressions for a namespace.
 #[derive(Clone, Debug)]
@@ -77,10 +89,23 @@ impl TryFrom<Vec<Namespace>> for NamespaceRegex {
     type Error = regex::Error;
 }
 
+/// Registration keys of unstable features, which ruma's [`Registration`] doesn't know about
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct UnstableRegistration {
+    /// Whether the appservice is told about device list changes of users it shares a room with
+    /// (MSC3202)
+    #[serde(default, rename = "org.matrix.msc3202")]
+    pub device_lists: bool,
+    /// Whether the to-device messages of its users are pushed to the appservice (MSC4203)
+    #[serde(default, rename = "org.matrix.msc4203")]
+    pub to_device: bool,
+}
+
 /// Appservice registration combined with its compiled regular expressions.
 #[derive(Clone, Debug)]
 pub struct RegistrationInfo {
     pub registration: Registration,
+    pub unstable: UnstableRegistration,
     pub users: NamespaceRegex,
     pub aliases: NamespaceRegex,
     pub rooms: NamespaceRegex,
@@ -100,33 +125,89 @@ impl RegistrationInfo {
     }
 }
 
-impl TryFrom<Registration> for RegistrationInfo {
-    fn try_from(value: Registration) -> Result<RegistrationInfo, regex::Error> {
+impl TryFrom<(Registration, UnstableRegistration)> for RegistrationInfo {
+    fn try_from(
+        (value, unstable): (Registration, UnstableRegistration),
+    ) -> Result<RegistrationInfo, regex::Error> {
         Ok(RegistrationInfo {
             users: value.namespaces.users.clone().try_into()?,
             aliases: value.namespaces.aliases.clone().try_into()?,
             rooms: value.namespaces.rooms.clone().try_into()?,
             registration: value,
+            unstable,
         })
     }
 
     type Error = regex::Error;
 }
 
+/// Outcome of the most recent requests to an appservice, kept in memory since startup
+#[derive(Clone, Debug, Default)]
+pub struct Health {
+    pub last_success: Option<SystemTime>,
+    pub last_failure: Option<(SystemTime, String)>,
+    /// When the current streak of failed requests started
+    pub unreachable_since: Option<SystemTime>,
+    alerted: bool,
+}
+
+impl Health {
+    /// Returns the message for the admin room if the appservice has been unreachable for longer
+    /// than configured and that wasn't reported yet
+    fn unreachable_alert(&mut self, id: &str, now: SystemTime) -> Option<String> {
+        let alert_after = services().globals.config.appservice_unreachable_alert?;
+        let unreachable_since = self.unreachable_since?;
+
+        if self.alerted
+            || !now
+                .duration_since(unreachable_since)
+                .is_ok_and(|unreachable| unreachable >= alert_after)
+        {
+            return None;
+        }
+
+        self.alerted = true;
+        let last_error = self
+            .last_failure
+            .as_ref()
+            .map_or("unknown", |(_, error)| error.as_str());
+        Some(format!(
+            "Appservice {id} has been unreachable since {}, last error: {last_error}",
+            humantime::format_rfc3339_seconds(unreachable_since)
+        ))
+    }
+}
+
+fn send_health_alert(message: String) {
+    warn!("{message}");
+    services()
+        .admin
+        .send_message(RoomMessageEventContent::text_plain(message));
+}
+
+/// Rooms an appservice is interested in, see [`Service::interesting_rooms`]
+#[derive(Default)]
+struct InterestingRooms {
+    rooms: HashSet<OwnedRoomId>,
+    /// Rooms whose members, aliases or state changed since they were last checked
+    changed: HashSet<OwnedRoomId>,
+}
+
 pub struct Service {
     pub db: &'static dyn Data,
     registration_info: RwLock<BTreeMap<String, RegistrationInfo>>,
+    health: StdMutex<HashMap<String, Health>>,
+    interesting_rooms: StdMutex<HashMap<String, InterestingRooms>>,
 }
 
 impl Service {
     pub fn build(db: &'static dyn Data) -> Result<Self> {
         let mut registration_info = BTreeMap::new();
         // Inserting registrations into cache
-        for appservice in db.all()? {
+        for (id, registration, unstable) in db.all()? {
             registration_info.insert(
-                appservice.0,
-                appservice
-                    .1
+                id,
+                (registration, unstable)
                     .try_into()
                     .expect("Should be validated on registration"),
             );
@@ -135,19 +216,32 @@ impl Service {
         Ok(Self {
             db,
             registration_info: RwLock::new(registration_info),
+            health: StdMutex::new(HashMap::new()),
+            interesting_rooms: StdMutex::new(HashMap::new()),
         })
     }
+
     /// Registers an appservice and returns the ID to the caller.
-    pub async fn register_appservice(&self, yaml: Registration) -> Result<String> {
+    pub async fn register_appservice(
+        &self,
+        yaml: Registration,
+        unstable: UnstableRegistration,
+    ) -> Result<String> {
         //TODO: Check for collisions between exclusive appservice namespaces
         services()
             .appservice
             .registration_info
             .write()
             .await
-            .insert(yaml.id.clone(), yaml.clone().try_into()?);
+            .insert(
+                yaml.id.clone(),
+                (yaml.clone(), unstable.clone()).try_into()?,
+            );
 
-        self.db.register_appservice(yaml)
+        // The namespaces might have changed
+        self.interesting_rooms.lock().unwrap().remove(&yaml.id);
+
+        self.db.register_appservice(yaml, unstable)
     }
 
     /// Removes an appservice registration.
@@ -164,9 +258,163 @@ impl Service {
             .remove(service_name)
             .ok_or_else(|| crate::Error::AdminCommand("Appservice not found"))?;
 
+        self.health.lock().unwrap().remove(service_name);
+        self.interesting_rooms.lock().unwrap().remove(service_name);
+
+        // The rooms it published would otherwise stay listed without anyone to remove them
+        services()
+            .rooms
+            .directory
+            .clear_appservice_networks(service_name)?;
+
         self.db.unregister_appservice(service_name)
     }
 
+    /// Returns the rooms the appservice is interested in, because one of its users is in them, or
+    /// they or their aliases are in its namespaces.
+    ///
+    /// All rooms are checked on the first call, afterwards only those marked by
+    /// [`Self::room_changed`] are checked again.
+    pub fn interesting_rooms(&self, info: &RegistrationInfo) -> HashSet<OwnedRoomId> {
+        let id = &info.registration.id;
+
+        let changed = {
+            let mut interesting_rooms = self.interesting_rooms.lock().unwrap();
+            match interesting_rooms.get_mut(id) {
+                Some(entry) => Some(mem::take(&mut entry.changed)),
+                None => {
+                    // Rooms changing while all rooms are checked are marked on this entry
+                    interesting_rooms.insert(id.clone(), InterestingRooms::default());
+                    None
+                }
+            }
+        };
+        let changed = changed.unwrap_or_else(|| {
+            services()
+                .rooms
+                .metadata
+                .iter_ids()
+                .filter_map(Result::ok)
+                .collect()
+        });
+
+        let mut checked = Vec::new();
+        let mut failed = Vec::new();
+        for room_id in changed {
+            match is_interested_in_room(&room_id, info) {
+                Ok(interested) => checked.push((room_id, interested)),
+                Err(e) => {
+                    warn!(
+                        "Failed to check whether appservice {id} is interested in {room_id}: {e}"
+                    );
+                    failed.push(room_id);
+                }
+            }
+        }
+
+        let mut interesting_rooms = self.interesting_rooms.lock().unwrap();
+        let entry = interesting_rooms.entry(id.clone()).or_default();
+        for (room_id, interested) in checked {
+            if interested {
+                entry.rooms.insert(room_id);
+            } else {
+                entry.rooms.remove(&room_id);
+            }
+        }
+        // Tried again on the next call
+        entry.changed.extend(failed);
+
+        entry.rooms.clone()
+    }
+
+    /// Marks that the members, aliases or state of a room changed, so that whether appservices
+    /// are interested in it is checked again.
+    pub fn room_changed(&self, room_id: &RoomId) {
+        for entry in self.interesting_rooms.lock().unwrap().values_mut() {
+            entry.changed.insert(room_id.to_owned());
+        }
+    }
+
+    /// Checks whether the appservice can be reached, returning how long it took to respond.
+    ///
+    /// Returns None if the appservice has no URL configured.
+    pub async fn ping(&self, id: &str) -> Result<Option<Duration>> {
+        let registration = self
+            .get_registration(id)
+            .await
+            .ok_or(crate::Error::AdminCommand("Appservice not found"))?;
+
+        let start = Instant::now();
+        let response = appservice_server::send_request(
+            registration,
+            send_ping::v1::Request {
+                transaction_id: None,
+            },
+        )
+        .await;
+        let elapsed = start.elapsed();
+
+        self.record_request(id, &response);
+
+        Ok(response?.map(|_| elapsed))
+    }
+
+    /// Returns the outcome of the latest requests sent to the appservice
+    pub fn health(&self, id: &str) -> Health {
+        self.health
+            .lock()
+            .unwrap()
+            .get(id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Remembers the outcome of a request to the appservice, and notifies the admin room if it
+    /// has been unreachable for longer than configured
+    pub fn record_request<T, E: Display>(&self, id: &str, result: &Result<T, E>) {
+        let mut health = self.health.lock().unwrap();
+        let status = health.entry(id.to_owned()).or_default();
+        let now = SystemTime::now();
+
+        let message = match result {
+            Ok(_) => {
+                status.last_success = Some(now);
+                status.unreachable_since = None;
+
+                std::mem::take(&mut status.alerted)
+                    .then(|| format!("Appservice {id} is reachable again."))
+            }
+            Err(e) => {
+                status.last_failure = Some((now, e.to_string()));
+                status.unreachable_since.get_or_insert(now);
+
+                status.unreachable_alert(id, now)
+            }
+        };
+        drop(health);
+
+        if let Some(message) = message {
+            send_health_alert(message);
+        }
+    }
+
+    /// Notifies the admin room about appservices which have been unreachable for longer than
+    /// configured, without waiting for another request to fail
+    pub fn check_unreachable(&self) {
+        let now = SystemTime::now();
+        let messages: Vec<_> = self
+            .health
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .filter_map(|(id, status)| status.unreachable_alert(id, now))
+            .collect();
+
+        for message in messages {
+            send_health_alert(message);
+        }
+    }
+
     pub async fn get_registration(&self, id: &str) -> Option<Registration> {
         self.registration_info
             .read()
@@ -224,3 +472,38 @@ impl Service {
         self.registration_info.read()
     }
 }
+
+/// Whether the appservice is interested in the room, because one of its users is in it, or the
+/// room or one of its aliases is in its namespaces
+fn is_interested_in_room(room_id: &RoomId, info: &RegistrationInfo) -> Result<bool> {
+    if info.rooms.is_match(room_id.as_str())
+        || services()
+            .rooms
+            .state_cache
+            .appservice_in_room(room_id, info)?
+    {
+        return Ok(true);
+    }
+
+    for alias in services().rooms.alias.local_aliases_for_room(room_id) {
+        if info.aliases.is_match(alias?.as_str()) {
+            return Ok(true);
+        }
+    }
+
+    let canonical_alias = services()
+        .rooms
+        .state_accessor
+        .room_state_get(room_id, &StateEventType::RoomCanonicalAlias, "")?
+        .and_then(|pdu| {
+            serde_json::from_str::<RoomCanonicalAliasEventContent>(pdu.content.get()).ok()
+        });
+
+    Ok(canonical_alias.is_some_and(|content| {
+        content
+            .alias
+            .iter()
+            .chain(&content.alt_aliases)
+            .any(|alias| info.aliases.is_match(alias.as_str()))
+    }))
+}
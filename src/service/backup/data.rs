@@ -0,0 +1,12 @@
+use std::path::Path;
+
+use crate::Result;
+
+pub trait Data: Send + Sync {
+    /// Writes a consistent copy of the database to the path, see
+    /// `KeyValueDatabaseEngine::backup`
+    fn backup(&self, path: &Path) -> Result<()>;
+
+    /// Returns the SHA-256 hashes of all media files, including thumbnails
+    fn media_filehashes<'a>(&'a self) -> Box<dyn Iterator<Item = Vec<u8>> + 'a>;
+}
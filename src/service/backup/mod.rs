@@ -0,0 +1,225 @@
+mod data;
+
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use chrono::Utc;
+pub use data::Data;
+use tokio::{
+    fs,
+    sync::Mutex,
+    time::{interval_at, Instant},
+};
+use tracing::{error, info, warn};
+
+use crate::{config::MediaBackendConfig, services, Error, Result};
+
+/// Beginning of the directory name of every backup, followed by the time it was created at
+const BACKUP_PREFIX: &str = "conduit-backup-";
+
+/// End of the directory name of a backup which is still being created
+const PARTIAL_SUFFIX: &str = ".partial";
+
+pub struct Service {
+    pub db: &'static dyn Data,
+    /// Held while a backup is created, so only one is created at a time
+    creating: Mutex<()>,
+}
+
+/// A backup that was created
+pub struct Backup {
+    pub path: PathBuf,
+    /// `None` if media wasn't included
+    pub media: Option<MediaBackup>,
+    /// Number of old backups which were removed to only keep the configured amount
+    pub removed: usize,
+}
+
+/// Number of media files of a backup, by how they got there
+#[derive(Default)]
+pub struct MediaBackup {
+    pub copied: usize,
+    /// Files which were hard linked from the previous backup instead of copying them
+    pub linked: usize,
+    /// Files which are in the database, but not in the media directory
+    pub missing: usize,
+}
+
+impl Service {
+    pub fn build(db: &'static dyn Data) -> Arc<Self> {
+        Arc::new(Self {
+            db,
+            creating: Mutex::new(()),
+        })
+    }
+
+    /// Creates backups in the configured interval, if any
+    pub fn start_scheduler(self: &Arc<Self>) {
+        let config = &services().globals.config.backup;
+        let Some(period) = config.interval else {
+            return;
+        };
+
+        if config.path.is_none() {
+            warn!("A backup interval is configured, but no backup path, so no backups are created");
+            return;
+        }
+
+        let self2 = Arc::clone(self);
+        tokio::spawn(async move {
+            // Not backing up right away, as that would happen on every restart
+            let mut interval = interval_at(Instant::now() + period, period);
+            loop {
+                interval.tick().await;
+
+                info!("Creating scheduled backup");
+                match self2.create(services().globals.config.backup.media).await {
+                    Ok(backup) => info!("Created backup at {}", backup.path.display()),
+                    Err(e) => error!("Failed to create scheduled backup: {e}"),
+                }
+            }
+        });
+    }
+
+    /// Creates a backup of the database, and of the media files if `include_media` is set, in
+    /// a new directory of the configured backup path. The server keeps running while doing so.
+    ///
+    /// The backup can be restored by using its directory as the `database_path`.
+    pub async fn create(&self, include_media: bool) -> Result<Backup> {
+        let config = &services().globals.config;
+
+        let directory = PathBuf::from(
+            config
+                .backup
+                .path
+                .as_ref()
+                .ok_or(Error::BadConfig("No backup path is configured."))?,
+        );
+
+        let media_backend = match &config.media.backend {
+            MediaBackendConfig::FileSystem {
+                path,
+                directory_structure,
+            } => Some((path, directory_structure)),
+            MediaBackendConfig::S3(_) => None,
+        };
+        if include_media && media_backend.is_none() {
+            return Err(Error::BadConfig(
+                "Only media stored on the filesystem can be backed up.",
+            ));
+        }
+
+        let Ok(_creating) = self.creating.try_lock() else {
+            return Err(Error::Conflict("A backup is already being created."));
+        };
+
+        fs::create_dir_all(&directory).await?;
+        let previous = list(&directory).await?;
+
+        let name = format!("{BACKUP_PREFIX}{}", Utc::now().format("%Y-%m-%dT%H-%M-%S"));
+        let path = directory.join(&name);
+        let partial_path = directory.join(format!("{name}{PARTIAL_SUFFIX}"));
+
+        if fs::try_exists(&path).await? {
+            return Err(Error::Conflict("A backup was already created this second."));
+        }
+
+        let db = self.db;
+        let db_path = partial_path.clone();
+        tokio::task::spawn_blocking(move || db.backup(&db_path))
+            .await
+            .expect("database backup doesn't panic")?;
+
+        let media = match media_backend {
+            Some((media_path, directory_structure)) if include_media => {
+                let mut media = MediaBackup::default();
+
+                for sha256_digest in self.db.media_filehashes().collect::<Vec<_>>() {
+                    let sha256_hex = hex::encode(sha256_digest);
+                    let relative_path = PathBuf::from_iter(services().globals.split_media_path(
+                        None,
+                        directory_structure,
+                        &sha256_hex,
+                    ));
+
+                    let destination = partial_path.join("media").join(&relative_path);
+                    if let Some(parent) = destination.parent() {
+                        fs::create_dir_all(parent).await?;
+                    }
+
+                    // Media files are named after their hash, so a file with the same path in the
+                    // previous backup has the same content
+                    if let Some(previous) = previous.last() {
+                        let previous_file = previous.join("media").join(&relative_path);
+                        if fs::hard_link(&previous_file, &destination).await.is_ok() {
+                            media.linked += 1;
+                            continue;
+                        }
+                    }
+
+                    match fs::copy(Path::new(media_path).join(&relative_path), &destination).await {
+                        Ok(_) => media.copied += 1,
+                        // The file might have been deleted since listing the media
+                        Err(e) if e.kind() == ErrorKind::NotFound => {
+                            warn!("Media file with sha256 hash {sha256_hex} is missing, so it isn't backed up");
+                            media.missing += 1;
+                        }
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+
+                Some(media)
+            }
+            _ => None,
+        };
+
+        fs::rename(&partial_path, &path).await?;
+
+        let mut removed = 0;
+        if config.backup.keep > 0 {
+            // The new backup counts towards the backups to keep
+            let excess = (previous.len() + 1).saturating_sub(config.backup.keep);
+            for old in previous.iter().take(excess) {
+                fs::remove_dir_all(old).await?;
+                removed += 1;
+            }
+        }
+
+        Ok(Backup {
+            path,
+            media,
+            removed,
+        })
+    }
+}
+
+/// Returns the paths of the finished backups in the directory, oldest first. Unfinished backups
+/// are removed, as they can't be used for restoring.
+async fn list(directory: &Path) -> Result<Vec<PathBuf>> {
+    let mut backups = Vec::new();
+
+    let mut entries = fs::read_dir(directory).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let file_name = entry.file_name();
+        let file_name = file_name.to_string_lossy();
+
+        if !file_name.starts_with(BACKUP_PREFIX) || !entry.file_type().await?.is_dir() {
+            continue;
+        }
+
+        if file_name.ends_with(PARTIAL_SUFFIX) {
+            warn!("Removing unfinished backup {}", entry.path().display());
+            fs::remove_dir_all(entry.path()).await?;
+        } else {
+            backups.push(entry.path());
+        }
+    }
+
+    // The names only differ in the time of creation, which sorts chronologically
+    backups.sort();
+
+    Ok(backups)
+}
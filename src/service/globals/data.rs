@@ -1,5 +1,6 @@
 use std::{
     collections::BTreeMap,
+    fmt,
     time::{Duration, SystemTime},
 };
 
@@ -67,6 +68,64 @@ impl From<ServerSigningKeys> for SigningKeys {
     }
 }
 
+/// Result of checking the references between the trees of the database
+#[derive(Default)]
+pub struct DatabaseCheck {
+    pub checks: Vec<CheckOutcome>,
+    /// Checks which weren't run, with the reason
+    pub skipped: Vec<&'static str>,
+}
+
+/// Problems found by one check of the database
+pub struct CheckOutcome {
+    /// The invariant which is checked
+    pub description: &'static str,
+    pub problems: usize,
+    /// Stays 0 if repairing wasn't requested, or isn't safe for these problems
+    pub repaired: usize,
+    /// Descriptions of the first few problems
+    pub examples: Vec<String>,
+}
+
+impl DatabaseCheck {
+    /// Number of problems which weren't repaired
+    pub fn remaining_problems(&self) -> usize {
+        self.checks
+            .iter()
+            .map(|check| check.problems - check.repaired)
+            .sum()
+    }
+}
+
+impl fmt::Display for DatabaseCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            if check.problems == 0 {
+                writeln!(f, "[ok] {}", check.description)?;
+                continue;
+            }
+
+            writeln!(
+                f,
+                "[{} problems, {} repaired] {}",
+                check.problems, check.repaired, check.description
+            )?;
+            for example in &check.examples {
+                writeln!(f, "  - {example}")?;
+            }
+            if check.examples.len() < check.problems {
+                writeln!(f, "  - ...")?;
+            }
+        }
+
+        for skipped in &self.skipped {
+            writeln!(f, "[skipped] {skipped}")?;
+        }
+
+        Ok(())
+    }
+}
+
 #[async_trait]
 pub trait Data: Send + Sync {
     fn next_count(&self) -> Result<u64>;
@@ -76,7 +135,14 @@ pub trait Data: Send + Sync {
     async fn watch(&self, user_id: &UserId, device_id: &DeviceId) -> Result<()>;
     fn cleanup(&self) -> Result<()>;
     fn memory_usage(&self) -> String;
+    /// Number of entries of every cache, by name
+    fn cache_sizes(&self) -> Vec<(&'static str, usize)>;
+    /// Statistics of the database engine, as name, description and value
+    fn database_statistics(&self) -> Result<Vec<(&'static str, &'static str, u64)>>;
     fn clear_caches(&self, amount: u32);
+    /// Checks the references between the trees without repairing anything, as repairs computed
+    /// while the server keeps writing could undo its writes
+    fn check_database(&self) -> Result<DatabaseCheck>;
     fn load_keypair(&self) -> Result<Ed25519KeyPair>;
     fn remove_keypair(&self) -> Result<()>;
     /// Only extends the cached keys, not moving any verify_keys to old_verify_keys, as if we suddenly
@@ -1,5 +1,5 @@
 mod data;
-pub use data::{Data, SigningKeys};
+pub use data::{CheckOutcome, Data, DatabaseCheck, SigningKeys};
 use ruma::{
     room_version_rules::RoomVersionRules, serde::Base64, MilliSecondsSinceUnixEpoch, OwnedDeviceId,
     OwnedEventId, OwnedRoomAliasId, OwnedRoomId, OwnedServerName, OwnedUserId, RoomAliasId,
@@ -8,8 +8,11 @@ use ruma::{
 use crate::api::server_server::DestinationResponse;
 
 use crate::{
-    config::{DirectoryStructure, MediaBackendConfig, TurnConfig},
-    services, Config, Error, Result,
+    config::{
+        AdminSocketConfig, DirectoryStructure, LdapConfig, MediaBackendConfig,
+        MediaRetentionConfig, TurnConfig,
+    },
+    services, Config, Error, Result, LOG_FILTER,
 };
 use futures_util::FutureExt;
 use hickory_resolver::TokioResolver;
@@ -22,7 +25,7 @@ use ruma::{
 use std::{
     collections::{BTreeMap, HashMap},
     error::Error as StdError,
-    fs,
+    fmt, fs,
     future::{self, Future},
     iter,
     net::{IpAddr, SocketAddr},
@@ -37,6 +40,7 @@ use std::{
 use tokio::sync::{broadcast, watch::Receiver, Mutex, RwLock, Semaphore};
 use tower_service::Service as TowerService;
 use tracing::{error, info};
+use tracing_subscriber::EnvFilter;
 
 type WellKnownMap = HashMap<OwnedServerName, DestinationResponse>;
 type TlsNameMap = HashMap<String, (Vec<IpAddr>, u16)>;
@@ -46,13 +50,63 @@ type SyncHandle = (
     Receiver<Option<Result<sync_events::v3::Response>>>, // rx
 );
 
+/// Settings which are applied when reloading the config
+const RELOADABLE_SETTINGS: &[&str] = &[
+    "allow_registration",
+    "allow_guest_access",
+    "turn",
+    "turn_username",
+    "turn_password",
+    "turn_uris",
+    "turn_secret",
+    "turn_ttl",
+    "well_known",
+    "media.retention",
+    "log",
+    "ldap",
+    "admin_socket",
+];
+
+/// The outcome of reloading the config
+pub struct ConfigReload {
+    /// The changed settings which were applied
+    pub applied: Vec<String>,
+    /// The settings which differ from the ones the server was started with, and only take effect
+    /// after restarting
+    pub restart_required: Vec<String>,
+}
+
+impl fmt::Display for ConfigReload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.applied.is_empty() {
+            writeln!(f, "No changes to apply.")?;
+        } else {
+            writeln!(f, "Applied changes to: {}", self.applied.join(", "))?;
+        }
+
+        if !self.restart_required.is_empty() {
+            writeln!(
+                f,
+                "Changes to these settings require a restart: {}",
+                self.restart_required.join(", ")
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
 pub struct Service {
     pub db: &'static dyn Data,
 
     pub actual_destination_cache: Arc<RwLock<WellKnownMap>>, // actual_destination, host
     pub tls_name_override: Arc<StdRwLock<TlsNameMap>>,
     pub config: Config,
+    /// The config as last (re)loaded, which the settings that can be changed without restarting
+    /// are read from
+    live_config: StdRwLock<Config>,
     allow_registration: RwLock<bool>,
+    allow_guest_access: RwLock<bool>,
     keypair: Arc<ruma::signatures::Ed25519KeyPair>,
     dns_resolver: TokioResolver,
     jwt_decoding_key: Option<jsonwebtoken::DecodingKey>,
@@ -194,11 +248,13 @@ impl Service {
 
         let mut s = Self {
             allow_registration: RwLock::new(config.allow_registration),
+            allow_guest_access: RwLock::new(config.allow_guest_access),
             admin_alias: RoomAliasId::parse(format!("#admins:{}", &config.server_name))
                 .expect("#admins:server_name is a valid alias name"),
             server_user: UserId::parse(format!("@conduit:{}", &config.server_name))
                 .expect("@conduit:server_name is valid"),
             db,
+            live_config: StdRwLock::new(config.clone()),
             config,
             keypair: Arc::new(keypair),
             dns_resolver: TokioResolver::builder_tokio()
@@ -322,6 +378,17 @@ impl Service {
         *self.allow_registration.read().await
     }
 
+    /// Allows for the temporary (non-persistent) toggling of guest access
+    pub async fn set_guest_access(&self, status: bool) {
+        let mut lock = self.allow_guest_access.write().await;
+        *lock = status;
+    }
+
+    /// Checks whether guests are allowed to register and use their accounts
+    pub async fn allow_guest_access(&self) -> bool {
+        *self.allow_guest_access.read().await
+    }
+
     pub fn allow_encryption(&self) -> bool {
         self.config.allow_encryption
     }
@@ -356,17 +423,91 @@ impl Service {
 
     pub fn turn(&self) -> Option<TurnConfig> {
         // We have to clone basically the entire thing on `/turnServers` otherwise
-        self.config.turn.clone()
+        self.live_config.read().unwrap().turn.clone()
     }
 
     pub fn well_known_server(&self) -> OwnedServerName {
         // Same as above, but for /.well-known/matrix/server
-        self.config.well_known.server.clone()
+        self.live_config.read().unwrap().well_known.server.clone()
     }
 
     pub fn well_known_client(&self) -> String {
         // Same as above, but for /.well-known/matrix/client
-        self.config.well_known.client.clone()
+        self.live_config.read().unwrap().well_known.client.clone()
+    }
+
+    pub fn media_retention(&self) -> MediaRetentionConfig {
+        self.live_config.read().unwrap().media.retention.clone()
+    }
+
+    pub fn ldap(&self) -> LdapConfig {
+        self.live_config.read().unwrap().ldap.clone()
+    }
+
+    pub fn admin_socket(&self) -> AdminSocketConfig {
+        self.live_config.read().unwrap().admin_socket.clone()
+    }
+
+    /// Reads the config again, applying the changes to the settings which can be changed without
+    /// restarting. Nothing is applied if the new config is invalid.
+    pub async fn reload_config(&self) -> Result<ConfigReload> {
+        let new = tokio::task::spawn_blocking(Config::load)
+            .await
+            .expect("loading the config doesn't panic")
+            .map_err(|e| Error::InvalidConfig(e.to_string()))?;
+        let mut log_filter = Some(
+            EnvFilter::try_new(&new.log)
+                .map_err(|e| Error::InvalidConfig(format!("invalid log filter: {e}")))?,
+        );
+        new.warn_deprecated();
+
+        let changed = self.live_config.read().unwrap().changed_settings(&new);
+
+        let mut reload = ConfigReload {
+            applied: Vec::new(),
+            restart_required: self
+                .config
+                .changed_settings(&new)
+                .into_iter()
+                .filter(|setting| !RELOADABLE_SETTINGS.contains(&setting.as_str()))
+                .collect(),
+        };
+
+        for setting in changed
+            .into_iter()
+            .filter(|setting| RELOADABLE_SETTINGS.contains(&setting.as_str()))
+        {
+            match setting.as_str() {
+                "allow_registration" => self.set_registration(new.allow_registration).await,
+                "allow_guest_access" => self.set_guest_access(new.allow_guest_access).await,
+                "log" => match LOG_FILTER.get() {
+                    Some(handle) => {
+                        let filter = log_filter.take().expect("settings are only listed once");
+                        if let Err(e) = handle.reload(filter) {
+                            error!("Failed to change the log filter: {e}");
+                            reload.restart_required.push(setting);
+                            continue;
+                        }
+                    }
+                    None => {
+                        reload.restart_required.push(setting);
+                        continue;
+                    }
+                },
+                // The others are read from the live config
+                _ => {}
+            }
+            reload.applied.push(setting);
+        }
+
+        info!(
+            applied = ?reload.applied,
+            restart_required = ?reload.restart_required,
+            "Reloaded the config"
+        );
+        *self.live_config.write().unwrap() = new;
+
+        Ok(reload)
     }
 
     pub fn dns_resolver(&self) -> &TokioResolver {
@@ -499,27 +640,7 @@ impl Service {
         directory_structure: &DirectoryStructure,
         sha256_hex: &'a str,
     ) -> Vec<&'a str> {
-        match directory_structure {
-            DirectoryStructure::Flat => match media_directory {
-                Some(path) => vec![path, sha256_hex],
-                None => vec![sha256_hex],
-            },
-            DirectoryStructure::Deep { length, depth } => {
-                let mut r: Vec<&'a str> = Vec::with_capacity((depth.get() + 2).into());
-                if let Some(path) = media_directory {
-                    r.push(path);
-                }
-                let mut filename = sha256_hex;
-                for _ in 0..depth.get() {
-                    let (current_path, next) = filename.split_at(length.get().into());
-                    filename = next;
-                    r.push(current_path);
-                }
-                r.push(filename);
-
-                r
-            }
-        }
+        split_media_path(media_directory, directory_structure, sha256_hex)
     }
 
     pub async fn shutdown(&self) {
@@ -532,6 +653,37 @@ impl Service {
     }
 }
 
+/// Splits the path of a media file into its components, as the directory structure may nest the
+/// file in directories named after the start of its hash. Also used without services, e.g. when
+/// checking the database offline.
+pub fn split_media_path<'a>(
+    media_directory: Option<&'a str>,
+    directory_structure: &DirectoryStructure,
+    sha256_hex: &'a str,
+) -> Vec<&'a str> {
+    match directory_structure {
+        DirectoryStructure::Flat => match media_directory {
+            Some(path) => vec![path, sha256_hex],
+            None => vec![sha256_hex],
+        },
+        DirectoryStructure::Deep { length, depth } => {
+            let mut r: Vec<&'a str> = Vec::with_capacity((depth.get() + 2).into());
+            if let Some(path) = media_directory {
+                r.push(path);
+            }
+            let mut filename = sha256_hex;
+            for _ in 0..depth.get() {
+                let (current_path, next) = filename.split_at(length.get().into());
+                filename = next;
+                r.push(current_path);
+            }
+            r.push(filename);
+
+            r
+        }
+    }
+}
+
 fn reqwest_client_builder(config: &Config) -> Result<reqwest::ClientBuilder> {
     let mut reqwest_client_builder = reqwest::Client::builder()
         .pool_max_idle_per_host(0)
@@ -1,5 +1,6 @@
 use crate::{Result, services};
 use ldap3::{LdapConn, Scope, SearchEntry};
+use std::collections::BTreeMap;
 use tokio::task::spawn_blocking;
 
 #[derive(Debug, Clone)]
@@ -8,6 +9,8 @@ pub struct LdapUser {
     pub localpart: String,
     pub displayname: String,
     pub email: String,
+    /// Custom profile fields, taken from the other entries of the `attribute_mapping`
+    pub profile_fields: BTreeMap<String, Option<String>>,
 }
 
 pub struct Service;
@@ -18,7 +21,7 @@ impl Service {
     }
 
     pub async fn find_ldap_user(&self, username: &str) -> Result<LdapUser> {
-        let ldap_config = services().globals.config.ldap.clone();
+        let ldap_config = services().globals.ldap();
         let username = username.to_owned();
 
         spawn_blocking(move || {
@@ -31,11 +34,7 @@ impl Service {
                     &ldap_config.base_dn,
                     Scope::Subtree,
                     &filter,
-                    &[
-                        ldap_config.attribute_mapping.get("localpart").unwrap(),
-                        ldap_config.attribute_mapping.get("displayname").unwrap(),
-                        ldap_config.attribute_mapping.get("email").unwrap(),
-                    ],
+                    ldap_config.attribute_mapping.values().collect::<Vec<_>>(),
                 )?
                 .success()?;
 
@@ -73,11 +72,26 @@ impl Service {
                 .ok_or_else(|| crate::Error::bad_config("LDAP attribute for email not found"))?
                 .to_owned();
 
+            let profile_fields = ldap_config
+                .attribute_mapping
+                .iter()
+                .filter(|(field, _)| {
+                    !matches!(field.as_str(), "localpart" | "displayname" | "email")
+                })
+                .map(|(field, attr)| {
+                    (
+                        field.clone(),
+                        entry.attrs.get(attr).and_then(|vals| vals.first()).cloned(),
+                    )
+                })
+                .collect();
+
             Ok(LdapUser {
                 dn,
                 localpart,
                 displayname,
                 email,
+                profile_fields,
             })
         })
         .await
@@ -1,4 +1,6 @@
-use ruma::{OwnedServerName, ServerName, UserId};
+use ruma::{
+    EventId, OwnedEventId, OwnedRoomId, OwnedServerName, OwnedUserId, RoomId, ServerName, UserId,
+};
 use sha2::{digest::Output, Sha256};
 
 use crate::{config::MediaRetentionConfig, Error, Result};
@@ -124,4 +126,51 @@ pub trait Data: Send + Sync {
     fn update_last_accessed(&self, server_name: &ServerName, media_id: &str) -> Result<()>;
 
     fn update_last_accessed_filehash(&self, sha256_digest: &[u8]) -> Result<()>;
+
+    /// Reserves an MXC URI for an asynchronous upload by the given user, until `expires_at` (in
+    /// unix millis).
+    fn create_pending(
+        &self,
+        servername: &ServerName,
+        media_id: &str,
+        user_id: &UserId,
+        expires_at: u64,
+    ) -> Result<()>;
+
+    /// Returns the user that reserved the MXC URI and when the reservation expires, if it was
+    /// reserved and not uploaded to yet.
+    fn search_pending(
+        &self,
+        servername: &ServerName,
+        media_id: &str,
+    ) -> Result<Option<(OwnedUserId, u64)>>;
+
+    fn remove_pending(&self, servername: &ServerName, media_id: &str) -> Result<()>;
+
+    /// Returns the media ids and expiry times of all MXC URIs reserved by the user on this server.
+    fn pending_from_user(&self, user_id: &UserId) -> Result<Vec<(String, u64)>>;
+
+    fn link_to_event(
+        &self,
+        servername: &ServerName,
+        media_id: &str,
+        room_id: &RoomId,
+        event_id: &EventId,
+    ) -> Result<()>;
+
+    /// Returns the rooms and ids of all events the media is linked to.
+    fn linked_events(
+        &self,
+        servername: &ServerName,
+        media_id: &str,
+    ) -> Result<Vec<(OwnedRoomId, OwnedEventId)>>;
+
+    /// Returns all media linked to events in the room.
+    fn linked_to_room(&self, room_id: &RoomId) -> Result<Vec<(OwnedServerName, String)>>;
+
+    /// Removes all links between the media and events.
+    fn unlink(&self, servername: &ServerName, media_id: &str) -> Result<()>;
+
+    /// Returns the number of stored files, including thumbnails, and their total size in bytes.
+    fn store_size(&self) -> (usize, u64);
 }
@@ -1,12 +1,12 @@
 mod data;
-use std::{io::Cursor, sync::Arc};
+use std::{collections::HashMap, io::Cursor, sync::Arc, time::Duration};
 
 pub use data::Data;
 use http::StatusCode;
 use ruma::{
     api::client::{error::ErrorKind, media::is_safe_inline_content_type},
     http_headers::{ContentDisposition, ContentDispositionType},
-    OwnedServerName, ServerName, UserId,
+    MilliSecondsSinceUnixEpoch, MxcUri, OwnedServerName, RoomId, ServerName, UInt, UserId,
 };
 use rusty_s3::{
     actions::{DeleteObjectsResponse, ObjectIdentifier},
@@ -17,7 +17,7 @@ use tracing::{error, info, warn};
 
 use crate::{
     config::{DirectoryStructure, MediaBackendConfig, S3MediaBackend},
-    services, utils, Error, Result,
+    services, utils, Error, PduEvent, Result,
 };
 use image::imageops::FilterType;
 
@@ -31,6 +31,7 @@ pub struct DbFileMeta {
 use tokio::{
     fs::{self, File},
     io::{AsyncReadExt, AsyncWriteExt},
+    sync::{Mutex, Notify, RwLock},
 };
 
 pub struct MediaQuery {
@@ -113,6 +114,10 @@ impl MediaType {
 
 pub struct Service {
     pub db: &'static dyn Data,
+    /// Woken up whenever media reserved for an asynchronous upload is uploaded
+    pub upload_notify: Notify,
+    /// Held while uploading to a reserved MXC URI, so it can only be uploaded to once
+    pub pending_mutex: RwLock<HashMap<(OwnedServerName, String), Arc<Mutex<()>>>>,
 }
 
 pub struct BlockedMediaInfo {
@@ -126,23 +131,24 @@ pub struct BlockedMediaInfo {
 impl Service {
     pub fn start_time_retention_checker(self: &Arc<Self>) {
         let self2 = Arc::clone(self);
-        if let Some(cleanup_interval) = services().globals.config.media.retention.cleanup_interval()
-        {
-            tokio::spawn(async move {
-                let mut i = cleanup_interval;
-                loop {
-                    i.tick().await;
+        tokio::spawn(async move {
+            // The policies can change when the config is reloaded, so the period is determined
+            // again after every check
+            loop {
+                let period = services().globals.media_retention().cleanup_period();
+                if period.is_some() {
                     let _ = self2.try_purge_time_retention().await;
                 }
-            });
-        }
+                tokio::time::sleep(period.unwrap_or(Duration::from_secs(60 * 60))).await;
+            }
+        });
     }
 
     async fn try_purge_time_retention(&self) -> Result<()> {
         info!("Checking if any media should be deleted due to time-based retention policies");
         let files = self
             .db
-            .cleanup_time_retention(&services().globals.config.media.retention);
+            .cleanup_time_retention(&services().globals.media_retention());
 
         let count = files.iter().filter(|res| res.is_ok()).count();
         info!("Found {count} media files to delete");
@@ -197,6 +203,180 @@ impl Service {
         }
     }
 
+    /// Reserves an MXC URI, which the user can upload to later. Returns when the reservation
+    /// expires.
+    pub fn create_pending(
+        &self,
+        servername: &ServerName,
+        media_id: &str,
+        user_id: &UserId,
+    ) -> Result<MilliSecondsSinceUnixEpoch> {
+        let now = utils::millis_since_unix_epoch();
+
+        let mut pending = 0;
+        for (media_id, expires_at) in self.db.pending_from_user(user_id)? {
+            if expires_at <= now {
+                self.db.remove_pending(user_id.server_name(), &media_id)?;
+            } else {
+                pending += 1;
+            }
+        }
+
+        if pending >= services().globals.config.media.max_pending_uploads {
+            return Err(Error::BadRequest(
+                ErrorKind::LimitExceeded { retry_after: None },
+                "Too many pending uploads, upload to your previously created MXC URIs first.",
+            ));
+        }
+
+        let expires_at = now.saturating_add(
+            services()
+                .globals
+                .config
+                .media
+                .pending_upload_expiry
+                .as_millis()
+                .try_into()
+                .unwrap_or(u64::MAX),
+        );
+
+        self.db
+            .create_pending(servername, media_id, user_id, expires_at)?;
+
+        Ok(MilliSecondsSinceUnixEpoch(
+            expires_at.try_into().unwrap_or(UInt::MAX),
+        ))
+    }
+
+    /// Uploads a file to an MXC URI which was reserved by the user.
+    pub async fn upload_pending(
+        &self,
+        servername: &ServerName,
+        media_id: &str,
+        filename: Option<&str>,
+        content_type: Option<&str>,
+        file: &[u8],
+        user_id: &UserId,
+    ) -> Result<()> {
+        let key = (servername.to_owned(), media_id.to_owned());
+        let mutex = Arc::clone(
+            self.pending_mutex
+                .write()
+                .await
+                .entry(key.clone())
+                .or_default(),
+        );
+        let pending_lock = mutex.lock().await;
+
+        let result = self
+            .upload_pending_locked(servername, media_id, filename, content_type, file, user_id)
+            .await;
+
+        drop(pending_lock);
+
+        // Only the map and this upload hold the mutex if no other upload of the media waits for
+        // it, and no upload can start waiting while the map is locked
+        let mut pending_mutex = self.pending_mutex.write().await;
+        if Arc::strong_count(&mutex) == 2 {
+            pending_mutex.remove(&key);
+        }
+
+        result
+    }
+
+    /// Uploads a file to a reserved MXC URI, while holding the lock of the MXC URI, so the check
+    /// whether it is still pending and the upload can't be interleaved with another upload.
+    async fn upload_pending_locked(
+        &self,
+        servername: &ServerName,
+        media_id: &str,
+        filename: Option<&str>,
+        content_type: Option<&str>,
+        file: &[u8],
+        user_id: &UserId,
+    ) -> Result<()> {
+        let Some((owner, expires_at)) = self.db.search_pending(servername, media_id)? else {
+            return Err(
+                if self.db.search_file_metadata(servername, media_id).is_ok() {
+                    Error::BadRequest(
+                        ErrorKind::CannotOverwriteMedia,
+                        "Media has already been uploaded.",
+                    )
+                } else {
+                    Error::BadRequest(ErrorKind::NotFound, "Media not found.")
+                },
+            );
+        };
+
+        if owner != user_id {
+            return Err(Error::BadRequest(
+                ErrorKind::forbidden(),
+                "You did not create this MXC URI.",
+            ));
+        }
+
+        if expires_at <= utils::millis_since_unix_epoch() {
+            self.db.remove_pending(servername, media_id)?;
+            return Err(Error::BadRequest(ErrorKind::NotFound, "Media not found."));
+        }
+
+        self.create(
+            servername,
+            media_id,
+            filename,
+            content_type,
+            file,
+            Some(user_id),
+        )
+        .await?;
+
+        self.db.remove_pending(servername, media_id)?;
+        self.upload_notify.notify_waiters();
+
+        Ok(())
+    }
+
+    /// Waits until media reserved for an asynchronous upload has been uploaded, returning
+    /// `M_NOT_YET_UPLOADED` if that doesn't happen within the timeout. Returns immediately for all
+    /// other media.
+    pub async fn wait_for_upload(
+        &self,
+        servername: &ServerName,
+        media_id: &str,
+        timeout: Duration,
+    ) -> Result<()> {
+        let is_pending = || {
+            Ok::<_, Error>(
+                self.db
+                    .search_pending(servername, media_id)?
+                    .is_some_and(|(_, expires_at)| expires_at > utils::millis_since_unix_epoch()),
+            )
+        };
+
+        // Don't let clients keep connections open for too long
+        let timeout = timeout.min(Duration::from_secs(60));
+
+        let uploaded = tokio::time::timeout(timeout, async {
+            loop {
+                // Register for notifications before checking, so no upload can be missed
+                let notified = self.upload_notify.notified();
+                if !is_pending()? {
+                    return Ok::<_, Error>(());
+                }
+                notified.await;
+            }
+        })
+        .await;
+
+        match uploaded {
+            Ok(result) => result,
+            Err(_) => Err(Error::BadRequest(
+                ErrorKind::NotYetUploaded,
+                "Media has not been uploaded yet.",
+            )),
+        }
+    }
+
     /// Uploads or replaces a file thumbnail.
     #[allow(clippy::too_many_arguments)]
     pub async fn upload_thumbnail(
@@ -510,6 +690,157 @@ impl Service {
         purge_files(hashes).await
     }
 
+    /// Purges all media linked to events in the room, except media which is also linked to events
+    /// in other rooms.
+    ///
+    /// If `force_filehash` is true, all media and/or thumbnails which share sha256 content hashes
+    /// with the purged media will also be purged, meaning that the media is guaranteed to be deleted
+    /// from the media backend. Otherwise, it will be deleted if only the media IDs requested to be
+    /// purged have that sha256 hash.
+    ///
+    /// Returns errors for all the files that were failed to be deleted, if any.
+    pub async fn purge_from_room(&self, room_id: &RoomId, force_filehash: bool) -> Vec<Error> {
+        let mut errors = Vec::new();
+
+        let media = match self.db.linked_to_room(room_id) {
+            Ok(media) => media,
+            Err(e) => return vec![e],
+        };
+
+        let mut only_in_room = Vec::new();
+        for (server_name, media_id) in media {
+            match self.db.linked_events(&server_name, &media_id) {
+                Ok(events) if events.iter().all(|(linked_room, _)| linked_room == room_id) => {
+                    only_in_room.push((server_name, media_id))
+                }
+                Ok(_) => {}
+                Err(e) => errors.push(e),
+            }
+        }
+
+        errors.extend(self.purge(&only_in_room, force_filehash).await);
+
+        for (server_name, media_id) in &only_in_room {
+            if let Err(e) = self.db.unlink(server_name, media_id) {
+                errors.push(e);
+            }
+        }
+
+        errors
+    }
+
+    /// Links media referenced in the content of an event to that event (MSC3911), so that only
+    /// users and servers who can see one of the events the media is linked to can download it.
+    ///
+    /// Media from this server is only linked if the sender of the event uploaded it, so users
+    /// can't give others access to media they just happen to know the MXC URI of.
+    pub fn link_to_event(&self, pdu: &PduEvent) -> Result<()> {
+        let Ok(content) = serde_json::from_str::<serde_json::Value>(pdu.content.get()) else {
+            return Ok(());
+        };
+
+        let mut mxc_uris = Vec::new();
+        find_media_references(&content, &mut mxc_uris);
+
+        for mxc in mxc_uris {
+            let mxc = <&MxcUri>::from(mxc.as_str());
+            let Ok((server_name, media_id)) = mxc.parts() else {
+                continue;
+            };
+
+            if server_name == services().globals.server_name() {
+                let uploader = match self.db.search_pending(server_name, media_id)? {
+                    Some((owner, _)) => Some(owner.localpart().to_owned()),
+                    None => self
+                        .db
+                        .query(server_name, media_id)?
+                        .source_file
+                        .and_then(|file| file.uploader_localpart),
+                };
+
+                if pdu.sender.server_name() != server_name
+                    || uploader.as_deref() != Some(pdu.sender.localpart())
+                {
+                    continue;
+                }
+            }
+
+            self.db
+                .link_to_event(server_name, media_id, &pdu.room_id(), &pdu.event_id)?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether a user may download the media, returning a not found error if they can't.
+    ///
+    /// Media from this server which is linked to events can only be downloaded by its uploader,
+    /// and authenticated users who can see one of the events. Other media is not restricted.
+    pub fn check_access(
+        &self,
+        server_name: &ServerName,
+        media_id: &str,
+        user_id: Option<&UserId>,
+    ) -> Result<()> {
+        if server_name != services().globals.server_name() {
+            return Ok(());
+        }
+
+        let events = self.db.linked_events(server_name, media_id)?;
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let allowed = match user_id {
+            Some(user_id) => {
+                (user_id.server_name() == server_name
+                    && self
+                        .db
+                        .query(server_name, media_id)?
+                        .source_file
+                        .and_then(|file| file.uploader_localpart)
+                        .as_deref()
+                        == Some(user_id.localpart()))
+                    || events.iter().any(|(room_id, event_id)| {
+                        services()
+                            .rooms
+                            .state_accessor
+                            .user_can_see_event(user_id, room_id, event_id)
+                            .unwrap_or(false)
+                    })
+            }
+            None => false,
+        };
+
+        if allowed {
+            Ok(())
+        } else {
+            Err(Error::BadRequest(ErrorKind::NotFound, "Media not found."))
+        }
+    }
+
+    /// Checks whether a server may download media from this server over federation, returning a
+    /// not found error if it can't.
+    pub fn check_server_access(&self, media_id: &str, origin: &ServerName) -> Result<()> {
+        let server_name = services().globals.server_name();
+
+        let events = self.db.linked_events(server_name, media_id)?;
+
+        if events.is_empty()
+            || events.iter().any(|(room_id, event_id)| {
+                services()
+                    .rooms
+                    .state_accessor
+                    .server_can_see_event(origin, room_id, event_id)
+                    .unwrap_or(false)
+            })
+        {
+            Ok(())
+        } else {
+            Err(Error::BadRequest(ErrorKind::NotFound, "Media not found."))
+        }
+    }
+
     /// Checks whether the media has been blocked by administrators, returning either
     /// a database error, or a not found error if it is blocked
     pub fn check_blocked(&self, server_name: &ServerName, media_id: &str) -> Result<()> {
@@ -579,7 +910,7 @@ impl Service {
     ) -> Result<Vec<Error>> {
         let files = self.db.files_to_delete(
             sha256_digest,
-            &services().globals.config.media.retention,
+            &services().globals.media_retention(),
             media_type,
             new_size,
         )?;
@@ -717,6 +1048,32 @@ pub async fn create_file(sha256_hex: &str, file: &[u8]) -> Result<()> {
     Ok(())
 }
 
+/// Collects all MXC URIs in the `url` and `thumbnail_url` fields of event content, including the
+/// ones nested in `info` and `file` objects.
+fn find_media_references(value: &serde_json::Value, mxc_uris: &mut Vec<String>) {
+    match value {
+        serde_json::Value::Object(object) => {
+            for (key, value) in object {
+                match value {
+                    serde_json::Value::String(uri)
+                        if (key == "url" || key == "thumbnail_url")
+                            && uri.starts_with("mxc://") =>
+                    {
+                        mxc_uris.push(uri.clone())
+                    }
+                    _ => find_media_references(value, mxc_uris),
+                }
+            }
+        }
+        serde_json::Value::Array(values) => {
+            for value in values {
+                find_media_references(value, mxc_uris);
+            }
+        }
+        _ => {}
+    }
+}
+
 /// The size of a chunk for S3 delete operation.
 const S3_CHUNK_SIZE: usize = 1000;
 
@@ -0,0 +1,307 @@
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::{Arc, Mutex as StdMutex},
+    time::{Duration, Instant},
+};
+
+use ruma::{OwnedServerName, ServerName};
+use tokio::{sync::Mutex, task};
+use tracing::warn;
+
+use crate::services;
+
+/// How long the metrics which need a scan of the database are reused, so that frequent scrapes
+/// don't keep the database busy
+const SCAN_TTL: Duration = Duration::from_secs(60);
+
+/// Upper bounds, in seconds, of the buckets of every duration histogram
+const BUCKETS: [f64; 13] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+#[derive(Default)]
+struct Histogram {
+    /// Number of observations per bucket, not cumulative
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+
+    /// Writes the samples of the histogram, `labels` being the already formatted labels without
+    /// the `le` one
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(self.buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{{labels}{separator}le=\"{bound}\"}} {cumulative}"
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{name}_bucket{{{labels}{separator}le=\"+Inf\"}} {}",
+            self.count
+        );
+        let braced = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{labels}}}")
+        };
+        let _ = writeln!(out, "{name}_sum{braced} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{braced} {}", self.count);
+    }
+}
+
+/// Metrics which are computed by scanning the database
+struct Scanned {
+    federation_queue_depths: HashMap<OwnedServerName, usize>,
+    media_files: usize,
+    media_bytes: u64,
+    /// `None` if the database backend failed to report them
+    database_statistics: Option<Vec<(&'static str, &'static str, u64)>>,
+}
+
+impl Scanned {
+    fn collect() -> Self {
+        let (media_files, media_bytes) = services().media.db.store_size();
+
+        Self {
+            federation_queue_depths: services().sending.federation_queue_depths(),
+            media_files,
+            media_bytes,
+            database_statistics: services()
+                .globals
+                .db
+                .database_statistics()
+                .inspect_err(|e| warn!("Failed to collect database statistics: {e}"))
+                .ok(),
+        }
+    }
+}
+
+/// Collects the metrics which are not derived from the state of other services, and renders all
+/// metrics in the Prometheus text exposition format.
+#[derive(Default)]
+pub struct Service {
+    /// Request durations by method, matched route and status code
+    http_requests: StdMutex<HashMap<(String, String, u16), Histogram>>,
+    incoming_pdus: StdMutex<Histogram>,
+    federation_failures: StdMutex<HashMap<OwnedServerName, u64>>,
+    /// The latest scan and when it was done. Held while scanning, so that concurrent scrapes
+    /// wait for the same scan.
+    scanned: Mutex<Option<(Instant, Arc<Scanned>)>>,
+}
+
+impl Service {
+    /// Records the handling of a HTTP request. `path` is the route template the request matched,
+    /// so that the number of label values stays bounded.
+    pub fn observe_http_request(
+        &self,
+        method: &str,
+        path: Option<&str>,
+        status: u16,
+        duration: Duration,
+    ) {
+        if !services().globals.config.metrics.enabled {
+            return;
+        }
+
+        self.http_requests
+            .lock()
+            .unwrap()
+            .entry((
+                method.to_owned(),
+                path.unwrap_or("unmatched").to_owned(),
+                status,
+            ))
+            .or_default()
+            .observe(duration);
+    }
+
+    /// Records how long it took to handle an incoming PDU, including fetching and handling its
+    /// missing prev events.
+    pub fn observe_incoming_pdu(&self, duration: Duration) {
+        self.incoming_pdus.lock().unwrap().observe(duration);
+    }
+
+    /// Records that a transaction to the given server failed.
+    pub fn record_federation_failure(&self, server_name: &ServerName) {
+        *self
+            .federation_failures
+            .lock()
+            .unwrap()
+            .entry(server_name.to_owned())
+            .or_default() += 1;
+    }
+
+    /// Returns the metrics which need a scan of the database, scanning again if the previous scan
+    /// is older than [`SCAN_TTL`]
+    async fn scanned(&self) -> Option<Arc<Scanned>> {
+        let mut scanned = self.scanned.lock().await;
+
+        if let Some((scanned_at, scanned)) = &*scanned {
+            if scanned_at.elapsed() < SCAN_TTL {
+                return Some(Arc::clone(scanned));
+            }
+        }
+
+        match task::spawn_blocking(Scanned::collect).await {
+            Ok(new) => {
+                let new = Arc::new(new);
+                *scanned = Some((Instant::now(), Arc::clone(&new)));
+                Some(new)
+            }
+            Err(e) => {
+                warn!("Failed to scan the database for metrics: {e}");
+                None
+            }
+        }
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format
+    pub async fn render(&self) -> String {
+        let mut out = String::new();
+        let scanned = self.scanned().await;
+
+        header(
+            &mut out,
+            "conduit_http_request_duration_seconds",
+            "Time it took to handle HTTP requests.",
+            "histogram",
+        );
+        for ((method, path, status), histogram) in self.http_requests.lock().unwrap().iter() {
+            histogram.render(
+                &mut out,
+                "conduit_http_request_duration_seconds",
+                &format!(
+                    "method=\"{}\",path=\"{}\",status=\"{status}\"",
+                    escape(method),
+                    escape(path)
+                ),
+            );
+        }
+
+        header(
+            &mut out,
+            "conduit_incoming_pdu_duration_seconds",
+            "Time it took to handle PDUs received over federation.",
+            "histogram",
+        );
+        self.incoming_pdus.lock().unwrap().render(
+            &mut out,
+            "conduit_incoming_pdu_duration_seconds",
+            "",
+        );
+
+        header(
+            &mut out,
+            "conduit_federation_queue_depth",
+            "Number of events waiting to be sent to a server.",
+            "gauge",
+        );
+        for (server_name, depth) in scanned
+            .iter()
+            .flat_map(|scanned| &scanned.federation_queue_depths)
+        {
+            let _ = writeln!(
+                out,
+                "conduit_federation_queue_depth{{destination=\"{}\"}} {depth}",
+                escape(server_name.as_str())
+            );
+        }
+
+        header(
+            &mut out,
+            "conduit_federation_send_failures_total",
+            "Number of transactions to a server which failed.",
+            "counter",
+        );
+        for (server_name, failures) in self.federation_failures.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "conduit_federation_send_failures_total{{destination=\"{}\"}} {failures}",
+                escape(server_name.as_str())
+            );
+        }
+
+        header(
+            &mut out,
+            "conduit_sync_waiters",
+            "Number of sync requests waiting for new events.",
+            "gauge",
+        );
+        let sync_waiters = services().globals.sync_receivers.read().await.len();
+        let _ = writeln!(out, "conduit_sync_waiters {sync_waiters}");
+
+        header(
+            &mut out,
+            "conduit_cache_entries",
+            "Number of entries in an in-memory cache.",
+            "gauge",
+        );
+        for (layer, caches) in [
+            ("database", services().globals.db.cache_sizes()),
+            ("service", services().cache_sizes().await),
+        ] {
+            for (cache, size) in caches {
+                let _ = writeln!(
+                    out,
+                    "conduit_cache_entries{{layer=\"{layer}\",cache=\"{cache}\"}} {size}"
+                );
+            }
+        }
+
+        let Some(scanned) = scanned else {
+            return out;
+        };
+
+        header(
+            &mut out,
+            "conduit_media_files",
+            "Number of stored media files, including thumbnails.",
+            "gauge",
+        );
+        let _ = writeln!(out, "conduit_media_files {}", scanned.media_files);
+        header(
+            &mut out,
+            "conduit_media_bytes",
+            "Total size of the stored media files.",
+            "gauge",
+        );
+        let _ = writeln!(out, "conduit_media_bytes {}", scanned.media_bytes);
+
+        for (name, description, value) in scanned.database_statistics.iter().flatten() {
+            let name = format!("conduit_database_{name}");
+            header(&mut out, &name, description, "gauge");
+            let _ = writeln!(out, "{name} {value}");
+        }
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Escapes a label value as required by the text exposition format
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
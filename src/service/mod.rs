@@ -4,21 +4,25 @@ use std::{
 };
 
 use lru_cache::LruCache;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Notify, RwLock};
 
 use crate::{Config, Result};
 
 pub mod account_data;
 pub mod admin;
 pub mod appservice;
+pub mod backup;
 pub mod globals;
 pub mod key_backups;
 pub mod ldap;
 pub mod media;
+pub mod metrics;
+pub mod oidc;
 pub mod pdu;
 pub mod pusher;
 pub mod rooms;
 pub mod sending;
+pub mod threepid;
 pub mod transaction_ids;
 pub mod uiaa;
 pub mod users;
@@ -33,17 +37,22 @@ pub struct Services {
     pub account_data: account_data::Service,
     pub admin: Arc<admin::Service>,
     pub admin_socket: Arc<admin::socket::Service>,
+    pub backup: Arc<backup::Service>,
     pub globals: globals::Service,
     pub key_backups: key_backups::Service,
     pub media: Arc<media::Service>,
+    pub metrics: metrics::Service,
     pub sending: Arc<sending::Service>,
     pub typing: tokio::task::JoinHandle<()>,
     pub ldap: ldap::Service,
+    pub oidc: oidc::Service,
+    pub threepid: threepid::Service,
 }
 
 impl Services {
     pub fn build<
         D: appservice::Data
+            + backup::Data
             + pusher::Data
             + rooms::Data
             + transaction_ids::Data
@@ -61,7 +70,7 @@ impl Services {
     ) -> Result<Self> {
         Ok(Self {
             appservice: appservice::Service::build(db)?,
-            pusher: pusher::Service { db },
+            pusher: pusher::Service::build(db),
             rooms: rooms::Service {
                 alias: rooms::alias::Service { db },
                 auth_chain: rooms::auth_chain::Service { db },
@@ -119,18 +128,36 @@ impl Services {
             account_data: account_data::Service { db },
             admin: admin::Service::build(),
             admin_socket: admin::socket::Service::build(&config)?,
+            backup: backup::Service::build(db),
             key_backups: key_backups::Service { db },
-            media: Arc::new(media::Service { db }),
+            media: Arc::new(media::Service {
+                db,
+                upload_notify: Notify::new(),
+                pending_mutex: RwLock::new(HashMap::new()),
+            }),
+            metrics: metrics::Service::default(),
             sending: sending::Service::build(db, &config),
             typing: tokio::spawn(
                 rooms::edus::typing::Service::typings_maintain_task()
             ),
             ldap: ldap::Service::build()?,
+            oidc: oidc::Service::build()?,
+            threepid: threepid::Service::build()?,
 
             globals: globals::Service::load(db, config)?,
         })
     }
     async fn memory_usage(&self) -> String {
+        self.cache_sizes()
+            .await
+            .into_iter()
+            .map(|(cache, size)| format!("{cache}: {size}"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Number of entries of every cache of the services, by name
+    pub async fn cache_sizes(&self) -> Vec<(&'static str, usize)> {
         let lazy_load_waiting = self.rooms.lazy_loading.lazy_load_waiting.lock().await.len();
         let server_visibility_cache = self
             .rooms
@@ -168,16 +195,14 @@ impl Services {
             .await
             .len();
 
-        format!(
-            "\
-lazy_load_waiting: {lazy_load_waiting}
-server_visibility_cache: {server_visibility_cache}
-user_visibility_cache: {user_visibility_cache}
-stateinfo_cache: {stateinfo_cache}
-lasttimelinecount_cache: {lasttimelinecount_cache}
-roomid_spacechunk_cache: {roomid_spacehierarchy_cache}\
-            "
-        )
+        vec![
+            ("lazy_load_waiting", lazy_load_waiting),
+            ("server_visibility_cache", server_visibility_cache),
+            ("user_visibility_cache", user_visibility_cache),
+            ("stateinfo_cache", stateinfo_cache),
+            ("lasttimelinecount_cache", lasttimelinecount_cache),
+            ("roomid_spacechunk_cache", roomid_spacehierarchy_cache),
+        ]
     }
     async fn clear_caches(&self, amount: u32) {
         if amount > 0 {
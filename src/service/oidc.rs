@@ -0,0 +1,474 @@
+use crate::{services, utils, Error, Result};
+use base64::{engine::general_purpose, Engine as _};
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use ruma::{api::client::error::ErrorKind, OwnedUserId, UserId};
+use serde::{de::DeserializeOwned, Deserialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    sync::Mutex as StdMutex,
+    time::{Duration, Instant},
+};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+use url::Url;
+
+/// Identity provider id advertised to clients for the configured OpenID Connect provider
+pub const IDP_ID: &str = "oidc";
+
+/// Path of the endpoint the provider redirects the user back to
+pub const CALLBACK_PATH: &str = "/_conduit/client/oidc/callback";
+
+/// How long the user has to log in at the provider before the login attempt expires
+const PENDING_LOGIN_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Endpoints of the provider, taken from its discovery document
+#[derive(Clone, Debug, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: Url,
+    token_endpoint: Url,
+    jwks_uri: Option<Url>,
+    #[serde(default)]
+    id_token_signing_alg_values_supported: Vec<String>,
+}
+
+/// A login which was redirected to the provider and waits for its callback
+struct PendingLogin {
+    redirect_url: Url,
+    code_verifier: String,
+    nonce: String,
+    started: Instant,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize)]
+struct IdTokenClaims {
+    nonce: Option<String>,
+    #[serde(flatten)]
+    claims: HashMap<String, serde_json::Value>,
+}
+
+pub struct Service {
+    provider: RwLock<Option<ProviderMetadata>>,
+    pending_logins: StdMutex<HashMap<String, PendingLogin>>, // by state
+    login_tokens: StdMutex<HashMap<String, (OwnedUserId, Instant)>>,
+}
+
+impl Service {
+    pub fn build() -> Result<Self> {
+        Ok(Self {
+            provider: RwLock::new(None),
+            pending_logins: StdMutex::new(HashMap::new()),
+            login_tokens: StdMutex::new(HashMap::new()),
+        })
+    }
+
+    /// Starts a login by building the URL of the provider's authorization endpoint. The user is
+    /// sent back to `redirect_url` with a login token once they logged in at the provider.
+    pub async fn authorization_url(&self, redirect_url: String) -> Result<Url> {
+        let oidc_config = &services().globals.config.oidc;
+
+        let redirect_url = Url::parse(&redirect_url)
+            .map_err(|_| Error::BadRequest(ErrorKind::InvalidParam, "Invalid redirect URL."))?;
+        if !is_allowed_redirect(&redirect_url) {
+            warn!("Refusing SSO login redirecting to {}", redirect_url);
+            return Err(Error::BadRequest(
+                ErrorKind::forbidden(),
+                "Redirect URL is not allowed.",
+            ));
+        }
+
+        let provider = self.provider().await?;
+
+        let state = utils::random_string(32);
+        let nonce = utils::random_string(32);
+        let code_verifier = utils::random_string(64);
+        let code_challenge =
+            general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+
+        let mut url = provider.authorization_endpoint;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &oidc_config.client_id)
+            .append_pair("redirect_uri", &callback_url())
+            .append_pair("scope", &oidc_config.scopes.join(" "))
+            .append_pair("state", &state)
+            .append_pair("nonce", &nonce)
+            .append_pair("code_challenge", &code_challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        let mut pending_logins = self.pending_logins.lock().unwrap();
+        pending_logins.retain(|_, pending| pending.started.elapsed() < PENDING_LOGIN_TTL);
+        pending_logins.insert(
+            state,
+            PendingLogin {
+                redirect_url,
+                code_verifier,
+                nonce,
+                started: Instant::now(),
+            },
+        );
+
+        Ok(url)
+    }
+
+    /// Finishes a login after the provider redirected the user back to us. Creates the user if
+    /// needed and returns the client's redirect URL, carrying a short-lived login token.
+    pub async fn complete_login(&self, state: &str, code: &str) -> Result<Url> {
+        let pending = self
+            .pending_logins
+            .lock()
+            .unwrap()
+            .remove(state)
+            .filter(|pending| pending.started.elapsed() < PENDING_LOGIN_TTL)
+            .ok_or(Error::BadRequest(
+                ErrorKind::forbidden(),
+                "Unknown or expired login attempt.",
+            ))?;
+
+        let mut redirect_url = pending.redirect_url;
+
+        let provider = self.provider().await?;
+        let id_token = self
+            .exchange_code(&provider, code, &pending.code_verifier)
+            .await?;
+        let claims = self.validate_id_token(&provider, &id_token).await?;
+
+        if claims.nonce.as_deref() != Some(&pending.nonce) {
+            return Err(Error::BadRequest(
+                ErrorKind::forbidden(),
+                "ID token nonce does not match.",
+            ));
+        }
+
+        let user_id = provision_user(&claims.claims).await?;
+
+        let token = utils::random_string(32);
+        let mut login_tokens = self.login_tokens.lock().unwrap();
+        login_tokens.retain(|_, (_, issued)| issued.elapsed() < login_token_ttl());
+        login_tokens.insert(token.clone(), (user_id, Instant::now()));
+
+        redirect_url
+            .query_pairs_mut()
+            .append_pair("loginToken", &token);
+
+        Ok(redirect_url)
+    }
+
+    /// Returns the user a login token was issued for, invalidating the token
+    pub fn consume_login_token(&self, token: &str) -> Option<OwnedUserId> {
+        self.login_tokens
+            .lock()
+            .unwrap()
+            .remove(token)
+            .filter(|(_, issued)| issued.elapsed() < login_token_ttl())
+            .map(|(user_id, _)| user_id)
+    }
+
+    async fn provider(&self) -> Result<ProviderMetadata> {
+        if let Some(provider) = &*self.provider.read().await {
+            return Ok(provider.clone());
+        }
+
+        let issuer = services()
+            .globals
+            .config
+            .oidc
+            .issuer
+            .clone()
+            .ok_or_else(|| Error::bad_config("OpenID Connect is enabled, but no issuer is set."))?;
+
+        let provider: ProviderMetadata = get_json(&format!(
+            "{}/.well-known/openid-configuration",
+            issuer.as_str().trim_end_matches('/')
+        ))
+        .await?;
+
+        if provider.issuer.trim_end_matches('/') != issuer.as_str().trim_end_matches('/') {
+            warn!(
+                "OpenID Connect provider claims to be {}, but {} is configured as issuer",
+                provider.issuer, issuer
+            );
+            return Err(Error::BadServerResponse(
+                "OpenID Connect provider's issuer does not match the configured issuer.",
+            ));
+        }
+
+        *self.provider.write().await = Some(provider.clone());
+
+        Ok(provider)
+    }
+
+    async fn exchange_code(
+        &self,
+        provider: &ProviderMetadata,
+        code: &str,
+        code_verifier: &str,
+    ) -> Result<String> {
+        let oidc_config = &services().globals.config.oidc;
+        let callback_url = callback_url();
+
+        let mut params = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &callback_url),
+            ("client_id", &oidc_config.client_id),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(client_secret) = &oidc_config.client_secret {
+            params.push(("client_secret", client_secret));
+        }
+
+        let body = serde_html_form::to_string(&params).expect("form params can be serialized");
+
+        let response = services()
+            .globals
+            .default_client()
+            .post(provider.token_endpoint.clone())
+            .header(
+                http::header::CONTENT_TYPE,
+                "application/x-www-form-urlencoded",
+            )
+            .body(body)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            warn!(
+                "OpenID Connect provider rejected authorization code: {} {:?}",
+                response.status(),
+                response.text().await
+            );
+            return Err(Error::BadServerResponse(
+                "OpenID Connect provider rejected the authorization code.",
+            ));
+        }
+
+        let response: TokenResponse =
+            serde_json::from_slice(&response.bytes().await?).map_err(|_| {
+                Error::BadServerResponse("OpenID Connect provider sent an invalid token response.")
+            })?;
+
+        Ok(response.id_token)
+    }
+
+    async fn validate_id_token(
+        &self,
+        provider: &ProviderMetadata,
+        id_token: &str,
+    ) -> Result<IdTokenClaims> {
+        let oidc_config = &services().globals.config.oidc;
+
+        let header = jsonwebtoken::decode_header(id_token)
+            .map_err(|_| Error::BadServerResponse("Invalid ID token header."))?;
+
+        if !accepted_algorithms(provider).contains(&header.alg) {
+            warn!(
+                "ID token is signed with {:?}, which is not accepted",
+                header.alg
+            );
+            return Err(Error::BadRequest(
+                ErrorKind::forbidden(),
+                "ID token is signed with an algorithm which is not accepted.",
+            ));
+        }
+
+        let key = match header.alg {
+            // Symmetric signatures use the client secret as key, and are only accepted if
+            // explicitly configured
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+                let client_secret =
+                    oidc_config
+                        .client_secret
+                        .as_ref()
+                        .ok_or(Error::BadServerResponse(
+                            "ID token is signed with a client secret, but none is configured.",
+                        ))?;
+                DecodingKey::from_secret(client_secret.as_bytes())
+            }
+            _ => {
+                let jwks_uri = provider.jwks_uri.as_ref().ok_or(Error::BadServerResponse(
+                    "OpenID Connect provider has no JWKS.",
+                ))?;
+                let jwks: JwkSet = get_json(jwks_uri.as_str()).await?;
+
+                let jwk = match &header.kid {
+                    Some(kid) => jwks.find(kid),
+                    // Without a key id, only a single key is unambiguous
+                    None if jwks.keys.len() == 1 => jwks.keys.first(),
+                    None => None,
+                }
+                .ok_or(Error::BadServerResponse("Unknown ID token signing key."))?;
+
+                DecodingKey::from_jwk(jwk)
+                    .map_err(|_| Error::BadServerResponse("Invalid ID token signing key."))?
+            }
+        };
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&provider.issuer]);
+        validation.set_audience(&[&oidc_config.client_id]);
+
+        jsonwebtoken::decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map(|token| token.claims)
+            .map_err(|e| {
+                warn!("Invalid ID token from OpenID Connect provider: {e}");
+                Error::BadRequest(ErrorKind::forbidden(), "ID token is invalid.")
+            })
+    }
+}
+
+/// Creates the user on their first login, like for LDAP. Existing accounts are only logged into
+/// if they belong to the same subject at the provider, or if linking accounts is enabled.
+async fn provision_user(claims: &HashMap<String, serde_json::Value>) -> Result<OwnedUserId> {
+    let attribute_mapping = &services().globals.config.oidc.attribute_mapping;
+    let claim = |attribute: &str| {
+        attribute_mapping
+            .get(attribute)
+            .and_then(|claim| claims.get(claim))
+            .and_then(|value| value.as_str())
+            .map(ToOwned::to_owned)
+    };
+
+    let subject = claims
+        .get("sub")
+        .and_then(|value| value.as_str())
+        .ok_or(Error::BadRequest(
+            ErrorKind::forbidden(),
+            "ID token lacks the sub claim.",
+        ))?;
+
+    let localpart = claim("localpart").ok_or(Error::BadRequest(
+        ErrorKind::forbidden(),
+        "ID token lacks the claim mapped to the localpart.",
+    ))?;
+
+    let user_id =
+        UserId::parse_with_server_name(localpart.to_lowercase(), services().globals.server_name())
+            .map_err(|_| Error::BadRequest(ErrorKind::InvalidUsername, "Username is invalid."))?;
+
+    if services().appservice.is_exclusive_user_id(&user_id).await {
+        return Err(Error::BadRequest(
+            ErrorKind::Exclusive,
+            "User id reserved by appservice.",
+        ));
+    }
+
+    if !services().users.exists(&user_id)? {
+        services().users.create(&user_id, None)?;
+        services().users.set_oidc_subject(&user_id, Some(subject))?;
+        services()
+            .users
+            .set_displayname(&user_id, claim("displayname"))?;
+        services().users.set_email(&user_id, claim("email"))?;
+
+        info!("New user {} registered through OpenID Connect", user_id);
+
+        return Ok(user_id);
+    }
+
+    // Deactivated accounts have no subject, and passwordless accounts can't be told apart from
+    // deactivated ones, so only accounts with a password are linked
+    match services().users.oidc_subject(&user_id)? {
+        Some(existing) if existing == subject => {}
+        None if services().globals.config.oidc.link_existing_accounts
+            && !services().users.is_deactivated(&user_id)? =>
+        {
+            services().users.set_oidc_subject(&user_id, Some(subject))?;
+            info!("Linked existing user {} to OpenID Connect", user_id);
+        }
+        _ => {
+            warn!(
+                "Refusing OpenID Connect login of subject {} as {}, which belongs to someone else",
+                subject, user_id
+            );
+            return Err(Error::BadRequest(
+                ErrorKind::forbidden(),
+                "The username is already taken by another account.",
+            ));
+        }
+    }
+
+    Ok(user_id)
+}
+
+/// Algorithms ID tokens may be signed with: the configured ones, or else the asymmetric ones the
+/// provider supports
+fn accepted_algorithms(provider: &ProviderMetadata) -> Vec<Algorithm> {
+    match &services().globals.config.oidc.id_token_signing_algs {
+        Some(algorithms) => algorithms.clone(),
+        None => provider
+            .id_token_signing_alg_values_supported
+            .iter()
+            .filter_map(|algorithm| algorithm.parse().ok())
+            .filter(|algorithm| {
+                !matches!(
+                    algorithm,
+                    Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+                )
+            })
+            .collect(),
+    }
+}
+
+/// Whether login tokens may be sent to the given client URL. Origins are compared by scheme, host
+/// and port, so that clients with custom URL schemes can be allowed too.
+fn is_allowed_redirect(redirect_url: &Url) -> bool {
+    let origin = |url: &Url| {
+        (
+            url.scheme().to_owned(),
+            url.host_str().map(ToOwned::to_owned),
+            url.port_or_known_default(),
+        )
+    };
+    let well_known_client = Url::parse(&services().globals.well_known_client()).ok();
+
+    services()
+        .globals
+        .config
+        .oidc
+        .allowed_redirect_origins
+        .iter()
+        .chain(well_known_client.as_ref())
+        .any(|allowed| origin(allowed) == origin(redirect_url))
+}
+
+/// URL of our callback endpoint, as registered at the provider
+fn callback_url() -> String {
+    format!(
+        "{}{CALLBACK_PATH}",
+        services().globals.well_known_client().trim_end_matches('/')
+    )
+}
+
+fn login_token_ttl() -> Duration {
+    Duration::from_secs(services().globals.config.oidc.login_token_ttl)
+}
+
+async fn get_json<T: DeserializeOwned>(url: &str) -> Result<T> {
+    let response = services().globals.default_client().get(url).send().await?;
+
+    if !response.status().is_success() {
+        warn!(
+            "OpenID Connect provider returned {} for {}",
+            response.status(),
+            url
+        );
+        return Err(Error::BadServerResponse(
+            "OpenID Connect provider returned an error.",
+        ));
+    }
+
+    serde_json::from_slice(&response.bytes().await?).map_err(|e| {
+        warn!(
+            "Invalid response from OpenID Connect provider at {}: {}",
+            url, e
+        );
+        Error::BadServerResponse("OpenID Connect provider returned an invalid response.")
+    })
+}
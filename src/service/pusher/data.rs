@@ -1,7 +1,7 @@
 use crate::Result;
 use ruma::{
     api::client::push::{set_pusher, Pusher},
-    UserId,
+    OwnedUserId, UserId,
 };
 
 pub trait Data: Send + Sync {
@@ -13,4 +13,7 @@ pub trait Data: Send + Sync {
 
     fn get_pushkeys<'a>(&'a self, sender: &UserId)
         -> Box<dyn Iterator<Item = Result<String>> + 'a>;
+
+    /// Returns the pushers of all users, along with the user they belong to
+    fn all_pushers<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(OwnedUserId, Pusher)>> + 'a>;
 }
@@ -6,7 +6,7 @@ use crate::{services, Error, PduEvent, Result, SUPPORTED_VERSIONS};
 use bytes::BytesMut;
 use ruma::{
     api::{
-        client::push::{set_pusher, Pusher, PusherKind},
+        client::push::{set_pusher, Pusher, PusherIds, PusherKind},
         push_gateway::send_event_notification::{
             self,
             v1::{Device, Notification, NotificationCounts, NotificationPriority},
@@ -16,18 +16,51 @@ use ruma::{
     events::TimelineEventType,
     push::{Action, PushConditionRoomCtx, PushFormat, Ruleset, Tweak},
     serde::Raw,
-    uint, RoomId, UInt, UserId,
+    uint, OwnedUserId, RoomId, UInt, UserId,
 };
 
-use std::{fmt::Debug, mem};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    mem,
+    sync::Mutex as StdMutex,
+    time::{Duration, SystemTime},
+};
 use tracing::{info, warn};
 
+/// How long we wait for a push gateway to respond before giving up on the request. Failed
+/// notifications are retried with exponential backoff by the sending service.
+const PUSH_GATEWAY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Outcome of the most recent deliveries to a pusher, kept in memory since startup
+#[derive(Clone, Debug, Default)]
+pub struct DeliveryStatus {
+    pub last_success: Option<SystemTime>,
+    pub last_failure: Option<(SystemTime, String)>,
+    pub consecutive_failures: u32,
+}
+
 pub struct Service {
     pub db: &'static dyn Data,
+    delivery_status: StdMutex<HashMap<(OwnedUserId, String), DeliveryStatus>>, // user and pushkey
 }
 
 impl Service {
+    pub fn build(db: &'static dyn Data) -> Self {
+        Self {
+            db,
+            delivery_status: StdMutex::new(HashMap::new()),
+        }
+    }
+
     pub fn set_pusher(&self, sender: &UserId, pusher: set_pusher::v3::PusherAction) -> Result<()> {
+        if let set_pusher::v3::PusherAction::Delete(ids) = &pusher {
+            self.delivery_status
+                .lock()
+                .unwrap()
+                .remove(&(sender.to_owned(), ids.pushkey.clone()));
+        }
+
         self.db.set_pusher(sender, pusher)
     }
 
@@ -43,6 +76,38 @@ impl Service {
         self.db.get_pushkeys(sender)
     }
 
+    /// Returns the pushers of all users on this server
+    pub fn all_pushers(&self) -> impl Iterator<Item = Result<(OwnedUserId, Pusher)>> + '_ {
+        self.db.all_pushers()
+    }
+
+    /// Returns the outcome of the latest deliveries to the given pusher, if any were attempted
+    pub fn delivery_status(&self, user: &UserId, pushkey: &str) -> Option<DeliveryStatus> {
+        self.delivery_status
+            .lock()
+            .unwrap()
+            .get(&(user.to_owned(), pushkey.to_owned()))
+            .cloned()
+    }
+
+    fn record_delivery<T>(&self, user: &UserId, pushkey: &str, result: &Result<T>) {
+        let mut delivery_status = self.delivery_status.lock().unwrap();
+        let status = delivery_status
+            .entry((user.to_owned(), pushkey.to_owned()))
+            .or_default();
+
+        match result {
+            Ok(_) => {
+                status.last_success = Some(SystemTime::now());
+                status.consecutive_failures = 0;
+            }
+            Err(e) => {
+                status.last_failure = Some((SystemTime::now(), e.to_string()));
+                status.consecutive_failures = status.consecutive_failures.saturating_add(1);
+            }
+        }
+    }
+
     #[tracing::instrument(skip(self, destination, request))]
     pub async fn send_request<T>(
         &self,
@@ -66,10 +131,10 @@ impl Service {
             })?
             .map(|body| body.freeze());
 
-        let reqwest_request = reqwest::Request::try_from(http_request)?;
+        let mut reqwest_request = reqwest::Request::try_from(http_request)?;
 
-        // TODO: we could keep this very short and let expo backoff do it's thing...
-        //*reqwest_request.timeout_mut() = Some(Duration::from_secs(5));
+        // We keep this short and let the exponential backoff of the sending service retry
+        *reqwest_request.timeout_mut() = Some(PUSH_GATEWAY_TIMEOUT);
 
         let url = reqwest_request.url().clone();
         let response = services()
@@ -92,12 +157,15 @@ impl Service {
                         .expect("http::response::Builder is usable"),
                 );
 
-                let body = response.bytes().await.unwrap_or_else(|e| {
-                    warn!("server error {}", e);
-                    Vec::new().into()
-                }); // TODO: handle timeout
+                let body = response.bytes().await.map_err(|e| {
+                    warn!(
+                        "Failed to read response body from pusher {}: {}",
+                        destination, e
+                    );
+                    Error::BadServerResponse("Push gateway did not send a complete response.")
+                })?;
 
-                if status != 200 {
+                if !status.is_success() {
                     info!(
                         "Push gateway returned bad response {} {}\n{}\n{:?}",
                         destination,
@@ -105,6 +173,10 @@ impl Service {
                         url,
                         crate::utils::string_from_bytes(&body)
                     );
+
+                    return Err(Error::BadServerResponse(
+                        "Push gateway returned unsuccessful status code.",
+                    ));
                 }
 
                 let response = T::IncomingResponse::try_from_http_response(
@@ -173,7 +245,7 @@ impl Service {
         }
 
         if notify == Some(true) {
-            self.send_notice(unread, pusher, tweaks, pdu).await?;
+            self.send_notice(user, unread, pusher, tweaks, pdu).await?;
         }
         // Else the event triggered no actions
 
@@ -203,9 +275,10 @@ impl Service {
         Ok(ruleset.get_actions(pdu, &ctx).await)
     }
 
-    #[tracing::instrument(skip(self, unread, pusher, tweaks, event))]
+    #[tracing::instrument(skip(self, user, unread, pusher, tweaks, event))]
     async fn send_notice(
         &self,
+        user: &UserId,
         unread: UInt,
         pusher: &Pusher,
         tweaks: Vec<Tweak>,
@@ -246,10 +319,7 @@ impl Service {
                     notifi.prio = NotificationPriority::High
                 }
 
-                if event_id_only {
-                    self.send_request(&http.url, send_event_notification::v1::Request::new(notifi))
-                        .await?;
-                } else {
+                if !event_id_only {
                     notifi.sender = Some(event.sender.clone());
                     notifi.event_type = Some(event.kind.clone());
                     notifi.content = serde_json::value::to_raw_value(&event.content).ok();
@@ -263,9 +333,24 @@ impl Service {
 
                     notifi.room_name =
                         services().rooms.state_accessor.get_name(&event.room_id())?;
+                }
 
-                    self.send_request(&http.url, send_event_notification::v1::Request::new(notifi))
-                        .await?;
+                let response = self
+                    .send_request(&http.url, send_event_notification::v1::Request::new(notifi))
+                    .await;
+                self.record_delivery(user, &pusher.ids.pushkey, &response);
+
+                // The push gateway spec requires us to stop sending notifications to rejected
+                // pushkeys, so we remove the corresponding pushers
+                for pushkey in response?.rejected {
+                    info!("Push gateway rejected pushkey {pushkey} of {user}, removing pusher");
+                    self.set_pusher(
+                        user,
+                        set_pusher::v3::PusherAction::Delete(PusherIds::new(
+                            pushkey,
+                            pusher.ids.app_id.clone(),
+                        )),
+                    )?;
                 }
 
                 Ok(())
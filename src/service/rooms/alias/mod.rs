@@ -28,7 +28,10 @@ impl Service {
                 "Only the server user can set this alias",
             ))
         } else {
-            self.db.set_alias(alias, room_id, user_id)
+            self.db.set_alias(alias, room_id, user_id)?;
+            services().appservice.room_changed(room_id);
+
+            Ok(())
         }
     }
 
@@ -65,7 +68,13 @@ impl Service {
     #[tracing::instrument(skip(self))]
     pub fn remove_alias(&self, alias: &RoomAliasId, user_id: &UserId) -> Result<()> {
         if self.user_can_remove_alias(alias, user_id)? {
-            self.db.remove_alias(alias)
+            let room_id = self.resolve_local_alias(alias)?;
+            self.db.remove_alias(alias)?;
+            if let Some(room_id) = room_id {
+                services().appservice.room_changed(&room_id);
+            }
+
+            Ok(())
         } else {
             Err(Error::BadRequest(
                 ErrorKind::forbidden(),
@@ -13,4 +13,34 @@ pub trait Data: Send + Sync {
 
     /// Returns the unsorted public room directory
     fn public_rooms<'a>(&'a self) -> Box<dyn Iterator<Item = Result<OwnedRoomId>> + 'a>;
+
+    /// Adds the room to the room directory of a network of an appservice
+    fn set_public_in_network(
+        &self,
+        appservice_id: &str,
+        network_id: &str,
+        room_id: &RoomId,
+    ) -> Result<()>;
+
+    /// Removes the room from the room directory of a network of an appservice
+    fn set_not_public_in_network(
+        &self,
+        appservice_id: &str,
+        network_id: &str,
+        room_id: &RoomId,
+    ) -> Result<()>;
+
+    /// Returns the unsorted room directory of a network of an appservice
+    fn network_public_rooms<'a>(
+        &'a self,
+        appservice_id: &str,
+        network_id: &str,
+    ) -> Box<dyn Iterator<Item = Result<OwnedRoomId>> + 'a>;
+
+    /// Removes all rooms from the room directories of all networks of an appservice
+    fn clear_appservice_networks(&self, appservice_id: &str) -> Result<()>;
+
+    /// Returns the unsorted room directories of all networks of all appservices
+    fn all_network_public_rooms<'a>(&'a self)
+        -> Box<dyn Iterator<Item = Result<OwnedRoomId>> + 'a>;
 }
@@ -29,4 +29,45 @@ impl Service {
     pub fn public_rooms(&self) -> impl Iterator<Item = Result<OwnedRoomId>> + '_ {
         self.db.public_rooms()
     }
+
+    #[tracing::instrument(skip(self))]
+    pub fn set_public_in_network(
+        &self,
+        appservice_id: &str,
+        network_id: &str,
+        room_id: &RoomId,
+    ) -> Result<()> {
+        self.db
+            .set_public_in_network(appservice_id, network_id, room_id)
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn set_not_public_in_network(
+        &self,
+        appservice_id: &str,
+        network_id: &str,
+        room_id: &RoomId,
+    ) -> Result<()> {
+        self.db
+            .set_not_public_in_network(appservice_id, network_id, room_id)
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn network_public_rooms<'a>(
+        &'a self,
+        appservice_id: &str,
+        network_id: &str,
+    ) -> impl Iterator<Item = Result<OwnedRoomId>> + 'a {
+        self.db.network_public_rooms(appservice_id, network_id)
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn clear_appservice_networks(&self, appservice_id: &str) -> Result<()> {
+        self.db.clear_appservice_networks(appservice_id)
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn all_network_public_rooms(&self) -> impl Iterator<Item = Result<OwnedRoomId>> + '_ {
+        self.db.all_network_public_rooms()
+    }
 }
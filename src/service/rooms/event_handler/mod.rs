@@ -252,6 +252,7 @@ impl Service {
                     }
                 }
                 let elapsed = start_time.elapsed();
+                services().metrics.observe_incoming_pdu(elapsed);
                 services()
                     .globals
                     .roomid_federationhandletime
@@ -288,6 +289,9 @@ impl Service {
                 pub_key_map,
             )
             .await;
+        services()
+            .metrics
+            .observe_incoming_pdu(start_time.elapsed());
         services()
             .globals
             .roomid_federationhandletime
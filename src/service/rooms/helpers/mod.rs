@@ -57,6 +57,17 @@ impl Service {
             });
         }
 
+        // Guests may only join rooms which allow guest access, which we can only tell for rooms we
+        // know about
+        if services().users.is_guest(sender_user)?
+            && !services().rooms.state_accessor.guest_can_join(room_id)?
+        {
+            return Err(Error::BadRequest(
+                ErrorKind::GuestAccessForbidden,
+                "This room does not allow guests to join.",
+            ));
+        }
+
         let mutex_state = Arc::clone(
             services()
                 .globals
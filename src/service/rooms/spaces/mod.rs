@@ -116,6 +116,8 @@ impl Display for PagnationToken {
 pub enum Identifier<'a> {
     UserId(&'a UserId),
     ServerName(&'a ServerName),
+    /// Unauthenticated requests, which can only see rooms anyone could join
+    Anonymous,
 }
 
 pub struct Service {
@@ -333,6 +335,108 @@ impl Service {
         }
     }
 
+    /// Gets the summary of a single room, for previewing it before joining (MSC3266)
+    ///
+    /// Asks the servers in `via` for the room's hierarchy if it is not known locally.
+    pub async fn get_room_preview(
+        &self,
+        room_id: &OwnedRoomId,
+        sender_user: Option<&UserId>,
+        via: &[OwnedServerName],
+    ) -> Result<RoomSummary> {
+        let summary = if services()
+            .rooms
+            .state
+            .get_room_shortstatehash(room_id)?
+            .is_some()
+        {
+            local_room_summary(room_id, room_join_rule(room_id)?.into())?
+        } else {
+            self.get_room_preview_federation(room_id, sender_user.is_some(), via)
+                .await?
+                .ok_or(Error::BadRequest(
+                    ErrorKind::NotFound,
+                    "The requested room was not found",
+                ))?
+        };
+
+        let identifier = match sender_user {
+            Some(user_id) => Identifier::UserId(user_id),
+            None => Identifier::Anonymous,
+        };
+
+        // World readable rooms can be previewed by anyone, just like their history can be read
+        if !summary.world_readable && !is_accessible_child(room_id, &summary.join_rule, &identifier)
+        {
+            return Err(Error::BadRequest(
+                ErrorKind::NotFound,
+                "The requested room is inaccessible",
+            ));
+        }
+
+        Ok(summary)
+    }
+
+    /// Gets the summary of a single remote room, from the cache or by asking the given servers
+    ///
+    /// The servers are chosen by the requester, so the answer is only cached for authenticated
+    /// requests, as the cache is shared by all users.
+    async fn get_room_preview_federation(
+        &self,
+        room_id: &OwnedRoomId,
+        cache: bool,
+        via: &[OwnedServerName],
+    ) -> Result<Option<RoomSummary>> {
+        if let Some(Some(cached)) = self
+            .roomid_spacehierarchy_cache
+            .lock()
+            .await
+            .get_mut(room_id)
+        {
+            return Ok(Some(cached.summary.summary.clone()));
+        }
+
+        for server in via {
+            if server == services().globals.server_name() {
+                continue;
+            }
+
+            debug!("Asking {server} for /hierarchy to preview {room_id}");
+            match services()
+                .sending
+                .send_federation_request(
+                    server,
+                    federation::space::get_hierarchy::v1::Request {
+                        room_id: room_id.to_owned(),
+                        suggested_only: false,
+                    },
+                )
+                .await
+            {
+                Ok(response) if response.room.summary.room_id != *room_id => {
+                    warn!("{server} returned the /hierarchy of another room to preview {room_id}");
+                }
+                Ok(response) => {
+                    let summary = response.room.summary.clone();
+
+                    if cache {
+                        self.roomid_spacehierarchy_cache.lock().await.insert(
+                            room_id.clone(),
+                            Some(CachedSpaceHierarchySummary {
+                                summary: response.room,
+                            }),
+                        );
+                    }
+
+                    return Ok(Some(summary));
+                }
+                Err(e) => debug!("Failed to get /hierarchy of {room_id} from {server}: {e}"),
+            }
+        }
+
+        Ok(None)
+    }
+
     fn get_room_summary(
         &self,
         current_room: &OwnedRoomId,
@@ -341,20 +445,7 @@ impl Service {
     ) -> Result<SpaceHierarchyParentSummary, Error> {
         let room_id: &RoomId = current_room;
 
-        let join_rule = services()
-            .rooms
-            .state_accessor
-            .room_state_get(room_id, &StateEventType::RoomJoinRules, "")?
-            .map(|s| {
-                serde_json::from_str(s.content.get())
-                    .map(|c: RoomJoinRulesEventContent| c.join_rule)
-                    .map_err(|e| {
-                        error!("Invalid room join rule event in database: {}", e);
-                        Error::BadDatabase("Invalid room join rule event in database.")
-                    })
-            })
-            .transpose()?
-            .unwrap_or(JoinRule::Invite);
+        let join_rule = room_join_rule(room_id)?;
 
         if !is_accessible_child(current_room, &join_rule.clone().into(), &identifier) {
             debug!("User is not allowed to see room {room_id}");
@@ -365,84 +456,8 @@ impl Service {
             ));
         }
 
-        let join_rule = join_rule.into();
-
         Ok(SpaceHierarchyParentSummary {
-            summary: RoomSummary {
-                canonical_alias: services()
-                    .rooms
-                    .state_accessor
-                    .room_state_get(room_id, &StateEventType::RoomCanonicalAlias, "")?
-                    .map_or(Ok(None), |s| {
-                        serde_json::from_str(s.content.get())
-                            .map(|c: RoomCanonicalAliasEventContent| c.alias)
-                            .map_err(|_| {
-                                Error::bad_database("Invalid canonical alias event in database.")
-                            })
-                    })?,
-                name: services().rooms.state_accessor.get_name(room_id)?,
-                num_joined_members: services()
-                    .rooms
-                    .state_cache
-                    .room_joined_count(room_id)?
-                    .unwrap_or_else(|| {
-                        warn!("Room {} has no member count", room_id);
-                        0
-                    })
-                    .try_into()
-                    .expect("user count should not be that big"),
-                room_id: room_id.to_owned(),
-                topic: services()
-                    .rooms
-                    .state_accessor
-                    .room_state_get(room_id, &StateEventType::RoomTopic, "")?
-                    .map_or(Ok(None), |s| {
-                        serde_json::from_str(s.content.get())
-                            .map(|c: RoomTopicEventContent| Some(c.topic))
-                            .map_err(|_| {
-                                error!("Invalid room topic event in database for room {}", room_id);
-                                Error::bad_database("Invalid room topic event in database.")
-                            })
-                    })?,
-                world_readable: services().rooms.state_accessor.world_readable(room_id)?,
-                guest_can_join: services().rooms.state_accessor.guest_can_join(room_id)?,
-                avatar_url: services()
-                    .rooms
-                    .state_accessor
-                    .room_state_get(room_id, &StateEventType::RoomAvatar, "")?
-                    .map(|s| {
-                        serde_json::from_str(s.content.get())
-                            .map(|c: RoomAvatarEventContent| c.url)
-                            .map_err(|_| {
-                                Error::bad_database("Invalid room avatar event in database.")
-                            })
-                    })
-                    .transpose()?
-                    // url is now an Option<String> so we must flatten
-                    .flatten(),
-                join_rule,
-                room_type: services()
-                    .rooms
-                    .state_accessor
-                    .room_state_get(room_id, &StateEventType::RoomCreate, "")?
-                    .map(|s| {
-                        serde_json::from_str::<RoomCreateEventContent>(s.content.get()).map_err(
-                            |e| {
-                                error!("Invalid room create event in database: {}", e);
-                                Error::BadDatabase("Invalid room create event in database.")
-                            },
-                        )
-                    })
-                    .transpose()?
-                    .and_then(|e| e.room_type),
-                encryption: services()
-                    .rooms
-                    .state_accessor
-                    .room_state_get(room_id, &StateEventType::RoomEncryption, "")?
-                    .and_then(|pdu| serde_json::from_str(pdu.content.get()).ok())
-                    .map(|content: RoomEncryptionEventContent| content.algorithm),
-                room_version: services().rooms.state.get_room_version(room_id).ok(),
-            },
+            summary: local_room_summary(room_id, join_rule.into())?,
             children_state,
         })
     }
@@ -589,6 +604,97 @@ fn next_room_to_traverse(
     stack.last_mut().and_then(|s| s.pop())
 }
 
+/// Returns the join rule of a room known to this server
+fn room_join_rule(room_id: &RoomId) -> Result<JoinRule> {
+    Ok(services()
+        .rooms
+        .state_accessor
+        .room_state_get(room_id, &StateEventType::RoomJoinRules, "")?
+        .map(|s| {
+            serde_json::from_str(s.content.get())
+                .map(|c: RoomJoinRulesEventContent| c.join_rule)
+                .map_err(|e| {
+                    error!("Invalid room join rule event in database: {}", e);
+                    Error::BadDatabase("Invalid room join rule event in database.")
+                })
+        })
+        .transpose()?
+        .unwrap_or(JoinRule::Invite))
+}
+
+/// Builds the summary of a room known to this server from its current state
+fn local_room_summary(room_id: &RoomId, join_rule: JoinRuleSummary) -> Result<RoomSummary> {
+    Ok(RoomSummary {
+        canonical_alias: services()
+            .rooms
+            .state_accessor
+            .room_state_get(room_id, &StateEventType::RoomCanonicalAlias, "")?
+            .map_or(Ok(None), |s| {
+                serde_json::from_str(s.content.get())
+                    .map(|c: RoomCanonicalAliasEventContent| c.alias)
+                    .map_err(|_| Error::bad_database("Invalid canonical alias event in database."))
+            })?,
+        name: services().rooms.state_accessor.get_name(room_id)?,
+        num_joined_members: services()
+            .rooms
+            .state_cache
+            .room_joined_count(room_id)?
+            .unwrap_or_else(|| {
+                warn!("Room {} has no member count", room_id);
+                0
+            })
+            .try_into()
+            .expect("user count should not be that big"),
+        room_id: room_id.to_owned(),
+        topic: services()
+            .rooms
+            .state_accessor
+            .room_state_get(room_id, &StateEventType::RoomTopic, "")?
+            .map_or(Ok(None), |s| {
+                serde_json::from_str(s.content.get())
+                    .map(|c: RoomTopicEventContent| Some(c.topic))
+                    .map_err(|_| {
+                        error!("Invalid room topic event in database for room {}", room_id);
+                        Error::bad_database("Invalid room topic event in database.")
+                    })
+            })?,
+        world_readable: services().rooms.state_accessor.world_readable(room_id)?,
+        guest_can_join: services().rooms.state_accessor.guest_can_join(room_id)?,
+        avatar_url: services()
+            .rooms
+            .state_accessor
+            .room_state_get(room_id, &StateEventType::RoomAvatar, "")?
+            .map(|s| {
+                serde_json::from_str(s.content.get())
+                    .map(|c: RoomAvatarEventContent| c.url)
+                    .map_err(|_| Error::bad_database("Invalid room avatar event in database."))
+            })
+            .transpose()?
+            // url is now an Option<String> so we must flatten
+            .flatten(),
+        join_rule,
+        room_type: services()
+            .rooms
+            .state_accessor
+            .room_state_get(room_id, &StateEventType::RoomCreate, "")?
+            .map(|s| {
+                serde_json::from_str::<RoomCreateEventContent>(s.content.get()).map_err(|e| {
+                    error!("Invalid room create event in database: {}", e);
+                    Error::BadDatabase("Invalid room create event in database.")
+                })
+            })
+            .transpose()?
+            .and_then(|e| e.room_type),
+        encryption: services()
+            .rooms
+            .state_accessor
+            .room_state_get(room_id, &StateEventType::RoomEncryption, "")?
+            .and_then(|pdu| serde_json::from_str(pdu.content.get()).ok())
+            .map(|content: RoomEncryptionEventContent| content.algorithm),
+        room_version: services().rooms.state.get_room_version(room_id).ok(),
+    })
+}
+
 /// Simply returns the stripped m.space.child events of a room
 async fn get_stripped_space_child_events(
     room_id: &RoomId,
@@ -666,6 +772,7 @@ fn is_accessible_child(
                 return true;
             }
         }
+        Identifier::Anonymous => {}
     } // Takes care of joinrules
     match join_rule {
         JoinRuleSummary::Restricted(RestrictedSummary { allowed_room_ids }) => {
@@ -691,6 +798,7 @@ fn is_accessible_child(
                             return true;
                         }
                     }
+                    Identifier::Anonymous => {}
                 }
             }
             false
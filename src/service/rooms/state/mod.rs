@@ -319,7 +319,13 @@ impl Service {
         shortstatehash: u64,
         mutex_lock: &MutexGuard<'_, ()>, // Take mutex guard to make sure users get the room state mutex
     ) -> Result<()> {
-        self.db.set_room_state(room_id, shortstatehash, mutex_lock)
+        self.db
+            .set_room_state(room_id, shortstatehash, mutex_lock)?;
+
+        // E.g. the canonical alias might have changed
+        services().appservice.room_changed(room_id);
+
+        Ok(())
     }
 
     /// Returns the room's version.
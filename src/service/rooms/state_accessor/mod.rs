@@ -123,15 +123,7 @@ impl Service {
             return Ok(*visibility);
         }
 
-        let history_visibility = self
-            .state_get(shortstatehash, &StateEventType::RoomHistoryVisibility, "")?
-            .map_or(Ok(HistoryVisibility::Shared), |s| {
-                serde_json::from_str(s.content.get())
-                    .map(|c: RoomHistoryVisibilityEventContent| c.history_visibility)
-                    .map_err(|_| {
-                        Error::bad_database("Invalid history visibility event in database.")
-                    })
-            })?;
+        let history_visibility = self.history_visibility(shortstatehash)?;
 
         let mut current_server_members = services()
             .rooms
@@ -164,8 +156,13 @@ impl Service {
         Ok(visibility)
     }
 
-    /// Whether a user is allowed to see an event, based on
-    /// the room's history_visibility at that event's state.
+    /// Whether a user is allowed to see an event, based on the room's history_visibility at that
+    /// event's state and the user's membership back then, following the rules of the spec:
+    ///
+    /// - `world_readable` events are visible to everyone
+    /// - Events are visible to users who were joined at the time
+    /// - `shared` events are also visible to users who joined at any point after the event
+    /// - `invited` events are also visible to users who were invited at the time
     #[tracing::instrument(skip(self, user_id, room_id, event_id))]
     pub fn user_can_see_event(
         &self,
@@ -187,60 +184,113 @@ impl Service {
             return Ok(*visibility);
         }
 
-        let currently_member = services().rooms.state_cache.is_joined(user_id, room_id)?;
-
-        let history_visibility = self
-            .state_get(shortstatehash, &StateEventType::RoomHistoryVisibility, "")?
-            .map_or(Ok(HistoryVisibility::Shared), |s| {
-                serde_json::from_str(s.content.get())
-                    .map(|c: RoomHistoryVisibilityEventContent| c.history_visibility)
-                    .map_err(|_| {
-                        Error::bad_database("Invalid history visibility event in database.")
-                    })
-            })?;
+        let history_visibility = self.history_visibility(shortstatehash)?;
 
         let visibility = match history_visibility {
             HistoryVisibility::WorldReadable => true,
-            HistoryVisibility::Shared => currently_member,
-            HistoryVisibility::Invited => {
-                // Allow if any member on requesting server was AT LEAST invited, else deny
-                self.user_was_invited(shortstatehash, user_id)
-            }
-            HistoryVisibility::Joined => {
-                // Allow if any member on requested server was joined, else deny
-                self.user_was_joined(shortstatehash, user_id)
-            }
+            _ if self.user_was_joined(shortstatehash, user_id) => true,
+            HistoryVisibility::Shared => self.user_joined_after(user_id, room_id, event_id)?,
+            HistoryVisibility::Invited => self.user_was_invited(shortstatehash, user_id),
+            HistoryVisibility::Joined => false,
             _ => {
                 error!("Unknown history visibility {history_visibility}");
                 false
             }
         };
 
-        self.user_visibility_cache
-            .lock()
-            .unwrap()
-            .insert((user_id.to_owned(), shortstatehash), visibility);
+        // Whether a user joined after a `shared` event can change when they join the room, so
+        // only results that stay the same forever are cached
+        if visibility || history_visibility != HistoryVisibility::Shared {
+            self.user_visibility_cache
+                .lock()
+                .unwrap()
+                .insert((user_id.to_owned(), shortstatehash), visibility);
+        }
 
         Ok(visibility)
     }
 
-    /// Whether a user is allowed to see an event, based on
-    /// the room's history_visibility at that event's state.
+    /// Whether a user might be able to see any events of a room, so endpoints can reject requests
+    /// for rooms the user never had access to without going through the whole history.
+    #[tracing::instrument(skip(self, user_id, room_id))]
+    pub fn user_can_see_room_history(&self, user_id: &UserId, room_id: &RoomId) -> Result<bool> {
+        Ok(services().rooms.state_cache.is_joined(user_id, room_id)?
+            || services().rooms.state_cache.once_joined(user_id, room_id)?
+            || services().rooms.state_cache.is_invited(user_id, room_id)?
+            || self.world_readable(room_id)?)
+    }
+
+    /// Whether a user is allowed to see the current state of a room, which is the case for
+    /// members and everyone peeking into world readable rooms.
     #[tracing::instrument(skip(self, user_id, room_id))]
     pub fn user_can_see_state_events(&self, user_id: &UserId, room_id: &RoomId) -> Result<bool> {
-        let currently_member = services().rooms.state_cache.is_joined(user_id, room_id)?;
+        Ok(services().rooms.state_cache.is_joined(user_id, room_id)?
+            || self.world_readable(room_id)?)
+    }
 
-        let history_visibility = self
-            .room_state_get(room_id, &StateEventType::RoomHistoryVisibility, "")?
+    /// The history visibility of the room at this state
+    fn history_visibility(&self, shortstatehash: u64) -> Result<HistoryVisibility> {
+        self.state_get(shortstatehash, &StateEventType::RoomHistoryVisibility, "")?
             .map_or(Ok(HistoryVisibility::Shared), |s| {
                 serde_json::from_str(s.content.get())
                     .map(|c: RoomHistoryVisibilityEventContent| c.history_visibility)
                     .map_err(|_| {
                         Error::bad_database("Invalid history visibility event in database.")
                     })
-            })?;
+            })
+    }
+
+    /// Whether the user was joined to the room at any point after the event, found by going back
+    /// through the user's membership changes until the event
+    fn user_joined_after(
+        &self,
+        user_id: &UserId,
+        room_id: &RoomId,
+        event_id: &EventId,
+    ) -> Result<bool> {
+        if services().rooms.state_cache.is_joined(user_id, room_id)? {
+            return Ok(true);
+        }
+
+        let timeline = &services().rooms.timeline;
+        let Some(event_count) = timeline.get_pdu_count(event_id)? else {
+            return Ok(false);
+        };
+
+        let mut membership_event =
+            self.room_state_get(room_id, &StateEventType::RoomMember, user_id.as_str())?;
+        let mut previous_count = None;
+        while let Some(pdu) = membership_event {
+            let Some(count) = timeline.get_pdu_count(&pdu.event_id)? else {
+                return Ok(false);
+            };
+
+            // Every change before this one happened before the event too. Changes are also
+            // required to go back in time, so a broken chain can't make this loop forever.
+            if count <= event_count || previous_count.is_some_and(|previous| count >= previous) {
+                return Ok(false);
+            }
+            previous_count = Some(count);
+
+            let membership = serde_json::from_str::<RoomMemberEventContent>(pdu.content.get())
+                .map_err(|_| Error::bad_database("Invalid room membership event in database."))?
+                .membership;
+            if membership == MembershipState::Join {
+                return Ok(true);
+            }
+
+            // The membership the user had before this change
+            membership_event = match self.pdu_shortstatehash(&pdu.event_id)? {
+                Some(shortstatehash) => self.state_get(
+                    shortstatehash,
+                    &StateEventType::RoomMember,
+                    user_id.as_str(),
+                )?,
+                None => None,
+            };
+        }
 
-        Ok(currently_member || history_visibility == HistoryVisibility::WorldReadable)
+        Ok(false)
     }
 
     /// Returns the state hash for this pdu.
@@ -203,7 +203,11 @@ impl Service {
 
     #[tracing::instrument(skip(self, room_id))]
     pub fn update_joined_count(&self, room_id: &RoomId) -> Result<()> {
-        self.db.update_joined_count(room_id)
+        self.db.update_joined_count(room_id)?;
+
+        services().appservice.room_changed(room_id);
+
+        Ok(())
     }
 
     #[tracing::instrument(skip(self, room_id))]
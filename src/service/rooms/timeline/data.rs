@@ -46,6 +46,7 @@ pub trait Data: Send + Sync {
         pdu: &PduEvent,
         json: &CanonicalJsonObject,
         count: u64,
+        search_body: Option<&str>,
     ) -> Result<()>;
 
     // Adds a new pdu to the backfilled timeline
@@ -54,6 +55,7 @@ pub trait Data: Send + Sync {
         pdu_id: &[u8],
         event_id: &EventId,
         json: &CanonicalJsonObject,
+        search_body: Option<&str>,
     ) -> Result<()>;
 
     /// Removes a pdu and creates a new one with the same id.
@@ -10,7 +10,7 @@ use std::{
 pub use data::Data;
 
 use ruma::{
-    api::{client::error::ErrorKind, federation},
+    api::{client::error::ErrorKind, federation, Direction},
     canonical_json::to_canonical_value,
     events::{
         push_rules::PushRulesEvent,
@@ -42,6 +42,9 @@ use crate::{
 
 use super::state_compressor::CompressedStateEvent;
 
+/// How many events `pdu_by_timestamp` looks at before giving up
+const MAX_TIMESTAMP_SCAN: usize = 10_000;
+
 #[derive(Hash, PartialEq, Eq, Clone, Copy, Debug)]
 pub enum PduCount {
     Backfilled(u64),
@@ -285,8 +288,15 @@ impl Service {
         let mut pdu_id = shortroomid.to_be_bytes().to_vec();
         pdu_id.extend_from_slice(&count2.to_be_bytes());
 
-        // Insert pdu
-        self.db.append_pdu(&pdu_id, pdu, &pdu_json, count2)?;
+        // Insert pdu, together with its search index entries
+        let body = message_body(pdu);
+        self.db.append_pdu(
+            &pdu_id,
+            pdu,
+            &pdu_json,
+            count2,
+            body.as_ref().ok().and_then(|body| body.as_deref()),
+        )?;
 
         drop(insert_lock);
 
@@ -479,20 +489,7 @@ impl Service {
                 }
             }
             TimelineEventType::RoomMessage => {
-                #[derive(Deserialize)]
-                struct ExtractBody {
-                    body: Option<String>,
-                }
-
-                let content = serde_json::from_str::<ExtractBody>(pdu.content.get())
-                    .map_err(|_| Error::bad_database("Invalid content in pdu."))?;
-
-                if let Some(body) = content.body {
-                    services()
-                        .rooms
-                        .search
-                        .index_pdu(shortroomid, &pdu_id, &body)?;
-
+                if let Some(body) = body? {
                     let server_user = services().globals.server_user();
 
                     // This will evaluate to false if the emergency password is set up so that
@@ -559,6 +556,15 @@ impl Service {
             _ => {}
         }
 
+        // Restrict access to media sent in messages to those who can see them. State events like
+        // avatars are left out, as they are also shown to users outside of the room
+        if pdu.state_key.is_none() {
+            // The event is already appended, failing here would only hide it from the caller
+            if let Err(e) = services().media.link_to_event(pdu) {
+                warn!("Failed to link media to event {}: {}", pdu.event_id, e);
+            }
+        }
+
         // Update Relationships
         #[derive(Deserialize)]
         struct ExtractRelatesTo {
@@ -1272,6 +1278,56 @@ impl Service {
         self.db.pdus_after(user_id, room_id, from)
     }
 
+    /// Returns the first event sent at or after (`dir=f`), or the last event sent at or before
+    /// (`dir=b`) the given timestamp, only looking at the history known to this server. History
+    /// is scanned from the newest event, and at most `MAX_TIMESTAMP_SCAN` events are looked at.
+    ///
+    /// The second return value is true if there might be a closer event in history this server
+    /// hasn't backfilled yet, or if the scan gave up before finding one.
+    #[tracing::instrument(skip(self))]
+    pub fn pdu_by_timestamp(
+        &self,
+        room_id: &RoomId,
+        ts: MilliSecondsSinceUnixEpoch,
+        dir: Direction,
+    ) -> Result<(Option<PduEvent>, bool)> {
+        let user_id = user_id!("@doesntmatter:conduit.rs");
+
+        let pdus = self
+            .pdus_until(user_id, room_id, PduCount::max())?
+            .filter_map(|r| r.ok())
+            .map(|(_, pdu)| pdu)
+            .take(MAX_TIMESTAMP_SCAN);
+
+        let mut scanned = 0;
+        let mut oldest_is_create = false;
+        let mut closest = None;
+        for pdu in pdus {
+            scanned += 1;
+            match dir {
+                // The first event at or after the timestamp is the one before the first event
+                // older than it
+                Direction::Forward if pdu.origin_server_ts < ts.0 => return Ok((closest, false)),
+                Direction::Backward if pdu.origin_server_ts <= ts.0 => {
+                    return Ok((Some(pdu), false))
+                }
+                _ => {}
+            }
+            oldest_is_create = pdu.kind == TimelineEventType::RoomCreate;
+            if matches!(dir, Direction::Forward) {
+                closest = Some(pdu);
+            }
+        }
+
+        if scanned == MAX_TIMESTAMP_SCAN {
+            return Ok((None, true));
+        }
+
+        // We reached the oldest event we know, and only the create event proves that it's the
+        // beginning of the room
+        Ok((closest, !oldest_is_create))
+    }
+
     /// Replace a PDU with the redacted form.
     #[tracing::instrument(skip(self, reason))]
     pub fn redact_pdu(
@@ -1432,27 +1488,19 @@ impl Service {
         pdu_id.extend_from_slice(&0_u64.to_be_bytes());
         pdu_id.extend_from_slice(&(u64::MAX - count).to_be_bytes());
 
-        // Insert pdu
-        self.db.prepend_backfill_pdu(&pdu_id, &event_id, &value)?;
+        // Insert pdu, together with its search index entries
+        let body = message_body(&pdu);
+        self.db.prepend_backfill_pdu(
+            &pdu_id,
+            &event_id,
+            &value,
+            body.as_ref().ok().and_then(|body| body.as_deref()),
+        )?;
 
         drop(insert_lock);
 
-        if pdu.kind == TimelineEventType::RoomMessage {
-            #[derive(Deserialize)]
-            struct ExtractBody {
-                body: Option<String>,
-            }
-
-            let content = serde_json::from_str::<ExtractBody>(pdu.content.get())
-                .map_err(|_| Error::bad_database("Invalid content in pdu."))?;
-
-            if let Some(body) = content.body {
-                services()
-                    .rooms
-                    .search
-                    .index_pdu(shortroomid, &pdu_id, &body)?;
-            }
-        }
+        // Invalid message content is still reported, even though the pdu was added
+        body?;
         drop(mutex_lock);
 
         info!("Prepended backfill pdu");
@@ -1460,6 +1508,23 @@ impl Service {
     }
 }
 
+/// Returns the body of a message event, which is added to the search index.
+fn message_body(pdu: &PduEvent) -> Result<Option<String>> {
+    #[derive(Deserialize)]
+    struct ExtractBody {
+        body: Option<String>,
+    }
+
+    if pdu.kind != TimelineEventType::RoomMessage {
+        return Ok(None);
+    }
+
+    let content = serde_json::from_str::<ExtractBody>(pdu.content.get())
+        .map_err(|_| Error::bad_database("Invalid content in pdu."))?;
+
+    Ok(content.body)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
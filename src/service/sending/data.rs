@@ -1,4 +1,6 @@
-use ruma::ServerName;
+use std::collections::HashMap;
+
+use ruma::{OwnedServerName, ServerName};
 
 use crate::Result;
 
@@ -24,7 +26,11 @@ pub trait Data: Send + Sync {
         &'a self,
         outgoing_kind: &OutgoingKind,
     ) -> Box<dyn Iterator<Item = Result<(SendingEventType, Vec<u8>)>> + 'a>;
+    /// Number of active and queued requests of every server with any
+    fn federation_queue_depths(&self) -> HashMap<OwnedServerName, usize>;
     fn mark_as_active(&self, events: &[(SendingEventType, Vec<u8>)]) -> Result<()>;
     fn set_latest_educount(&self, server_name: &ServerName, educount: u64) -> Result<()>;
     fn get_latest_educount(&self, server_name: &ServerName) -> Result<u64>;
+    fn set_latest_appservice_educount(&self, appservice_id: &str, educount: u64) -> Result<()>;
+    fn get_latest_appservice_educount(&self, appservice_id: &str) -> Result<u64>;
 }
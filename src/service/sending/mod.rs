@@ -3,10 +3,10 @@ mod data;
 pub use data::Data;
 
 use std::{
-    collections::{BTreeMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     fmt::Debug,
-    sync::Arc,
-    time::Duration,
+    sync::{Arc, Mutex as StdMutex},
+    time::{Duration, Instant},
 };
 
 use crate::{
@@ -22,7 +22,8 @@ use base64::{engine::general_purpose, Engine as _};
 
 use ruma::{
     api::{
-        appservice::{self, Registration},
+        appservice::{self, event::push_events::v1::EphemeralData, Registration},
+        client::sync::sync_events::DeviceLists,
         federation::{
             self,
             transactions::edu::{
@@ -34,11 +35,15 @@ use ruma::{
     device_id,
     events::{
         push_rules::PushRulesEvent, receipt::ReceiptType, AnySyncEphemeralRoomEvent,
-        GlobalAccountDataEventType,
+        AnyToDeviceEvent, GlobalAccountDataEventType,
     },
-    push, uint, MilliSecondsSinceUnixEpoch, OwnedRoomId, OwnedServerName, OwnedUserId, ServerName,
-    UInt, UserId,
+    push,
+    serde::Raw,
+    uint, DeviceId, MilliSecondsSinceUnixEpoch, OwnedRoomId, OwnedServerName, OwnedUserId, RoomId,
+    ServerName, UInt, UserId,
 };
+use serde::{Deserialize, Serialize};
+use serde_json::value::to_raw_value;
 use tokio::{
     select,
     sync::{mpsc, Mutex, RwLock, Semaphore},
@@ -86,6 +91,29 @@ pub enum SendingEventType {
     Edu(Vec<u8>), // pdu json
 }
 
+/// Data sent to appservices besides PDUs, stored as JSON in `SendingEventType::Edu`
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "kind", content = "data", rename_all = "snake_case")]
+pub enum AppserviceEdu {
+    /// Typing notifications, read receipts and presence (MSC2409)
+    Ephemeral(Raw<EphemeralData>),
+    /// To-device messages for users of the appservice (MSC4203)
+    ToDevice(Raw<AnyToDeviceEvent>),
+    /// A user sharing a room with the appservice changed their device list (MSC3202)
+    DeviceListChanged(OwnedUserId),
+}
+
+/// Number of failed attempts after which the events for a push gateway are dropped. Appservices
+/// are then only tried again once new events are queued for them.
+const MAX_ATTEMPTS: u32 = 10;
+
+/// Failed attempts to send to a push gateway or appservice since the last successful one. Servers
+/// are simply tried again on the next tick instead.
+struct Backoff {
+    tries: u32,
+    retry_at: Instant,
+}
+
 pub struct Service {
     db: &'static dyn Data,
     pub federation_typers_stop:
@@ -95,6 +123,7 @@ pub struct Service {
     pub(super) maximum_requests: Arc<Semaphore>,
     pub sender: mpsc::UnboundedSender<(OutgoingKind, SendingEventType, Vec<u8>)>,
     receiver: Mutex<mpsc::UnboundedReceiver<(OutgoingKind, SendingEventType, Vec<u8>)>>,
+    backoff: StdMutex<HashMap<OutgoingKind, Backoff>>,
 }
 
 
@@ -108,6 +137,7 @@ impl Service {
             receiver: Mutex::new(receiver),
             federation_typers_stop: RwLock::new(BTreeMap::new()),
             maximum_requests: Arc::new(Semaphore::new(config.max_concurrent_requests as usize)),
+            backoff: StdMutex::new(HashMap::new()),
         })
     }
 
@@ -142,18 +172,46 @@ impl Service {
                         .collect();
                     destinations.remove(services().globals.server_name());
 
+                    // Appservices which want ephemeral events or device list changes are
+                    // handled the same way
+                    let appservices: Vec<String> = services()
+                        .appservice
+                        .read()
+                        .await
+                        .values()
+                        .filter(|info| {
+                            info.registration.receive_ephemeral || info.unstable.device_lists
+                        })
+                        .map(|info| info.registration.id.clone())
+                        .collect();
+
+                    // Destinations which failed before are retried once their backoff is over
+                    let backed_off: Vec<OutgoingKind> =
+                        self.backoff.lock().unwrap().keys().cloned().collect();
+
                     let running_destinations_lock = running_destinations.lock().await;
-                    for dest in destinations {
-                        let outgoing_kind = OutgoingKind::Normal(dest);
-                        if !running_destinations_lock.contains(&outgoing_kind) {
+                    for outgoing_kind in destinations
+                        .into_iter()
+                        .map(OutgoingKind::Normal)
+                        .chain(appservices.into_iter().map(OutgoingKind::Appservice))
+                        .chain(backed_off)
+                    {
+                        if !running_destinations_lock.contains(&outgoing_kind)
+                            && self.may_send(&outgoing_kind, false)
+                        {
                             Arc::clone(&self).spawn_worker(outgoing_kind, Arc::clone(&running_destinations));
                         }
                     }
+
+                    // Backed off appservices aren't sent any requests which could fail
+                    services().appservice.check_unreachable();
                 }
                 Some((outgoing_kind, event, _key)) = receiver.recv() => {
                     self.db.queue_requests(&[(&outgoing_kind, event)])?;
                     let running_destinations_lock = running_destinations.lock().await;
-                    if !running_destinations_lock.contains(&outgoing_kind) {
+                    if !running_destinations_lock.contains(&outgoing_kind)
+                        && self.may_send(&outgoing_kind, true)
+                    {
                         Arc::clone(&self).spawn_worker(outgoing_kind, Arc::clone(&running_destinations));
                     }
                 }
@@ -161,6 +219,29 @@ impl Service {
         }
     }
 
+    /// Whether a worker may be started for the destination, which is not the case while it is
+    /// backed off. `new_events` is set if events were just queued for it, which also gives
+    /// destinations which ran out of attempts another one.
+    fn may_send(&self, outgoing_kind: &OutgoingKind, new_events: bool) -> bool {
+        match self.backoff.lock().unwrap().get(outgoing_kind) {
+            None => true,
+            Some(backoff) if backoff.tries >= MAX_ATTEMPTS => new_events,
+            Some(backoff) => Instant::now() >= backoff.retry_at,
+        }
+    }
+
+    /// Remembers a failed attempt, returning the number of attempts which failed in a row
+    fn record_failure(&self, outgoing_kind: &OutgoingKind) -> u32 {
+        let mut backoff = self.backoff.lock().unwrap();
+        let backoff = backoff.entry(outgoing_kind.clone()).or_insert(Backoff {
+            tries: 0,
+            retry_at: Instant::now(),
+        });
+        backoff.tries += 1;
+        backoff.retry_at = Instant::now() + retry_backoff(backoff.tries);
+        backoff.tries
+    }
+
     fn spawn_worker(
         self: Arc<Self>,
         outgoing_kind: OutgoingKind,
@@ -195,10 +276,34 @@ impl Service {
                 .collect::<Vec<_>>();
 
             let mut selected_edus = Vec::new();
-            if let OutgoingKind::Normal(server_name) = &outgoing_kind {
-                if let Ok(edus) = self.select_edus(server_name).await {
-                    selected_edus = edus;
+            let mut appservice_educount = None;
+            match &outgoing_kind {
+                OutgoingKind::Normal(server_name) => {
+                    if let Ok(edus) = self.select_edus(server_name).await {
+                        selected_edus = edus
+                            .into_iter()
+                            .map(|edu| serde_json::to_vec(&edu).unwrap())
+                            .collect();
+                    }
                 }
+                OutgoingKind::Appservice(id) => match self.select_appservice_edus(id).await {
+                    Ok((edus, educount)) => {
+                        // Nothing to deliver, so there is no transaction to wait for either
+                        if edus.is_empty() {
+                            if let Err(e) = self.db.set_latest_appservice_educount(id, educount) {
+                                error!("Failed to update EDU count of appservice {id}: {e}");
+                            }
+                        }
+
+                        selected_edus = edus
+                            .into_iter()
+                            .map(|edu| serde_json::to_vec(&edu).unwrap())
+                            .collect();
+                        appservice_educount = Some(educount);
+                    }
+                    Err(e) => warn!("Failed to select EDUs for appservice {id}: {e}"),
+                },
+                OutgoingKind::Push(..) => {}
             }
 
             if active_events.is_empty() && new_events.is_empty() && selected_edus.is_empty() {
@@ -214,20 +319,37 @@ impl Service {
 
             active_events.extend(new_events.into_iter().map(|(e, k)| (k, e)));
 
-            let mut events_to_send = active_events
-                .into_iter()
-                .map(|(_key, event)| event)
-                .collect::<Vec<_>>();
-
-            events_to_send.extend(
-                selected_edus
+            let result = if let OutgoingKind::Push(..) = &outgoing_kind {
+                // Pushes are sent one by one and forgotten once delivered, so that a failure
+                // doesn't make the ones delivered before it be sent again
+                let mut result = Ok(outgoing_kind.clone());
+                for (key, event) in active_events {
+                    result = Self::handle_events(outgoing_kind.clone(), vec![event]).await;
+                    if result.is_err() {
+                        break;
+                    }
+                    if let Err(e) = self.db.delete_active_request(key) {
+                        error!(
+                            "Failed to delete delivered push for {:?}: {e}",
+                            outgoing_kind
+                        );
+                    }
+                }
+                result
+            } else {
+                let mut events_to_send = active_events
                     .into_iter()
-                    .map(|edu| SendingEventType::Edu(serde_json::to_vec(&edu).unwrap())),
-            );
+                    .map(|(_key, event)| event)
+                    .collect::<Vec<_>>();
+
+                events_to_send.extend(selected_edus.into_iter().map(SendingEventType::Edu));
 
-            let result = Self::handle_events(outgoing_kind.clone(), events_to_send).await;
+                Self::handle_events(outgoing_kind.clone(), events_to_send).await
+            };
 
             if result.is_ok() {
+                self.backoff.lock().unwrap().remove(&outgoing_kind);
+
                 if let Err(e) = self.db.delete_all_active_requests_for(&outgoing_kind) {
                     error!(
                         "Failed to delete active requests for {:?}, trying again later: {e}",
@@ -236,15 +358,63 @@ impl Service {
                     break;
                 }
 
+                if let (OutgoingKind::Appservice(id), Some(educount)) =
+                    (&outgoing_kind, appservice_educount)
+                {
+                    if let Err(e) = self.db.set_latest_appservice_educount(id, educount) {
+                        error!("Failed to update EDU count of appservice {id}: {e}");
+                    }
+                }
+
                 if !had_db_events {
                     break;
                 }
+            } else if let OutgoingKind::Normal(server_name) = &outgoing_kind {
+                // The events stay active, so they are picked up again on the next tick together
+                // with anything queued in the meantime
+                services().metrics.record_federation_failure(server_name);
+                break;
             } else {
+                // The events stay active, so they are picked up again together with anything
+                // queued in the meantime once the backoff is over
+                let tries = self.record_failure(&outgoing_kind);
+
+                match &outgoing_kind {
+                    OutgoingKind::Push(..) if tries >= MAX_ATTEMPTS => {
+                        warn!(
+                            "Sending to {:?} failed {tries} times, dropping its notifications",
+                            outgoing_kind
+                        );
+                        self.backoff.lock().unwrap().remove(&outgoing_kind);
+                        if let Err(e) = self.db.delete_all_active_requests_for(&outgoing_kind) {
+                            error!("Failed to drop notifications for {:?}: {e}", outgoing_kind);
+                        }
+                    }
+                    OutgoingKind::Appservice(_) if tries >= MAX_ATTEMPTS => {
+                        warn!(
+                            "Sending to {:?} failed {tries} times, waiting for new events before \
+                             trying again",
+                            outgoing_kind
+                        );
+                    }
+                    _ => {
+                        warn!(
+                            "Sending to {:?} failed {tries} time(s), retrying in {:?}",
+                            outgoing_kind,
+                            retry_backoff(tries)
+                        );
+                    }
+                }
                 break;
             }
         }
     }
 
+    /// Number of queued and in-flight events per federation destination.
+    pub fn federation_queue_depths(&self) -> HashMap<OwnedServerName, usize> {
+        self.db.federation_queue_depths()
+    }
+
     
 
     #[tracing::instrument(skip(self, server_name))]
@@ -367,6 +537,107 @@ impl Service {
         Ok(events)
     }
 
+    /// Collects the ephemeral events and device list changes in rooms the appservice is
+    /// interested in, which happened since its last successful transaction. Also returns the
+    /// count up to which they were collected.
+    #[tracing::instrument(skip(self))]
+    pub async fn select_appservice_edus(
+        &self,
+        appservice_id: &str,
+    ) -> Result<(Vec<AppserviceEdu>, u64)> {
+        let since = self.db.get_latest_appservice_educount(appservice_id)?;
+        let until = services().globals.current_count()?;
+
+        let Some(appservice) = services().appservice.read().await.get(appservice_id).cloned()
+        else {
+            return Ok((Vec::new(), since));
+        };
+
+        let receive_ephemeral = appservice.registration.receive_ephemeral;
+        let device_lists = appservice.unstable.device_lists;
+
+        // Every ephemeral event and key change bumps the global count, so nothing happened
+        if !(receive_ephemeral || device_lists) || since >= until {
+            return Ok((Vec::new(), until));
+        }
+
+        let mut edus = Vec::new();
+        let mut presence = BTreeMap::new();
+        let mut device_list_changes = HashSet::new();
+
+        for room_id in services().appservice.interesting_rooms(&appservice) {
+            if device_lists {
+                device_list_changes.extend(
+                    services()
+                        .users
+                        .keys_changed(room_id.as_ref(), since, Some(until))
+                        .filter_map(Result::ok),
+                );
+            }
+
+            if !receive_ephemeral {
+                continue;
+            }
+
+            let last_typing_update = services()
+                .rooms
+                .edus
+                .typing
+                .last_typing_update(&room_id)
+                .await?;
+            if last_typing_update > since && last_typing_update <= until {
+                let typing = services().rooms.edus.typing.typings_all(&room_id).await?;
+                edus.push(AppserviceEdu::Ephemeral(ephemeral_in_room(
+                    serde_json::json!({
+                        "type": "m.typing",
+                        "content": typing.content,
+                    }),
+                    &room_id,
+                )?));
+            }
+
+            for r in services()
+                .rooms
+                .edus
+                .read_receipt
+                .readreceipts_since(&room_id, since)
+            {
+                let (_user_id, count, read_receipt) = r?;
+
+                if count > until {
+                    continue;
+                }
+
+                let event = serde_json::from_str(read_receipt.json().get())
+                    .map_err(|_| Error::bad_database("Invalid edu event in read_receipts."))?;
+
+                edus.push(AppserviceEdu::Ephemeral(ephemeral_in_room(event, &room_id)?));
+            }
+
+            presence.extend(
+                services()
+                    .rooms
+                    .edus
+                    .presence
+                    .presence_since(&room_id, since)?,
+            );
+        }
+
+        for (_user_id, event) in presence {
+            edus.push(AppserviceEdu::Ephemeral(Raw::from_json(
+                to_raw_value(&event).expect("presence event can be serialized"),
+            )));
+        }
+
+        edus.extend(
+            device_list_changes
+                .into_iter()
+                .map(AppserviceEdu::DeviceListChanged),
+        );
+
+        Ok((edus, until))
+    }
+
     #[tracing::instrument(skip(self, pdu_id, user, pushkey))]
     pub fn send_push_pdu(&self, pdu_id: &[u8], user: &UserId, pushkey: String) -> Result<()> {
         let outgoing_kind = OutgoingKind::Push(user.to_owned(), pushkey);
@@ -438,6 +709,47 @@ impl Service {
         Ok(())
     }
 
+    /// Queues a to-device message for a user of an appservice, which will be delivered as part
+    /// of an appservice transaction (MSC4203)
+    #[tracing::instrument(skip(self, content))]
+    pub fn send_to_device_appservice(
+        &self,
+        appservice_id: String,
+        sender: &UserId,
+        target_user_id: &UserId,
+        target_device_id: &DeviceId,
+        event_type: &str,
+        content: serde_json::Value,
+    ) -> Result<()> {
+        let event = serde_json::json!({
+            "type": event_type,
+            "sender": sender,
+            "to_user_id": target_user_id,
+            "to_device_id": target_device_id,
+            "content": content,
+        });
+
+        let outgoing_kind = OutgoingKind::Appservice(appservice_id);
+        let event = SendingEventType::Edu(
+            serde_json::to_vec(&AppserviceEdu::ToDevice(Raw::from_json(
+                to_raw_value(&event).expect("json value can be serialized"),
+            )))
+            .expect("AppserviceEdu can be serialized"),
+        );
+        let keys = self.db.queue_requests(&[(&outgoing_kind, event.clone())])?;
+        self.sender
+            .send((outgoing_kind, event, keys.into_iter().next().unwrap()))
+            .unwrap();
+
+        Ok(())
+    }
+
+    /// Number of requests waiting to be sent to the destination, including those in flight
+    pub fn queue_depth(&self, outgoing_kind: &OutgoingKind) -> usize {
+        self.db.active_requests_for(outgoing_kind).count()
+            + self.db.queued_requests(outgoing_kind).count()
+    }
+
     /// Cleanup event data
     /// Used for instance after we remove an appservice registration
     ///
@@ -457,6 +769,9 @@ impl Service {
         match &kind {
             OutgoingKind::Appservice(id) => {
                 let mut pdu_jsons = Vec::new();
+                let mut ephemeral = Vec::new();
+                let mut to_device = Vec::new();
+                let mut device_lists = DeviceLists::new();
 
                 for event in &events {
                     match event {
@@ -474,15 +789,36 @@ impl Service {
                                 })?
                                 .to_room_event())
                         }
-                        SendingEventType::Edu(_) => {
-                            // Appservices don't need EDUs (?)
-                        }
+                        SendingEventType::Edu(edu) => match serde_json::from_slice(edu) {
+                            Ok(AppserviceEdu::Ephemeral(event)) => ephemeral.push(event),
+                            Ok(AppserviceEdu::ToDevice(event)) => to_device.push(event),
+                            Ok(AppserviceEdu::DeviceListChanged(user_id)) => {
+                                device_lists.changed.push(user_id)
+                            }
+                            Err(e) => warn!("Invalid EDU queued for appservice {id}: {e}"),
+                        },
                     }
                 }
 
+                let mut request = appservice::event::push_events::v1::Request::new(
+                    (&*general_purpose::URL_SAFE_NO_PAD.encode(calculate_hash(
+                        &events
+                            .iter()
+                            .map(|e| match e {
+                                SendingEventType::Edu(b) | SendingEventType::Pdu(b) => &**b,
+                            })
+                            .collect::<Vec<_>>(),
+                    )))
+                        .into(),
+                    pdu_jsons,
+                );
+                request.ephemeral = ephemeral;
+                request.to_device = to_device;
+                request.device_lists = device_lists;
+
                 let permit = services().sending.maximum_requests.acquire().await;
 
-                let response = match appservice_server::send_request(
+                let response = appservice_server::send_request(
                     services()
                         .appservice
                         .get_registration(id)
@@ -495,23 +831,13 @@ impl Service {
                                 ),
                             )
                         })?,
-                    appservice::event::push_events::v1::Request {
-                        //TODO: ephemeral pushing
-                        ephemeral: Vec::new(),
-                        events: pdu_jsons,
-                        txn_id: (&*general_purpose::URL_SAFE_NO_PAD.encode(calculate_hash(
-                            &events
-                                .iter()
-                                .map(|e| match e {
-                                    SendingEventType::Edu(b) | SendingEventType::Pdu(b) => &**b,
-                                })
-                                .collect::<Vec<_>>(),
-                        )))
-                            .into(),
-                    },
+                    request,
                 )
-                .await
-                {
+                .await;
+
+                services().appservice.record_request(id, &response);
+
+                let response = match response {
                     Ok(_) => Ok(kind.clone()),
                     Err(e) => Err((kind.clone(), e)),
                 };
@@ -590,14 +916,14 @@ impl Service {
 
                     let permit = services().sending.maximum_requests.acquire().await;
 
-                    let _response = services()
+                    let response = services()
                         .pusher
                         .send_push_notice(userid, unread, &pusher, rules_for_user, &pdu)
-                        .await
-                        .map(|_response| kind.clone())
-                        .map_err(|e| (kind.clone(), e));
+                        .await;
 
                     drop(permit);
+
+                    response.map_err(|e| (kind.clone(), e))?;
                 }
                 Ok(OutgoingKind::Push(userid.clone(), pushkey.clone()))
             }
@@ -812,3 +1138,23 @@ impl Service {
         )
     }
 }
+
+/// Adds the room id to an ephemeral event, as appservices receive them outside of a room's context
+fn ephemeral_in_room(
+    mut event: serde_json::Value,
+    room_id: &RoomId,
+) -> Result<Raw<EphemeralData>> {
+    event
+        .as_object_mut()
+        .ok_or_else(|| Error::bad_database("Ephemeral event is not a JSON object."))?
+        .insert("room_id".to_owned(), room_id.as_str().into());
+
+    Ok(Raw::from_json(to_raw_value(&event).expect("json value can be serialized")))
+}
+
+/// Delay before retrying a failed destination, doubling with every try and capped at a day
+fn retry_backoff(tries: u32) -> Duration {
+    Duration::from_secs(30)
+        .saturating_mul(2_u32.saturating_pow(tries.saturating_sub(1)))
+        .min(Duration::from_secs(60 * 60 * 24))
+}
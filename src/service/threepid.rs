@@ -0,0 +1,269 @@
+use crate::{services, utils, Error, Result};
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use ruma::{api::client::error::ErrorKind, ClientSecret, OwnedSessionId, SessionId, UInt};
+use std::{
+    collections::HashMap,
+    sync::Mutex as StdMutex,
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+use url::Url;
+
+/// Path of the endpoint users submit their email validation tokens to
+pub const SUBMIT_TOKEN_PATH: &str = "/_conduit/client/email/submit_token";
+
+const SID_LENGTH: usize = 32;
+const VALIDATION_TOKEN_LENGTH: usize = 8;
+
+/// What an email address is being validated for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValidationPurpose {
+    AddThreepid,
+    PasswordReset,
+}
+
+/// An email address validation which was requested by a client
+struct ValidationSession {
+    purpose: ValidationPurpose,
+    client_secret: String,
+    email: String,
+    send_attempt: UInt,
+    token: String,
+    next_link: Option<String>,
+    validated: bool,
+    started: Instant,
+}
+
+pub struct Service {
+    sessions: StdMutex<HashMap<String, ValidationSession>>, // by sid
+}
+
+impl Service {
+    pub fn build() -> Result<Self> {
+        Ok(Self {
+            sessions: StdMutex::new(HashMap::new()),
+        })
+    }
+
+    /// Sends an email with a validation token to the given address. Retries of the same send
+    /// attempt reuse the session without sending another email, like the spec requires.
+    pub async fn request_token(
+        &self,
+        purpose: ValidationPurpose,
+        client_secret: &ClientSecret,
+        email: &str,
+        send_attempt: UInt,
+        next_link: Option<String>,
+    ) -> Result<OwnedSessionId> {
+        if !services().globals.config.email.enabled {
+            return Err(Error::BadRequest(
+                ErrorKind::ThreepidDenied,
+                "Email addresses are not supported by this server.",
+            ));
+        }
+
+        let email = email.trim().to_lowercase();
+        let to: Mailbox = email
+            .parse()
+            .map_err(|_| Error::BadRequest(ErrorKind::InvalidParam, "Invalid email address."))?;
+
+        if let Some(next_link) = &next_link {
+            Url::parse(next_link)
+                .map_err(|_| Error::BadRequest(ErrorKind::InvalidParam, "Invalid next_link."))?;
+        }
+
+        let (sid, token) = {
+            let mut sessions = self.sessions.lock().unwrap();
+            sessions.retain(|_, session| session.started.elapsed() < token_ttl());
+
+            match sessions.iter_mut().find(|(_, session)| {
+                session.purpose == purpose
+                    && session.client_secret == client_secret.as_str()
+                    && session.email == email
+            }) {
+                Some((sid, session)) if session.send_attempt >= send_attempt => {
+                    return Ok(to_session_id(sid));
+                }
+                Some((sid, session)) => {
+                    session.send_attempt = send_attempt;
+                    session.next_link = next_link;
+                    (sid.clone(), session.token.clone())
+                }
+                None => {
+                    let sid = utils::random_string(SID_LENGTH);
+                    let token = utils::random_string(VALIDATION_TOKEN_LENGTH);
+                    sessions.insert(
+                        sid.clone(),
+                        ValidationSession {
+                            purpose,
+                            client_secret: client_secret.as_str().to_owned(),
+                            email: email.clone(),
+                            send_attempt,
+                            token: token.clone(),
+                            next_link,
+                            validated: false,
+                            started: Instant::now(),
+                        },
+                    );
+                    (sid, token)
+                }
+            }
+        };
+
+        let mut link = Url::parse(&submit_url()).expect("well_known client is a valid URL");
+        link.query_pairs_mut()
+            .append_pair("sid", &sid)
+            .append_pair("client_secret", client_secret.as_str())
+            .append_pair("token", &token);
+
+        let server_name = services().globals.server_name();
+        let (subject, action) = match purpose {
+            ValidationPurpose::AddThreepid => (
+                format!("Confirm your email address for {server_name}"),
+                format!("add this email address to your Matrix account on {server_name}"),
+            ),
+            ValidationPurpose::PasswordReset => (
+                format!("Reset your password on {server_name}"),
+                format!("reset the password of your Matrix account on {server_name}"),
+            ),
+        };
+
+        send_email(
+            to,
+            &subject,
+            format!(
+                "To {action}, open the following link:\n\n{link}\n\n\
+                Or enter this code in your client: {token}\n\n\
+                If you did not request this, you can ignore this email."
+            ),
+        )
+        .await?;
+
+        info!("Sent validation email for {:?} to {}", purpose, email);
+
+        Ok(to_session_id(&sid))
+    }
+
+    /// Validates the email address of a session, if the token matches. Returns the URL the
+    /// client asked to send the user to afterwards.
+    pub fn submit_token(
+        &self,
+        sid: &str,
+        client_secret: &str,
+        token: &str,
+    ) -> Result<Option<String>> {
+        let mut sessions = self.sessions.lock().unwrap();
+
+        let session = sessions
+            .get_mut(sid)
+            .filter(|session| {
+                session.started.elapsed() < token_ttl()
+                    && session.client_secret == client_secret
+                    && session.token == token
+            })
+            .ok_or(Error::BadRequest(
+                ErrorKind::ThreepidAuthFailed,
+                "Unknown session or invalid token.",
+            ))?;
+
+        session.validated = true;
+
+        Ok(session.next_link.clone())
+    }
+
+    /// Returns the email address of a validated session, ending the session
+    pub fn take_validated(
+        &self,
+        purpose: ValidationPurpose,
+        sid: &SessionId,
+        client_secret: &ClientSecret,
+    ) -> Result<String> {
+        let mut sessions = self.sessions.lock().unwrap();
+
+        match sessions.get(sid.as_str()) {
+            Some(session)
+                if session.purpose == purpose
+                    && session.client_secret == client_secret.as_str()
+                    && session.started.elapsed() < token_ttl() =>
+            {
+                if !session.validated {
+                    return Err(Error::BadRequest(
+                        ErrorKind::ThreepidAuthFailed,
+                        "Email address has not been validated yet.",
+                    ));
+                }
+            }
+            _ => {
+                return Err(Error::BadRequest(
+                    ErrorKind::ThreepidAuthFailed,
+                    "Unknown validation session.",
+                ))
+            }
+        }
+
+        Ok(sessions
+            .remove(sid.as_str())
+            .expect("session exists, as we just checked")
+            .email)
+    }
+}
+
+/// URL clients and users submit validation tokens to
+pub fn submit_url() -> String {
+    format!(
+        "{}{SUBMIT_TOKEN_PATH}",
+        services().globals.well_known_client().trim_end_matches('/')
+    )
+}
+
+async fn send_email(to: Mailbox, subject: &str, body: String) -> Result<()> {
+    let email_config = &services().globals.config.email;
+
+    let smtp_url = email_config
+        .smtp_url
+        .as_deref()
+        .ok_or_else(|| Error::bad_config("Emails are enabled, but no SMTP server is set."))?;
+    let from: Mailbox = email_config
+        .from
+        .as_deref()
+        .ok_or_else(|| Error::bad_config("Emails are enabled, but no sender is set."))?
+        .parse()
+        .map_err(|_| Error::bad_config("Invalid sender for emails."))?;
+
+    let message = Message::builder()
+        .from(from)
+        .to(to)
+        .subject(subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(body)
+        .map_err(|e| {
+            warn!("Failed to build email: {e}");
+            Error::BadServerResponse("Failed to build email.")
+        })?;
+
+    let transport = AsyncSmtpTransport::<Tokio1Executor>::from_url(smtp_url)
+        .map_err(|e| {
+            warn!("Invalid SMTP server URL: {e}");
+            Error::bad_config("Invalid SMTP server URL.")
+        })?
+        .build();
+
+    transport.send(message).await.map_err(|e| {
+        warn!("Failed to send email: {e}");
+        Error::BadServerResponse("Failed to send email.")
+    })?;
+
+    Ok(())
+}
+
+fn to_session_id(sid: &str) -> OwnedSessionId {
+    sid.try_into()
+        .expect("random alphanumeric strings are valid session ids")
+}
+
+fn token_ttl() -> Duration {
+    Duration::from_secs(services().globals.config.email.token_ttl)
+}
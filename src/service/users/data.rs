@@ -4,9 +4,11 @@ use ruma::{
     encryption::{CrossSigningKey, DeviceKeys, OneTimeKey},
     events::AnyToDeviceEvent,
     serde::Raw,
+    thirdparty::{Medium, ThirdPartyIdentifier},
     DeviceId, MilliSecondsSinceUnixEpoch, OneTimeKeyAlgorithm, OwnedDeviceId, OwnedMxcUri,
     OwnedOneTimeKeyId, OwnedUserId, UInt, UserId,
 };
+use serde_json::Value as JsonValue;
 use std::collections::BTreeMap;
 
 pub trait Data: Send + Sync {
@@ -16,6 +18,12 @@ pub trait Data: Send + Sync {
     /// Check if account is deactivated
     fn is_deactivated(&self, user_id: &UserId) -> Result<bool>;
 
+    /// Check if the user is a guest
+    fn is_guest(&self, user_id: &UserId) -> Result<bool>;
+
+    /// Marks the user as guest, or as full account after they upgraded their account
+    fn set_guest(&self, user_id: &UserId, is_guest: bool) -> Result<()>;
+
     /// Returns the number of users registered on this server.
     fn count(&self) -> Result<usize>;
 
@@ -54,9 +62,54 @@ pub trait Data: Send + Sync {
     /// Sets a new avatar_url or removes it if avatar_url is None.
     fn set_blurhash(&self, user_id: &UserId, blurhash: Option<String>) -> Result<()>;
 
+    /// Get the email of a user, as set by LDAP or OpenID Connect.
+    fn email(&self, user_id: &UserId) -> Result<Option<String>>;
+
     /// Sets a new email or removes it if email is None.
     fn set_email(&self, user_id: &UserId, email: Option<String>) -> Result<()>;
 
+    /// Get the subject identifying the user at the OpenID Connect provider.
+    fn oidc_subject(&self, user_id: &UserId) -> Result<Option<String>>;
+
+    /// Sets the subject identifying the user at the OpenID Connect provider or removes it if
+    /// subject is None.
+    fn set_oidc_subject(&self, user_id: &UserId, subject: Option<&str>) -> Result<()>;
+
+    /// Get a custom profile field of a user.
+    fn profile_field(&self, user_id: &UserId, field: &str) -> Result<Option<JsonValue>>;
+
+    /// Sets a custom profile field or removes it if value is None.
+    fn set_profile_field(
+        &self,
+        user_id: &UserId,
+        field: &str,
+        value: Option<&JsonValue>,
+    ) -> Result<()>;
+
+    /// Returns all custom profile fields of a user.
+    fn profile_fields(&self, user_id: &UserId) -> Result<BTreeMap<String, JsonValue>>;
+
+    /// Adds a validated third party identifier to a user.
+    fn add_threepid(
+        &self,
+        user_id: &UserId,
+        medium: &Medium,
+        address: &str,
+        added_at: MilliSecondsSinceUnixEpoch,
+    ) -> Result<()>;
+
+    /// Removes a third party identifier from a user.
+    fn remove_threepid(&self, user_id: &UserId, medium: &Medium, address: &str) -> Result<()>;
+
+    /// Returns all third party identifiers of a user.
+    fn threepids<'a>(
+        &'a self,
+        user_id: &UserId,
+    ) -> Box<dyn Iterator<Item = Result<ThirdPartyIdentifier>> + 'a>;
+
+    /// Find out which user a third party identifier belongs to.
+    fn find_from_threepid(&self, medium: &Medium, address: &str) -> Result<Option<OwnedUserId>>;
+
     /// Adds a new device to a user.
     fn create_device(
         &self,
@@ -10,15 +10,18 @@ pub use data::Data;
 use ruma::{
     api::client::{
         device::Device,
+        error::ErrorKind,
         filter::FilterDefinition,
         sync::sync_events::{self},
     },
     encryption::{CrossSigningKey, DeviceKeys, OneTimeKey},
     events::AnyToDeviceEvent,
     serde::Raw,
+    thirdparty::{Medium, ThirdPartyIdentifier},
     DeviceId, MilliSecondsSinceUnixEpoch, OneTimeKeyAlgorithm, OwnedDeviceId, OwnedMxcUri,
     OwnedOneTimeKeyId, OwnedRoomId, OwnedUserId, UInt, UserId,
 };
+use serde_json::Value as JsonValue;
 use tokio::{sync::Mutex, time::interval};
 use tracing::{debug, warn};
 
@@ -273,6 +276,16 @@ impl Service {
         self.db.is_deactivated(user_id)
     }
 
+    /// Check if the user is a guest
+    pub fn is_guest(&self, user_id: &UserId) -> Result<bool> {
+        self.db.is_guest(user_id)
+    }
+
+    /// Marks the user as guest, or as full account after they upgraded their account
+    pub fn set_guest(&self, user_id: &UserId, is_guest: bool) -> Result<()> {
+        self.db.set_guest(user_id, is_guest)
+    }
+
     /// Check if a user is an admin
     pub fn is_admin(&self, user_id: &UserId) -> Result<bool> {
         if let Some(admin_room_id) = services().admin.get_admin_room()? {
@@ -354,10 +367,103 @@ impl Service {
     }
 
     /// Sets a new email or removes it if email is None.
+    ///
+    /// The email also becomes a validated email third party identifier of the user, as it comes
+    /// from a trusted source like LDAP.
     pub fn set_email(&self, user_id: &UserId, email: Option<String>) -> Result<()> {
+        let old_email = self.db.email(user_id)?;
+        if old_email == email {
+            return Ok(());
+        }
+
+        if let Some(old_email) = old_email {
+            self.remove_threepid(user_id, &Medium::Email, &old_email)?;
+        }
+
+        if let Some(email) = &email {
+            if let Err(e) = self.add_threepid(user_id, &Medium::Email, email) {
+                warn!("Could not add email of {user_id} as third party identifier: {e}");
+            }
+        }
+
         self.db.set_email(user_id, email)
     }
 
+    /// Get the subject identifying the user at the OpenID Connect provider, if the user was
+    /// provisioned by or linked to it.
+    pub fn oidc_subject(&self, user_id: &UserId) -> Result<Option<String>> {
+        self.db.oidc_subject(user_id)
+    }
+
+    /// Sets the subject identifying the user at the OpenID Connect provider or removes it if
+    /// subject is None.
+    pub fn set_oidc_subject(&self, user_id: &UserId, subject: Option<&str>) -> Result<()> {
+        self.db.set_oidc_subject(user_id, subject)
+    }
+
+    /// Get a custom profile field of a user.
+    pub fn profile_field(&self, user_id: &UserId, field: &str) -> Result<Option<JsonValue>> {
+        self.db.profile_field(user_id, field)
+    }
+
+    /// Sets a custom profile field or removes it if value is None.
+    pub fn set_profile_field(
+        &self,
+        user_id: &UserId,
+        field: &str,
+        value: Option<&JsonValue>,
+    ) -> Result<()> {
+        self.db.set_profile_field(user_id, field, value)
+    }
+
+    /// Returns all custom profile fields of a user.
+    pub fn profile_fields(&self, user_id: &UserId) -> Result<BTreeMap<String, JsonValue>> {
+        self.db.profile_fields(user_id)
+    }
+
+    /// Adds a validated third party identifier to a user.
+    ///
+    /// Fails if the identifier already belongs to another user.
+    pub fn add_threepid(&self, user_id: &UserId, medium: &Medium, address: &str) -> Result<()> {
+        let address = normalize_threepid_address(medium, address);
+
+        match self.db.find_from_threepid(medium, &address)? {
+            Some(owner) if owner != user_id => Err(Error::BadRequest(
+                ErrorKind::ThreepidInUse,
+                "Third party identifier is already in use.",
+            )),
+            Some(_) => Ok(()),
+            None => {
+                self.db
+                    .add_threepid(user_id, medium, &address, MilliSecondsSinceUnixEpoch::now())
+            }
+        }
+    }
+
+    /// Removes a third party identifier from a user.
+    pub fn remove_threepid(&self, user_id: &UserId, medium: &Medium, address: &str) -> Result<()> {
+        self.db.remove_threepid(
+            user_id,
+            medium,
+            &normalize_threepid_address(medium, address),
+        )
+    }
+
+    /// Returns all third party identifiers of a user.
+    pub fn threepids(&self, user_id: &UserId) -> Result<Vec<ThirdPartyIdentifier>> {
+        self.db.threepids(user_id).collect()
+    }
+
+    /// Find out which user a third party identifier belongs to.
+    pub fn find_from_threepid(
+        &self,
+        medium: &Medium,
+        address: &str,
+    ) -> Result<Option<OwnedUserId>> {
+        self.db
+            .find_from_threepid(medium, &normalize_threepid_address(medium, address))
+    }
+
     /// Adds a new device to a user.
     pub fn create_device(
         &self,
@@ -520,7 +626,7 @@ impl Service {
         self.db.get_user_signing_key(user_id)
     }
 
-    pub fn add_to_device_event(
+    pub async fn add_to_device_event(
         &self,
         sender: &UserId,
         target_user_id: &UserId,
@@ -528,6 +634,27 @@ impl Service {
         event_type: &str,
         content: serde_json::Value,
     ) -> Result<()> {
+        // Appservices which opted into MSC4203 get the to-device messages of their users pushed
+        // in transactions instead
+        let appservice_id = services()
+            .appservice
+            .read()
+            .await
+            .values()
+            .find(|info| info.unstable.to_device && info.is_exclusive_user_match(target_user_id))
+            .map(|info| info.registration.id.clone());
+
+        if let Some(appservice_id) = appservice_id {
+            return services().sending.send_to_device_appservice(
+                appservice_id,
+                sender,
+                target_user_id,
+                target_device_id,
+                event_type,
+                content,
+            );
+        }
+
         self.db.add_to_device_event(
             sender,
             target_user_id,
@@ -609,7 +736,16 @@ impl Service {
         // password without logging in should check if the account is deactivated.
         self.db.set_password(user_id, None)?;
 
-        // TODO: Unhook 3PID
+        // Unhook all third party identifiers, so they can be used by other accounts
+        for threepid in self.threepids(user_id)? {
+            self.db
+                .remove_threepid(user_id, &threepid.medium, &threepid.address)?;
+        }
+
+        // Accounts without a password can't be told apart from deactivated ones, so logging in
+        // through OpenID Connect relies on the subject being gone instead
+        self.db.set_oidc_subject(user_id, None)?;
+
         Ok(())
     }
 
@@ -672,3 +808,11 @@ pub fn clean_signatures<F: Fn(&UserId) -> bool>(
 
     Ok(())
 }
+
+/// Email addresses are case-insensitive, so they are stored in lowercase
+fn normalize_threepid_address(medium: &Medium, address: &str) -> String {
+    match medium {
+        Medium::Email => address.trim().to_lowercase(),
+        _ => address.to_owned(),
+    }
+}
@@ -0,0 +1,331 @@
+//! Harness for running Conduit from integration tests.
+//!
+//! [`Server`] runs Conduit in the test process, so tests can use the services directly. As the
+//! services are a global of the process, there can only be one of these per test binary. Further
+//! instances, e.g. to test federation between two servers, run the `conduit` binary in a child
+//! process with [`ServerProcess`].
+
+use std::{
+    fs, io,
+    net::{SocketAddr, TcpListener},
+    ops::Deref,
+    path::Path,
+    process::{Child, Command},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use ruma::{OwnedServerName, UserId};
+use tempfile::TempDir;
+use tokio::{runtime, sync::oneshot, time::sleep};
+
+use crate::{service::users, services, utils, Config, Error, KeyValueDatabase, Result, Services};
+
+/// Whether the in-process server was already started, see [`Server::new_for_testing`]
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+/// How long to wait for a [`ServerProcess`] to accept requests
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A Conduit server running in the test process on an ephemeral port, with an in-memory database
+///
+/// Its directory, holding media and the admin socket, is removed when this is dropped.
+pub struct Server {
+    /// URL the server can be reached at, e.g. `http://127.0.0.1:41234`
+    pub base_url: String,
+    pub address: SocketAddr,
+    pub config: Config,
+    /// Users service, with additional helpers for setting up tests
+    pub users: TestUsers,
+    services: &'static Services,
+    _directory: TempDir,
+}
+
+impl Server {
+    /// Starts a server with the default test configuration.
+    ///
+    /// Panics if the server can't be started, or if it was already started in this process.
+    pub async fn new_for_testing() -> Self {
+        Self::new_for_testing_with(|_| {}).await
+    }
+
+    /// Starts a server, letting the caller adjust the test configuration first.
+    ///
+    /// The server runs on a runtime of its own, so it outlives the runtimes of individual tests
+    /// and can be shared between them.
+    pub async fn new_for_testing_with(configure: impl FnOnce(&mut Config)) -> Self {
+        assert!(
+            !STARTED.swap(true, Ordering::SeqCst),
+            "Only one in-process server can run per test binary, use ServerProcess for more"
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("can bind to an ephemeral port");
+        let address = listener.local_addr().expect("listener has an address");
+        let base_url = format!("http://{address}");
+
+        let directory = temp_directory().expect("can create a temporary directory");
+        let mut config = test_config(address, directory.path());
+        config.well_known.client = base_url.clone();
+        configure(&mut config);
+
+        let (ready_sender, ready_receiver) = oneshot::channel();
+
+        let server_config = config.clone();
+        thread::spawn(move || {
+            let runtime = runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .expect("can build a runtime for the test server");
+            let handle = runtime.handle().clone();
+
+            runtime.block_on(async move {
+                if let Err(e) = KeyValueDatabase::load_or_create(server_config).await {
+                    let _ = ready_sender.send(Err(e));
+                    return;
+                }
+
+                listener
+                    .set_nonblocking(true)
+                    .expect("listener can be made non-blocking");
+                let listener = tokio::net::TcpListener::from_std(listener)
+                    .expect("listener can be used with tokio");
+
+                let _ = ready_sender.send(Ok(handle));
+
+                axum::serve(
+                    listener,
+                    crate::routes(&services().globals.config).into_make_service(),
+                )
+                .await
+                .expect("test server runs until the process exits");
+            });
+        });
+
+        let runtime = ready_receiver
+            .await
+            .expect("test server thread reports whether it started")
+            .expect("test server can load its database");
+
+        let services = services();
+
+        Self {
+            base_url,
+            address,
+            config,
+            users: TestUsers {
+                service: Arc::clone(&services.users),
+                runtime,
+            },
+            services,
+            _directory: directory,
+        }
+    }
+
+    /// Returns credentials for a new user, with a username not used by other test users.
+    ///
+    /// The user isn't created, so tests can decide how to register it.
+    pub fn test_user(&self) -> TestUser {
+        TestUser {
+            username: format!("test_{}", utils::random_string(10).to_lowercase()),
+            password: utils::random_string(16),
+        }
+    }
+}
+
+impl Deref for Server {
+    type Target = Services;
+
+    fn deref(&self) -> &Self::Target {
+        self.services
+    }
+}
+
+/// The users service of a test [`Server`]
+pub struct TestUsers {
+    service: Arc<users::Service>,
+    runtime: runtime::Handle,
+}
+
+impl TestUsers {
+    /// Grants or revokes admin privileges, by joining the user to or removing them from the admin
+    /// room.
+    ///
+    /// Waits for the membership to be changed, so tests can use it without awaiting.
+    pub fn make_admin(&self, user_id: &UserId, is_admin: bool) -> Result<()> {
+        let displayname = self
+            .service
+            .displayname(user_id)?
+            .unwrap_or_else(|| user_id.localpart().to_owned());
+
+        // Runtimes can't be blocked on from within a runtime, so we wait in another thread
+        thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    self.runtime.block_on(async {
+                        if is_admin {
+                            services().admin.make_user_admin(user_id, displayname).await
+                        } else {
+                            services().admin.revoke_user_admin(user_id).await
+                        }
+                    })
+                })
+                .join()
+                .expect("changing the admin room membership doesn't panic")
+        })
+    }
+}
+
+impl Deref for TestUsers {
+    type Target = users::Service;
+
+    fn deref(&self) -> &Self::Target {
+        &self.service
+    }
+}
+
+/// Credentials for a user of a test server
+#[derive(Clone, Debug)]
+pub struct TestUser {
+    username: String,
+    password: String,
+}
+
+impl TestUser {
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn password(&self) -> &str {
+        &self.password
+    }
+}
+
+/// A Conduit server running the `conduit` binary in a child process, on an ephemeral port with an
+/// in-memory database.
+///
+/// The process is killed and its directory, holding its configuration and media, removed when
+/// this is dropped.
+///
+/// Instances federate with each other over plain HTTP, as builds with the `testing` feature don't
+/// use TLS for destinations on `localhost`.
+pub struct ServerProcess {
+    /// URL the server can be reached at, e.g. `http://127.0.0.1:41234`
+    pub base_url: String,
+    pub address: SocketAddr,
+    /// Server name of this instance, `localhost:{port}`, so every instance has its own
+    pub server_name: OwnedServerName,
+    child: Child,
+    _directory: TempDir,
+}
+
+impl ServerProcess {
+    /// Starts the `conduit` binary at the given path, which integration tests can get with
+    /// `env!("CARGO_BIN_EXE_conduit")`.
+    ///
+    /// The configuration can be extended with TOML, which is appended to the `[global]` table.
+    pub async fn spawn(binary: impl AsRef<Path>, extra_config: &str) -> Result<Self> {
+        // The binary binds the port itself, so there is a small window in which another process
+        // could take it
+        let address = TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+        let base_url = format!("http://{address}");
+        let server_name: OwnedServerName = format!("localhost:{}", address.port())
+            .try_into()
+            .expect("localhost with a port is a valid server name");
+
+        let directory = temp_directory()?;
+
+        let config_path = directory.path().join("conduit.toml");
+        fs::write(
+            &config_path,
+            format!(
+                "[global]\n\
+                 server_name = \"{server_name}\"\n\
+                 address = \"{}\"\n\
+                 port = {}\n\
+                 database_backend = \"memory\"\n\
+                 database_path = \"{}\"\n\
+                 allow_federation = true\n\
+                 log = \"warn\"\n\
+                 {extra_config}\n",
+                address.ip(),
+                address.port(),
+                directory.path().join("db").display(),
+            ),
+        )?;
+
+        let child = Command::new(binary.as_ref())
+            .env("CONDUIT_CONFIG", &config_path)
+            .spawn()?;
+
+        let server = Self {
+            base_url,
+            address,
+            server_name,
+            child,
+            _directory: directory,
+        };
+
+        server.wait_until_ready().await?;
+
+        Ok(server)
+    }
+
+    async fn wait_until_ready(&self) -> Result<()> {
+        let client = reqwest::Client::new();
+        let url = format!("{}/_matrix/client/versions", self.base_url);
+
+        let mut waited = Duration::ZERO;
+        while waited < STARTUP_TIMEOUT {
+            if client
+                .get(&url)
+                .send()
+                .await
+                .is_ok_and(|response| response.status().is_success())
+            {
+                return Ok(());
+            }
+
+            sleep(Duration::from_millis(100)).await;
+            waited += Duration::from_millis(100);
+        }
+
+        Err(Error::BadServerResponse(
+            "Test server process didn't start in time",
+        ))
+    }
+}
+
+impl Drop for ServerProcess {
+    fn drop(&mut self) {
+        // The directory is only removed after the process stopped using it
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Configuration of a test server on the given address, with only what tests commonly need
+fn test_config(address: SocketAddr, database_path: &Path) -> Config {
+    let mut config = Config::default();
+    config.address = address.ip();
+    config.port = address.port();
+    config.server_name = "localhost"
+        .try_into()
+        .expect("localhost is a valid server name");
+    config.database_backend = "memory".to_owned();
+    config.database_path = database_path
+        .to_str()
+        .expect("temporary directory path is valid unicode")
+        .to_owned();
+    config.unix_socket_path = format!("{}/admin.sock", config.database_path);
+    config.log = "warn".to_owned();
+    config
+}
+
+/// A new directory in the temporary directory of the system, for media and the admin socket
+fn temp_directory() -> io::Result<TempDir> {
+    tempfile::Builder::new().prefix("conduit-test-").tempdir()
+}
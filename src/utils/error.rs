@@ -59,6 +59,8 @@ pub enum Error {
     BadServerResponse(&'static str),
     #[error("{0}")]
     BadConfig(&'static str),
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String), // This is only needed when a reloaded config is rejected
     #[error("{0}")]
     /// Don't create this directly. Use Error::bad_database instead.
     BadDatabase(&'static str),
@@ -131,7 +133,8 @@ impl Error {
                     LimitExceeded { .. } => StatusCode::TOO_MANY_REQUESTS,
                     UserDeactivated => StatusCode::FORBIDDEN,
                     TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
-                    ConnectionTimeout => StatusCode::GATEWAY_TIMEOUT,
+                    ConnectionTimeout | NotYetUploaded => StatusCode::GATEWAY_TIMEOUT,
+                    CannotOverwriteMedia => StatusCode::CONFLICT,
                     BadStatus { .. } | ConnectionFailed => StatusCode::BAD_GATEWAY,
                     _ => StatusCode::BAD_REQUEST,
                 },
@@ -159,6 +162,7 @@ impl Error {
             Self::RocksDbError { .. } => db_error,
             Self::IoError { .. } => db_error,
             Self::BadConfig { .. } => db_error,
+            Self::InvalidConfig { .. } => db_error,
             Self::BadDatabase { .. } => db_error,
             _ => self.to_string(),
         }
@@ -0,0 +1,65 @@
+use conduit::admin_protocol::{self, Request, Response, Status, MAX_FRAME_SIZE};
+use std::io::Cursor;
+
+#[test]
+fn test_admin_protocol_frames() {
+    let mut buffer = Vec::new();
+    admin_protocol::write_frame(
+        &mut buffer,
+        &Request {
+            command: "list-local-users".to_owned(),
+            body: String::new(),
+        },
+    )
+    .unwrap();
+    admin_protocol::write_frame(
+        &mut buffer,
+        &Request {
+            command: "register-appservice".to_owned(),
+            body: "```\nid: bridge\n```".to_owned(),
+        },
+    )
+    .unwrap();
+
+    let length = u32::from_be_bytes(buffer[..4].try_into().unwrap()) as usize;
+    let first: serde_json::Value = serde_json::from_slice(&buffer[4..4 + length]).unwrap();
+    assert_eq!(first["command"], "list-local-users");
+
+    let mut reader = Cursor::new(buffer);
+    let first: Request = admin_protocol::read_frame(&mut reader).unwrap().unwrap();
+    assert_eq!(first.command, "list-local-users");
+    let second: Request = admin_protocol::read_frame(&mut reader).unwrap().unwrap();
+    assert_eq!(second.body, "```\nid: bridge\n```");
+    assert!(admin_protocol::read_frame::<Request>(&mut reader)
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_admin_protocol_rejects_large_frames() {
+    let mut frame = (MAX_FRAME_SIZE + 1).to_be_bytes().to_vec();
+    frame.extend_from_slice(b"{}");
+
+    assert!(admin_protocol::read_frame::<Request>(&mut Cursor::new(frame)).is_err());
+
+    let large = Response::new(Status::Ok, "a".repeat(MAX_FRAME_SIZE as usize));
+    assert!(admin_protocol::encode(&large).is_err());
+}
+
+#[test]
+fn test_admin_protocol_responses() {
+    let response: Response = serde_json::from_value(serde_json::json!({
+        "status": "failed",
+        "text": "The specified user does not exist!",
+    }))
+    .unwrap();
+    assert_eq!(response.status, Status::Failed);
+    assert!(response.data.is_none());
+
+    assert_eq!(Status::Ok.exit_code(), 0);
+    assert_eq!(Status::Help.exit_code(), 0);
+    assert_eq!(Status::Failed.exit_code(), 1);
+    assert_eq!(Status::Error.exit_code(), 1);
+    assert_eq!(Status::Invalid.exit_code(), 2);
+    assert_eq!(Status::Forbidden.exit_code(), 4);
+}
@@ -0,0 +1,72 @@
+use conduit::testing::ServerProcess;
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+
+async fn spawn() -> ServerProcess {
+    ServerProcess::spawn(env!("CARGO_BIN_EXE_conduit"), "allow_registration = true")
+        .await
+        .unwrap()
+}
+
+/// Registers a user on the given server, returning their user ID and access token
+async fn register(server: &ServerProcess, username: &str) -> (String, String) {
+    let client = reqwest::Client::new();
+    let url = format!("{}/_matrix/client/v3/register", server.base_url);
+    let mut body = json!({
+        "username": username,
+        "password": "password",
+    });
+
+    // The first request only starts the user-interactive authentication
+    let res = client.post(&url).json(&body).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let uiaa: Value = res.json().await.unwrap();
+    body["auth"] = json!({
+        "type": "m.login.dummy",
+        "session": uiaa["session"],
+    });
+
+    let res = client.post(&url).json(&body).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let registered: Value = res.json().await.unwrap();
+
+    (
+        registered["user_id"].as_str().unwrap().to_owned(),
+        registered["access_token"].as_str().unwrap().to_owned(),
+    )
+}
+
+#[tokio::test]
+async fn test_remote_profile() {
+    let local = spawn().await;
+    let remote = spawn().await;
+    let client = reqwest::Client::new();
+
+    let (user_id, access_token) = register(&remote, "alice").await;
+    assert_eq!(user_id, format!("@alice:{}", remote.server_name));
+    let res = client
+        .put(format!(
+            "{}/_matrix/client/v3/profile/{user_id}/displayname",
+            remote.base_url
+        ))
+        .bearer_auth(&access_token)
+        .json(&json!({ "displayname": "Alice" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // The local server has to ask the remote one, which checks the request's signature against the
+    // keys it fetches from the local server
+    let res = client
+        .get(format!(
+            "{}/_matrix/client/v3/profile/{user_id}",
+            local.base_url
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let profile: Value = res.json().await.unwrap();
+    assert_eq!(profile["displayname"], "Alice");
+}
@@ -0,0 +1,48 @@
+use conduit::Server;
+use ruma::UserId;
+use std::sync::Arc;
+use tokio::sync::OnceCell;
+
+static SERVER: OnceCell<Arc<Server>> = OnceCell::const_new();
+
+async fn server() -> &'static Arc<Server> {
+    SERVER
+        .get_or_init(|| async {
+            let server = Server::new_for_testing().await;
+            Arc::new(server)
+        })
+        .await
+}
+
+#[tokio::test]
+async fn test_server_responds() {
+    let server = server().await;
+
+    let res = reqwest::get(format!("{}/_matrix/client/versions", server.base_url))
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), 200);
+}
+
+#[tokio::test]
+async fn test_make_admin() {
+    let server = server().await;
+    let test_user = server.test_user();
+
+    let user_id =
+        UserId::parse_with_server_name(test_user.username(), server.config.server_name.as_str())
+            .unwrap();
+    server
+        .users
+        .create(&user_id, Some(test_user.password()))
+        .unwrap();
+
+    assert!(!server.users.is_admin(&user_id).unwrap());
+
+    server.users.make_admin(&user_id, true).unwrap();
+    assert!(server.users.is_admin(&user_id).unwrap());
+
+    server.users.make_admin(&user_id, false).unwrap();
+    assert!(!server.users.is_admin(&user_id).unwrap());
+}
@@ -0,0 +1,254 @@
+use conduit::Server;
+use reqwest::StatusCode;
+use ruma::UserId;
+use serde_json::{json, Value};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use tokio::sync::OnceCell;
+
+static SERVER: OnceCell<Arc<Server>> = OnceCell::const_new();
+
+/// Makes transaction ids unique across all tests of this binary
+static TRANSACTION: AtomicUsize = AtomicUsize::new(0);
+
+async fn server() -> &'static Arc<Server> {
+    SERVER
+        .get_or_init(|| async {
+            let server = Server::new_for_testing().await;
+            Arc::new(server)
+        })
+        .await
+}
+
+/// A logged in user of the test server
+struct Client {
+    server: &'static Arc<Server>,
+    http: reqwest::Client,
+    user_id: String,
+    access_token: String,
+}
+
+impl Client {
+    async fn new() -> Self {
+        let server = server().await;
+        let test_user = server.test_user();
+        let user_id = UserId::parse_with_server_name(
+            test_user.username(),
+            server.config.server_name.as_str(),
+        )
+        .unwrap();
+        server
+            .users
+            .create(&user_id, Some(test_user.password()))
+            .unwrap();
+
+        let http = reqwest::Client::new();
+        let login: Value = http
+            .post(format!("{}/_matrix/client/v3/login", server.base_url))
+            .json(&json!({
+                "type": "m.login.password",
+                "identifier": {
+                    "type": "m.id.user",
+                    "user": user_id.localpart(),
+                },
+                "password": test_user.password(),
+            }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        Self {
+            server,
+            http,
+            user_id: user_id.to_string(),
+            access_token: login["access_token"].as_str().unwrap().to_owned(),
+        }
+    }
+
+    async fn request(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        body: Value,
+    ) -> (StatusCode, Value) {
+        let mut request = self
+            .http
+            .request(
+                method,
+                format!("{}/_matrix/client/v3{path}", self.server.base_url),
+            )
+            .bearer_auth(&self.access_token);
+        if !body.is_null() {
+            request = request.json(&body);
+        }
+        let res = request.send().await.unwrap();
+        let status = res.status();
+        (status, res.json().await.unwrap_or(Value::Null))
+    }
+
+    async fn post(&self, path: &str, body: Value) -> Value {
+        let (status, body) = self.request(reqwest::Method::POST, path, body).await;
+        assert_eq!(status, StatusCode::OK, "POST {path} failed: {body}");
+        body
+    }
+
+    /// Creates a public room with the given history visibility
+    async fn create_room(&self, history_visibility: &str) -> String {
+        let body = self
+            .post(
+                "/createRoom",
+                json!({
+                    "preset": "public_chat",
+                    "initial_state": [{
+                        "type": "m.room.history_visibility",
+                        "state_key": "",
+                        "content": { "history_visibility": history_visibility },
+                    }],
+                }),
+            )
+            .await;
+        body["room_id"].as_str().unwrap().to_owned()
+    }
+
+    async fn send_message(&self, room_id: &str) -> String {
+        let transaction = TRANSACTION.fetch_add(1, Ordering::SeqCst);
+        let (status, body) = self
+            .request(
+                reqwest::Method::PUT,
+                &format!("/rooms/{room_id}/send/m.room.message/{transaction}"),
+                json!({ "msgtype": "m.text", "body": "hello" }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK, "sending failed: {body}");
+        body["event_id"].as_str().unwrap().to_owned()
+    }
+
+    async fn join(&self, room_id: &str) {
+        self.post(&format!("/rooms/{room_id}/join"), json!({}))
+            .await;
+    }
+
+    async fn leave(&self, room_id: &str) {
+        self.post(&format!("/rooms/{room_id}/leave"), json!({}))
+            .await;
+    }
+
+    async fn invite(&self, room_id: &str, user: &Client) {
+        self.post(
+            &format!("/rooms/{room_id}/invite"),
+            json!({ "user_id": user.user_id }),
+        )
+        .await;
+    }
+
+    async fn ban(&self, room_id: &str, user: &Client) {
+        self.post(
+            &format!("/rooms/{room_id}/ban"),
+            json!({ "user_id": user.user_id }),
+        )
+        .await;
+    }
+
+    async fn can_see(&self, room_id: &str, event_id: &str) -> bool {
+        let (status, body) = self
+            .request(
+                reqwest::Method::GET,
+                &format!("/rooms/{room_id}/event/{event_id}"),
+                Value::Null,
+            )
+            .await;
+        match status {
+            StatusCode::OK => true,
+            StatusCode::FORBIDDEN => false,
+            _ => panic!("unexpected response {status}: {body}"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_world_readable_history() {
+    let alice = Client::new().await;
+    let bob = Client::new().await;
+
+    let room_id = alice.create_room("world_readable").await;
+    let event_id = alice.send_message(&room_id).await;
+
+    assert!(bob.can_see(&room_id, &event_id).await);
+}
+
+#[tokio::test]
+async fn test_shared_history() {
+    let alice = Client::new().await;
+    let bob = Client::new().await;
+    let outsider = Client::new().await;
+
+    let room_id = alice.create_room("shared").await;
+    let event_id = alice.send_message(&room_id).await;
+
+    assert!(!bob.can_see(&room_id, &event_id).await);
+    bob.join(&room_id).await;
+    assert!(bob.can_see(&room_id, &event_id).await);
+
+    assert!(!outsider.can_see(&room_id, &event_id).await);
+}
+
+#[tokio::test]
+async fn test_invited_history() {
+    let alice = Client::new().await;
+    let bob = Client::new().await;
+
+    let room_id = alice.create_room("invited").await;
+    let before_invite = alice.send_message(&room_id).await;
+    alice.invite(&room_id, &bob).await;
+    let after_invite = alice.send_message(&room_id).await;
+    bob.join(&room_id).await;
+
+    assert!(!bob.can_see(&room_id, &before_invite).await);
+    assert!(bob.can_see(&room_id, &after_invite).await);
+}
+
+#[tokio::test]
+async fn test_joined_history() {
+    let alice = Client::new().await;
+    let bob = Client::new().await;
+
+    let room_id = alice.create_room("joined").await;
+    alice.invite(&room_id, &bob).await;
+    let before_join = alice.send_message(&room_id).await;
+    bob.join(&room_id).await;
+    let after_join = alice.send_message(&room_id).await;
+
+    assert!(!bob.can_see(&room_id, &before_join).await);
+    assert!(bob.can_see(&room_id, &after_join).await);
+}
+
+#[tokio::test]
+async fn test_former_member_keeps_shared_history() {
+    let alice = Client::new().await;
+    let bob = Client::new().await;
+    let carol = Client::new().await;
+
+    let room_id = alice.create_room("shared").await;
+    let event_id = alice.send_message(&room_id).await;
+
+    // Their latest membership changes are an invite and a ban, but they joined after the event
+    // before that
+    bob.join(&room_id).await;
+    bob.leave(&room_id).await;
+    alice.invite(&room_id, &bob).await;
+    assert!(bob.can_see(&room_id, &event_id).await);
+
+    carol.join(&room_id).await;
+    carol.leave(&room_id).await;
+    alice.ban(&room_id, &carol).await;
+    assert!(carol.can_see(&room_id, &event_id).await);
+
+    // Having been joined only before an event doesn't give access to it
+    let later_event_id = alice.send_message(&room_id).await;
+    assert!(!bob.can_see(&room_id, &later_event_id).await);
+}
@@ -0,0 +1,290 @@
+// An integration test for OpenID Connect single sign-on.
+//
+// This test is designed to be run with `cargo test --test oidc`.
+// Unlike the LDAP test it needs no external services, as it runs a minimal mock provider itself.
+//
+// The test will:
+// 1. Start a mock OpenID Connect provider, which logs in every user as `alice`.
+// 2. Create a temporary Conduit server configuration pointing to the provider.
+// 3. Start the Conduit server on a random, available port.
+// 4. Go through the SSO redirects and log in with the resulting login token.
+
+use axum::{
+    extract::{Query, State},
+    response::Redirect,
+    routing::{get, post},
+    Form, Json, Router,
+};
+use base64::{engine::general_purpose, Engine as _};
+use conduit::{Config, KeyValueDatabase};
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use reqwest::{redirect::Policy, StatusCode, Url};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const CLIENT_ID: &str = "conduit";
+const CLIENT_SECRET: &str = "mock-provider-secret";
+
+/// Nonce and PKCE code challenge of each authorization code handed out
+type Codes = Arc<Mutex<HashMap<String, (String, String)>>>;
+
+#[derive(Clone)]
+struct MockProvider {
+    issuer: String,
+    codes: Codes,
+}
+
+async fn discovery(State(provider): State<MockProvider>) -> Json<serde_json::Value> {
+    Json(json!({
+        "issuer": provider.issuer,
+        "authorization_endpoint": format!("{}/authorize", provider.issuer),
+        "token_endpoint": format!("{}/token", provider.issuer),
+        "jwks_uri": format!("{}/jwks", provider.issuer),
+        "id_token_signing_alg_values_supported": ["RS256", "HS256"],
+    }))
+}
+
+async fn authorize(
+    State(provider): State<MockProvider>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Redirect {
+    assert_eq!(params["client_id"], CLIENT_ID);
+    assert_eq!(params["code_challenge_method"], "S256");
+
+    let code = format!("code-{}", provider.codes.lock().unwrap().len());
+    provider.codes.lock().unwrap().insert(
+        code.clone(),
+        (params["nonce"].clone(), params["code_challenge"].clone()),
+    );
+
+    let mut redirect_uri = Url::parse(&params["redirect_uri"]).unwrap();
+    redirect_uri
+        .query_pairs_mut()
+        .append_pair("code", &code)
+        .append_pair("state", &params["state"]);
+
+    Redirect::to(redirect_uri.as_str())
+}
+
+async fn token(
+    State(provider): State<MockProvider>,
+    Form(params): Form<HashMap<String, String>>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let (nonce, code_challenge) = provider
+        .codes
+        .lock()
+        .unwrap()
+        .remove(&params["code"])
+        .ok_or(StatusCode::BAD_REQUEST)?;
+
+    let verifier_hash =
+        general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(params["code_verifier"].as_bytes()));
+    if verifier_hash != code_challenge || params["client_secret"] != CLIENT_SECRET {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let id_token = jsonwebtoken::encode(
+        &Header::default(),
+        &json!({
+            "iss": provider.issuer,
+            "aud": CLIENT_ID,
+            "sub": "alice-subject",
+            "iat": now,
+            "exp": now + 60,
+            "nonce": nonce,
+            "preferred_username": "Alice",
+            "name": "Alice Example",
+            "email": "alice@example.com",
+        }),
+        &EncodingKey::from_secret(CLIENT_SECRET.as_bytes()),
+    )
+    .unwrap();
+
+    Ok(Json(json!({
+        "access_token": "unused",
+        "token_type": "Bearer",
+        "id_token": id_token,
+    })))
+}
+
+async fn start_mock_provider() -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let issuer = format!("http://{}", listener.local_addr().unwrap());
+
+    let app = Router::new()
+        .route("/.well-known/openid-configuration", get(discovery))
+        .route("/authorize", get(authorize))
+        .route("/token", post(token))
+        .with_state(MockProvider {
+            issuer: issuer.clone(),
+            codes: Arc::default(),
+        });
+
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    issuer
+}
+
+async fn setup() -> SocketAddr {
+    let issuer = start_mock_provider().await;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server_address = listener.local_addr().unwrap();
+
+    let db_path = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut config = Config::default();
+    config.server_name = "localhost"
+        .try_into()
+        .expect("should be a valid server name");
+    config.database_path = db_path
+        .path()
+        .to_str()
+        .expect("path is valid unicode")
+        .to_owned();
+    config.log = "warn,conduit=info".to_owned();
+    // The provider redirects back to the client URL
+    config.well_known.client = format!("http://{server_address}");
+
+    config.oidc.enabled = true;
+    config.oidc.issuer = Some(issuer.parse().unwrap());
+    config.oidc.client_id = CLIENT_ID.to_owned();
+    config.oidc.client_secret = Some(CLIENT_SECRET.to_owned());
+    config.oidc.allowed_redirect_origins = vec!["http://client.example".parse().unwrap()];
+    // The mock provider signs ID tokens with the client secret
+    config.oidc.id_token_signing_algs = Some(vec![Algorithm::HS256]);
+
+    KeyValueDatabase::load_or_create(config)
+        .await
+        .expect("Failed to load database");
+
+    // Keep the database around for the rest of the test
+    std::mem::forget(db_path);
+
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            conduit::routes(&Config::default()).into_make_service(),
+        )
+        .await
+        .unwrap();
+    });
+
+    server_address
+}
+
+fn location(res: &reqwest::Response) -> Url {
+    assert!(
+        res.status().is_redirection(),
+        "Expected a redirect, got {}",
+        res.status()
+    );
+    Url::parse(res.headers()["location"].to_str().unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn test_oidc_login_flow() {
+    let server_address = setup().await;
+    let base_url = format!("http://{server_address}");
+    let client = reqwest::Client::builder()
+        .redirect(Policy::none())
+        .build()
+        .unwrap();
+
+    // Test Case 1: SSO is advertised
+    let body: serde_json::Value = client
+        .get(format!("{base_url}/_matrix/client/v3/login"))
+        .send()
+        .await
+        .expect("Request failed")
+        .json()
+        .await
+        .expect("Failed to parse response body");
+    let flows = body["flows"].as_array().unwrap();
+    assert!(flows.iter().any(|flow| flow["type"] == "m.login.sso"));
+    assert!(flows.iter().any(|flow| flow["type"] == "m.login.token"));
+
+    // Test Case 2: Login tokens are only sent to allowed clients
+    let res = client
+        .get(format!("{base_url}/_matrix/client/v3/login/sso/redirect"))
+        .query(&[("redirectUrl", "https://attacker.example/done")])
+        .send()
+        .await
+        .expect("Request failed");
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    // Test Case 3: Conduit redirects to the provider, which redirects back to Conduit
+    let res = client
+        .get(format!("{base_url}/_matrix/client/v3/login/sso/redirect"))
+        .query(&[("redirectUrl", "http://client.example/done")])
+        .send()
+        .await
+        .expect("Request failed");
+    let authorize_url = location(&res);
+
+    let res = client
+        .get(authorize_url)
+        .send()
+        .await
+        .expect("Request failed");
+    let callback_url = location(&res);
+    assert!(callback_url.as_str().starts_with(&base_url));
+
+    // Test Case 4: The callback sends us back to the client with a login token
+    let res = client
+        .get(callback_url.clone())
+        .send()
+        .await
+        .expect("Request failed");
+    let client_url = location(&res);
+    assert_eq!(client_url.host_str(), Some("client.example"));
+    let login_token = client_url
+        .query_pairs()
+        .find(|(key, _)| key == "loginToken")
+        .map(|(_, value)| value.into_owned())
+        .expect("Redirect should contain a login token");
+
+    // Test Case 5: The login token can be used once
+    let login = json!({
+        "type": "m.login.token",
+        "token": login_token,
+    });
+    let res = client
+        .post(format!("{base_url}/_matrix/client/v3/login"))
+        .json(&login)
+        .send()
+        .await
+        .expect("Request failed");
+    assert_eq!(res.status(), StatusCode::OK, "Expected successful login");
+    let body: serde_json::Value = res.json().await.expect("Failed to parse response body");
+    assert_eq!(body["user_id"], "@alice:localhost");
+
+    let res = client
+        .post(format!("{base_url}/_matrix/client/v3/login"))
+        .json(&login)
+        .send()
+        .await
+        .expect("Request failed");
+    assert_ne!(
+        res.status(),
+        StatusCode::OK,
+        "Login token should be single use"
+    );
+
+    // Test Case 6: The callback can't be replayed
+    let res = client
+        .get(callback_url)
+        .send()
+        .await
+        .expect("Request failed");
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+}